@@ -6,10 +6,11 @@
 use std::path::PathBuf;
 
 use crate::{
-    AutomationLaneId, AutomationTarget, ClipId, ClipboardNote, CurveType, DrumStep,
-    EffectId, EffectType, EqConfig, EffectSlot, EnvConfig, FilterConfig, FilterType,
-    InstrumentId, LfoConfig, MixerSelection, MusicalSettings, Param, PlacementId,
-    ServerStatus, SourceType, VstPluginKind,
+    ArpConfig, AutomationLaneId, AutomationTarget, ClipId, ClipboardNote, CurveType, DrumStep,
+    EffectId, EffectType, EqConfig, EffectSlot, EnvConfig, FilterConfig, FilterType, GeneratorRegion,
+    InstrumentId, LfoConfig, MixerSelection, MusicalSettings, NoteGenerator, NoteId, NoteSelectionOp,
+    NoteTransform, Param, PlacementId, ServerStatus, SourceType, TakeId, TrigCondition, VoiceSettings,
+    VstPluginKind,
 };
 
 // ============================================================================
@@ -261,7 +262,7 @@ impl AudioDirty {
 }
 
 /// Result of dispatching an action — contains side effects for the UI layer to process.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct DispatchResult {
     pub quit: bool,
     pub nav: Vec<NavIntent>,
@@ -274,20 +275,6 @@ pub struct DispatchResult {
     pub reset_playhead: bool,
 }
 
-impl Default for DispatchResult {
-    fn default() -> Self {
-        Self {
-            quit: false,
-            nav: Vec::new(),
-            status: Vec::new(),
-            project_name: None,
-            audio_dirty: AudioDirty::default(),
-            stop_playback: false,
-            reset_playhead: false,
-        }
-    }
-}
-
 impl DispatchResult {
     pub fn none() -> Self {
         Self::default()
@@ -384,6 +371,10 @@ pub enum SessionAction {
     AdjustHumanizeVelocity(f32),
     AdjustHumanizeTiming(f32),
     ToggleMasterMute,
+    /// Re-seed probability resolution (changes which probabilistic hits play)
+    SetProbabilitySeed(u64),
+    /// Lock the current loop iteration's probability outcome, or release the lock
+    ToggleProbabilityLock,
 }
 
 /// MIDI configuration actions.
@@ -445,6 +436,13 @@ pub enum ArrangementAction {
     EnterClipEdit(ClipId),
     ExitClipEdit,
     PlayStop,
+    AuditionClipTake { clip_id: ClipId, take: Option<TakeId> },
+    SetClipCompRegion { clip_id: ClipId, start_tick: u32, end_tick: u32, take: TakeId },
+    FlattenClipComp(ClipId),
+    /// Fill a clip with generated notes on a grid of `step_ticks`
+    GenerateClip { clip_id: ClipId, step_ticks: u32, generator: NoteGenerator },
+    /// Replace a clip's notes with a mini-notation pattern
+    SetClipNotation { clip_id: ClipId, source: String, ticks_per_cycle: u32 },
 }
 
 /// Piano roll actions — all variants carry the data they need.
@@ -482,6 +480,67 @@ pub enum PianoRollAction {
     CancelExport,
     /// Copy notes within a region to the clipboard
     CopyNotes { track: usize, start_tick: u32, end_tick: u32, start_pitch: u8, end_pitch: u8 },
+    /// Delete notes by id
+    DeleteNotes { track: usize, ids: Vec<NoteId> },
+    /// Move notes by id (tick and semitone offsets)
+    MoveNotes { track: usize, ids: Vec<NoteId>, tick_delta: i32, pitch_delta: i16 },
+    /// Lengthen or shorten notes by id
+    ResizeNotes { track: usize, ids: Vec<NoteId>, duration_delta: i32 },
+    /// Set velocity on notes by id
+    SetNoteVelocity { track: usize, ids: Vec<NoteId>, velocity: u8 },
+    /// Set the trig condition on notes by id
+    SetNoteCondition { track: usize, ids: Vec<NoteId>, condition: TrigCondition },
+    /// Hold or release fill mode (for Fill trig conditions)
+    SetFill(bool),
+    /// Change the note selection on a track
+    UpdateSelection { track: usize, op: NoteSelectionOp },
+    /// Apply a batch transform to notes by id (usually the selection)
+    TransformSelection { track: usize, ids: Vec<NoteId>, transform: NoteTransform },
+    /// Apply a batch transform to every note in a region
+    TransformRegion {
+        track: usize,
+        start_tick: u32,
+        end_tick: u32,
+        start_pitch: u8,
+        end_pitch: u8,
+        transform: NoteTransform,
+    },
+    /// Change the voice count of a track's instrument
+    AdjustVoiceCount(usize, i8),       // (track, delta)
+    CycleStealMode(usize),             // track
+    ToggleLegato(usize),               // track
+    /// Rewrite a mono track's notes so none overlap
+    ResolveOverlaps(usize),            // track
+    TogglePunch,
+    SetPunchIn(u32),
+    SetPunchOut(u32),
+    /// Save the current loop range under a name
+    SaveLoopRange(String),
+    RecallLoopRange(usize),            // saved loop index
+    DeleteLoopRange(usize),            // saved loop index
+    CycleNoteRecordMode,
+    /// Play a take in place of the track's notes (None stops auditioning)
+    AuditionTake { track: usize, take: Option<TakeId> },
+    /// Use a take for a region of the comp
+    SetCompRegion { track: usize, start_tick: u32, end_tick: u32, take: TakeId },
+    DeleteTake { track: usize, take: TakeId },
+    /// Write the comp into the track's notes
+    FlattenComp(usize),                // track
+    ToggleStepInput,
+    /// Step input key pressed (held keys form a chord)
+    StepInputNoteOn { track: usize, pitch: u8, velocity: u8 },
+    /// Step input key released (advances once all keys are up)
+    StepInputNoteOff { pitch: u8 },
+    /// Enter a chord and advance in one go
+    StepInputChord { track: usize, pitches: Vec<u8>, velocity: u8 },
+    StepInputRest,
+    StepInputTie(usize),               // track
+    StepInputBack(usize),              // track
+    SetStepCursor(u32),
+    CycleStepLength,
+    CycleStepModifier,
+    /// Replace a region's notes with generated ones
+    Generate { track: usize, region: GeneratorRegion, generator: NoteGenerator },
 }
 
 /// Drum sequencer actions.
@@ -508,6 +567,26 @@ pub enum SequencerAction {
     ToggleReverse(usize),              // pad_idx
     AdjustPadPitch(usize, i8),         // (pad_idx, delta semitones)
     AdjustStepPitch(usize, usize, i8), // (pad_idx, step_idx, delta)
+    SetStepCondition(usize, usize, TrigCondition), // (pad_idx, step_idx, condition)
+    CycleStepCondition(usize, usize), // (pad_idx, step_idx)
+    /// Hold or release fill mode (for Fill trig conditions)
+    SetFill(bool),
+    /// Override a parameter for one step's hit (value in the target's units)
+    SetParamLock { pad: usize, step: usize, target: AutomationTarget, value: f32 },
+    ClearParamLock { pad: usize, step: usize, target: AutomationTarget },
+    ClearParamLocks(usize, usize), // (pad_idx, step_idx)
+    AdjustStepNudge(usize, usize, f32),   // (pad_idx, step_idx, delta in steps)
+    AdjustRatchets(usize, usize, i8),     // (pad_idx, step_idx, delta retriggers)
+    AdjustRatchetRamp(usize, usize, f32), // (pad_idx, step_idx, delta)
+    AdjustStepGate(usize, usize, f32),    // (pad_idx, step_idx, delta in steps)
+    ToggleTie(usize, usize),              // (pad_idx, step_idx)
+    AdjustPadLength(usize, i8),           // (pad_idx, delta steps) — pad gets its own length
+    ResetPadLength(usize),                // pad_idx — follow the pattern length again
+    CyclePadResolution(usize),            // pad_idx — 1/16, 1/8T, 1/32
+    SetChokeGroup(usize, Option<u8>),     // (pad_idx, group) — None leaves any group
+    CycleChokeGroup(usize),               // pad_idx
+    TogglePadMode(usize),                 // pad_idx — one-shot / gate
+    AdjustPadVoices(usize, i8),           // (pad_idx, delta) — polyphony limit, 0 = unlimited
     /// Delete steps in region (used by Cut)
     DeleteStepsInRegion {
         start_pad: usize,
@@ -523,6 +602,8 @@ pub enum SequencerAction {
     },
     /// Copy steps within a region to the clipboard
     CopySteps { start_pad: usize, end_pad: usize, start_step: usize, end_step: usize },
+    /// Fill the pattern from mini-notation (words are pad names or indices)
+    ApplyNotation(String),
 }
 
 /// Data carried by InstrumentAction::Update to apply edits without dispatch reading pane state.
@@ -537,6 +618,8 @@ pub struct InstrumentUpdate {
     pub lfo: LfoConfig,
     pub amp_envelope: EnvConfig,
     pub polyphonic: bool,
+    pub voice: VoiceSettings,
+    pub arp: ArpConfig,
     pub active: bool,
 }
 
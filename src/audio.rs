@@ -32,7 +32,10 @@ pub enum AudioFeedback {
     BpmUpdate(f32),
     DrumSequencerStep {
         instrument_id: InstrumentId,
+        /// Pattern step, in sixteenths
         step: usize,
+        /// Step each pad's row is on (rows can differ in length and resolution)
+        pad_steps: Vec<usize>,
     },
     ServerStatus {
         status: ServerStatus,
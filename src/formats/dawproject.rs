@@ -0,0 +1,904 @@
+//! DAWproject (open XML-in-zip exchange format) export and import.
+//!
+//! Export writes `project.xml` and `metadata.xml`: transport, one track per
+//! instrument plus one per mixer bus, the master channel, note clips and
+//! automation points. Instruments live outside the session, so callers
+//! describe them with [`DawProjectTrack`]. Import reads back the subset we can
+//! represent; devices we can't (plugins, other hosts' built-ins) become
+//! [`DawDevice::Placeholder`]s with a warning, and audio clips are skipped
+//! with a warning.
+
+use std::collections::HashMap;
+
+use super::xml::{self, XmlNode, XmlWriter};
+use super::{zip, FormatError};
+use crate::state::arrangement::PlayMode;
+use crate::state::automation::{AutomationLane, AutomationPoint, AutomationTarget, CurveType};
+use crate::state::instrument::{EffectType, MixerBus, MixerSend, OutputTarget, SourceType};
+use crate::state::piano_roll::Note;
+use crate::state::session::SessionState;
+use crate::InstrumentId;
+
+const SOURCE_PREFIX: &str = "imbolc.source.";
+const EFFECT_PREFIX: &str = "imbolc.effect.";
+
+/// A device we can't represent, kept so the user can see what was there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DevicePlaceholder {
+    /// Element name, e.g. `Vst3Plugin` or `BuiltinDevice`
+    pub kind: String,
+    pub name: String,
+    /// `instrument`, `noteFX`, `audioFX` or `analyzer`
+    pub role: String,
+    pub vendor: Option<String>,
+    pub device_id: Option<String>,
+}
+
+/// One entry in a track's device chain.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DawDevice {
+    Source(SourceType),
+    Effect(EffectType),
+    Placeholder(DevicePlaceholder),
+}
+
+/// Mixer and device settings of one instrument, in both directions.
+#[derive(Debug, Clone)]
+pub struct DawProjectTrack {
+    pub instrument_id: InstrumentId,
+    pub name: String,
+    pub devices: Vec<DawDevice>,
+    /// Linear, 0.0-1.0
+    pub level: f32,
+    /// -1.0 (left) to 1.0 (right)
+    pub pan: f32,
+    pub mute: bool,
+    pub solo: bool,
+    pub output: OutputTarget,
+    pub sends: Vec<MixerSend>,
+}
+
+impl DawProjectTrack {
+    pub fn new(instrument_id: InstrumentId, name: impl Into<String>, source: SourceType) -> Self {
+        Self {
+            instrument_id,
+            name: name.into(),
+            devices: vec![DawDevice::Source(source)],
+            level: 0.8,
+            pan: 0.0,
+            mute: false,
+            solo: false,
+            output: OutputTarget::Master,
+            sends: Vec::new(),
+        }
+    }
+
+    /// The first source device, if any
+    pub fn source(&self) -> Option<SourceType> {
+        self.devices.iter().find_map(|d| match d {
+            DawDevice::Source(s) => Some(*s),
+            _ => None,
+        })
+    }
+
+    pub fn placeholders(&self) -> impl Iterator<Item = &DevicePlaceholder> {
+        self.devices.iter().filter_map(|d| match d {
+            DawDevice::Placeholder(p) => Some(p),
+            _ => None,
+        })
+    }
+}
+
+/// A written project archive.
+#[derive(Debug, Clone)]
+pub struct DawProjectExport {
+    pub data: Vec<u8>,
+    /// Things that were approximated or left out
+    pub warnings: Vec<String>,
+}
+
+/// A note clip on an instrument's timeline.
+#[derive(Debug, Clone)]
+pub struct DawProjectClip {
+    pub instrument_id: InstrumentId,
+    pub name: String,
+    pub start_tick: u32,
+    pub length_ticks: u32,
+    /// Ticks from the clip start
+    pub notes: Vec<Note>,
+}
+
+/// A parsed project, rescaled to the session's tick resolution.
+#[derive(Debug, Clone)]
+pub struct DawProjectImport {
+    pub ticks_per_beat: u32,
+    pub bpm: f32,
+    pub time_signature: (u8, u8),
+    /// Instruments to create, with ids counting up from the requested first id
+    pub tracks: Vec<DawProjectTrack>,
+    /// Effect channels, numbered from 1 in file order
+    pub buses: Vec<MixerBus>,
+    pub master_level: f32,
+    pub master_mute: bool,
+    pub clips: Vec<DawProjectClip>,
+    /// Values normalized to each target's default range
+    pub automation: Vec<(AutomationTarget, Vec<AutomationPoint>)>,
+    pub warnings: Vec<String>,
+}
+
+/// Decimal without trailing zeros
+fn num(value: f64) -> String {
+    let s = format!("{:.6}", value);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+fn flag(value: bool) -> String {
+    value.to_string()
+}
+
+// ---------------------------------------------------------------------------
+// Export
+
+struct Ids(u32);
+
+impl Ids {
+    fn next(&mut self) -> String {
+        self.0 += 1;
+        format!("id{}", self.0)
+    }
+}
+
+fn device_element(w: &mut XmlWriter, ids: &mut Ids, device: &DawDevice, track: &str, warnings: &mut Vec<String>) {
+    let (kind, role, name, vendor, device_id) = match device {
+        DawDevice::Source(source) => {
+            if matches!(source, SourceType::Custom(_) | SourceType::Vst(_)) {
+                warnings.push(format!(
+                    "track \"{}\": {} source can't be described portably and will import as a placeholder",
+                    track,
+                    source.name()
+                ));
+            }
+            let name = source.name().to_string();
+            ("BuiltinDevice", "instrument", name.clone(), Some("imbolc".to_string()), Some(format!("{}{}", SOURCE_PREFIX, name)))
+        }
+        DawDevice::Effect(effect) => {
+            if matches!(effect, EffectType::Vst(_)) {
+                warnings.push(format!(
+                    "track \"{}\": {} effect can't be described portably and will import as a placeholder",
+                    track,
+                    effect.name()
+                ));
+            }
+            let name = effect.name().to_string();
+            ("BuiltinDevice", "audioFX", name.clone(), Some("imbolc".to_string()), Some(format!("{}{}", EFFECT_PREFIX, name)))
+        }
+        DawDevice::Placeholder(p) => {
+            (p.kind.as_str(), p.role.as_str(), p.name.clone(), p.vendor.clone(), p.device_id.clone())
+        }
+    };
+    let mut attrs = vec![("id", ids.next()), ("name", name.clone()), ("deviceName", name), ("deviceRole", role.to_string())];
+    if let Some(vendor) = vendor {
+        attrs.push(("deviceVendor", vendor));
+    }
+    if let Some(device_id) = device_id {
+        attrs.push(("deviceID", device_id));
+    }
+    w.empty(kind, &attrs);
+}
+
+/// Volume, pan and mute elements of a channel; returns the (volume, pan) ids
+fn channel_params(w: &mut XmlWriter, ids: &mut Ids, level: f32, pan: f32, mute: bool) -> (String, String) {
+    let volume_id = ids.next();
+    let pan_id = ids.next();
+    w.empty("Mute", &[("id", ids.next()), ("name", "Mute".into()), ("value", flag(mute))]);
+    w.empty(
+        "Pan",
+        &[
+            ("id", pan_id.clone()),
+            ("name", "Pan".into()),
+            ("unit", "normalized".into()),
+            ("min", "0".into()),
+            ("max", "1".into()),
+            ("value", num(((pan as f64 + 1.0) / 2.0).clamp(0.0, 1.0))),
+        ],
+    );
+    w.empty(
+        "Volume",
+        &[
+            ("id", volume_id.clone()),
+            ("name", "Volume".into()),
+            ("unit", "linear".into()),
+            ("min", "0".into()),
+            ("max", "1".into()),
+            ("value", num(level as f64)),
+        ],
+    );
+    (volume_id, pan_id)
+}
+
+fn write_notes(w: &mut XmlWriter, notes: &[Note], tpb: f64) {
+    w.open("Notes", &[]);
+    for note in notes {
+        w.empty(
+            "Note",
+            &[
+                ("time", num(note.tick as f64 / tpb)),
+                ("duration", num(note.duration as f64 / tpb)),
+                ("channel", "0".into()),
+                ("key", note.pitch.min(127).to_string()),
+                ("vel", num(note.velocity as f64 / 127.0)),
+                ("rel", "0.5".into()),
+            ],
+        );
+    }
+    w.close();
+}
+
+/// Export the session as a DAWproject archive. `tracks` describes the
+/// instruments to include; notes come from the arrangement when it has
+/// placements, otherwise from the piano roll.
+pub fn export_dawproject(session: &SessionState, tracks: &[DawProjectTrack]) -> DawProjectExport {
+    let mut warnings = Vec::new();
+    let mut ids = Ids(0);
+    let tpb = session.piano_roll.ticks_per_beat.max(1) as f64;
+    let mut params: HashMap<AutomationTarget, String> = HashMap::new();
+
+    let mut w = XmlWriter::new();
+    w.open("Project", &[("version", "1.0".into())]);
+    w.empty("Application", &[("name", "imbolc".into()), ("version", env!("CARGO_PKG_VERSION").into())]);
+
+    w.open("Transport", &[]);
+    let tempo_id = ids.next();
+    params.insert(AutomationTarget::Bpm, tempo_id.clone());
+    w.empty(
+        "Tempo",
+        &[
+            ("id", tempo_id),
+            ("name", "Tempo".into()),
+            ("unit", "bpm".into()),
+            ("min", "30".into()),
+            ("max", "300".into()),
+            ("value", session.bpm.to_string()),
+        ],
+    );
+    let (num_beats, den) = session.time_signature;
+    w.empty(
+        "TimeSignature",
+        &[("id", ids.next()), ("numerator", num_beats.to_string()), ("denominator", den.to_string())],
+    );
+    w.close();
+
+    w.open("Structure", &[]);
+    let master_id = ids.next();
+    let bus_channels: HashMap<u8, String> = session.mixer.buses.iter().map(|b| (b.id, ids.next())).collect();
+    let mut track_ids: HashMap<InstrumentId, String> = HashMap::new();
+    for track in tracks {
+        let track_id = ids.next();
+        track_ids.insert(track.instrument_id, track_id.clone());
+        let destination = match track.output {
+            OutputTarget::Bus(bus) => bus_channels.get(&bus).cloned().unwrap_or_else(|| {
+                warnings.push(format!("track \"{}\": output bus {} doesn't exist; routed to master", track.name, bus));
+                master_id.clone()
+            }),
+            OutputTarget::Master => master_id.clone(),
+        };
+        w.open(
+            "Track",
+            &[
+                ("id", track_id),
+                ("name", track.name.clone()),
+                ("contentType", "notes".into()),
+                ("loaded", "true".into()),
+            ],
+        );
+        w.open(
+            "Channel",
+            &[
+                ("id", ids.next()),
+                ("role", "regular".into()),
+                ("audioChannels", "2".into()),
+                ("destination", destination),
+                ("solo", flag(track.solo)),
+            ],
+        );
+        w.open("Devices", &[]);
+        for device in &track.devices {
+            device_element(&mut w, &mut ids, device, &track.name, &mut warnings);
+        }
+        w.close();
+        let (volume, pan) = channel_params(&mut w, &mut ids, track.level, track.pan, track.mute);
+        params.insert(AutomationTarget::InstrumentLevel(track.instrument_id), volume);
+        params.insert(AutomationTarget::InstrumentPan(track.instrument_id), pan);
+        if !track.sends.is_empty() {
+            w.open("Sends", &[]);
+            for (index, send) in track.sends.iter().enumerate() {
+                let Some(destination) = bus_channels.get(&send.bus_id) else {
+                    warnings.push(format!("track \"{}\": send to missing bus {} was left out", track.name, send.bus_id));
+                    continue;
+                };
+                w.open("Send", &[("id", ids.next()), ("destination", destination.clone()), ("type", "post".into())]);
+                let volume = ids.next();
+                w.empty(
+                    "Volume",
+                    &[
+                        ("id", volume.clone()),
+                        ("name", "Send".into()),
+                        ("unit", "linear".into()),
+                        ("min", "0".into()),
+                        ("max", "1".into()),
+                        ("value", num(send.level as f64)),
+                    ],
+                );
+                w.empty("Enable", &[("id", ids.next()), ("value", flag(send.enabled))]);
+                w.close();
+                params.insert(AutomationTarget::SendLevel(track.instrument_id, index), volume);
+            }
+            w.close();
+        }
+        w.close();
+        w.close();
+    }
+    for bus in &session.mixer.buses {
+        w.open(
+            "Track",
+            &[
+                ("id", ids.next()),
+                ("name", bus.name.clone()),
+                ("contentType", "audio".into()),
+                ("loaded", "true".into()),
+            ],
+        );
+        w.open(
+            "Channel",
+            &[
+                ("id", bus_channels[&bus.id].clone()),
+                ("role", "effect".into()),
+                ("audioChannels", "2".into()),
+                ("destination", master_id.clone()),
+                ("solo", flag(bus.solo)),
+            ],
+        );
+        let (volume, _) = channel_params(&mut w, &mut ids, bus.level, bus.pan, bus.mute);
+        params.insert(AutomationTarget::BusLevel(bus.id), volume);
+        w.close();
+        w.close();
+    }
+    w.open("Channel", &[("id", master_id), ("name", "Master".into()), ("role", "master".into()), ("audioChannels", "2".into())]);
+    channel_params(&mut w, &mut ids, session.mixer.master_level, 0.0, session.mixer.master_mute);
+    w.close();
+    w.close();
+
+    w.open("Arrangement", &[("id", ids.next())]);
+    w.open("Lanes", &[("id", ids.next()), ("timeUnit", "beats".into())]);
+    let arrangement = &session.arrangement;
+    let song = !arrangement.placements().is_empty();
+    for track in tracks {
+        let mut clips: Vec<(u32, u32, String, Vec<Note>)> = Vec::new();
+        if song {
+            let mut placements = arrangement.placements_for_instrument(track.instrument_id);
+            placements.sort_by_key(|p| p.start_tick);
+            for placement in placements {
+                let Some(clip) = arrangement.clip(placement.clip_id) else {
+                    continue;
+                };
+                let length = placement.effective_length(clip);
+                let notes = clip.notes.iter().filter(|n| n.tick < length).cloned().collect();
+                clips.push((placement.start_tick, length, clip.name.clone(), notes));
+            }
+        } else if let Some(roll) = session.piano_roll.tracks.get(&track.instrument_id) {
+            let end = roll.notes.iter().map(Note::end_tick).max().unwrap_or(0);
+            if end > 0 {
+                let bar = session.piano_roll.ticks_per_bar().max(1);
+                clips.push((0, end.div_ceil(bar) * bar, track.name.clone(), roll.notes.to_vec()));
+            }
+        }
+        if clips.is_empty() {
+            continue;
+        }
+        w.open("Lanes", &[("id", ids.next()), ("track", track_ids[&track.instrument_id].clone())]);
+        w.open("Clips", &[("id", ids.next())]);
+        for (start, length, name, notes) in clips {
+            w.open(
+                "Clip",
+                &[
+                    ("time", num(start as f64 / tpb)),
+                    ("duration", num(length as f64 / tpb)),
+                    ("playStart", "0".into()),
+                    ("name", name),
+                ],
+            );
+            write_notes(&mut w, &notes, tpb);
+            w.close();
+        }
+        w.close();
+        w.close();
+    }
+
+    let mut lanes: Vec<AutomationLane> = session.automation.lanes.clone();
+    if song {
+        lanes.extend(arrangement.flatten_automation());
+    }
+    for lane in lanes.iter().filter(|l| !l.points.is_empty()) {
+        let Some(parameter) = params.get(&lane.target) else {
+            warnings.push(format!("automation for {} has no DAWproject equivalent and was left out", lane.target.name()));
+            continue;
+        };
+        let pan = matches!(lane.target, AutomationTarget::InstrumentPan(_));
+        let unit = match lane.target {
+            AutomationTarget::Bpm => "bpm",
+            _ if pan => "normalized",
+            _ => "linear",
+        };
+        if lane.points.iter().any(|p| matches!(p.curve, CurveType::Exponential | CurveType::SCurve)) {
+            warnings.push(format!("curved automation for {} was approximated as linear", lane.target.name()));
+        }
+        w.open("Points", &[("id", ids.next()), ("unit", unit.into())]);
+        w.empty("Target", &[("parameter", parameter.clone())]);
+        for point in &lane.points {
+            let actual = lane.min_value + point.value * (lane.max_value - lane.min_value);
+            let value = if pan { (actual + 1.0) / 2.0 } else { actual };
+            let interpolation = if point.curve == CurveType::Step { "hold" } else { "linear" };
+            w.empty(
+                "RealPoint",
+                &[
+                    ("time", num(point.tick as f64 / tpb)),
+                    ("value", num(value as f64)),
+                    ("interpolation", interpolation.into()),
+                ],
+            );
+        }
+        w.close();
+    }
+    let project = w.finish();
+
+    let mut meta = XmlWriter::new();
+    meta.open("MetaData", &[]);
+    meta.text("Comment", &[], "Exported from imbolc");
+    let metadata = meta.finish();
+
+    let data = zip::write_stored(&[("project.xml", project.as_bytes()), ("metadata.xml", metadata.as_bytes())]);
+    DawProjectExport { data, warnings }
+}
+
+// ---------------------------------------------------------------------------
+// Import
+
+fn value_of(node: &XmlNode, name: &str) -> Option<f64> {
+    node.child(name)?.attr_f64("value")
+}
+
+fn parse_device(node: &XmlNode, track: &str, warnings: &mut Vec<String>) -> DawDevice {
+    let name = node.attr("deviceName").or_else(|| node.attr("name")).unwrap_or(&node.name).to_string();
+    let device_id = node.attr("deviceID").unwrap_or_default();
+    if node.name == "BuiltinDevice" {
+        if let Some(source_name) = device_id.strip_prefix(SOURCE_PREFIX) {
+            if let Some(source) = SourceType::all().into_iter().find(|s| s.name() == source_name) {
+                return DawDevice::Source(source);
+            }
+        }
+        if let Some(effect_name) = device_id.strip_prefix(EFFECT_PREFIX) {
+            if let Some(effect) = EffectType::all().into_iter().find(|e| e.name() == effect_name) {
+                return DawDevice::Effect(effect);
+            }
+        }
+    }
+    warnings.push(format!("track \"{}\": device \"{}\" ({}) is not supported; kept as a placeholder", track, name, node.name));
+    DawDevice::Placeholder(DevicePlaceholder {
+        kind: node.name.clone(),
+        name,
+        role: node.attr("deviceRole").unwrap_or("audioFX").to_string(),
+        vendor: node.attr("deviceVendor").map(str::to_string),
+        device_id: node.attr("deviceID").map(str::to_string),
+    })
+}
+
+/// Tracks in document order, with group tracks flattened
+fn collect_tracks<'a>(node: &'a XmlNode, out: &mut Vec<&'a XmlNode>, warnings: &mut Vec<String>) {
+    for track in node.children_named("Track") {
+        if track.child("Track").is_some() {
+            warnings.push(format!("group track \"{}\" was flattened", track.attr("name").unwrap_or_default()));
+            collect_tracks(track, out, warnings);
+        } else {
+            out.push(track);
+        }
+    }
+}
+
+struct Timeline<'a> {
+    ticks_per_beat: f64,
+    /// Beats per time unit (1 for beats, bpm/60 for seconds)
+    scale: f64,
+    tracks: &'a HashMap<String, InstrumentId>,
+    names: &'a HashMap<InstrumentId, String>,
+}
+
+impl Timeline<'_> {
+    fn ticks(&self, time: f64) -> u32 {
+        (time * self.scale * self.ticks_per_beat).round().max(0.0) as u32
+    }
+
+    fn clip(&self, node: &XmlNode, instrument_id: InstrumentId, import: &mut DawProjectImport) {
+        let name = node
+            .attr("name")
+            .map(str::to_string)
+            .unwrap_or_else(|| self.names.get(&instrument_id).cloned().unwrap_or_default());
+        let mut note_lists = Vec::new();
+        node.find_all("Notes", &mut note_lists);
+        if note_lists.is_empty() {
+            import.warnings.push(format!("clip \"{}\" has no notes (audio clips aren't supported) and was skipped", name));
+            return;
+        }
+        let start = node.attr_f64("time").unwrap_or(0.0);
+        let play_start = node.attr_f64("playStart").unwrap_or(0.0);
+        let offset = self.ticks(play_start);
+        let length = self.ticks(node.attr_f64("duration").unwrap_or(0.0));
+        let mut notes = Vec::new();
+        for list in note_lists {
+            for n in list.children_named("Note") {
+                let tick = self.ticks(n.attr_f64("time").unwrap_or(0.0));
+                if tick < offset {
+                    continue;
+                }
+                let duration = self.ticks(n.attr_f64("duration").unwrap_or(0.0)).max(1);
+                let pitch = n.attr_f64("key").unwrap_or(60.0).clamp(0.0, 127.0) as u8;
+                let velocity = (n.attr_f64("vel").unwrap_or(0.8) * 127.0).round().clamp(1.0, 127.0) as u8;
+                notes.push(Note::new(tick - offset, duration, pitch, velocity));
+            }
+        }
+        let end = notes.iter().map(Note::end_tick).max().unwrap_or(0);
+        import.clips.push(DawProjectClip {
+            instrument_id,
+            name,
+            start_tick: self.ticks(start),
+            length_ticks: if length > 0 { length } else { end },
+            notes,
+        });
+    }
+
+    /// Walk a lanes timeline; `track` is inherited from enclosing lanes
+    fn lanes(
+        &self,
+        node: &XmlNode,
+        track: Option<InstrumentId>,
+        params: &HashMap<String, AutomationTarget>,
+        import: &mut DawProjectImport,
+    ) {
+        let track = node.attr("track").and_then(|t| self.tracks.get(t).copied()).or(track);
+        for child in &node.children {
+            match child.name.as_str() {
+                "Lanes" => self.lanes(child, track, params, import),
+                "Clips" => {
+                    let Some(instrument_id) = track else {
+                        import.warnings.push("clips outside an instrument track were skipped".into());
+                        continue;
+                    };
+                    for clip in child.children_named("Clip") {
+                        self.clip(clip, instrument_id, import);
+                    }
+                }
+                "Points" => {
+                    let parameter = child.child("Target").and_then(|t| t.attr("parameter")).unwrap_or_default();
+                    let Some(target) = params.get(parameter) else {
+                        import.warnings.push(format!("automation for parameter \"{}\" isn't supported and was skipped", parameter));
+                        continue;
+                    };
+                    let pan = matches!(target, AutomationTarget::InstrumentPan(_));
+                    let mut points = Vec::new();
+                    for p in child.children_named("RealPoint") {
+                        let value = p.attr_f64("value").unwrap_or(0.0) as f32;
+                        let normalized = if pan { value.clamp(0.0, 1.0) } else { target.normalize_value(value) };
+                        let curve = if p.attr("interpolation") == Some("hold") { CurveType::Step } else { CurveType::Linear };
+                        points.push(AutomationPoint::with_curve(self.ticks(p.attr_f64("time").unwrap_or(0.0)), normalized, curve));
+                    }
+                    points.sort_by_key(|p| p.tick);
+                    import.automation.push((target.clone(), points));
+                }
+                "Notes" | "Audio" | "Video" | "Markers" | "Warps" => {
+                    import.warnings.push(format!("{} outside a clip were skipped", child.name));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Read a DAWproject archive. Instrument ids count up from
+/// `first_instrument_id` in track order.
+pub fn import_dawproject(
+    data: &[u8],
+    ticks_per_beat: u32,
+    first_instrument_id: InstrumentId,
+) -> Result<DawProjectImport, FormatError> {
+    let entries = zip::read(data)?;
+    let Some((_, project)) = entries.iter().find(|(name, _)| name == "project.xml") else {
+        return Err(FormatError::InvalidHeader("archive has no project.xml".into()));
+    };
+    let root = xml::parse(&String::from_utf8_lossy(project))?;
+    if root.name != "Project" {
+        return Err(FormatError::InvalidHeader(format!("root element is <{}>, not <Project>", root.name)));
+    }
+
+    let mut import = DawProjectImport {
+        ticks_per_beat,
+        bpm: 120.0,
+        time_signature: (4, 4),
+        tracks: Vec::new(),
+        buses: Vec::new(),
+        master_level: 1.0,
+        master_mute: false,
+        clips: Vec::new(),
+        automation: Vec::new(),
+        warnings: Vec::new(),
+    };
+    let mut params: HashMap<String, AutomationTarget> = HashMap::new();
+
+    if let Some(transport) = root.child("Transport") {
+        if let Some(tempo) = transport.child("Tempo") {
+            import.bpm = tempo.attr_f64("value").unwrap_or(120.0) as f32;
+            if let Some(id) = tempo.attr("id") {
+                params.insert(id.to_string(), AutomationTarget::Bpm);
+            }
+        }
+        if let Some(sig) = transport.child("TimeSignature") {
+            let numerator = sig.attr_f64("numerator").unwrap_or(4.0).clamp(1.0, 255.0) as u8;
+            let denominator = sig.attr_f64("denominator").unwrap_or(4.0).clamp(1.0, 255.0) as u8;
+            import.time_signature = (numerator, denominator);
+        }
+    }
+
+    let mut track_ids: HashMap<String, InstrumentId> = HashMap::new();
+    let mut names: HashMap<InstrumentId, String> = HashMap::new();
+    if let Some(structure) = root.child("Structure") {
+        let mut tracks = Vec::new();
+        collect_tracks(structure, &mut tracks, &mut import.warnings);
+
+        // Effect channels become buses; their ids are needed for routing first
+        let mut bus_channels: HashMap<String, u8> = HashMap::new();
+        for track in &tracks {
+            let Some(channel) = track.child("Channel").filter(|c| c.attr("role") == Some("effect")) else {
+                continue;
+            };
+            let id = import.buses.len() as u8 + 1;
+            let mut bus = MixerBus::new(id);
+            if let Some(name) = track.attr("name") {
+                bus.name = name.to_string();
+            }
+            bus.level = value_of(channel, "Volume").unwrap_or(1.0) as f32;
+            bus.pan = value_of(channel, "Pan").map_or(0.0, |p| p as f32 * 2.0 - 1.0);
+            bus.mute = channel.child("Mute").and_then(|m| m.attr("value")) == Some("true");
+            bus.solo = channel.attr("solo") == Some("true");
+            if let Some(volume) = channel.child("Volume").and_then(|v| v.attr("id")) {
+                params.insert(volume.to_string(), AutomationTarget::BusLevel(id));
+            }
+            if let Some(channel_id) = channel.attr("id") {
+                bus_channels.insert(channel_id.to_string(), id);
+            }
+            import.buses.push(bus);
+        }
+
+        let mut masters = Vec::new();
+        structure.find_all("Channel", &mut masters);
+        if let Some(master) = masters.into_iter().find(|c| c.attr("role") == Some("master")) {
+            import.master_level = value_of(master, "Volume").unwrap_or(1.0) as f32;
+            import.master_mute = master.child("Mute").and_then(|m| m.attr("value")) == Some("true");
+        }
+
+        for track in tracks {
+            let channel = track.child("Channel");
+            let role = channel.and_then(|c| c.attr("role")).unwrap_or("regular");
+            if role == "effect" || role == "master" {
+                continue;
+            }
+            let name = track.attr("name").unwrap_or("Track").to_string();
+            let content = track.attr("contentType").unwrap_or_default();
+            if !content.split_whitespace().any(|c| c == "notes") {
+                import.warnings.push(format!("track \"{}\" ({}) isn't a note track and was skipped", name, content));
+                continue;
+            }
+            let instrument_id = first_instrument_id + import.tracks.len() as InstrumentId;
+            let mut t = DawProjectTrack::new(instrument_id, name.clone(), SourceType::Saw);
+            t.devices.clear();
+            if let Some(id) = track.attr("id") {
+                track_ids.insert(id.to_string(), instrument_id);
+            }
+            names.insert(instrument_id, name.clone());
+            if let Some(channel) = channel {
+                if let Some(devices) = channel.child("Devices") {
+                    for device in &devices.children {
+                        t.devices.push(parse_device(device, &name, &mut import.warnings));
+                    }
+                }
+                t.level = value_of(channel, "Volume").unwrap_or(0.8) as f32;
+                t.pan = value_of(channel, "Pan").map_or(0.0, |p| p as f32 * 2.0 - 1.0);
+                t.mute = channel.child("Mute").and_then(|m| m.attr("value")) == Some("true");
+                t.solo = channel.attr("solo") == Some("true");
+                if let Some(bus) = channel.attr("destination").and_then(|d| bus_channels.get(d)) {
+                    t.output = OutputTarget::Bus(*bus);
+                }
+                if let Some(volume) = channel.child("Volume").and_then(|v| v.attr("id")) {
+                    params.insert(volume.to_string(), AutomationTarget::InstrumentLevel(instrument_id));
+                }
+                if let Some(pan) = channel.child("Pan").and_then(|v| v.attr("id")) {
+                    params.insert(pan.to_string(), AutomationTarget::InstrumentPan(instrument_id));
+                }
+                for send in channel.child("Sends").into_iter().flat_map(|s| s.children_named("Send")) {
+                    let Some(bus_id) = send.attr("destination").and_then(|d| bus_channels.get(d)) else {
+                        import.warnings.push(format!("track \"{}\": send to an unknown channel was skipped", name));
+                        continue;
+                    };
+                    if let Some(volume) = send.child("Volume").and_then(|v| v.attr("id")) {
+                        params.insert(volume.to_string(), AutomationTarget::SendLevel(instrument_id, t.sends.len()));
+                    }
+                    let mut s = MixerSend::new(*bus_id);
+                    s.level = value_of(send, "Volume").unwrap_or(0.0) as f32;
+                    s.enabled = send.child("Enable").and_then(|e| e.attr("value")) != Some("false");
+                    t.sends.push(s);
+                }
+            }
+            if t.source().is_none() {
+                import.warnings.push(format!("track \"{}\" has no supported instrument device", name));
+            }
+            import.tracks.push(t);
+        }
+    }
+
+    if let Some(lanes) = root.child("Arrangement").and_then(|a| a.child("Lanes")) {
+        let scale = if lanes.attr("timeUnit") == Some("seconds") { import.bpm as f64 / 60.0 } else { 1.0 };
+        let timeline = Timeline { ticks_per_beat: ticks_per_beat as f64, scale, tracks: &track_ids, names: &names };
+        timeline.lanes(lanes, None, &params, &mut import);
+    }
+    if root.child("Scenes").is_some_and(|s| !s.children.is_empty()) {
+        import.warnings.push("scenes (clip launcher) were skipped".into());
+    }
+    Ok(import)
+}
+
+impl DawProjectImport {
+    /// Write tempo, meter, mixer buses, clips and automation into a session.
+    /// Instruments for `tracks` are expected to exist (or be created by the
+    /// caller).
+    pub fn apply(&self, session: &mut SessionState) {
+        session.set_bpm(self.bpm.round().clamp(1.0, u16::MAX as f32) as u16);
+        session.set_time_signature(self.time_signature);
+        if !self.buses.is_empty() {
+            session.mixer.buses = self.buses.clone();
+            session.mixer.next_bus_id = self.buses.iter().map(|b| b.id).max().unwrap_or(0).saturating_add(1);
+        }
+        session.mixer.master_level = self.master_level;
+        session.mixer.master_mute = self.master_mute;
+
+        let arrangement = &mut session.arrangement;
+        for clip in &self.clips {
+            let clip_id = arrangement.add_clip(clip.name.clone(), clip.instrument_id, clip.length_ticks);
+            for note in &clip.notes {
+                arrangement.add_clip_note(clip_id, note.clone());
+            }
+            arrangement.add_placement(clip_id, clip.instrument_id, clip.start_tick);
+        }
+        if !self.clips.is_empty() {
+            arrangement.play_mode = PlayMode::Song;
+        }
+        for (target, points) in &self.automation {
+            let id = session.automation.add_lane(target.clone());
+            if let Some(lane) = session.automation.lane_mut(id) {
+                lane.points = points.clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> SessionState {
+        let mut session = SessionState::new();
+        session.set_bpm(128);
+        let clip = session.arrangement.add_clip("Riff".into(), 1, 1920);
+        session.arrangement.add_clip_note(clip, Note::new(0, 240, 60, 100));
+        session.arrangement.add_clip_note(clip, Note::new(480, 240, 64, 80));
+        session.arrangement.add_placement(clip, 1, 0);
+        session.arrangement.add_placement(clip, 1, 3840);
+        let lane = session.automation.add_lane(AutomationTarget::InstrumentPan(1));
+        if let Some(lane) = session.automation.lane_mut(lane) {
+            lane.points = vec![AutomationPoint::new(0, 0.25), AutomationPoint::with_curve(960, 1.0, CurveType::Step)];
+        }
+        session.automation.add_lane(AutomationTarget::LfoRate(1));
+        if let Some(lane) = session.automation.lane_for_target_mut(&AutomationTarget::LfoRate(1)) {
+            lane.points.push(AutomationPoint::new(0, 0.5));
+        }
+        session
+    }
+
+    #[test]
+    fn round_trips_session() {
+        let session = session();
+        let mut track = DawProjectTrack::new(1, "Lead", SourceType::Saw);
+        track.devices.push(DawDevice::Effect(EffectType::Reverb));
+        track.level = 0.5;
+        track.pan = -0.5;
+        track.output = OutputTarget::Bus(2);
+        let mut send = MixerSend::new(1);
+        send.level = 0.25;
+        send.enabled = true;
+        track.sends.push(send);
+
+        let export = export_dawproject(&session, &[track]);
+        assert!(export.warnings.iter().any(|w| w.contains("LFO")));
+
+        let import = import_dawproject(&export.data, 480, 1).unwrap();
+        assert!(import.warnings.is_empty(), "{:?}", import.warnings);
+        assert_eq!(import.bpm, 128.0);
+        assert_eq!(import.buses.len(), session.mixer.buses.len());
+
+        let t = &import.tracks[0];
+        assert_eq!((t.name.as_str(), t.level, t.pan, t.output), ("Lead", 0.5, -0.5, OutputTarget::Bus(2)));
+        assert_eq!(t.devices, vec![DawDevice::Source(SourceType::Saw), DawDevice::Effect(EffectType::Reverb)]);
+        assert_eq!((t.sends[0].bus_id, t.sends[0].level, t.sends[0].enabled), (1, 0.25, true));
+
+        let clips: Vec<(u32, u32, usize)> = import.clips.iter().map(|c| (c.start_tick, c.length_ticks, c.notes.len())).collect();
+        assert_eq!(clips, vec![(0, 1920, 2), (3840, 1920, 2)]);
+        let notes: Vec<(u32, u32, u8, u8)> =
+            import.clips[0].notes.iter().map(|n| (n.tick, n.duration, n.pitch, n.velocity)).collect();
+        assert_eq!(notes, vec![(0, 240, 60, 100), (480, 240, 64, 80)]);
+
+        let (target, points) = &import.automation[0];
+        assert_eq!(*target, AutomationTarget::InstrumentPan(1));
+        let points: Vec<(u32, f32, CurveType)> = points.iter().map(|p| (p.tick, p.value, p.curve)).collect();
+        assert_eq!(points, vec![(0, 0.25, CurveType::Linear), (960, 1.0, CurveType::Step)]);
+
+        let mut target = SessionState::new();
+        import.apply(&mut target);
+        assert_eq!(target.bpm, 128);
+        assert_eq!(target.arrangement.placements().len(), 2);
+        assert_eq!(target.arrangement.play_mode, PlayMode::Song);
+    }
+
+    #[test]
+    fn unknown_devices_become_placeholders() {
+        let project = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Project version="1.0">
+  <Transport><Tempo id="t" unit="bpm" value="90"/><TimeSignature numerator="3" denominator="4"/></Transport>
+  <Structure>
+    <Track id="a" name="Keys" contentType="notes">
+      <Channel id="c" role="regular">
+        <Devices>
+          <Vst3Plugin deviceName="Diva" deviceRole="instrument" deviceVendor="u-he"/>
+          <BuiltinDevice deviceName="EQ+" deviceRole="audioFX" deviceVendor="Bitwig"/>
+        </Devices>
+        <Volume id="v" value="0.7"/>
+      </Channel>
+    </Track>
+    <Track id="b" name="Vox" contentType="audio"><Channel role="regular"/></Track>
+  </Structure>
+  <Arrangement>
+    <Lanes timeUnit="seconds">
+      <Lanes track="a">
+        <Clips>
+          <Clip time="2" duration="2" playStart="0"><Notes><Note time="0.5" duration="0.5" key="48" vel="1"/></Notes></Clip>
+          <Clip time="4" duration="1"><Audio path="vox.wav"/></Clip>
+        </Clips>
+      </Lanes>
+    </Lanes>
+  </Arrangement>
+</Project>"#;
+        let data = zip::write_stored(&[("project.xml", project.as_bytes())]);
+        let import = import_dawproject(&data, 480, 5).unwrap();
+        assert_eq!(import.time_signature, (3, 4));
+
+        let keys = &import.tracks[0];
+        assert_eq!((keys.instrument_id, keys.level), (5, 0.7));
+        let names: Vec<&str> = keys.placeholders().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["Diva", "EQ+"]);
+        assert_eq!(keys.source(), None);
+        for expected in ["Diva", "EQ+", "no supported instrument", "Vox", "audio clips"] {
+            assert!(import.warnings.iter().any(|w| w.contains(expected)), "missing {:?} in {:?}", expected, import.warnings);
+        }
+
+        // 90 bpm: 1.5 beats per second
+        let clip = &import.clips[0];
+        assert_eq!((clip.start_tick, clip.length_ticks), (1440, 1440));
+        assert_eq!((clip.notes[0].tick, clip.notes[0].duration, clip.notes[0].velocity), (360, 360, 127));
+    }
+}
@@ -0,0 +1,135 @@
+//! File format import and export.
+//!
+//! Pure byte-level parsers and writers: nothing here touches audio or the
+//! filesystem, callers pass bytes in and get state (or bytes) back.
+
+pub mod dawproject;
+pub mod musicxml;
+pub mod smf;
+pub mod tracker;
+mod xml;
+mod zip;
+
+use std::fmt;
+
+/// Error from parsing a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// Data ended before a structure was complete
+    Truncated { offset: usize },
+    /// Magic bytes or header fields don't match the format
+    InvalidHeader(String),
+    /// Valid file using a feature we can't read
+    Unsupported(String),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Truncated { offset } => write!(f, "unexpected end of data at byte {}", offset),
+            FormatError::InvalidHeader(msg) => write!(f, "invalid header: {}", msg),
+            FormatError::Unsupported(msg) => write!(f, "unsupported: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// Bounds-checked cursor over a byte slice.
+#[derive(Debug, Clone)]
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Reader positioned at `offset`
+    pub(crate) fn at(data: &'a [u8], offset: usize) -> Result<Self, FormatError> {
+        if offset > data.len() {
+            return Err(FormatError::Truncated { offset });
+        }
+        Ok(Self { data, pos: offset })
+    }
+
+    pub(crate) fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub(crate) fn seek(&mut self, offset: usize) -> Result<(), FormatError> {
+        if offset > self.data.len() {
+            return Err(FormatError::Truncated { offset });
+        }
+        self.pos = offset;
+        Ok(())
+    }
+
+    pub(crate) fn skip(&mut self, n: usize) -> Result<(), FormatError> {
+        self.bytes(n).map(|_| ())
+    }
+
+    pub(crate) fn bytes(&mut self, n: usize) -> Result<&'a [u8], FormatError> {
+        let end = self.pos.checked_add(n).filter(|&e| e <= self.data.len());
+        let Some(end) = end else {
+            return Err(FormatError::Truncated { offset: self.data.len() });
+        };
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn i8(&mut self) -> Result<i8, FormatError> {
+        Ok(self.u8()? as i8)
+    }
+
+    pub(crate) fn u16_le(&mut self) -> Result<u16, FormatError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub(crate) fn u16_be(&mut self) -> Result<u16, FormatError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub(crate) fn u32_le(&mut self) -> Result<u32, FormatError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub(crate) fn u32_be(&mut self) -> Result<u32, FormatError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// MIDI variable-length quantity (at most four bytes)
+    pub(crate) fn vlq(&mut self) -> Result<u32, FormatError> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let b = self.u8()?;
+            value = (value << 7) | (b & 0x7F) as u32;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(FormatError::InvalidHeader(format!("variable-length value too long at byte {}", self.pos)))
+    }
+
+    /// Fixed-size text field, cut at the first NUL and trimmed
+    pub(crate) fn text(&mut self, n: usize) -> Result<String, FormatError> {
+        let b = self.bytes(n)?;
+        let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+        Ok(String::from_utf8_lossy(&b[..end]).trim_end().to_string())
+    }
+}
@@ -0,0 +1,427 @@
+//! MusicXML (partwise, 4.0) export of a clip or piano roll track.
+//!
+//! Notes are quantized to a grid, split at barlines with ties, and written as
+//! notatable durations (plain or single-dotted, tied where needed).
+//! Overlapping notes go to separate voices; notes sharing a start and end are
+//! written as chords. Pitches are spelled with sharps or flats to match the
+//! key signature.
+
+use std::collections::BTreeMap;
+
+use super::smf::key_signature;
+use super::xml::XmlWriter;
+use crate::state::arrangement::ClipId;
+use crate::state::music::{Key, Scale};
+use crate::state::piano_roll::Note;
+use crate::state::session::SessionState;
+use crate::InstrumentId;
+
+/// A single-part score to write.
+#[derive(Debug, Clone)]
+pub struct MusicXmlExport {
+    pub title: String,
+    pub ticks_per_beat: u32,
+    pub bpm: f32,
+    pub time_signature: (u8, u8),
+    pub key: Key,
+    pub scale: Scale,
+    /// Quantize grid in divisions of a quarter note (4 = sixteenths); rounded
+    /// to a power of two up to 16
+    pub divisions: u32,
+    /// Length to fill with bars; 0 uses the end of the last note
+    pub length_ticks: u32,
+    pub notes: Vec<Note>,
+}
+
+/// A chord (or single note) in one voice, in grid steps
+#[derive(Debug, Clone)]
+struct Chord {
+    start: u32,
+    end: u32,
+    pitches: Vec<u8>,
+}
+
+/// Note type of one beat of the denominator
+fn beat_unit(den: u32) -> &'static str {
+    match den {
+        1 => "whole",
+        2 => "half",
+        8 => "eighth",
+        16 => "16th",
+        32 => "32nd",
+        _ => "quarter",
+    }
+}
+
+/// Notatable durations in grid steps, longest first, with dot flags
+fn note_values(divisions: u32) -> Vec<(u32, &'static str, bool)> {
+    let types = [(4, 1, "whole"), (2, 1, "half"), (1, 1, "quarter"), (1, 2, "eighth"), (1, 4, "16th"), (1, 8, "32nd"), (1, 16, "64th")];
+    let mut values = Vec::new();
+    for (mul, div, name) in types {
+        let steps = divisions * mul;
+        if steps % div != 0 {
+            continue;
+        }
+        let steps = steps / div;
+        if steps % 2 == 0 {
+            values.push((steps * 3 / 2, name, true));
+        }
+        values.push((steps, name, false));
+    }
+    values
+}
+
+/// Split a duration into notatable pieces, largest first
+fn split_duration(mut steps: u32, values: &[(u32, &'static str, bool)]) -> Vec<(u32, &'static str, bool)> {
+    let mut pieces = Vec::new();
+    while steps > 0 {
+        let Some(&value) = values.iter().find(|v| v.0 <= steps) else {
+            break;
+        };
+        pieces.push(value);
+        steps -= value.0;
+    }
+    pieces
+}
+
+/// (step, alter, octave) for a MIDI pitch
+fn spell(pitch: u8, flats: bool) -> (&'static str, i8, i32) {
+    const SHARPS: [(&str, i8); 12] = [
+        ("C", 0), ("C", 1), ("D", 0), ("D", 1), ("E", 0), ("F", 0), ("F", 1), ("G", 0), ("G", 1), ("A", 0), ("A", 1), ("B", 0),
+    ];
+    const FLATS: [(&str, i8); 12] = [
+        ("C", 0), ("D", -1), ("D", 0), ("E", -1), ("E", 0), ("F", 0), ("G", -1), ("G", 0), ("A", -1), ("A", 0), ("B", -1), ("B", 0),
+    ];
+    let (step, alter) = if flats { FLATS } else { SHARPS }[(pitch % 12) as usize];
+    (step, alter, pitch as i32 / 12 - 1)
+}
+
+fn mode_name(scale: Scale) -> &'static str {
+    match scale {
+        Scale::Major | Scale::Pentatonic => "major",
+        Scale::Minor | Scale::Blues => "minor",
+        Scale::Dorian => "dorian",
+        Scale::Phrygian => "phrygian",
+        Scale::Lydian => "lydian",
+        Scale::Mixolydian => "mixolydian",
+        Scale::Aeolian => "aeolian",
+        Scale::Locrian => "locrian",
+        Scale::Chromatic => "none",
+    }
+}
+
+/// One written `<note>` (or rest) in a voice
+struct Piece<'a> {
+    pitches: &'a [u8],
+    steps: u32,
+    kind: (&'static str, bool),
+    voice: usize,
+    tie_stop: bool,
+    tie_start: bool,
+}
+
+impl MusicXmlExport {
+    /// Meter, key and tempo from the session, with no notes yet
+    pub fn new(session: &SessionState, title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            ticks_per_beat: session.piano_roll.ticks_per_beat,
+            bpm: session.bpm as f32,
+            time_signature: session.time_signature,
+            key: session.key,
+            scale: session.scale,
+            divisions: 4,
+            length_ticks: 0,
+            notes: Vec::new(),
+        }
+    }
+
+    /// One arrangement clip, filling the clip's length
+    pub fn clip(session: &SessionState, clip_id: ClipId) -> Option<Self> {
+        let clip = session.arrangement.clip(clip_id)?;
+        let mut export = Self::new(session, clip.name.clone());
+        export.length_ticks = clip.length_ticks();
+        export.notes = clip.notes.to_vec();
+        Some(export)
+    }
+
+    /// One piano roll track
+    pub fn track(session: &SessionState, instrument_id: InstrumentId, name: &str) -> Option<Self> {
+        let track = session.piano_roll.tracks.get(&instrument_id)?;
+        let mut export = Self::new(session, name);
+        export.notes = track.notes.to_vec();
+        Some(export)
+    }
+
+    /// Quantized chords grouped into voices, in grid steps
+    fn voices(&self, step_ticks: f64) -> Vec<Vec<Chord>> {
+        let quantize = |tick: u32| (tick as f64 / step_ticks).round() as u32;
+        let mut chords: BTreeMap<(u32, u32), Vec<u8>> = BTreeMap::new();
+        for note in &self.notes {
+            let start = quantize(note.tick);
+            let end = quantize(note.end_tick()).max(start + 1);
+            let pitches = chords.entry((start, end)).or_default();
+            if !pitches.contains(&note.pitch) {
+                pitches.push(note.pitch);
+            }
+        }
+        let mut voices: Vec<Vec<Chord>> = Vec::new();
+        for ((start, end), mut pitches) in chords {
+            pitches.sort_unstable();
+            let chord = Chord { start, end, pitches };
+            match voices.iter_mut().find(|v| v.last().map_or(true, |c| c.end <= start)) {
+                Some(voice) => voice.push(chord),
+                None => voices.push(vec![chord]),
+            }
+        }
+        if voices.is_empty() {
+            voices.push(Vec::new());
+        }
+        voices
+    }
+
+    fn write_piece(&self, w: &mut XmlWriter, piece: &Piece, flats: bool) {
+        let rests: &[u8] = &[0];
+        let pitches = if piece.pitches.is_empty() { rests } else { piece.pitches };
+        for (i, &pitch) in pitches.iter().enumerate() {
+            w.open("note", &[]);
+            if i > 0 {
+                w.empty("chord", &[]);
+            }
+            if piece.pitches.is_empty() {
+                w.empty("rest", &[]);
+            } else {
+                let (step, alter, octave) = spell(pitch, flats);
+                w.open("pitch", &[]);
+                w.text("step", &[], step);
+                if alter != 0 {
+                    w.text("alter", &[], &alter.to_string());
+                }
+                w.text("octave", &[], &octave.to_string());
+                w.close();
+            }
+            w.text("duration", &[], &piece.steps.to_string());
+            if piece.tie_stop {
+                w.empty("tie", &[("type", "stop".into())]);
+            }
+            if piece.tie_start {
+                w.empty("tie", &[("type", "start".into())]);
+            }
+            w.text("voice", &[], &(piece.voice + 1).to_string());
+            w.text("type", &[], piece.kind.0);
+            if piece.kind.1 {
+                w.empty("dot", &[]);
+            }
+            if piece.tie_stop || piece.tie_start {
+                w.open("notations", &[]);
+                if piece.tie_stop {
+                    w.empty("tied", &[("type", "stop".into())]);
+                }
+                if piece.tie_start {
+                    w.empty("tied", &[("type", "start".into())]);
+                }
+                w.close();
+            }
+            w.close();
+        }
+    }
+
+    pub fn to_xml(&self) -> String {
+        let (num, den) = (self.time_signature.0.max(1) as u32, self.time_signature.1.max(1) as u32);
+        let divisions = self.divisions.clamp(1, 16).next_power_of_two().max(den / 4);
+        // Divisions count quarters; a piano roll beat is one unit of the denominator
+        let step_ticks = self.ticks_per_beat.max(1) as f64 * den as f64 / 4.0 / divisions as f64;
+        let measure = (num * divisions * 4 / den).max(1);
+        let values = note_values(divisions);
+        let voices = self.voices(step_ticks);
+
+        let end = voices.iter().flatten().map(|c| c.end).max().unwrap_or(0);
+        let length = ((self.length_ticks as f64 / step_ticks).round() as u32).max(end);
+        let measures = length.div_ceil(measure).max(1);
+
+        let (fifths, _) = key_signature(self.key, self.scale);
+        let flats = fifths < 0;
+        let average = self.notes.iter().map(|n| n.pitch as u32).sum::<u32>() / self.notes.len().max(1) as u32;
+        let bass = !self.notes.is_empty() && average < 55;
+
+        let mut w = XmlWriter::new();
+        w.raw(r#"<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 4.0 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">"#);
+        w.open("score-partwise", &[("version", "4.0".into())]);
+        w.open("work", &[]);
+        w.text("work-title", &[], &self.title);
+        w.close();
+        w.open("identification", &[]);
+        w.open("encoding", &[]);
+        w.text("software", &[], "imbolc");
+        w.close();
+        w.close();
+        w.open("part-list", &[]);
+        w.open("score-part", &[("id", "P1".into())]);
+        w.text("part-name", &[], &self.title);
+        w.close();
+        w.close();
+
+        w.open("part", &[("id", "P1".into())]);
+        for m in 0..measures {
+            let (start, stop) = (m * measure, (m + 1) * measure);
+            w.open("measure", &[("number", (m + 1).to_string())]);
+            if m == 0 {
+                w.open("attributes", &[]);
+                w.text("divisions", &[], &divisions.to_string());
+                w.open("key", &[]);
+                w.text("fifths", &[], &fifths.to_string());
+                w.text("mode", &[], mode_name(self.scale));
+                w.close();
+                w.open("time", &[]);
+                w.text("beats", &[], &num.to_string());
+                w.text("beat-type", &[], &den.to_string());
+                w.close();
+                w.open("clef", &[]);
+                w.text("sign", &[], if bass { "F" } else { "G" });
+                w.text("line", &[], if bass { "4" } else { "2" });
+                w.close();
+                w.close();
+                w.open("direction", &[("placement", "above".into())]);
+                w.open("direction-type", &[]);
+                w.open("metronome", &[]);
+                w.text("beat-unit", &[], beat_unit(den));
+                w.text("per-minute", &[], &format!("{}", self.bpm));
+                w.close();
+                w.close();
+                // Sound tempo is always in quarters per minute
+                w.empty("sound", &[("tempo", format!("{}", self.bpm * 4.0 / den as f32))]);
+                w.close();
+            }
+
+            let mut written = 0;
+            for (v, voice) in voices.iter().enumerate() {
+                let chords: Vec<&Chord> = voice.iter().filter(|c| c.start < stop && c.end > start).collect();
+                if v > 0 && chords.is_empty() {
+                    continue;
+                }
+                if written > 0 {
+                    w.open("backup", &[]);
+                    w.text("duration", &[], &measure.to_string());
+                    w.close();
+                }
+                written += 1;
+                if chords.is_empty() {
+                    w.open("note", &[]);
+                    w.empty("rest", &[("measure", "yes".into())]);
+                    w.text("duration", &[], &measure.to_string());
+                    w.text("voice", &[], &(v + 1).to_string());
+                    w.close();
+                    continue;
+                }
+
+                let rest = |w: &mut XmlWriter, steps: u32| {
+                    for kind in split_duration(steps, &values) {
+                        let piece = Piece { pitches: &[], steps: kind.0, kind: (kind.1, kind.2), voice: v, tie_stop: false, tie_start: false };
+                        self.write_piece(w, &piece, flats);
+                    }
+                };
+                let mut cursor = start;
+                for chord in chords {
+                    let (from, to) = (chord.start.max(start), chord.end.min(stop));
+                    if from > cursor {
+                        rest(&mut w, from - cursor);
+                    }
+                    let pieces = split_duration(to - from, &values);
+                    let count = pieces.len();
+                    for (i, kind) in pieces.into_iter().enumerate() {
+                        let piece = Piece {
+                            pitches: &chord.pitches,
+                            steps: kind.0,
+                            kind: (kind.1, kind.2),
+                            voice: v,
+                            tie_stop: i > 0 || chord.start < from,
+                            tie_start: i + 1 < count || chord.end > to,
+                        };
+                        self.write_piece(&mut w, &piece, flats);
+                    }
+                    cursor = to;
+                }
+                if cursor < stop {
+                    rest(&mut w, stop - cursor);
+                }
+            }
+            w.close();
+        }
+        w.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(notes: Vec<Note>) -> MusicXmlExport {
+        let mut export = MusicXmlExport::new(&SessionState::new(), "Test");
+        export.notes = notes;
+        export
+    }
+
+    #[test]
+    fn splits_durations_into_notatable_values() {
+        let values = note_values(4);
+        let names = |steps| split_duration(steps, &values).into_iter().map(|v| (v.1, v.2)).collect::<Vec<_>>();
+        assert_eq!(names(6), vec![("quarter", true)]);
+        assert_eq!(names(5), vec![("quarter", false), ("16th", false)]);
+        assert_eq!(names(16), vec![("whole", false)]);
+        assert_eq!(names(11), vec![("half", false), ("eighth", true)]);
+        assert_eq!(spell(61, false), ("C", 1, 4));
+        assert_eq!(spell(61, true), ("D", -1, 4));
+    }
+
+    #[test]
+    fn ties_across_barlines_and_quantizes() {
+        // Starts a few ticks late, lasts two beats over the first barline
+        let xml = export(vec![Note::new(1450, 950, 62, 100)]).to_xml();
+        assert_eq!(xml.matches("<measure ").count(), 2);
+        assert_eq!(xml.matches("<tie type=\"start\"/>").count(), 1);
+        assert_eq!(xml.matches("<tie type=\"stop\"/>").count(), 1);
+        // Three beats of rest, then a quarter tied to a quarter
+        assert!(xml.contains("<type>half</type>\n        <dot/>"));
+        assert!(xml.contains("<step>D</step>"));
+        assert!(xml.contains("<fifths>0</fifths>"));
+        assert!(xml.contains("<beat-type>4</beat-type>"));
+        assert!(xml.contains("<beat-unit>quarter</beat-unit>"));
+        assert!(xml.contains("<per-minute>120</per-minute>"));
+    }
+
+    #[test]
+    fn chords_voices_and_key_signature() {
+        let mut e = export(vec![
+            Note::new(0, 960, 60, 100),
+            Note::new(0, 960, 64, 100),
+            // Overlaps the chord, so it gets its own voice
+            Note::new(480, 480, 70, 100),
+        ]);
+        e.key = Key::F;
+        let xml = e.to_xml();
+        assert_eq!(xml.matches("<chord/>").count(), 1);
+        assert!(xml.contains("<voice>2</voice>"));
+        assert_eq!(xml.matches("<backup>").count(), 1);
+        assert!(xml.contains("<fifths>-1</fifths>"));
+        // B flat, not A sharp, in F major
+        assert!(xml.contains("<step>B</step>\n          <alter>-1</alter>"));
+        // Half rest after the chord; quarter and half rests around the Bb
+        assert_eq!(xml.matches("<rest/>").count(), 3);
+    }
+
+    #[test]
+    fn compound_meter_bars_line_up() {
+        let mut session = SessionState::new();
+        session.set_time_signature((6, 8));
+        let bar = session.piano_roll.ticks_per_bar();
+        let mut e = MusicXmlExport::new(&session, "Test");
+        // A dotted quarter (three eighth-note beats) starting on the second bar
+        e.notes = vec![Note::new(bar, bar / 2, 60, 100)];
+        let xml = e.to_xml();
+        assert_eq!(xml.matches("<measure ").count(), 2);
+        assert_eq!(xml.matches("<rest measure=\"yes\"/>").count(), 1);
+        assert!(xml.contains("<type>quarter</type>\n        <dot/>"));
+        assert!(xml.contains("<beat-unit>eighth</beat-unit>"));
+        assert!(xml.contains("<sound tempo=\"60\"/>"));
+    }
+}
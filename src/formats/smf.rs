@@ -0,0 +1,931 @@
+//! Standard MIDI File (type 0 and 1) import and type 1 export.
+//!
+//! On import, notes are grouped into parts, one per MIDI track or per channel, and each
+//! part becomes a piano roll track or an arrangement clip for its own
+//! instrument. Channel 10 parts are drum parts for a Kit instrument. Tempo
+//! and time signature meta events go to the session; mapped controllers can
+//! become automation lanes. Export writes one MIDI track per instrument after
+//! a conductor track, Kit instruments on channel 10, with mappable automation
+//! lanes as controllers.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use super::{FormatError, Reader};
+use crate::state::arrangement::{ClipId, PlayMode};
+use crate::state::automation::{AutomationLane, AutomationPoint, AutomationTarget, CurveType};
+use crate::state::instrument::SourceType;
+use crate::state::music::{Key, Scale};
+use crate::state::piano_roll::Note;
+use crate::state::session::SessionState;
+use crate::InstrumentId;
+
+/// Zero-based MIDI channel used for drums (channel 10)
+pub const DRUM_CHANNEL: u8 = 9;
+
+/// How notes are grouped into parts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SmfSplit {
+    /// One part per MIDI track (drum notes in a track get their own part).
+    /// Type 0 files are always split by channel.
+    #[default]
+    Track,
+    /// One part per MIDI channel across all tracks
+    Channel,
+}
+
+/// Where imported parts are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SmfTarget {
+    /// Piano roll tracks
+    #[default]
+    Tracks,
+    /// Arrangement clips, each placed at the start of the song
+    Clips,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmfImportOptions {
+    pub split: SmfSplit,
+    /// Turn mapped controllers (see [`cc_target`]) into automation lanes
+    pub cc_automation: bool,
+    /// Instrument id of the first part; later parts count up from it
+    pub first_instrument_id: InstrumentId,
+}
+
+impl Default for SmfImportOptions {
+    fn default() -> Self {
+        Self { split: SmfSplit::Track, cc_automation: false, first_instrument_id: 1 }
+    }
+}
+
+/// Notes (and optionally automation) for one instrument.
+#[derive(Debug, Clone)]
+pub struct SmfPart {
+    pub instrument_id: InstrumentId,
+    pub name: String,
+    /// Channel when every note in the part uses the same one
+    pub channel: Option<u8>,
+    /// First program change seen for the part
+    pub program: Option<u8>,
+    /// `Kit` for drum parts, a plain synth otherwise
+    pub source: SourceType,
+    pub notes: Vec<Note>,
+    pub automation: Vec<(AutomationTarget, Vec<AutomationPoint>)>,
+}
+
+impl SmfPart {
+    pub fn is_drums(&self) -> bool {
+        self.source == SourceType::Kit
+    }
+}
+
+/// A parsed MIDI file, rescaled to the session's tick resolution.
+#[derive(Debug, Clone)]
+pub struct SmfImport {
+    pub format: u16,
+    /// Resolution the ticks below are in. As on the piano roll, a beat is
+    /// one unit of the time signature's denominator.
+    pub ticks_per_beat: u32,
+    pub parts: Vec<SmfPart>,
+    /// Tempo at the start of the file
+    pub bpm: f32,
+    /// (tick, bpm) for every later tempo change
+    pub tempo_changes: Vec<(u32, f32)>,
+    pub time_signature: (u8, u8),
+    pub warnings: Vec<String>,
+}
+
+/// Automation target for a controller number on an instrument
+pub fn cc_target(cc: u8, instrument_id: InstrumentId) -> Option<AutomationTarget> {
+    match cc {
+        7 => Some(AutomationTarget::InstrumentLevel(instrument_id)),
+        10 => Some(AutomationTarget::InstrumentPan(instrument_id)),
+        71 => Some(AutomationTarget::FilterResonance(instrument_id)),
+        72 => Some(AutomationTarget::EnvelopeRelease(instrument_id)),
+        73 => Some(AutomationTarget::EnvelopeAttack(instrument_id)),
+        74 => Some(AutomationTarget::FilterCutoff(instrument_id)),
+        _ => None,
+    }
+}
+
+/// Controller number for an automation target, the inverse of [`cc_target`]
+pub fn target_cc(target: &AutomationTarget) -> Option<u8> {
+    match target {
+        AutomationTarget::InstrumentLevel(_) => Some(7),
+        AutomationTarget::InstrumentPan(_) => Some(10),
+        AutomationTarget::FilterResonance(_) => Some(71),
+        AutomationTarget::EnvelopeRelease(_) => Some(72),
+        AutomationTarget::EnvelopeAttack(_) => Some(73),
+        AutomationTarget::FilterCutoff(_) => Some(74),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+enum EventKind {
+    NoteOn { channel: u8, pitch: u8, velocity: u8 },
+    NoteOff { channel: u8, pitch: u8 },
+    Controller { channel: u8, cc: u8, value: u8 },
+    Program { channel: u8, program: u8 },
+    /// Microseconds per quarter note
+    Tempo(u32),
+    TimeSignature(u8, u8),
+    TrackName(String),
+}
+
+#[derive(Debug, Clone)]
+struct Event {
+    tick: u64,
+    kind: EventKind,
+}
+
+fn read_track(data: &[u8]) -> Result<Vec<Event>, FormatError> {
+    let mut r = Reader::new(data);
+    let mut events = Vec::new();
+    let mut tick = 0u64;
+    let mut running = None;
+    while r.remaining() > 0 {
+        tick += r.vlq()? as u64;
+        let mut status = r.u8()?;
+        let first_data = if status < 0x80 {
+            let Some(s) = running else {
+                return Err(FormatError::InvalidHeader(format!("data byte without status at byte {}", r.pos())));
+            };
+            let data = status;
+            status = s;
+            Some(data)
+        } else {
+            None
+        };
+        match status {
+            0xFF => {
+                let kind = r.u8()?;
+                let length = r.vlq()? as usize;
+                let body = r.bytes(length)?;
+                let event = match (kind, body) {
+                    (0x2F, _) => break,
+                    (0x03, name) => Some(EventKind::TrackName(String::from_utf8_lossy(name).trim().to_string())),
+                    (0x51, [a, b, c]) => Some(EventKind::Tempo(u32::from_be_bytes([0, *a, *b, *c]))),
+                    (0x58, [n, d, ..]) => Some(EventKind::TimeSignature(*n, 1u8.checked_shl(*d as u32).unwrap_or(4))),
+                    _ => None,
+                };
+                events.extend(event.map(|kind| Event { tick, kind }));
+            }
+            0xF0 | 0xF7 => {
+                let length = r.vlq()? as usize;
+                r.skip(length)?;
+            }
+            0x80..=0xEF => {
+                running = Some(status);
+                let channel = status & 0x0F;
+                let a = match first_data {
+                    Some(a) => a,
+                    None => r.u8()?,
+                };
+                let kind = match status & 0xF0 {
+                    0x80 => {
+                        r.skip(1)?;
+                        Some(EventKind::NoteOff { channel, pitch: a })
+                    }
+                    0x90 => {
+                        let velocity = r.u8()?;
+                        Some(if velocity == 0 {
+                            EventKind::NoteOff { channel, pitch: a }
+                        } else {
+                            EventKind::NoteOn { channel, pitch: a, velocity }
+                        })
+                    }
+                    0xB0 => Some(EventKind::Controller { channel, cc: a, value: r.u8()? }),
+                    0xC0 => Some(EventKind::Program { channel, program: a }),
+                    0xD0 => None,
+                    // Poly aftertouch and pitch bend carry a second data byte
+                    _ => {
+                        r.skip(1)?;
+                        None
+                    }
+                };
+                events.extend(kind.map(|kind| Event { tick, kind }));
+            }
+            _ => return Err(FormatError::Unsupported(format!("status byte {:#04x}", status))),
+        }
+    }
+    Ok(events)
+}
+
+/// Part key: (track, channel); unused halves are None depending on the split
+type PartKey = (Option<usize>, Option<u8>);
+
+#[derive(Default)]
+struct PartBuilder {
+    name: Option<String>,
+    channels: HashSet<u8>,
+    program: Option<u8>,
+    drums: bool,
+    notes: Vec<Note>,
+    controllers: BTreeMap<u8, Vec<(u32, u8)>>,
+}
+
+pub fn import_smf(data: &[u8], options: &SmfImportOptions, ticks_per_beat: u32) -> Result<SmfImport, FormatError> {
+    let mut r = Reader::new(data);
+    if r.bytes(4)? != b"MThd" {
+        return Err(FormatError::InvalidHeader("missing MThd chunk".into()));
+    }
+    let header_length = r.u32_be()? as usize;
+    let header_start = r.pos();
+    let format = r.u16_be()?;
+    let track_count = r.u16_be()? as usize;
+    let division = r.u16_be()?;
+    r.seek(header_start + header_length)?;
+    if format > 1 {
+        return Err(FormatError::Unsupported(format!("SMF type {}", format)));
+    }
+    if division & 0x8000 != 0 {
+        return Err(FormatError::Unsupported("SMPTE time division".into()));
+    }
+    let mut tracks = Vec::with_capacity(track_count);
+    while tracks.len() < track_count && r.remaining() > 0 {
+        let id = r.bytes(4)?;
+        let length = r.u32_be()? as usize;
+        let body = r.bytes(length)?;
+        if id == b"MTrk" {
+            tracks.push(read_track(body)?);
+        }
+    }
+
+    // MIDI ticks and tempos count quarter notes; piano roll beats are units of
+    // the denominator of the starting time signature
+    let den = tracks
+        .iter()
+        .flatten()
+        .filter(|e| e.tick == 0)
+        .find_map(|e| match e.kind {
+            EventKind::TimeSignature(_, d) => Some(d.max(1) as u64),
+            _ => None,
+        })
+        .unwrap_or(4);
+    let ppq = division.max(1) as u64;
+    let scale = |tick: u64| (tick * ticks_per_beat as u64 * den / (ppq * 4)).min(u32::MAX as u64) as u32;
+
+    let mut warnings = Vec::new();
+    let split = if format == 0 { SmfSplit::Channel } else { options.split };
+    let mut tempos: Vec<(u32, f32)> = Vec::new();
+    let mut time_signatures: Vec<(u32, (u8, u8))> = Vec::new();
+    let mut parts: BTreeMap<PartKey, PartBuilder> = BTreeMap::new();
+    let mut skipped_cc = HashSet::new();
+
+    for (t, events) in tracks.iter().enumerate() {
+        let mut track_name = None;
+        let mut open: HashMap<(u8, u8), VecDeque<(u64, u8)>> = HashMap::new();
+        let key_for = |channel: u8| -> PartKey {
+            match split {
+                SmfSplit::Track => (Some(t), (channel == DRUM_CHANNEL).then_some(DRUM_CHANNEL)),
+                SmfSplit::Channel => (None, Some(channel)),
+            }
+        };
+        let last_tick = events.last().map_or(0, |e| e.tick);
+        for event in events {
+            match &event.kind {
+                EventKind::Tempo(us) => {
+                    // Microsecond resolution can't hit most BPMs exactly
+                    let bpm = (60_000_000_000.0 * den as f64 / 4.0 / (*us).max(1) as f64).round() / 1000.0;
+                    tempos.push((scale(event.tick), bpm as f32));
+                }
+                EventKind::TimeSignature(n, d) => time_signatures.push((scale(event.tick), (*n, *d))),
+                EventKind::TrackName(name) => track_name = Some(name.clone()),
+                EventKind::NoteOn { channel, pitch, velocity } => {
+                    open.entry((*channel, *pitch)).or_default().push_back((event.tick, *velocity));
+                }
+                EventKind::NoteOff { channel, pitch } => {
+                    let Some((start, velocity)) = open.get_mut(&(*channel, *pitch)).and_then(VecDeque::pop_front) else {
+                        continue;
+                    };
+                    let part = parts.entry(key_for(*channel)).or_default();
+                    part.channels.insert(*channel);
+                    part.drums = *channel == DRUM_CHANNEL;
+                    let tick = scale(start);
+                    part.notes.push(Note::new(tick, scale(event.tick).saturating_sub(tick).max(1), *pitch, velocity));
+                }
+                EventKind::Controller { channel, cc, value } => {
+                    if !options.cc_automation {
+                        continue;
+                    }
+                    if cc_target(*cc, 0).is_none() {
+                        if skipped_cc.insert(*cc) {
+                            warnings.push(format!("controller {} has no automation target", cc));
+                        }
+                        continue;
+                    }
+                    let part = parts.entry(key_for(*channel)).or_default();
+                    part.controllers.entry(*cc).or_default().push((scale(event.tick), *value));
+                }
+                EventKind::Program { channel, program } => {
+                    let part = parts.entry(key_for(*channel)).or_default();
+                    part.program.get_or_insert(*program);
+                }
+            }
+        }
+        let hanging: usize = open.values().map(VecDeque::len).sum();
+        if hanging > 0 {
+            warnings.push(format!("track {} has {} notes without note-off; ended at track end", t, hanging));
+            for ((channel, pitch), starts) in open {
+                let part = parts.entry(key_for(channel)).or_default();
+                part.channels.insert(channel);
+                part.drums = channel == DRUM_CHANNEL;
+                for (start, velocity) in starts {
+                    let tick = scale(start);
+                    part.notes.push(Note::new(tick, scale(last_tick).saturating_sub(tick).max(1), pitch, velocity));
+                }
+            }
+        }
+        if split == SmfSplit::Track {
+            for (key, part) in parts.range_mut((Some(t), None)..=(Some(t), Some(u8::MAX))) {
+                if let Some(name) = &track_name {
+                    part.name = Some(if key.1.is_some() && part.drums { format!("{} Drums", name) } else { name.clone() });
+                }
+            }
+        }
+    }
+
+    tempos.sort_by_key(|&(tick, _)| tick);
+    time_signatures.sort_by_key(|&(tick, _)| tick);
+    let bpm = tempos.iter().find(|&&(tick, _)| tick == 0).map_or(120.0, |&(_, b)| b);
+    let tempo_changes: Vec<(u32, f32)> = tempos.into_iter().filter(|&(tick, _)| tick > 0).collect();
+    let time_signature = time_signatures.first().filter(|(tick, _)| *tick == 0).map_or((4, 4), |&(_, ts)| ts);
+    if time_signatures.iter().any(|&(tick, _)| tick > 0) {
+        warnings.push("time signature changes after the start were ignored".into());
+    }
+
+    let mut out = Vec::new();
+    for ((_, channel), builder) in parts {
+        if builder.notes.is_empty() {
+            continue;
+        }
+        let instrument_id = options.first_instrument_id + out.len() as InstrumentId;
+        let single_channel = (builder.channels.len() == 1).then(|| builder.channels.iter().copied().next()).flatten();
+        let name = builder.name.unwrap_or_else(|| match channel.or(single_channel) {
+            Some(DRUM_CHANNEL) => "Drums".to_string(),
+            Some(c) => format!("Channel {}", c + 1),
+            None => format!("Part {}", out.len() + 1),
+        });
+        let mut notes = builder.notes;
+        notes.sort_by_key(|n| (n.tick, n.pitch));
+        let automation = builder
+            .controllers
+            .into_iter()
+            .filter_map(|(cc, values)| {
+                let target = cc_target(cc, instrument_id)?;
+                let points = values
+                    .into_iter()
+                    .map(|(tick, v)| AutomationPoint::with_curve(tick, v as f32 / 127.0, CurveType::Step))
+                    .collect();
+                Some((target, points))
+            })
+            .collect();
+        out.push(SmfPart {
+            instrument_id,
+            name,
+            channel: single_channel,
+            program: builder.program,
+            source: if builder.drums { SourceType::Kit } else { SourceType::Saw },
+            notes,
+            automation,
+        });
+    }
+
+    Ok(SmfImport { format, ticks_per_beat, parts: out, bpm, tempo_changes, time_signature, warnings })
+}
+
+impl SmfImport {
+    /// Ticks per bar, counted like
+    /// [`PianoRollState::ticks_per_bar`](crate::state::piano_roll::PianoRollState::ticks_per_bar)
+    /// since the ticks are already in piano roll beats
+    pub fn ticks_per_bar(&self) -> u32 {
+        self.ticks_per_beat * self.time_signature.0.max(1) as u32
+    }
+
+    /// End of the last note, rounded up to a whole bar
+    pub fn length_ticks(&self) -> u32 {
+        let bar = self.ticks_per_bar().max(1);
+        let end = self.parts.iter().flat_map(|p| &p.notes).map(Note::end_tick).max().unwrap_or(0);
+        end.div_ceil(bar).max(1) * bar
+    }
+
+    /// Write the import into a session. Instruments with the parts' ids are
+    /// expected to exist (or be created by the caller).
+    pub fn apply(&self, session: &mut SessionState, target: SmfTarget) {
+        session.set_bpm(self.bpm.round().clamp(1.0, u16::MAX as f32) as u16);
+        session.set_time_signature(self.time_signature);
+        if !self.tempo_changes.is_empty() {
+            let id = session.automation.add_lane(AutomationTarget::Bpm);
+            if let Some(lane) = session.automation.lane_mut(id) {
+                let normalize = |bpm: f32| (bpm - lane.min_value) / (lane.max_value - lane.min_value);
+                let mut points = vec![AutomationPoint::with_curve(0, normalize(self.bpm), CurveType::Step)];
+                for &(tick, bpm) in &self.tempo_changes {
+                    points.push(AutomationPoint::with_curve(tick, normalize(bpm), CurveType::Step));
+                }
+                lane.points = points;
+            }
+        }
+
+        match target {
+            SmfTarget::Tracks => {
+                for part in &self.parts {
+                    let roll = &mut session.piano_roll;
+                    roll.add_track(part.instrument_id);
+                    let Some(index) = roll.track_order.iter().position(|&id| id == part.instrument_id) else {
+                        continue;
+                    };
+                    for note in &part.notes {
+                        roll.insert_note(index, note.clone());
+                    }
+                    for (target, points) in &part.automation {
+                        let id = session.automation.add_lane(target.clone());
+                        if let Some(lane) = session.automation.lane_mut(id) {
+                            lane.points = points.clone();
+                        }
+                    }
+                }
+            }
+            SmfTarget::Clips => {
+                let length = self.length_ticks();
+                let arrangement = &mut session.arrangement;
+                for part in &self.parts {
+                    let clip_id = arrangement.add_clip(part.name.clone(), part.instrument_id, length);
+                    for note in &part.notes {
+                        arrangement.add_clip_note(clip_id, note.clone());
+                    }
+                    for (target, points) in &part.automation {
+                        let mut lane = AutomationLane::new(arrangement.next_clip_lane_id(), target.clone());
+                        lane.points = points.clone();
+                        if let Some(clip) = arrangement.clip_mut(clip_id) {
+                            clip.automation_lanes.push(lane);
+                        }
+                    }
+                    arrangement.add_placement(clip_id, part.instrument_id, 0);
+                }
+                arrangement.play_mode = PlayMode::Song;
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Export
+
+/// One exported MIDI track.
+#[derive(Debug, Clone)]
+pub struct SmfExportPart {
+    pub name: String,
+    /// Zero-based; use [`DRUM_CHANNEL`] for Kit instruments
+    pub channel: u8,
+    pub notes: Vec<Note>,
+    /// Lanes with a controller mapping are written as CC; others are skipped
+    pub automation: Vec<AutomationLane>,
+}
+
+/// A type 1 MIDI file to write: a conductor track plus one track per part.
+#[derive(Debug, Clone)]
+pub struct SmfExport {
+    pub ticks_per_beat: u32,
+    pub bpm: f32,
+    /// BPM automation, written as tempo changes
+    pub tempo_lane: Option<AutomationLane>,
+    pub time_signature: (u8, u8),
+    pub key: Key,
+    pub scale: Scale,
+    pub parts: Vec<SmfExportPart>,
+}
+
+/// Key signature meta data (sharps/flats, minor flag) for a key and scale
+pub(crate) fn key_signature(key: Key, scale: Scale) -> (i8, u8) {
+    // Semitones from the scale's tonic up to its relative major
+    let (to_major, minor) = match scale {
+        Scale::Minor | Scale::Aeolian | Scale::Blues => (3, 1),
+        Scale::Dorian => (10, 0),
+        Scale::Phrygian => (8, 0),
+        Scale::Lydian => (7, 0),
+        Scale::Mixolydian => (5, 0),
+        Scale::Locrian => (1, 0),
+        Scale::Major | Scale::Pentatonic | Scale::Chromatic => (0, 0),
+    };
+    const SHARPS: [i8; 12] = [0, -5, 2, -3, 4, -1, 6, 1, -4, 3, -2, 5];
+    (SHARPS[((key.semitone() + to_major) % 12) as usize], minor)
+}
+
+fn write_vlq(mut value: u32, out: &mut Vec<u8>) {
+    let mut bytes = [0u8; 4];
+    let mut n = 0;
+    loop {
+        bytes[n] = (value & 0x7F) as u8 | if n > 0 { 0x80 } else { 0 };
+        n += 1;
+        value >>= 7;
+        if value == 0 || n == 4 {
+            break;
+        }
+    }
+    out.extend(bytes[..n].iter().rev());
+}
+
+fn meta(kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0xFF, kind];
+    write_vlq(data.len() as u32, &mut out);
+    out.extend_from_slice(data);
+    out
+}
+
+/// (tick, order, bytes); order puts meta and controllers before note-offs
+/// before note-ons at the same tick
+type TrackEvent = (u32, u8, Vec<u8>);
+
+fn track_chunk(mut events: Vec<TrackEvent>, out: &mut Vec<u8>) {
+    events.sort_by_key(|e| (e.0, e.1));
+    let mut body = Vec::new();
+    let mut last = 0;
+    for (tick, _, bytes) in events {
+        write_vlq(tick - last, &mut body);
+        body.extend(bytes);
+        last = tick;
+    }
+    body.extend([0, 0xFF, 0x2F, 0]);
+    out.extend_from_slice(b"MTrk");
+    out.extend((body.len() as u32).to_be_bytes());
+    out.extend(body);
+}
+
+/// Lane values (normalized 0.0-1.0) at its points and every `step` ticks
+/// along curved segments, keeping only changes of `quantize(value)`
+fn sample_lane<T: PartialEq + Copy>(lane: &AutomationLane, step: u32, quantize: impl Fn(f32) -> T) -> Vec<(u32, T)> {
+    let range = (lane.max_value - lane.min_value).max(f32::EPSILON);
+    let mut ticks = Vec::new();
+    for (i, point) in lane.points.iter().enumerate() {
+        ticks.push(point.tick);
+        if let (Some(next), false) = (lane.points.get(i + 1), point.curve == CurveType::Step) {
+            ticks.extend((point.tick + step..next.tick).step_by(step.max(1) as usize));
+        }
+    }
+    let mut out: Vec<(u32, T)> = Vec::new();
+    for tick in ticks {
+        let Some(value) = lane.value_at(tick) else {
+            continue;
+        };
+        let q = quantize(((value - lane.min_value) / range).clamp(0.0, 1.0));
+        if out.last().map_or(true, |&(_, last)| last != q) {
+            out.push((tick, q));
+        }
+    }
+    out
+}
+
+impl SmfExport {
+    /// Tempo, meter and key from the session, with no parts yet
+    pub fn new(session: &SessionState) -> Self {
+        Self {
+            ticks_per_beat: session.piano_roll.ticks_per_beat,
+            bpm: session.bpm as f32,
+            tempo_lane: session.automation.lane_for_target(&AutomationTarget::Bpm).cloned(),
+            time_signature: session.time_signature,
+            key: session.key,
+            scale: session.scale,
+            parts: Vec::new(),
+        }
+    }
+
+    /// Add a part for an instrument with `source`. Kit parts go on the drum
+    /// channel; others take the next melodic channel, wrapping around after
+    /// fifteen.
+    pub fn add_part(
+        &mut self,
+        name: String,
+        source: SourceType,
+        notes: Vec<Note>,
+        automation: Vec<AutomationLane>,
+    ) -> &mut SmfExportPart {
+        let channel = if source.is_kit() {
+            DRUM_CHANNEL
+        } else {
+            let used = (self.parts.iter().filter(|p| p.channel != DRUM_CHANNEL).count() % 15) as u8;
+            if used >= DRUM_CHANNEL {
+                used + 1
+            } else {
+                used
+            }
+        };
+        self.parts.push(SmfExportPart { name, channel, notes, automation });
+        self.parts.last_mut().expect("just pushed")
+    }
+
+    /// One piano roll track and its session automation
+    pub fn track(session: &SessionState, instrument_id: InstrumentId, source: SourceType, name: &str) -> Option<Self> {
+        let track = session.piano_roll.tracks.get(&instrument_id)?;
+        let lanes = session
+            .automation
+            .lanes
+            .iter()
+            .filter(|l| l.target.instrument_id() == Some(instrument_id))
+            .cloned()
+            .collect();
+        let mut export = Self::new(session);
+        export.add_part(name.to_string(), source, track.notes.to_vec(), lanes);
+        Some(export)
+    }
+
+    /// One clip (ticks from the clip start) and its clip automation, for an
+    /// instrument with `source`
+    pub fn clip(session: &SessionState, clip_id: ClipId, source: SourceType) -> Option<Self> {
+        let clip = session.arrangement.clip(clip_id)?;
+        let mut export = Self::new(session);
+        export.tempo_lane = None;
+        export.add_part(clip.name.clone(), source, clip.notes.to_vec(), clip.automation_lanes.clone());
+        Some(export)
+    }
+
+    /// The whole arrangement, one part per instrument in id order. Parts are
+    /// named after the instrument's first clip; `source` gives each
+    /// instrument's source type.
+    pub fn song(session: &SessionState, source: impl Fn(InstrumentId) -> SourceType) -> Self {
+        let arrangement = &session.arrangement;
+        let mut lanes = arrangement.flatten_automation();
+        lanes.extend(session.automation.lanes.iter().cloned());
+        let mut by_instrument: Vec<(InstrumentId, Vec<Note>)> = arrangement.flatten_to_notes().into_iter().collect();
+        by_instrument.sort_by_key(|(id, _)| *id);
+
+        let mut export = Self::new(session);
+        for (id, notes) in by_instrument {
+            let name = arrangement
+                .clips_for_instrument(id)
+                .first()
+                .map_or_else(|| format!("Instrument {}", id), |c| c.name.clone());
+            let automation = lanes.iter().filter(|l| l.target.instrument_id() == Some(id)).cloned().collect();
+            export.add_part(name, source(id), notes, automation);
+        }
+        export
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = b"MThd".to_vec();
+        out.extend(6u32.to_be_bytes());
+        out.extend(1u16.to_be_bytes());
+        out.extend((self.parts.len() as u16 + 1).to_be_bytes());
+        // MIDI counts quarter notes, a piano roll beat is one unit of the
+        // denominator. Ticks stay as they are when a quarter is a whole
+        // number of them that fits the header.
+        let (num, den) = self.time_signature;
+        let whole = self.ticks_per_beat.max(1) as u64 * den.max(1) as u64;
+        let ppq = (whole / 4).clamp(1, 0x7FFF);
+        let midi = |tick: u32| ((tick as u64 * ppq * 4 + whole / 2) / whole).min(u32::MAX as u64) as u32;
+        out.extend((ppq as u16).to_be_bytes());
+
+        let tempo = |bpm: f32| {
+            let quarter_bpm = bpm.max(1.0) * 4.0 / den.max(1) as f32;
+            let us = (60_000_000.0 / quarter_bpm).round().min(0xFF_FFFF as f32) as u32;
+            meta(0x51, &us.to_be_bytes()[1..])
+        };
+        let (sharps, minor) = key_signature(self.key, self.scale);
+        let mut conductor: Vec<TrackEvent> = vec![
+            (0, 0, meta(0x58, &[num, den.max(1).trailing_zeros() as u8, 24, 8])),
+            (0, 0, meta(0x59, &[sharps as u8, minor])),
+        ];
+        let tempo_points = self.tempo_lane.as_ref().map_or_else(Vec::new, |lane| {
+            let (min, max) = (lane.min_value, lane.max_value);
+            sample_lane(lane, self.ticks_per_beat / 4, |v| (min + v * (max - min)).round() as u32)
+        });
+        if tempo_points.first().map_or(true, |&(tick, _)| tick > 0) {
+            conductor.push((0, 0, tempo(self.bpm)));
+        }
+        for (tick, bpm) in tempo_points {
+            conductor.push((midi(tick), 0, tempo(bpm as f32)));
+        }
+        track_chunk(conductor, &mut out);
+
+        for part in &self.parts {
+            let ch = part.channel & 0x0F;
+            let mut events: Vec<TrackEvent> = vec![(0, 0, meta(0x03, part.name.as_bytes()))];
+            for lane in &part.automation {
+                let Some(cc) = target_cc(&lane.target) else {
+                    continue;
+                };
+                for (tick, value) in sample_lane(lane, self.ticks_per_beat / 16, |v| (v * 127.0).round() as u8) {
+                    events.push((midi(tick), 1, vec![0xB0 | ch, cc, value]));
+                }
+            }
+            for note in &part.notes {
+                let pitch = note.pitch.min(127);
+                events.push((midi(note.tick), 3, vec![0x90 | ch, pitch, note.velocity.clamp(1, 127)]));
+                events.push((midi(note.end_tick()), 2, vec![0x80 | ch, pitch, 0]));
+            }
+            track_chunk(events, &mut out);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend((body.len() as u32).to_be_bytes());
+        out.extend_from_slice(body);
+        out
+    }
+
+    /// Events as (delta, bytes); end-of-track is appended
+    fn track(events: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (delta, bytes) in events {
+            write_vlq(*delta, &mut body);
+            body.extend_from_slice(bytes);
+        }
+        body.extend([0, 0xFF, 0x2F, 0]);
+        chunk(b"MTrk", &body)
+    }
+
+    fn file(format: u16, ppq: u16, tracks: &[Vec<u8>]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend(format.to_be_bytes());
+        header.extend((tracks.len() as u16).to_be_bytes());
+        header.extend(ppq.to_be_bytes());
+        let mut out = chunk(b"MThd", &header);
+        for t in tracks {
+            out.extend_from_slice(t);
+        }
+        out
+    }
+
+    fn type1() -> Vec<u8> {
+        let conductor = track(&[
+            (0, &[0xFF, 0x51, 3, 0x07, 0xA1, 0x20]), // 120 bpm
+            (0, &[0xFF, 0x58, 4, 3, 2, 24, 8]),      // 3/4
+            (192, &[0xFF, 0x51, 3, 0x09, 0x27, 0xC0]), // 100 bpm
+        ]);
+        let lead = track(&[
+            (0, &[0xFF, 0x03, 4, b'L', b'e', b'a', b'd']),
+            (0, &[0xC0, 5]),
+            (0, &[0xB0, 74, 0]),
+            (0, &[0x90, 60, 100]),
+            (48, &[64, 90]), // running status
+            (48, &[60, 0]),  // note-on velocity 0 ends C
+            (0, &[0xB0, 74, 127]),
+            (48, &[0x80, 64, 0]),
+            (0, &[0xB0, 1, 64]),
+            (0, &[0x99, 36, 110]),
+            (24, &[0x89, 36, 0]),
+        ]);
+        file(1, 96, &[conductor, lead])
+    }
+
+    #[test]
+    fn imports_type1_by_track() {
+        let options = SmfImportOptions { cc_automation: true, ..Default::default() };
+        let import = import_smf(&type1(), &options, 480).unwrap();
+        assert_eq!(import.bpm, 120.0);
+        assert_eq!(import.tempo_changes, vec![(960, 100.0)]);
+        assert_eq!(import.time_signature, (3, 4));
+        assert_eq!(import.warnings, vec!["controller 1 has no automation target".to_string()]);
+
+        assert_eq!(import.parts.len(), 2);
+        let lead = &import.parts[0];
+        assert_eq!((lead.instrument_id, lead.name.as_str(), lead.program, lead.channel), (1, "Lead", Some(5), Some(0)));
+        let notes: Vec<(u32, u32, u8, u8)> = lead.notes.iter().map(|n| (n.tick, n.duration, n.pitch, n.velocity)).collect();
+        assert_eq!(notes, vec![(0, 480, 60, 100), (240, 480, 64, 90)]);
+        assert_eq!(lead.automation.len(), 1);
+        assert_eq!(lead.automation[0].0, AutomationTarget::FilterCutoff(1));
+        assert_eq!(lead.automation[0].1.iter().map(|p| (p.tick, p.value)).collect::<Vec<_>>(), vec![(0, 0.0), (480, 1.0)]);
+
+        let drums = &import.parts[1];
+        assert!(drums.is_drums());
+        assert_eq!((drums.name.as_str(), drums.notes[0].tick, drums.notes[0].duration), ("Lead Drums", 720, 120));
+    }
+
+    #[test]
+    fn applies_to_tracks_and_clips() {
+        let import = import_smf(&type1(), &SmfImportOptions::default(), 480).unwrap();
+        let mut session = SessionState::new();
+        import.apply(&mut session, SmfTarget::Tracks);
+        assert_eq!((session.bpm, session.time_signature), (120, (3, 4)));
+        assert_eq!(session.piano_roll.tracks[&1].notes.len(), 2);
+        assert_eq!(session.piano_roll.tracks[&2].notes.len(), 1);
+        let bpm = session.automation.lane_for_target(&AutomationTarget::Bpm).unwrap();
+        assert_eq!(bpm.value_at(1000).map(f32::round), Some(100.0));
+
+        let mut session = SessionState::new();
+        import.apply(&mut session, SmfTarget::Clips);
+        let arr = &session.arrangement;
+        assert_eq!(arr.play_mode, PlayMode::Song);
+        assert_eq!(arr.clips.len(), 2);
+        // Notes end at 840; a 3/4 bar is 1440 ticks
+        assert_eq!(arr.clips[0].length_ticks(), 1440);
+        assert_eq!(arr.placements().len(), 2);
+    }
+
+    #[test]
+    fn type0_splits_by_channel() {
+        let t = track(&[(0, &[0x90, 60, 100]), (0, &[0x91, 48, 80]), (96, &[0x80, 60, 0]), (0, &[0x81, 48, 0])]);
+        let import = import_smf(&file(0, 96, &[t]), &SmfImportOptions::default(), 96).unwrap();
+        let names: Vec<&str> = import.parts.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["Channel 1", "Channel 2"]);
+
+        assert!(matches!(import_smf(b"RIFF", &SmfImportOptions::default(), 480), Err(FormatError::InvalidHeader(_))));
+        assert!(matches!(
+            import_smf(&file(2, 96, &[]), &SmfImportOptions::default(), 480),
+            Err(FormatError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn export_round_trips_through_import() {
+        let mut session = SessionState::new();
+        session.set_bpm(90);
+        session.set_time_signature((6, 8));
+        session.key = Key::A;
+        session.scale = Scale::Minor;
+        session.piano_roll.add_track(3);
+        session.piano_roll.insert_note(0, Note::new(0, 240, 57, 100));
+        session.piano_roll.insert_note(0, Note::new(240, 480, 60, 80));
+        let id = session.automation.add_lane(AutomationTarget::FilterCutoff(3));
+        let lane = session.automation.lane_mut(id).unwrap();
+        lane.points = vec![
+            AutomationPoint::with_curve(0, 0.0, CurveType::Step),
+            AutomationPoint::with_curve(480, 1.0, CurveType::Step),
+        ];
+
+        let bytes = SmfExport::track(&session, 3, SourceType::Saw, "Bass").unwrap().to_bytes();
+        // A minor: no sharps or flats, minor flag set
+        assert!(bytes.windows(5).any(|w| w == [0xFF, 0x59, 2, 0, 1]));
+
+        let options = SmfImportOptions { cc_automation: true, first_instrument_id: 3, ..Default::default() };
+        let import = import_smf(&bytes, &options, 480).unwrap();
+        assert_eq!((import.bpm, import.time_signature), (90.0, (6, 8)));
+        // Bars are six beats long, as on the piano roll
+        assert_eq!(import.length_ticks(), session.piano_roll.ticks_per_bar());
+        let part = &import.parts[0];
+        assert_eq!(part.name, "Bass");
+        let notes: Vec<(u32, u32, u8, u8)> = part.notes.iter().map(|n| (n.tick, n.duration, n.pitch, n.velocity)).collect();
+        assert_eq!(notes, vec![(0, 240, 57, 100), (240, 480, 60, 80)]);
+        assert_eq!(part.automation[0].0, AutomationTarget::FilterCutoff(3));
+        assert_eq!(part.automation[0].1.iter().map(|p| (p.tick, p.value)).collect::<Vec<_>>(), vec![(0, 0.0), (480, 1.0)]);
+    }
+
+    #[test]
+    fn compound_meter_bars_line_up() {
+        // One 6/8 bar is three quarters: 288 ticks at 96 ppq
+        let t = track(&[
+            (0, &[0xFF, 0x58, 4, 6, 3, 24, 8]),
+            (0, &[0xFF, 0x51, 3, 0x0F, 0x42, 0x40]), // 60 quarters a minute
+            (288, &[0x90, 60, 100]),
+            (144, &[0x80, 60, 0]),
+        ]);
+        let import = import_smf(&file(0, 96, &[t]), &SmfImportOptions::default(), 480).unwrap();
+        assert_eq!(import.ticks_per_bar(), 2880);
+        assert_eq!(import.bpm, 120.0);
+        let note = &import.parts[0].notes[0];
+        assert_eq!((note.tick, note.duration), (2880, 1440));
+
+        let mut session = SessionState::new();
+        import.apply(&mut session, SmfTarget::Tracks);
+        assert_eq!(session.piano_roll.ticks_per_bar(), 2880);
+        let bytes = SmfExport::track(&session, 1, SourceType::Saw, "Lead").unwrap().to_bytes();
+        // Quarters of two eighth-note beats; the note still starts three quarters in
+        assert_eq!(&bytes[12..14], &960u16.to_be_bytes());
+        assert!(bytes.windows(6).any(|w| w == [0xFF, 0x51, 3, 0x0F, 0x42, 0x40]));
+        assert!(bytes.windows(5).any(|w| w == [0x96, 0x40, 0x90, 60, 100]));
+    }
+
+    #[test]
+    fn exports_song_with_tempo_changes() {
+        let mut session = SessionState::new();
+        let import = import_smf(&type1(), &SmfImportOptions::default(), 480).unwrap();
+        import.apply(&mut session, SmfTarget::Clips);
+        let source = |id| import.parts.iter().find(|p| p.instrument_id == id).map_or(SourceType::Saw, |p| p.source);
+        let export = SmfExport::song(&session, source);
+        assert_eq!(export.parts.iter().map(|p| (p.name.as_str(), p.channel)).collect::<Vec<_>>(), vec![("Lead", 0), ("Lead Drums", 9)]);
+
+        let again = import_smf(&export.to_bytes(), &SmfImportOptions::default(), 480).unwrap();
+        assert_eq!(again.bpm, 120.0);
+        assert_eq!(again.tempo_changes, vec![(960, 100.0)]);
+        assert_eq!(again.parts.iter().map(|p| p.notes.len()).collect::<Vec<_>>(), vec![2, 1]);
+        assert!(again.parts[1].is_drums());
+    }
+
+    #[test]
+    fn melodic_channels_skip_drums_and_wrap() {
+        let mut export = SmfExport::new(&SessionState::new());
+        export.add_part("Kit".into(), SourceType::Kit, Vec::new(), Vec::new());
+        for i in 0..300 {
+            export.add_part(format!("Part {}", i), SourceType::Saw, Vec::new(), Vec::new());
+        }
+        let channels: Vec<u8> = export.parts.iter().map(|p| p.channel).collect();
+        assert_eq!(channels[..12], [9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 11]);
+        assert_eq!(channels[16], 0);
+        assert!(channels[1..].iter().all(|&c| c != DRUM_CHANNEL && c < 16));
+    }
+
+    #[test]
+    fn key_signatures() {
+        assert_eq!(key_signature(Key::G, Scale::Major), (1, 0));
+        assert_eq!(key_signature(Key::F, Scale::Major), (-1, 0));
+        assert_eq!(key_signature(Key::E, Scale::Minor), (1, 1));
+        assert_eq!(key_signature(Key::D, Scale::Dorian), (0, 0));
+    }
+}
@@ -0,0 +1,280 @@
+//! Impulse Tracker modules (IT).
+
+use super::s3m::{effect, order};
+use super::{
+    build, instrument, pcm_i16, pcm_i8, pcm_u16, pcm_u8, sample_rate_or_default, ImportedSample, ModCell,
+    ModNote, ModPattern, Module, ModuleImport, MAX_CHANNELS, MAX_PATTERNS, MAX_ROWS,
+};
+use crate::formats::{FormatError, Reader};
+
+/// IT notes are MIDI numbers already (C-5 = 60); 254/255 and fades end the note
+fn note(byte: u8) -> Option<ModNote> {
+    match byte {
+        0..=119 => Some(ModNote::On(byte)),
+        _ => Some(ModNote::Off),
+    }
+}
+
+fn read_pattern(data: &[u8], offset: usize) -> Result<ModPattern, FormatError> {
+    if offset == 0 {
+        return Ok(ModPattern { rows: vec![Vec::new(); 64] });
+    }
+    let mut r = Reader::at(data, offset)?;
+    let length = r.u16_le()? as usize;
+    let row_count = r.u16_le()? as usize;
+    if row_count > MAX_ROWS {
+        return Err(FormatError::InvalidHeader(format!("pattern at {} has {} rows", offset, row_count)));
+    }
+    r.skip(4)?;
+    let mut p = Reader::new(r.bytes(length)?);
+
+    // Packing remembers the last mask and values per channel
+    let mut last_mask = [0u8; MAX_CHANNELS];
+    let mut last_note = [0u8; MAX_CHANNELS];
+    let mut last_instrument = [0u8; MAX_CHANNELS];
+    let mut last_volume = [0u8; MAX_CHANNELS];
+    let mut last_command = [(0u8, 0u8); MAX_CHANNELS];
+    let mut channels = 0;
+    let mut rows = Vec::with_capacity(row_count);
+    for _ in 0..row_count {
+        let mut row = vec![ModCell::default(); MAX_CHANNELS];
+        loop {
+            let what = p.u8()?;
+            if what == 0 {
+                break;
+            }
+            let c = ((what - 1) & 63) as usize;
+            channels = channels.max(c + 1);
+            if what & 128 != 0 {
+                last_mask[c] = p.u8()?;
+            }
+            let mask = last_mask[c];
+            if mask & 1 != 0 {
+                last_note[c] = p.u8()?;
+            }
+            if mask & 2 != 0 {
+                last_instrument[c] = p.u8()?;
+            }
+            if mask & 4 != 0 {
+                last_volume[c] = p.u8()?;
+            }
+            if mask & 8 != 0 {
+                last_command[c] = (p.u8()?, p.u8()?);
+            }
+            let cell = &mut row[c];
+            if mask & (1 | 16) != 0 {
+                cell.note = note(last_note[c]);
+            }
+            if mask & (2 | 32) != 0 {
+                cell.instrument = last_instrument[c] as u16;
+            }
+            if mask & (4 | 64) != 0 && last_volume[c] <= 64 {
+                cell.volume = Some(last_volume[c]);
+            }
+            if mask & (8 | 128) != 0 {
+                let (command, param) = last_command[c];
+                cell.effects.extend(effect(command, param));
+            }
+        }
+        rows.push(row);
+    }
+    for row in &mut rows {
+        row.truncate(channels);
+    }
+    Ok(ModPattern { rows })
+}
+
+fn read_sample(data: &[u8], offset: usize, warnings: &mut Vec<String>) -> Result<ImportedSample, FormatError> {
+    let mut r = Reader::at(data, offset)?;
+    if r.bytes(4)? != b"IMPS" {
+        return Err(FormatError::InvalidHeader(format!("no sample header at {}", offset)));
+    }
+    r.skip(14)?;
+    let flags = r.u8()?;
+    let volume = r.u8()?.min(64);
+    let name = r.text(26)?;
+    let convert = r.u8()?;
+    r.skip(1)?;
+    let length = r.u32_le()? as usize;
+    let loop_start = r.u32_le()?;
+    let loop_end = r.u32_le()?;
+    let c5speed = r.u32_le()?;
+    r.skip(8)?;
+    let pointer = r.u32_le()? as usize;
+
+    let mut sample = ImportedSample::empty(name);
+    sample.sample_rate = sample_rate_or_default(c5speed);
+    sample.volume = volume as f32 / 64.0;
+    if flags & 1 == 0 {
+        return Ok(sample);
+    }
+    if flags & 8 != 0 {
+        warnings.push(format!("sample \"{}\" is compressed and was not decoded", sample.name));
+        return Ok(sample);
+    }
+    if flags & 4 != 0 {
+        warnings.push(format!("sample \"{}\" is stereo; using the left channel", sample.name));
+    }
+    let sixteen_bit = flags & 2 != 0;
+    let signed = convert & 1 != 0;
+    let bytes = Reader::at(data, pointer)?.bytes(length * if sixteen_bit { 2 } else { 1 })?;
+    sample.data = match (sixteen_bit, signed) {
+        (false, true) => pcm_i8(bytes),
+        (false, false) => pcm_u8(bytes),
+        (true, true) => pcm_i16(bytes),
+        (true, false) => pcm_u16(bytes),
+    };
+    sample.loop_range = (flags & 16 != 0 && loop_end > loop_start).then_some((loop_start, loop_end));
+    Ok(sample)
+}
+
+pub fn import_it(data: &[u8], ticks_per_beat: u32) -> Result<ModuleImport, FormatError> {
+    if !data.starts_with(b"IMPM") {
+        return Err(FormatError::InvalidHeader("missing IMPM signature".into()));
+    }
+    let mut r = Reader::at(data, 4)?;
+    let name = r.text(26)?;
+    r.seek(0x20)?;
+    let order_count = r.u16_le()? as usize;
+    let instrument_count = r.u16_le()? as usize;
+    let sample_count = r.u16_le()? as usize;
+    let pattern_count = r.u16_le()? as usize;
+    if pattern_count > MAX_PATTERNS {
+        return Err(FormatError::InvalidHeader(format!("{} patterns", pattern_count)));
+    }
+    r.seek(0x2C)?;
+    let use_instruments = r.u16_le()? & 4 != 0;
+    r.seek(0x32)?;
+    let speed = r.u8()?;
+    let tempo = r.u8()?;
+    r.seek(0xC0)?;
+    let orders = r.bytes(order_count)?.iter().map(|&b| order(b)).collect();
+    let mut table = |count: usize| -> Result<Vec<usize>, FormatError> {
+        (0..count).map(|_| r.u32_le().map(|o| o as usize)).collect()
+    };
+    let instrument_offsets = table(instrument_count)?;
+    let sample_offsets = table(sample_count)?;
+    let pattern_offsets = table(pattern_count)?;
+
+    let patterns = pattern_offsets
+        .into_iter()
+        .map(|offset| read_pattern(data, offset))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut module = Module { name, speed, tempo, orders, patterns, ..Default::default() };
+    let mut samples = Vec::with_capacity(sample_count);
+    for offset in sample_offsets {
+        samples.push(read_sample(data, offset, &mut module.warnings)?);
+    }
+
+    if use_instruments {
+        for (i, offset) in instrument_offsets.into_iter().enumerate() {
+            let mut r = Reader::at(data, offset)?;
+            if r.bytes(4)? != b"IMPI" {
+                return Err(FormatError::InvalidHeader(format!("no instrument header at {}", offset)));
+            }
+            r.seek(offset + 32)?;
+            let name = r.text(26)?;
+            // Keyboard table: (note, sample) pairs; use the sample under C-5
+            r.seek(offset + 64 + 60 * 2 + 1)?;
+            let number = r.u8()? as usize;
+            let sample = match number.checked_sub(1).and_then(|s| samples.get(s)) {
+                Some(s) => s.clone(),
+                None => ImportedSample::empty(name.clone()),
+            };
+            module.instruments.push(instrument(i + 1, name, sample));
+        }
+    } else {
+        for (i, sample) in samples.into_iter().enumerate() {
+            module.instruments.push(instrument(i + 1, sample.name.clone(), sample));
+        }
+    }
+    Ok(build(module, ticks_per_beat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample mode, one signed 16-bit sample, one pattern using packing memory
+    fn fixture() -> Vec<u8> {
+        let mut d = vec![0u8; 0xC0];
+        d[..4].copy_from_slice(b"IMPM");
+        d[4..11].copy_from_slice(b"it test");
+        d[0x20..0x28].copy_from_slice(&[2, 0, 0, 0, 1, 0, 1, 0]);
+        d[0x32] = 6;
+        d[0x33] = 120;
+        d.extend_from_slice(&[0, 255]);
+        let sample_at = d.len() + 8;
+        d.extend_from_slice(&(sample_at as u32).to_le_bytes());
+        let pattern_at = sample_at + 80 + 4;
+        d.extend_from_slice(&(pattern_at as u32).to_le_bytes());
+
+        let mut s = vec![0u8; 80];
+        s[..4].copy_from_slice(b"IMPS");
+        s[18] = 1 | 2 | 16; // data, 16-bit, loop
+        s[19] = 64;
+        s[20..25].copy_from_slice(b"pluck");
+        s[46] = 1; // signed
+        s[48..52].copy_from_slice(&2u32.to_le_bytes());
+        s[56..60].copy_from_slice(&2u32.to_le_bytes());
+        s[60..64].copy_from_slice(&44100u32.to_le_bytes());
+        s[72..76].copy_from_slice(&((sample_at + 80) as u32).to_le_bytes());
+        d.extend_from_slice(&s);
+        d.extend_from_slice(&[0x00, 0x40, 0x00, 0xC0]);
+
+        let packed: Vec<u8> = vec![
+            // row 0: ch 1 C-5 inst 1 vol 32
+            0x81, 1 | 2 | 4, 60, 1, 32, 0,
+            // row 1: ch 1 D-5 reusing mask and instrument; ch 2 SD2 on a new note
+            0x81, 1 | 32, 62, 0x82, 1 | 2 | 8, 64, 1, 19, 0xD2, 0,
+            // row 2: ch 1 note off, ch 2 cut after 3 ticks
+            0x81, 1, 255, 0x82, 8, 19, 0xC3, 0,
+            // row 3: empty
+            0,
+        ];
+        d.extend_from_slice(&(packed.len() as u16).to_le_bytes());
+        d.extend_from_slice(&4u16.to_le_bytes());
+        d.extend_from_slice(&[0; 4]);
+        d.extend_from_slice(&packed);
+        d
+    }
+
+    #[test]
+    fn imports_it_fixture() {
+        let import = import_it(&fixture(), 480).unwrap();
+        assert_eq!(import.name, "it test");
+        assert_eq!(import.bpm, 120.0);
+
+        let pluck = &import.instruments[0];
+        assert_eq!((pluck.id, pluck.name.as_str(), pluck.sample.sample_rate), (1, "pluck", 44100));
+        assert_eq!(pluck.sample.data, vec![0.5, -0.5]);
+        assert_eq!(pluck.sample.loop_range, Some((0, 2)));
+
+        let clip = &import.arrangement.clips[0];
+        assert_eq!(clip.length_ticks(), 480);
+        let mut notes: Vec<(u32, u32, u8, u8)> =
+            clip.notes.iter().map(|n| (n.tick, n.duration, n.pitch, n.velocity)).collect();
+        notes.sort();
+        // SD2 at speed 6 delays a third of a row; SC3 cuts half way into row 2
+        assert_eq!(notes, vec![(0, 120, 60, 64), (120, 120, 62, 127), (160, 140, 64, 127)]);
+    }
+
+    #[test]
+    fn detects_formats() {
+        let it = super::super::import_module(&fixture(), 480).unwrap();
+        assert_eq!(it.name, "it test");
+        assert!(matches!(import_it(b"IMP", 480), Err(FormatError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn rejects_oversized_headers() {
+        let mut data = fixture();
+        data[0x26..0x28].copy_from_slice(&60000u16.to_le_bytes());
+        assert!(matches!(import_it(&data, 480), Err(FormatError::InvalidHeader(_))));
+
+        let mut data = fixture();
+        let pattern_at = u32::from_le_bytes(data[0xC6..0xCA].try_into().unwrap()) as usize;
+        data[pattern_at + 2..pattern_at + 4].copy_from_slice(&60000u16.to_le_bytes());
+        assert!(matches!(import_it(&data, 480), Err(FormatError::InvalidHeader(_))));
+    }
+}
@@ -0,0 +1,474 @@
+//! Tracker module import (MOD, XM, S3M, IT) into an arrangement.
+//!
+//! Each format parser fills a common [`Module`] description, which is then
+//! laid out on the timeline: every pattern becomes one clip per instrument it
+//! uses, the order list becomes clip placements, and speed/tempo commands
+//! become BPM changes. Rows are sixteenth notes (four rows per beat), so
+//! speed 6 at tempo 125 plays at 125 BPM.
+
+mod it;
+mod protracker;
+mod s3m;
+mod xm;
+
+pub use it::import_it;
+pub use protracker::import_mod;
+pub use s3m::import_s3m;
+pub use xm::import_xm;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::FormatError;
+use crate::state::arrangement::{ArrangementState, ClipId, PlayMode};
+use crate::state::instrument::SourceType;
+use crate::state::piano_roll::Note;
+use crate::InstrumentId;
+
+/// Rows per beat when laying patterns out on the timeline
+pub const ROWS_PER_BEAT: u32 = 4;
+
+/// Sample rate used by trackers when a sample doesn't give one
+const DEFAULT_SAMPLE_RATE: u32 = 8363;
+
+/// Most channels any supported format can address (IT's limit)
+pub(crate) const MAX_CHANNELS: usize = 64;
+
+/// Most rows a pattern may have
+pub(crate) const MAX_ROWS: usize = 256;
+
+/// Most patterns a module may have; order lists address them by byte
+pub(crate) const MAX_PATTERNS: usize = 256;
+
+/// A module laid out as an arrangement, ready to add to a session.
+#[derive(Debug, Clone)]
+pub struct ModuleImport {
+    pub name: String,
+    /// Clips use the module's 1-based instrument numbers as instrument ids
+    pub arrangement: ArrangementState,
+    pub instruments: Vec<ImportedInstrument>,
+    /// Tempo at the start of the song
+    pub bpm: f32,
+    /// (tick, bpm) for every later tempo change, in order
+    pub tempo_changes: Vec<(u32, f32)>,
+    /// Things that were skipped or approximated
+    pub warnings: Vec<String>,
+}
+
+/// A module instrument as a sample-based instrument.
+#[derive(Debug, Clone)]
+pub struct ImportedInstrument {
+    pub id: InstrumentId,
+    pub name: String,
+    pub source: SourceType,
+    pub sample: ImportedSample,
+}
+
+/// Decoded sample data, mono.
+#[derive(Debug, Clone, Default)]
+pub struct ImportedSample {
+    pub name: String,
+    pub data: Vec<f32>,
+    /// Playback rate that sounds `root_pitch`
+    pub sample_rate: u32,
+    pub root_pitch: u8,
+    /// Loop start and end in frames
+    pub loop_range: Option<(u32, u32)>,
+    /// Default volume, 0.0-1.0
+    pub volume: f32,
+}
+
+impl ImportedSample {
+    fn empty(name: String) -> Self {
+        Self { name, sample_rate: DEFAULT_SAMPLE_RATE, root_pitch: 60, ..Default::default() }
+    }
+}
+
+/// Import any supported module, detected from its header.
+pub fn import_module(data: &[u8], ticks_per_beat: u32) -> Result<ModuleImport, FormatError> {
+    if data.starts_with(b"Extended Module: ") {
+        import_xm(data, ticks_per_beat)
+    } else if data.starts_with(b"IMPM") {
+        import_it(data, ticks_per_beat)
+    } else if data.get(0x2C..0x30) == Some(b"SCRM") {
+        import_s3m(data, ticks_per_beat)
+    } else {
+        import_mod(data, ticks_per_beat)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Common module description filled by the format parsers
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ModNote {
+    /// MIDI pitch
+    On(u8),
+    Off,
+}
+
+/// Effects that matter for layout; everything else is dropped by the parsers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ModEffect {
+    Speed(u8),
+    Tempo(u8),
+    /// Set volume (0-64)
+    Volume(u8),
+    PatternBreak,
+    PositionJump(u8),
+    /// Start the note this many ticks (of the speed) into the row
+    NoteDelay(u8),
+    /// Cut the note this many ticks into the row
+    NoteCut(u8),
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ModCell {
+    pub note: Option<ModNote>,
+    /// 1-based, 0 = none
+    pub instrument: u16,
+    /// Volume column (0-64)
+    pub volume: Option<u8>,
+    pub effects: Vec<ModEffect>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ModPattern {
+    /// rows[row][channel]
+    pub rows: Vec<Vec<ModCell>>,
+}
+
+/// Order list entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ModOrder {
+    Pattern(usize),
+    /// "+++" marker, skipped
+    Skip,
+    /// "---" marker, end of song
+    End,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Module {
+    pub name: String,
+    pub speed: u8,
+    pub tempo: u8,
+    pub orders: Vec<ModOrder>,
+    pub patterns: Vec<ModPattern>,
+    /// Index i is instrument number i + 1
+    pub instruments: Vec<ImportedInstrument>,
+    pub warnings: Vec<String>,
+}
+
+pub(crate) fn instrument(number: usize, name: String, sample: ImportedSample) -> ImportedInstrument {
+    ImportedInstrument { id: number as InstrumentId, name, source: SourceType::PitchedSampler, sample }
+}
+
+pub(crate) fn sample_rate_or_default(rate: u32) -> u32 {
+    if rate == 0 { DEFAULT_SAMPLE_RATE } else { rate }
+}
+
+/// Tracker volume (0-64) as note velocity
+fn velocity(volume: u8) -> u8 {
+    ((volume.min(64) as u32 * 127 + 32) / 64).max(1) as u8
+}
+
+fn bpm(speed: u8, tempo: u8) -> f32 {
+    tempo as f32 * 24.0 / (speed.max(1) as f32 * ROWS_PER_BEAT as f32)
+}
+
+/// Speed and tempo after applying a row's effects
+fn apply_timing(row: &[ModCell], speed: &mut u8, tempo: &mut u8) {
+    for effect in row.iter().flat_map(|c| &c.effects) {
+        match *effect {
+            ModEffect::Speed(s) if s > 0 => *speed = s,
+            ModEffect::Tempo(t) if t > 0 => *tempo = t,
+            _ => {}
+        }
+    }
+}
+
+/// Rows actually played and the jump (if any) that ends the pattern
+fn played_rows(pattern: &ModPattern) -> (usize, Option<usize>) {
+    for (r, row) in pattern.rows.iter().enumerate() {
+        let mut ends = false;
+        let mut jump = None;
+        for effect in row.iter().flat_map(|c| &c.effects) {
+            match *effect {
+                ModEffect::PatternBreak => ends = true,
+                ModEffect::PositionJump(p) => {
+                    ends = true;
+                    jump = Some(p as usize);
+                }
+                _ => {}
+            }
+        }
+        if ends {
+            return (r + 1, jump);
+        }
+    }
+    (pattern.rows.len(), None)
+}
+
+/// Notes of one pattern, grouped by instrument number
+fn pattern_notes(pattern: &ModPattern, module: &Module, mut speed: u8, row_ticks: u32) -> BTreeMap<u16, Vec<Note>> {
+    let mut notes: BTreeMap<u16, Vec<Note>> = BTreeMap::new();
+    let channels = pattern.rows.iter().map(Vec::len).max().unwrap_or(0);
+    // Per channel: last instrument and the note still sounding
+    let mut last_instrument = vec![0u16; channels];
+    let mut open: Vec<Option<(u16, Note)>> = vec![None; channels];
+    let mut tempo = 0;
+    let close = |notes: &mut BTreeMap<u16, Vec<Note>>, slot: &mut Option<(u16, Note)>, tick: u32| {
+        if let Some((instrument, mut note)) = slot.take() {
+            note.duration = tick.saturating_sub(note.tick).max(1);
+            notes.entry(instrument).or_default().push(note);
+        }
+    };
+
+    for (r, row) in pattern.rows.iter().enumerate() {
+        apply_timing(row, &mut speed, &mut tempo);
+        let row_start = r as u32 * row_ticks;
+        let sub_tick = |t: u8| row_start + t as u32 * row_ticks / speed.max(1) as u32;
+        for (c, cell) in row.iter().enumerate() {
+            if cell.instrument > 0 {
+                last_instrument[c] = cell.instrument;
+            }
+            let mut delay = None;
+            let mut cut = None;
+            let mut set_volume = None;
+            for effect in &cell.effects {
+                match *effect {
+                    ModEffect::NoteDelay(t) => delay = Some(t),
+                    ModEffect::NoteCut(t) => cut = Some(t),
+                    ModEffect::Volume(v) => set_volume = Some(v),
+                    _ => {}
+                }
+            }
+            let tick = delay.map_or(row_start, sub_tick);
+            match cell.note {
+                Some(ModNote::On(pitch)) => {
+                    close(&mut notes, &mut open[c], tick);
+                    let number = last_instrument[c];
+                    let Some(inst) = module.instruments.get((number as usize).wrapping_sub(1)) else {
+                        continue;
+                    };
+                    let volume = cell
+                        .volume
+                        .or(set_volume)
+                        .unwrap_or((inst.sample.volume * 64.0).round() as u8);
+                    open[c] = Some((number, Note::new(tick, 1, pitch.min(127), velocity(volume))));
+                }
+                Some(ModNote::Off) => close(&mut notes, &mut open[c], tick),
+                None => {}
+            }
+            if let Some(t) = cut {
+                let end = sub_tick(t).max(tick + 1);
+                close(&mut notes, &mut open[c], end);
+            }
+        }
+    }
+    let end = pattern.rows.len() as u32 * row_ticks;
+    for slot in &mut open {
+        close(&mut notes, slot, end);
+    }
+    notes
+}
+
+/// Lay a parsed module out as an arrangement
+pub(crate) fn build(mut module: Module, ticks_per_beat: u32) -> ModuleImport {
+    let row_ticks = (ticks_per_beat / ROWS_PER_BEAT).max(1);
+    let mut arrangement = ArrangementState::new();
+    arrangement.play_mode = PlayMode::Song;
+    let mut warnings = std::mem::take(&mut module.warnings);
+
+    let mut speed = module.speed.max(1);
+    let mut tempo = module.tempo.max(1);
+    let mut start_bpm = bpm(speed, tempo);
+    let mut current_bpm = start_bpm;
+    let mut tempo_changes = Vec::new();
+
+    // Clips per pattern, built the first time the pattern is played
+    let mut clips: HashMap<usize, Vec<(InstrumentId, ClipId)>> = HashMap::new();
+    let mut used_instruments: HashSet<u16> = HashSet::new();
+    let mut visited = HashSet::new();
+    let mut tick = 0u32;
+    let mut pos = 0usize;
+
+    while let Some(&order) = module.orders.get(pos) {
+        if !visited.insert(pos) {
+            break;
+        }
+        let index = match order {
+            ModOrder::Pattern(i) => i,
+            ModOrder::Skip => {
+                pos += 1;
+                continue;
+            }
+            ModOrder::End => break,
+        };
+        let Some(pattern) = module.patterns.get(index) else {
+            warnings.push(format!("order {} refers to missing pattern {}", pos, index));
+            pos += 1;
+            continue;
+        };
+        let (rows, jump) = played_rows(pattern);
+        let pattern_length = pattern.rows.len() as u32 * row_ticks;
+        let length = rows as u32 * row_ticks;
+
+        let placed = clips.entry(index).or_insert_with(|| {
+            let mut made = Vec::new();
+            for (number, notes) in pattern_notes(pattern, &module, speed, row_ticks) {
+                used_instruments.insert(number);
+                let inst = &module.instruments[number as usize - 1];
+                let name = if inst.name.is_empty() {
+                    format!("Pattern {:02} #{}", index, number)
+                } else {
+                    format!("Pattern {:02} {}", index, inst.name)
+                };
+                let clip_id = arrangement.add_clip(name, inst.id, pattern_length);
+                for note in notes {
+                    arrangement.add_clip_note(clip_id, note);
+                }
+                made.push((inst.id, clip_id));
+            }
+            made
+        });
+        for &(instrument_id, clip_id) in placed.iter() {
+            let placement = arrangement.add_placement(clip_id, instrument_id, tick);
+            if length < pattern_length {
+                arrangement.resize_placement(placement, Some(length));
+            }
+        }
+
+        for (r, row) in pattern.rows.iter().take(rows).enumerate() {
+            apply_timing(row, &mut speed, &mut tempo);
+            let row_bpm = bpm(speed, tempo);
+            if row_bpm != current_bpm {
+                current_bpm = row_bpm;
+                let at = tick + r as u32 * row_ticks;
+                if at == 0 {
+                    start_bpm = row_bpm;
+                } else {
+                    tempo_changes.push((at, row_bpm));
+                }
+            }
+        }
+        tick += length;
+
+        pos = match jump {
+            // Jumping back means the song loops: stop after one pass
+            Some(target) if target <= pos => break,
+            Some(target) => target,
+            None => pos + 1,
+        };
+    }
+
+    let instruments = module
+        .instruments
+        .into_iter()
+        .filter(|i| !i.sample.data.is_empty() || used_instruments.contains(&(i.id as u16)))
+        .collect();
+
+    ModuleImport {
+        name: module.name,
+        arrangement,
+        instruments,
+        bpm: start_bpm,
+        tempo_changes,
+        warnings,
+    }
+}
+
+/// Signed 8-bit PCM
+pub(crate) fn pcm_i8(bytes: &[u8]) -> Vec<f32> {
+    bytes.iter().map(|&b| b as i8 as f32 / 128.0).collect()
+}
+
+/// Unsigned 8-bit PCM
+pub(crate) fn pcm_u8(bytes: &[u8]) -> Vec<f32> {
+    bytes.iter().map(|&b| (b as f32 - 128.0) / 128.0).collect()
+}
+
+/// Signed 16-bit little-endian PCM
+pub(crate) fn pcm_i16(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+        .collect()
+}
+
+/// Unsigned 16-bit little-endian PCM
+pub(crate) fn pcm_u16(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(2)
+        .map(|b| (u16::from_le_bytes([b[0], b[1]]) as f32 - 32768.0) / 32768.0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(note: Option<ModNote>, instrument: u16, effects: Vec<ModEffect>) -> ModCell {
+        ModCell { note, instrument, volume: None, effects }
+    }
+
+    fn module(patterns: Vec<ModPattern>, orders: Vec<ModOrder>) -> Module {
+        let sample = ImportedSample { volume: 1.0, ..ImportedSample::empty("s".into()) };
+        Module {
+            name: "test".into(),
+            speed: 6,
+            tempo: 125,
+            orders,
+            patterns,
+            instruments: vec![instrument(1, "Lead".into(), sample.clone()), instrument(2, "Bass".into(), sample)],
+            warnings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn patterns_become_clips_per_instrument() {
+        let mut rows = vec![vec![ModCell::default(), ModCell::default()]; 4];
+        rows[0][0] = cell(Some(ModNote::On(60)), 1, vec![]);
+        rows[0][1] = cell(Some(ModNote::On(36)), 2, vec![]);
+        rows[2][0] = cell(Some(ModNote::Off), 0, vec![]);
+        rows[3][1] = cell(Some(ModNote::On(38)), 0, vec![ModEffect::NoteDelay(3)]);
+        let m = module(vec![ModPattern { rows }], vec![ModOrder::Pattern(0), ModOrder::Skip, ModOrder::Pattern(0)]);
+        let import = build(m, 480);
+
+        assert_eq!(import.arrangement.clips.len(), 2);
+        assert_eq!(import.arrangement.placements().len(), 4);
+        assert_eq!(import.arrangement.placements()[2].start_tick, 480);
+        let lead = &import.arrangement.clips[0];
+        assert_eq!((lead.name.as_str(), lead.instrument_id, lead.length_ticks()), ("Pattern 00 Lead", 1, 480));
+        let lead_notes: Vec<(u32, u32, u8)> = lead.notes.iter().map(|n| (n.tick, n.duration, n.pitch)).collect();
+        assert_eq!(lead_notes, vec![(0, 240, 60)]);
+        let bass = &import.arrangement.clips[1];
+        let bass_notes: Vec<(u32, u32, u8)> = bass.notes.iter().map(|n| (n.tick, n.duration, n.pitch)).collect();
+        // Second note uses the channel's last instrument and is delayed half a row
+        assert_eq!(bass_notes, vec![(0, 420, 36), (420, 60, 38)]);
+    }
+
+    #[test]
+    fn speed_tempo_break_and_jump() {
+        let mut first = vec![vec![ModCell::default()]; 8];
+        first[0][0] = cell(Some(ModNote::On(60)), 1, vec![ModEffect::Tempo(150)]);
+        first[4][0] = cell(None, 0, vec![ModEffect::Speed(3), ModEffect::PatternBreak]);
+        let mut second = vec![vec![ModCell::default()]; 4];
+        second[1][0] = cell(None, 0, vec![ModEffect::PositionJump(0)]);
+        let m = module(
+            vec![ModPattern { rows: first }, ModPattern { rows: second }],
+            vec![ModOrder::Pattern(0), ModOrder::Pattern(1), ModOrder::Pattern(0), ModOrder::End],
+        );
+        let import = build(m, 480);
+
+        assert_eq!(import.bpm, 150.0);
+        assert_eq!(import.tempo_changes, vec![(480, 300.0)]);
+        // Break after row 4 trims the first placement to five rows
+        let p = &import.arrangement.placements()[0];
+        assert_eq!(p.length_override, Some(600));
+        // The backward jump ends the song after the second pattern
+        assert_eq!(import.arrangement.placements().len(), 1);
+        assert_eq!(import.arrangement.arrangement_length(), 600);
+        assert!(import.instruments.iter().all(|i| i.id == 1));
+    }
+}
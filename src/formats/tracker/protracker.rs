@@ -0,0 +1,205 @@
+//! ProTracker-style MOD (31 samples, 4-32 channels).
+
+use super::{
+    build, instrument, pcm_i8, ImportedSample, ModCell, ModEffect, ModNote, ModOrder, ModPattern, Module,
+    ModuleImport, MAX_CHANNELS,
+};
+use crate::formats::{FormatError, Reader};
+
+const ROWS: usize = 64;
+const SAMPLES: usize = 31;
+
+/// Channel count from the signature at offset 1080
+fn channels(signature: &[u8]) -> Option<usize> {
+    match signature {
+        b"M.K." | b"M!K!" | b"FLT4" | b"4CHN" => Some(4),
+        b"FLT8" | b"OKTA" | b"CD81" => Some(8),
+        [d, b'C', b'H', b'N'] if d.is_ascii_digit() => Some((d - b'0') as usize),
+        [a, b, b'C', b'H'] if a.is_ascii_digit() && b.is_ascii_digit() => {
+            Some(((a - b'0') * 10 + (b - b'0')) as usize)
+        }
+        _ => None,
+    }
+    .filter(|&n| (1..=MAX_CHANNELS).contains(&n))
+}
+
+/// MIDI pitch for an Amiga period; period 428 (ProTracker C-2) is middle C
+fn period_pitch(period: u16) -> u8 {
+    let semitones = 12.0 * (428.0 / period as f32).log2();
+    (60.0 + semitones.round()).clamp(0.0, 127.0) as u8
+}
+
+/// MOD effect numbering, shared with XM
+pub(crate) fn mod_effect(command: u8, param: u8) -> Option<ModEffect> {
+    match command {
+        0x0B => Some(ModEffect::PositionJump(param)),
+        0x0C => Some(ModEffect::Volume(param.min(64))),
+        0x0D => Some(ModEffect::PatternBreak),
+        0x0E => match param >> 4 {
+            0x0C => Some(ModEffect::NoteCut(param & 0x0F)),
+            0x0D => Some(ModEffect::NoteDelay(param & 0x0F)),
+            _ => None,
+        },
+        0x0F if param < 0x20 => Some(ModEffect::Speed(param)),
+        0x0F => Some(ModEffect::Tempo(param)),
+        _ => None,
+    }
+}
+
+struct SampleHeader {
+    name: String,
+    length: usize,
+    finetune: i8,
+    volume: u8,
+    loop_start: usize,
+    loop_length: usize,
+}
+
+pub fn import_mod(data: &[u8], ticks_per_beat: u32) -> Result<ModuleImport, FormatError> {
+    let mut r = Reader::new(data);
+    let signature = data.get(1080..1084).ok_or(FormatError::Truncated { offset: data.len() })?;
+    let channels = channels(signature)
+        .ok_or_else(|| FormatError::InvalidHeader(format!("unknown MOD signature {:?}", String::from_utf8_lossy(signature))))?;
+
+    let name = r.text(20)?;
+    let mut headers = Vec::with_capacity(SAMPLES);
+    for _ in 0..SAMPLES {
+        let name = r.text(22)?;
+        let length = r.u16_be()? as usize * 2;
+        let nibble = r.u8()? & 0x0F;
+        let finetune = if nibble >= 8 { nibble as i8 - 16 } else { nibble as i8 };
+        let volume = r.u8()?.min(64);
+        let loop_start = r.u16_be()? as usize * 2;
+        let loop_length = r.u16_be()? as usize * 2;
+        headers.push(SampleHeader { name, length, finetune, volume, loop_start, loop_length });
+    }
+    let song_length = (r.u8()? as usize).clamp(1, 128);
+    r.skip(1)?;
+    let table = r.bytes(128)?;
+    let pattern_count = table.iter().copied().max().unwrap_or(0) as usize + 1;
+    let orders = table[..song_length].iter().map(|&p| ModOrder::Pattern(p as usize)).collect();
+    r.skip(4)?;
+
+    let mut patterns = Vec::with_capacity(pattern_count);
+    for _ in 0..pattern_count {
+        let mut rows = Vec::with_capacity(ROWS);
+        for _ in 0..ROWS {
+            let mut row = Vec::with_capacity(channels);
+            for _ in 0..channels {
+                let b = r.bytes(4)?;
+                let sample = (b[0] & 0xF0) as u16 | (b[2] >> 4) as u16;
+                let period = ((b[0] & 0x0F) as u16) << 8 | b[1] as u16;
+                row.push(ModCell {
+                    note: (period > 0).then(|| ModNote::On(period_pitch(period))),
+                    instrument: sample,
+                    volume: None,
+                    effects: mod_effect(b[2] & 0x0F, b[3]).into_iter().collect(),
+                });
+            }
+            rows.push(row);
+        }
+        patterns.push(ModPattern { rows });
+    }
+
+    let mut module = Module { name, speed: 6, tempo: 125, orders, patterns, ..Default::default() };
+    for (i, h) in headers.into_iter().enumerate() {
+        let available = h.length.min(r.remaining());
+        if available < h.length {
+            module.warnings.push(format!("sample {} is truncated", i + 1));
+        }
+        let pcm = pcm_i8(r.bytes(available)?);
+        let loop_range = (h.loop_length > 2 && h.loop_start < pcm.len())
+            .then(|| (h.loop_start as u32, (h.loop_start + h.loop_length).min(pcm.len()) as u32));
+        let sample = ImportedSample {
+            data: pcm,
+            sample_rate: (8363.0 * 2f32.powf(h.finetune as f32 / 96.0)).round() as u32,
+            loop_range,
+            volume: h.volume as f32 / 64.0,
+            ..ImportedSample::empty(h.name.clone())
+        };
+        module.instruments.push(instrument(i + 1, h.name, sample));
+    }
+    Ok(build(module, ticks_per_beat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 4-channel M.K. file with one pattern and one looping sample
+    fn fixture() -> Vec<u8> {
+        let mut d = Vec::new();
+        d.extend_from_slice(b"fixture song\0\0\0\0\0\0\0\0");
+        for i in 0..SAMPLES {
+            let mut name = [0u8; 22];
+            if i == 0 {
+                name[..4].copy_from_slice(b"kick");
+            }
+            d.extend_from_slice(&name);
+            let (len, fine, vol, ls, ll) = if i == 0 { (4u16, 0u8, 48u8, 1u16, 2u16) } else { (0, 0, 0, 0, 1) };
+            d.extend_from_slice(&len.to_be_bytes());
+            d.extend_from_slice(&[fine, vol]);
+            d.extend_from_slice(&ls.to_be_bytes());
+            d.extend_from_slice(&ll.to_be_bytes());
+        }
+        d.push(2); // song length
+        d.push(127);
+        d.extend_from_slice(&[0u8; 128]);
+        d.extend_from_slice(b"M.K.");
+        for row in 0..ROWS {
+            for ch in 0..4 {
+                let cell: [u8; 4] = match (row, ch) {
+                    (0, 0) => [0x00, 0xD6, 0x1F, 0x03], // C-3 (214), sample 1, speed 3
+                    (0, 1) => [0x01, 0xAC, 0x1C, 0x20], // C-2 (428), sample 1, volume 32
+                    (8, 0) => [0x00, 0x00, 0x0E, 0xC2], // cut channel 0 after 2 ticks
+                    (16, 2) => [0x00, 0x00, 0x0D, 0x00], // pattern break
+                    _ => [0; 4],
+                };
+                d.extend_from_slice(&cell);
+            }
+        }
+        d.extend_from_slice(&[0, 64, 127, 0x80, 1, 2, 3, 4]);
+        d
+    }
+
+    #[test]
+    fn imports_mod_fixture() {
+        let import = import_mod(&fixture(), 480).unwrap();
+        assert_eq!(import.name, "fixture song");
+        assert_eq!(import.bpm, 250.0);
+        assert_eq!(import.instruments.len(), 1);
+        let kick = &import.instruments[0];
+        assert_eq!((kick.id, kick.name.as_str(), kick.sample.data.len()), (1, "kick", 8));
+        assert_eq!(kick.sample.loop_range, Some((2, 6)));
+        assert_eq!(kick.sample.sample_rate, 8363);
+
+        let arr = &import.arrangement;
+        assert_eq!(arr.clips.len(), 1);
+        assert_eq!(arr.placements().len(), 2);
+        assert_eq!(arr.placements()[1].start_tick, 17 * 120);
+        let mut notes: Vec<(u32, u32, u8, u8)> =
+            arr.clips[0].notes.iter().map(|n| (n.tick, n.duration, n.pitch, n.velocity)).collect();
+        notes.sort();
+        // Channel 0 cut two ticks (of speed 3) into row 8; channel 1 rings to the end
+        assert_eq!(notes, vec![(0, 8 * 120 + 80, 72, 95), (0, 64 * 120, 60, 64)]);
+    }
+
+    #[test]
+    fn rejects_unknown_signature() {
+        let mut data = fixture();
+        data[1080..1084].copy_from_slice(b"????");
+        assert!(matches!(import_mod(&data, 480), Err(FormatError::InvalidHeader(_))));
+        assert_eq!(import_mod(&data[..500], 480).unwrap_err(), FormatError::Truncated { offset: 500 });
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let mut data = fixture();
+        data[1080..1084].copy_from_slice(b"99CH");
+        assert!(matches!(import_mod(&data, 480), Err(FormatError::InvalidHeader(_))));
+
+        // Pattern data cut off mid-row
+        let data = fixture();
+        assert!(matches!(import_mod(&data[..1084 + 100], 480), Err(FormatError::Truncated { .. })));
+    }
+}
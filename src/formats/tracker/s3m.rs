@@ -0,0 +1,237 @@
+//! Scream Tracker 3 modules (S3M).
+
+use super::{
+    build, instrument, pcm_i16, pcm_i8, pcm_u16, pcm_u8, sample_rate_or_default, ImportedSample, ModCell,
+    ModEffect, ModNote, ModOrder, ModPattern, Module, ModuleImport, MAX_PATTERNS,
+};
+use crate::formats::{FormatError, Reader};
+
+const ROWS: usize = 64;
+const CHANNELS: usize = 32;
+
+/// ST3 note byte: octave in the high nibble, semitone in the low; C-4 is middle C
+pub(crate) fn note(byte: u8) -> Option<ModNote> {
+    match byte {
+        255 => None,
+        254 => Some(ModNote::Off),
+        b if b & 0x0F < 12 => Some(ModNote::On(((b >> 4) * 12 + (b & 0x0F) + 12).min(127))),
+        _ => None,
+    }
+}
+
+/// Scream Tracker effect letters (A = 1), shared with IT
+pub(crate) fn effect(command: u8, param: u8) -> Option<ModEffect> {
+    match command {
+        1 => Some(ModEffect::Speed(param)),
+        2 => Some(ModEffect::PositionJump(param)),
+        3 => Some(ModEffect::PatternBreak),
+        19 => match param >> 4 {
+            0x0C => Some(ModEffect::NoteCut(param & 0x0F)),
+            0x0D => Some(ModEffect::NoteDelay(param & 0x0F)),
+            _ => None,
+        },
+        // T0x/T1x are tempo slides
+        20 if param >= 0x20 => Some(ModEffect::Tempo(param)),
+        _ => None,
+    }
+}
+
+pub(crate) fn order(byte: u8) -> ModOrder {
+    match byte {
+        255 => ModOrder::End,
+        254 => ModOrder::Skip,
+        p => ModOrder::Pattern(p as usize),
+    }
+}
+
+fn read_pattern(data: &[u8], offset: usize) -> Result<ModPattern, FormatError> {
+    let mut rows = vec![vec![ModCell::default(); CHANNELS]; ROWS];
+    if offset == 0 {
+        return Ok(ModPattern { rows });
+    }
+    let mut r = Reader::at(data, offset)?;
+    let length = r.u16_le()? as usize;
+    let mut p = Reader::new(r.bytes(length.saturating_sub(2))?);
+    for row in &mut rows {
+        loop {
+            let what = p.u8()?;
+            if what == 0 {
+                break;
+            }
+            let cell = &mut row[(what & 31) as usize];
+            if what & 32 != 0 {
+                cell.note = note(p.u8()?);
+                cell.instrument = p.u8()? as u16;
+            }
+            if what & 64 != 0 {
+                cell.volume = Some(p.u8()?.min(64));
+            }
+            if what & 128 != 0 {
+                let command = p.u8()?;
+                let param = p.u8()?;
+                cell.effects.extend(effect(command, param));
+            }
+        }
+    }
+    Ok(ModPattern { rows })
+}
+
+fn read_sample(data: &[u8], offset: usize, signed: bool) -> Result<(String, ImportedSample), FormatError> {
+    let mut r = Reader::at(data, offset)?;
+    let kind = r.u8()?;
+    r.skip(12)?;
+    let high = r.u8()? as usize;
+    let low = r.u16_le()? as usize;
+    let length = r.u32_le()? as usize;
+    let loop_start = r.u32_le()?;
+    let loop_end = r.u32_le()?;
+    let volume = r.u8()?.min(64);
+    r.skip(2)?;
+    let flags = r.u8()?;
+    let c2spd = r.u32_le()?;
+    r.skip(12)?;
+    let name = r.text(28)?;
+    let mut sample = ImportedSample::empty(name.clone());
+    if kind != 1 {
+        return Ok((name, sample));
+    }
+
+    let sixteen_bit = flags & 4 != 0;
+    let bytes = length * if sixteen_bit { 2 } else { 1 };
+    let mut d = Reader::at(data, ((high << 16) | low) * 16)?;
+    let bytes = d.bytes(bytes)?;
+    sample.data = match (sixteen_bit, signed) {
+        (false, true) => pcm_i8(bytes),
+        (false, false) => pcm_u8(bytes),
+        (true, true) => pcm_i16(bytes),
+        (true, false) => pcm_u16(bytes),
+    };
+    sample.sample_rate = sample_rate_or_default(c2spd);
+    sample.volume = volume as f32 / 64.0;
+    sample.loop_range = (flags & 1 != 0 && loop_end > loop_start).then_some((loop_start, loop_end));
+    Ok((name, sample))
+}
+
+pub fn import_s3m(data: &[u8], ticks_per_beat: u32) -> Result<ModuleImport, FormatError> {
+    if data.get(0x2C..0x30) != Some(b"SCRM") {
+        return Err(FormatError::InvalidHeader("missing SCRM signature".into()));
+    }
+    let mut r = Reader::new(data);
+    let name = r.text(28)?;
+    r.seek(0x20)?;
+    let order_count = r.u16_le()? as usize;
+    let instrument_count = r.u16_le()? as usize;
+    let pattern_count = r.u16_le()? as usize;
+    if pattern_count > MAX_PATTERNS {
+        return Err(FormatError::InvalidHeader(format!("{} patterns", pattern_count)));
+    }
+    r.seek(0x2A)?;
+    let signed = r.u16_le()? == 1;
+    r.seek(0x31)?;
+    let speed = r.u8()?;
+    let tempo = r.u8()?;
+    r.seek(0x60)?;
+    let orders = r.bytes(order_count)?.iter().map(|&b| order(b)).collect();
+    let mut instrument_offsets = Vec::with_capacity(instrument_count);
+    for _ in 0..instrument_count {
+        instrument_offsets.push(r.u16_le()? as usize * 16);
+    }
+    let mut pattern_offsets = Vec::with_capacity(pattern_count);
+    for _ in 0..pattern_count {
+        pattern_offsets.push(r.u16_le()? as usize * 16);
+    }
+
+    let patterns = pattern_offsets
+        .into_iter()
+        .map(|offset| read_pattern(data, offset))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut module = Module { name, speed, tempo, orders, patterns, ..Default::default() };
+    for (i, offset) in instrument_offsets.into_iter().enumerate() {
+        let (name, sample) = read_sample(data, offset, signed)?;
+        module.instruments.push(instrument(i + 1, name, sample));
+    }
+    Ok(build(module, ticks_per_beat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One instrument (unsigned 8-bit) and one pattern, laid out on
+    /// 16-byte paragraphs: header 0x00, pointers 0x60, instrument 0x70,
+    /// sample data 0xC0, pattern 0xD0
+    fn fixture() -> Vec<u8> {
+        let mut d = vec![0u8; 0xD0];
+        d[..10].copy_from_slice(b"s3m test\0\0");
+        d[0x1C] = 0x1A;
+        d[0x1D] = 16;
+        d[0x20..0x26].copy_from_slice(&[2, 0, 1, 0, 1, 0]);
+        d[0x2A] = 2; // unsigned samples
+        d[0x2C..0x30].copy_from_slice(b"SCRM");
+        d[0x31] = 6;
+        d[0x32] = 125;
+        d[0x60..0x62].copy_from_slice(&[0, 255]);
+        d[0x62..0x64].copy_from_slice(&7u16.to_le_bytes()); // instrument at 0x70
+        d[0x64..0x66].copy_from_slice(&13u16.to_le_bytes()); // pattern at 0xD0
+
+        let ins = 0x70;
+        d[ins] = 1;
+        d[ins + 14..ins + 16].copy_from_slice(&12u16.to_le_bytes()); // data at 0xC0
+        d[ins + 16..ins + 20].copy_from_slice(&4u32.to_le_bytes());
+        d[ins + 20..ins + 24].copy_from_slice(&1u32.to_le_bytes());
+        d[ins + 24..ins + 28].copy_from_slice(&3u32.to_le_bytes());
+        d[ins + 28] = 32;
+        d[ins + 31] = 1; // loop
+        d[ins + 32..ins + 36].copy_from_slice(&22050u32.to_le_bytes());
+        d[ins + 48..ins + 53].copy_from_slice(b"snare");
+        d[0xC0..0xC4].copy_from_slice(&[128, 192, 64, 128]);
+
+        let mut rows: Vec<u8> = vec![
+            // row 0: ch 0 C-5 inst 1 vol 64, speed 3 (A03)
+            32 | 64 | 128, 0x50, 1, 64, 1, 3, 0,
+            // row 1: ch 0 note cut, ch 1 tempo 0x96 (T96)
+            32, 254, 0, 128 | 1, 20, 0x96, 0,
+        ];
+        rows.extend(std::iter::repeat(0u8).take(ROWS - 2));
+        d.extend_from_slice(&(rows.len() as u16 + 2).to_le_bytes());
+        d.extend_from_slice(&rows);
+        d
+    }
+
+    #[test]
+    fn imports_s3m_fixture() {
+        let import = import_s3m(&fixture(), 480).unwrap();
+        assert_eq!(import.name, "s3m test");
+        assert_eq!(import.bpm, 250.0);
+        assert_eq!(import.tempo_changes, vec![(120, 300.0)]);
+
+        let snare = &import.instruments[0];
+        assert_eq!((snare.name.as_str(), snare.sample.sample_rate), ("snare", 22050));
+        assert_eq!(snare.sample.data, vec![0.0, 0.5, -0.5, 0.0]);
+        assert_eq!(snare.sample.loop_range, Some((1, 3)));
+        assert_eq!(snare.sample.volume, 0.5);
+
+        let arr = &import.arrangement;
+        assert_eq!(arr.placements().len(), 1);
+        let notes: Vec<(u32, u32, u8, u8)> = arr.clips[0].notes.iter().map(|n| (n.tick, n.duration, n.pitch, n.velocity)).collect();
+        assert_eq!(notes, vec![(0, 120, 72, 127)]);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        // Every pattern gets a full grid, so a huge count must not be trusted
+        let mut data = fixture();
+        data[0x24..0x26].copy_from_slice(&60000u16.to_le_bytes());
+        assert!(matches!(import_s3m(&data, 480), Err(FormatError::InvalidHeader(_))));
+        let data = fixture();
+        assert!(matches!(import_s3m(&data[..0xD8], 480), Err(FormatError::Truncated { .. })));
+    }
+
+    #[test]
+    fn note_bytes() {
+        assert_eq!(note(0x40), Some(ModNote::On(60)));
+        assert_eq!(note(0x3B), Some(ModNote::On(59)));
+        assert_eq!(note(254), Some(ModNote::Off));
+        assert_eq!(note(255), None);
+    }
+}
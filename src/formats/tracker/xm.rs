@@ -0,0 +1,298 @@
+//! FastTracker II extended modules (XM).
+
+use super::protracker::mod_effect;
+use super::{
+    build, instrument, ImportedSample, ModCell, ModNote, ModOrder, ModPattern, Module, ModuleImport,
+    MAX_CHANNELS, MAX_PATTERNS, MAX_ROWS,
+};
+use crate::formats::{FormatError, Reader};
+
+const KEY_OFF: u8 = 97;
+
+/// Note/instrument/volume/effect/param, as stored
+fn cell(note: u8, instrument: u8, volume: u8, command: u8, param: u8) -> ModCell {
+    let note = match note {
+        1..=96 => Some(ModNote::On(note + 11)),
+        KEY_OFF => Some(ModNote::Off),
+        _ => None,
+    };
+    ModCell {
+        note,
+        instrument: instrument as u16,
+        volume: (0x10..=0x50).contains(&volume).then(|| volume - 0x10),
+        effects: mod_effect(command, param).into_iter().collect(),
+    }
+}
+
+fn read_pattern(r: &mut Reader, channels: usize) -> Result<ModPattern, FormatError> {
+    let start = r.pos();
+    let header_length = r.u32_le()? as usize;
+    r.skip(1)?;
+    let rows = r.u16_le()? as usize;
+    if rows > MAX_ROWS {
+        return Err(FormatError::InvalidHeader(format!("pattern at {} has {} rows", start, rows)));
+    }
+    let packed_size = r.u16_le()? as usize;
+    r.seek(start + header_length)?;
+    let data = r.bytes(packed_size)?;
+
+    let mut p = Reader::new(data);
+    let mut pattern = ModPattern { rows: Vec::with_capacity(rows) };
+    for _ in 0..rows {
+        let mut row = Vec::with_capacity(channels);
+        for _ in 0..channels {
+            if packed_size == 0 {
+                row.push(ModCell::default());
+                continue;
+            }
+            let first = p.u8()?;
+            let mut fields = [0u8; 5];
+            if first & 0x80 != 0 {
+                for (bit, field) in fields.iter_mut().enumerate() {
+                    if first & (1 << bit) != 0 {
+                        *field = p.u8()?;
+                    }
+                }
+            } else {
+                fields[0] = first;
+                for field in &mut fields[1..] {
+                    *field = p.u8()?;
+                }
+            }
+            let [note, instrument, volume, command, param] = fields;
+            row.push(cell(note, instrument, volume, command, param));
+        }
+        pattern.rows.push(row);
+    }
+    Ok(pattern)
+}
+
+struct SampleHeader {
+    length: usize,
+    loop_start: usize,
+    loop_length: usize,
+    volume: u8,
+    finetune: i8,
+    kind: u8,
+    relative_note: i8,
+    name: String,
+}
+
+fn decode_deltas(bytes: &[u8], sixteen_bit: bool) -> Vec<f32> {
+    if sixteen_bit {
+        let mut acc = 0i16;
+        bytes
+            .chunks_exact(2)
+            .map(|b| {
+                acc = acc.wrapping_add(i16::from_le_bytes([b[0], b[1]]));
+                acc as f32 / 32768.0
+            })
+            .collect()
+    } else {
+        let mut acc = 0i8;
+        bytes
+            .iter()
+            .map(|&b| {
+                acc = acc.wrapping_add(b as i8);
+                acc as f32 / 128.0
+            })
+            .collect()
+    }
+}
+
+pub fn import_xm(data: &[u8], ticks_per_beat: u32) -> Result<ModuleImport, FormatError> {
+    if !data.starts_with(b"Extended Module: ") {
+        return Err(FormatError::InvalidHeader("missing XM signature".into()));
+    }
+    let mut r = Reader::at(data, 17)?;
+    let name = r.text(20)?;
+    r.seek(60)?;
+    let header_size = r.u32_le()? as usize;
+    let song_length = r.u16_le()? as usize;
+    r.skip(2)?; // restart position
+    let channels = r.u16_le()? as usize;
+    let pattern_count = r.u16_le()? as usize;
+    let instrument_count = r.u16_le()? as usize;
+    if channels > MAX_CHANNELS {
+        return Err(FormatError::InvalidHeader(format!("{} channels", channels)));
+    }
+    if pattern_count > MAX_PATTERNS {
+        return Err(FormatError::InvalidHeader(format!("{} patterns", pattern_count)));
+    }
+    r.skip(2)?; // flags
+    let speed = r.u16_le()?.min(255) as u8;
+    let tempo = r.u16_le()?.min(255) as u8;
+    let table = r.bytes(256)?;
+    let orders = table[..song_length.min(256)].iter().map(|&p| ModOrder::Pattern(p as usize)).collect();
+
+    r.seek(60 + header_size)?;
+    let mut patterns = Vec::with_capacity(pattern_count);
+    for _ in 0..pattern_count {
+        patterns.push(read_pattern(&mut r, channels)?);
+    }
+
+    let mut module = Module { name, speed, tempo, orders, patterns, ..Default::default() };
+    for number in 1..=instrument_count {
+        let start = r.pos();
+        let size = r.u32_le()? as usize;
+        let name = r.text(22)?;
+        r.skip(1)?;
+        let sample_count = r.u16_le()? as usize;
+        if sample_count == 0 {
+            r.seek(start + size)?;
+            module.instruments.push(instrument(number, name.clone(), ImportedSample::empty(name)));
+            continue;
+        }
+        let header_size = r.u32_le()? as usize;
+        let keymap = r.bytes(96)?;
+        // Samples come from the one mapped to C-4
+        let chosen = (keymap[48] as usize).min(sample_count - 1);
+        if sample_count > 1 {
+            module.warnings.push(format!("instrument {} has {} samples; using sample {}", number, sample_count, chosen + 1));
+        }
+        r.seek(start + size)?;
+
+        let mut headers = Vec::with_capacity(sample_count);
+        for _ in 0..sample_count {
+            let header_start = r.pos();
+            headers.push(SampleHeader {
+                length: r.u32_le()? as usize,
+                loop_start: r.u32_le()? as usize,
+                loop_length: r.u32_le()? as usize,
+                volume: r.u8()?.min(64),
+                finetune: r.i8()?,
+                kind: r.u8()?,
+                relative_note: {
+                    r.skip(1)?; // panning
+                    r.i8()?
+                },
+                name: {
+                    r.skip(1)?;
+                    r.text(22)?
+                },
+            });
+            r.seek(header_start + header_size)?;
+        }
+
+        let mut sample = ImportedSample::empty(name.clone());
+        for (i, h) in headers.into_iter().enumerate() {
+            let bytes = r.bytes(h.length)?;
+            if i != chosen {
+                continue;
+            }
+            let sixteen_bit = h.kind & 0x10 != 0;
+            let frame = if sixteen_bit { 2 } else { 1 };
+            let semitones = h.relative_note as f32 + h.finetune as f32 / 128.0;
+            sample = ImportedSample {
+                name: h.name,
+                data: decode_deltas(bytes, sixteen_bit),
+                sample_rate: (8363.0 * 2f32.powf(semitones / 12.0)).round() as u32,
+                root_pitch: 60,
+                loop_range: (h.kind & 0x03 != 0 && h.loop_length > 0)
+                    .then(|| ((h.loop_start / frame) as u32, ((h.loop_start + h.loop_length) / frame) as u32)),
+                volume: h.volume as f32 / 64.0,
+            };
+        }
+        module.instruments.push(instrument(number, name, sample));
+    }
+    Ok(build(module, ticks_per_beat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two channels, one packed pattern, one instrument with a 16-bit sample
+    fn fixture() -> Vec<u8> {
+        let mut d = Vec::new();
+        d.extend_from_slice(b"Extended Module: ");
+        d.extend_from_slice(b"xm fixture\0\0\0\0\0\0\0\0\0\0");
+        d.push(0x1A);
+        d.extend_from_slice(&[b' '; 20]);
+        d.extend_from_slice(&0x0104u16.to_le_bytes());
+        d.extend_from_slice(&276u32.to_le_bytes());
+        for v in [1u16, 0, 2, 1, 1, 1, 3, 140] {
+            d.extend_from_slice(&v.to_le_bytes());
+        }
+        d.extend_from_slice(&[0u8; 256]);
+
+        let packed: Vec<u8> = vec![
+            // row 0: unpacked C-4 inst 1 vol 0x30, then packed D-4 inst 1
+            49, 1, 0x30, 0, 0, 0x80 | 0x01 | 0x02, 51, 1,
+            // row 1: key off on channel 0, tempo 150 on channel 1
+            0x80 | 0x01, KEY_OFF, 0x80 | 0x08 | 0x10, 0x0F, 150,
+            // rows 2-3 empty
+            0x80, 0x80, 0x80, 0x80,
+        ];
+        d.extend_from_slice(&9u32.to_le_bytes());
+        d.push(0);
+        d.extend_from_slice(&4u16.to_le_bytes());
+        d.extend_from_slice(&(packed.len() as u16).to_le_bytes());
+        d.extend_from_slice(&packed);
+
+        let inst_size = 263u32;
+        let inst_start = d.len();
+        d.extend_from_slice(&inst_size.to_le_bytes());
+        d.extend_from_slice(b"piano\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        d.push(0);
+        d.extend_from_slice(&1u16.to_le_bytes());
+        d.extend_from_slice(&40u32.to_le_bytes());
+        d.resize(inst_start + inst_size as usize, 0);
+        // sample header: 4 bytes (two 16-bit frames), forward loop over both
+        d.extend_from_slice(&4u32.to_le_bytes());
+        d.extend_from_slice(&0u32.to_le_bytes());
+        d.extend_from_slice(&4u32.to_le_bytes());
+        d.extend_from_slice(&[64, 0, 0x11, 128, 12, 0]);
+        d.extend_from_slice(b"piano C\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        for delta in [1000i16, -2000] {
+            d.extend_from_slice(&delta.to_le_bytes());
+        }
+        d
+    }
+
+    #[test]
+    fn imports_xm_fixture() {
+        let import = import_xm(&fixture(), 480).unwrap();
+        assert_eq!(import.name, "xm fixture");
+        assert_eq!(import.bpm, 280.0);
+        assert_eq!(import.tempo_changes, vec![(120, 300.0)]);
+
+        let piano = &import.instruments[0];
+        assert_eq!(piano.name, "piano");
+        assert_eq!(piano.sample.name, "piano C");
+        assert_eq!(piano.sample.data, vec![1000.0 / 32768.0, -1000.0 / 32768.0]);
+        assert_eq!(piano.sample.loop_range, Some((0, 2)));
+        assert_eq!(piano.sample.sample_rate, 16726);
+
+        let clip = &import.arrangement.clips[0];
+        assert_eq!(clip.length_ticks(), 480);
+        let mut notes: Vec<(u32, u32, u8, u8)> =
+            clip.notes.iter().map(|n| (n.tick, n.duration, n.pitch, n.velocity)).collect();
+        notes.sort();
+        assert_eq!(notes, vec![(0, 120, 60, 64), (0, 480, 62, 127)]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(matches!(import_xm(b"IMPM", 480), Err(FormatError::InvalidHeader(_))));
+        let data = fixture();
+        assert!(matches!(import_xm(&data[..300], 480), Err(FormatError::Truncated { .. })));
+    }
+
+    #[test]
+    fn rejects_oversized_headers() {
+        let mut data = fixture();
+        data[68..70].copy_from_slice(&1000u16.to_le_bytes());
+        assert!(matches!(import_xm(&data, 480), Err(FormatError::InvalidHeader(_))));
+
+        let mut data = fixture();
+        data[70..72].copy_from_slice(&60000u16.to_le_bytes());
+        assert!(matches!(import_xm(&data, 480), Err(FormatError::InvalidHeader(_))));
+
+        // An empty pattern with 60000 rows would otherwise be laid out cell by cell
+        let mut data = fixture();
+        data[341..343].copy_from_slice(&60000u16.to_le_bytes());
+        data[343..345].copy_from_slice(&0u16.to_le_bytes());
+        assert!(matches!(import_xm(&data, 480), Err(FormatError::InvalidHeader(_))));
+    }
+}
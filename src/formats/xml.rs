@@ -0,0 +1,317 @@
+//! Minimal XML writer and reader for the XML-based formats.
+//!
+//! The reader handles what project files use: elements, attributes, text,
+//! comments, CDATA and the five predefined entities plus numeric references.
+//! No DTDs or namespaces beyond keeping prefixed names as written.
+
+use super::FormatError;
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(semi) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..semi];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(char::from_u32),
+            _ => None,
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Indenting XML writer.
+#[derive(Debug, Default)]
+pub(crate) struct XmlWriter {
+    out: String,
+    stack: Vec<String>,
+}
+
+impl XmlWriter {
+    pub(crate) fn new() -> Self {
+        Self { out: "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string(), stack: Vec::new() }
+    }
+
+    /// Raw line after the declaration (e.g. a DOCTYPE)
+    pub(crate) fn raw(&mut self, line: &str) {
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn start_tag(&mut self, name: &str, attrs: &[(&str, String)]) {
+        self.out.push_str(&"  ".repeat(self.stack.len()));
+        self.out.push('<');
+        self.out.push_str(name);
+        for (key, value) in attrs {
+            self.out.push_str(&format!(" {}=\"{}\"", key, escape(value)));
+        }
+    }
+
+    pub(crate) fn open(&mut self, name: &str, attrs: &[(&str, String)]) {
+        self.start_tag(name, attrs);
+        self.out.push_str(">\n");
+        self.stack.push(name.to_string());
+    }
+
+    pub(crate) fn empty(&mut self, name: &str, attrs: &[(&str, String)]) {
+        self.start_tag(name, attrs);
+        self.out.push_str("/>\n");
+    }
+
+    pub(crate) fn text(&mut self, name: &str, attrs: &[(&str, String)], text: &str) {
+        self.start_tag(name, attrs);
+        self.out.push_str(&format!(">{}</{}>\n", escape(text), name));
+    }
+
+    pub(crate) fn close(&mut self) {
+        if let Some(name) = self.stack.pop() {
+            self.out.push_str(&"  ".repeat(self.stack.len()));
+            self.out.push_str(&format!("</{}>\n", name));
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> String {
+        while !self.stack.is_empty() {
+            self.close();
+        }
+        self.out
+    }
+}
+
+/// A parsed element.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct XmlNode {
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<XmlNode>,
+    pub text: String,
+}
+
+impl XmlNode {
+    pub(crate) fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub(crate) fn attr_f64(&self, key: &str) -> Option<f64> {
+        self.attr(key)?.trim().parse().ok()
+    }
+
+    pub(crate) fn child(&self, name: &str) -> Option<&XmlNode> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub(crate) fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a XmlNode> + 'a {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    /// Descendants named `name`, not looking inside matches
+    pub(crate) fn find_all<'a>(&'a self, name: &str, out: &mut Vec<&'a XmlNode>) {
+        for child in &self.children {
+            if child.name == name {
+                out.push(child);
+            } else {
+                child.find_all(name, out);
+            }
+        }
+    }
+}
+
+/// Deepest element nesting accepted; parsing recurses once per level
+const MAX_DEPTH: usize = 256;
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, msg: &str) -> FormatError {
+        FormatError::InvalidHeader(format!("XML: {} at byte {}", msg, self.pos))
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_ws(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.src.len() - trimmed.len();
+    }
+
+    /// Skip to just past `end`
+    fn skip_past(&mut self, end: &str) -> Result<&'a str, FormatError> {
+        let Some(i) = self.rest().find(end) else {
+            return Err(FormatError::Truncated { offset: self.src.len() });
+        };
+        let skipped = &self.rest()[..i];
+        self.pos += i + end.len();
+        Ok(skipped)
+    }
+
+    fn name(&mut self) -> Result<String, FormatError> {
+        let end = self
+            .rest()
+            .find(|c: char| c.is_whitespace() || matches!(c, '>' | '/' | '='))
+            .unwrap_or(self.rest().len());
+        if end == 0 {
+            return Err(self.error("expected a name"));
+        }
+        let name = self.rest()[..end].to_string();
+        self.pos += end;
+        Ok(name)
+    }
+
+    /// Skip declarations, comments and doctypes between elements
+    fn skip_misc(&mut self) -> Result<(), FormatError> {
+        loop {
+            self.skip_ws();
+            if self.rest().starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest().starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest().starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn element(&mut self, depth: usize) -> Result<XmlNode, FormatError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("elements nested too deeply"));
+        }
+        if !self.rest().starts_with('<') {
+            return Err(self.error("expected '<'"));
+        }
+        self.pos += 1;
+        let mut node = XmlNode { name: self.name()?, ..Default::default() };
+        loop {
+            self.skip_ws();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok(node);
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+            let key = self.name()?;
+            self.skip_ws();
+            if !self.rest().starts_with('=') {
+                return Err(self.error("expected '='"));
+            }
+            self.pos += 1;
+            self.skip_ws();
+            let quote = self.rest().chars().next().filter(|c| *c == '"' || *c == '\'');
+            let Some(quote) = quote else {
+                return Err(self.error("expected a quoted value"));
+            };
+            self.pos += 1;
+            let value = self.skip_past(if quote == '"' { "\"" } else { "'" })?;
+            node.attrs.push((key, unescape(value)));
+        }
+
+        loop {
+            let text_end = self.rest().find('<').ok_or(FormatError::Truncated { offset: self.src.len() })?;
+            node.text.push_str(&unescape(&self.rest()[..text_end]));
+            self.pos += text_end;
+            if self.rest().starts_with("</") {
+                self.pos += 2;
+                let name = self.name()?;
+                if name != node.name {
+                    return Err(self.error(&format!("expected </{}>", node.name)));
+                }
+                self.skip_past(">")?;
+                node.text = node.text.trim().to_string();
+                return Ok(node);
+            } else if self.rest().starts_with("<![CDATA[") {
+                self.pos += 9;
+                let data = self.skip_past("]]>")?;
+                node.text.push_str(data);
+            } else if self.rest().starts_with("<!--") || self.rest().starts_with("<?") {
+                self.skip_misc()?;
+            } else {
+                node.children.push(self.element(depth + 1)?);
+            }
+        }
+    }
+}
+
+/// Parse a document and return its root element
+pub(crate) fn parse(src: &str) -> Result<XmlNode, FormatError> {
+    let mut parser = Parser { src, pos: 0 };
+    parser.skip_misc()?;
+    parser.element(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_and_reads_back() {
+        let mut w = XmlWriter::new();
+        w.open("Project", &[("version", "1.0".into())]);
+        w.empty("Tempo", &[("value", "120".into())]);
+        w.text("Name", &[], "Bass & <Drums>");
+        let xml = w.finish();
+
+        let root = parse(&xml).unwrap();
+        assert_eq!(root.name, "Project");
+        assert_eq!(root.attr("version"), Some("1.0"));
+        assert_eq!(root.child("Tempo").and_then(|t| t.attr_f64("value")), Some(120.0));
+        assert_eq!(root.child("Name").unwrap().text, "Bass & <Drums>");
+    }
+
+    #[test]
+    fn skips_comments_and_reads_cdata() {
+        let root = parse("<?xml version='1.0'?><!-- c --><a x='&#65;&#x42;'><!-- d --><b><![CDATA[<raw>]]></b></a>").unwrap();
+        assert_eq!(root.attr("x"), Some("AB"));
+        assert_eq!(root.child("b").unwrap().text, "<raw>");
+        assert!(parse("<a><b></a>").is_err());
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = |depth: usize| format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        assert!(parse(&nested(MAX_DEPTH + 1)).is_ok());
+        assert!(matches!(parse(&nested(100_000)), Err(FormatError::InvalidHeader(_))));
+    }
+}
@@ -0,0 +1,367 @@
+//! Zip archives for container formats: stored (uncompressed) writing, and
+//! reading of stored or deflated entries.
+
+use super::{FormatError, Reader};
+
+const LOCAL_HEADER: u32 = 0x0403_4B50;
+const CENTRAL_HEADER: u32 = 0x0201_4B50;
+const END_OF_DIRECTORY: u32 = 0x0605_4B50;
+/// 1980-01-01, the earliest zip date
+const DOS_DATE: u16 = 0x0021;
+/// Smallest end of central directory record
+const END_RECORD_LENGTH: usize = 22;
+/// Most output reserved up front; sizes come from the archive and aren't trusted
+const MAX_RESERVE: usize = 1 << 20;
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Archive of uncompressed entries, in order
+pub(crate) fn write_stored(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for (name, data) in entries {
+        let offset = out.len() as u32;
+        let crc = crc32(data);
+        let size = data.len() as u32;
+        let common = |buf: &mut Vec<u8>| {
+            buf.extend(20u16.to_le_bytes()); // version needed
+            buf.extend(0u16.to_le_bytes()); // flags
+            buf.extend(0u16.to_le_bytes()); // stored
+            buf.extend(0u16.to_le_bytes()); // time
+            buf.extend(DOS_DATE.to_le_bytes());
+            buf.extend(crc.to_le_bytes());
+            buf.extend(size.to_le_bytes());
+            buf.extend(size.to_le_bytes());
+            buf.extend((name.len() as u16).to_le_bytes());
+            buf.extend(0u16.to_le_bytes()); // extra length
+        };
+        out.extend(LOCAL_HEADER.to_le_bytes());
+        common(&mut out);
+        out.extend(name.as_bytes());
+        out.extend_from_slice(data);
+
+        central.extend(CENTRAL_HEADER.to_le_bytes());
+        central.extend(20u16.to_le_bytes()); // version made by
+        common(&mut central);
+        central.extend([0u8; 6]); // comment length, disk, internal attributes
+        central.extend(0u32.to_le_bytes()); // external attributes
+        central.extend(offset.to_le_bytes());
+        central.extend(name.as_bytes());
+    }
+    let directory_offset = out.len() as u32;
+    out.extend_from_slice(&central);
+    out.extend(END_OF_DIRECTORY.to_le_bytes());
+    out.extend([0u8; 4]); // disk numbers
+    out.extend((entries.len() as u16).to_le_bytes());
+    out.extend((entries.len() as u16).to_le_bytes());
+    out.extend((central.len() as u32).to_le_bytes());
+    out.extend(directory_offset.to_le_bytes());
+    out.extend(0u16.to_le_bytes());
+    out
+}
+
+/// All file entries as (name, contents)
+pub(crate) fn read(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, FormatError> {
+    if data.len() < END_RECORD_LENGTH {
+        return Err(FormatError::InvalidHeader("not a zip archive".into()));
+    }
+    // The end record may be followed by a comment
+    let search_from = data.len().saturating_sub(END_RECORD_LENGTH + u16::MAX as usize);
+    let end = (search_from..=data.len() - END_RECORD_LENGTH)
+        .rev()
+        .find(|&i| data[i..i + 4] == END_OF_DIRECTORY.to_le_bytes())
+        .ok_or_else(|| FormatError::InvalidHeader("not a zip archive".into()))?;
+    let mut r = Reader::at(data, end + 10)?;
+    let count = r.u16_le()? as usize;
+    r.skip(4)?;
+    let directory = r.u32_le()? as usize;
+
+    let mut entries = Vec::with_capacity(count);
+    let mut r = Reader::at(data, directory)?;
+    for _ in 0..count {
+        if r.u32_le()? != CENTRAL_HEADER {
+            return Err(FormatError::InvalidHeader("bad zip directory entry".into()));
+        }
+        r.skip(4)?;
+        let flags = r.u16_le()?;
+        let method = r.u16_le()?;
+        r.skip(4)?;
+        let crc = r.u32_le()?;
+        let compressed = r.u32_le()? as usize;
+        let size = r.u32_le()? as usize;
+        let name_length = r.u16_le()? as usize;
+        let extra_length = r.u16_le()? as usize;
+        let comment_length = r.u16_le()? as usize;
+        r.skip(8)?;
+        let offset = r.u32_le()? as usize;
+        let name = String::from_utf8_lossy(r.bytes(name_length)?).to_string();
+        r.skip(extra_length + comment_length)?;
+        if name.ends_with('/') {
+            continue;
+        }
+        if flags & 1 != 0 {
+            return Err(FormatError::Unsupported(format!("encrypted zip entry {}", name)));
+        }
+
+        let mut local = Reader::at(data, offset)?;
+        if local.u32_le()? != LOCAL_HEADER {
+            return Err(FormatError::InvalidHeader(format!("bad local header for {}", name)));
+        }
+        local.skip(22)?;
+        let local_name = local.u16_le()? as usize;
+        let local_extra = local.u16_le()? as usize;
+        local.skip(local_name + local_extra)?;
+        let raw = local.bytes(compressed)?;
+        let contents = match method {
+            0 => raw.to_vec(),
+            8 => inflate(raw, size)?,
+            m => return Err(FormatError::Unsupported(format!("zip compression method {} for {}", m, name))),
+        };
+        if crc32(&contents) != crc {
+            return Err(FormatError::InvalidHeader(format!("checksum mismatch in {}", name)));
+        }
+        entries.push((name, contents));
+    }
+    Ok(entries)
+}
+
+// ---------------------------------------------------------------------------
+// DEFLATE decoding (RFC 1951)
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl Bits<'_> {
+    fn bit(&mut self) -> Result<u32, FormatError> {
+        let byte = *self.data.get(self.pos).ok_or(FormatError::Truncated { offset: self.pos })?;
+        let b = (byte >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        Ok(b as u32)
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, FormatError> {
+        let mut value = 0;
+        for i in 0..n {
+            value |= self.bit()? << i;
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// Canonical Huffman decoding table
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &l in lengths {
+            counts[l as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for i in 1..16 {
+            offsets[i] = offsets[i - 1] + counts[i - 1];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &l) in lengths.iter().enumerate() {
+            if l > 0 {
+                symbols[offsets[l as usize] as usize] = symbol as u16;
+                offsets[l as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, FormatError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.bit()? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(FormatError::InvalidHeader("bad deflate code".into()))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Decode a raw deflate stream that should produce `size` bytes
+fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>, FormatError> {
+    let too_long = || FormatError::InvalidHeader(format!("deflate output exceeds {} bytes", size));
+    let mut bits = Bits { data, pos: 0, bit: 0 };
+    let mut out = Vec::with_capacity(size.min(MAX_RESERVE));
+    loop {
+        let last = bits.bit()?;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let mut r = Reader::at(data, bits.pos)?;
+                let len = r.u16_le()? as usize;
+                r.skip(2)?;
+                if out.len() + len > size {
+                    return Err(too_long());
+                }
+                out.extend_from_slice(r.bytes(len)?);
+                bits.pos = r.pos();
+            }
+            kind @ (1 | 2) => {
+                let (lit, dist) = if kind == 1 {
+                    let mut lengths = [8u8; 288];
+                    lengths[144..256].fill(9);
+                    lengths[256..280].fill(7);
+                    (Huffman::new(&lengths), Huffman::new(&[5u8; 30]))
+                } else {
+                    dynamic_tables(&mut bits)?
+                };
+                loop {
+                    let symbol = lit.decode(&mut bits)? as usize;
+                    match symbol {
+                        0..=255 if out.len() == size => return Err(too_long()),
+                        0..=255 => out.push(symbol as u8),
+                        256 => break,
+                        _ => {
+                            let i = symbol - 257;
+                            if i >= 29 {
+                                return Err(FormatError::InvalidHeader("bad deflate length".into()));
+                            }
+                            let length = LENGTH_BASE[i] as usize + bits.bits(LENGTH_EXTRA[i] as u32)? as usize;
+                            let d = dist.decode(&mut bits)? as usize;
+                            if d >= 30 {
+                                return Err(FormatError::InvalidHeader("bad deflate distance".into()));
+                            }
+                            let distance = DIST_BASE[d] as usize + bits.bits(DIST_EXTRA[d] as u32)? as usize;
+                            if distance > out.len() {
+                                return Err(FormatError::InvalidHeader("deflate distance too far back".into()));
+                            }
+                            if out.len() + length > size {
+                                return Err(too_long());
+                            }
+                            let start = out.len() - distance;
+                            for k in 0..length {
+                                out.push(out[start + k]);
+                            }
+                        }
+                    }
+                }
+            }
+            _ => return Err(FormatError::InvalidHeader("bad deflate block type".into())),
+        }
+        if last == 1 {
+            return Ok(out);
+        }
+    }
+}
+
+fn dynamic_tables(bits: &mut Bits) -> Result<(Huffman, Huffman), FormatError> {
+    const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+    let mut lengths = [0u8; 19];
+    for &i in ORDER.iter().take(code_lengths) {
+        lengths[i] = bits.bits(3)? as u8;
+    }
+    let code = Huffman::new(&lengths);
+    let mut all = Vec::with_capacity(literals + distances);
+    while all.len() < literals + distances {
+        let symbol = code.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let prev = *all.last().ok_or_else(|| FormatError::InvalidHeader("bad deflate repeat".into()))?;
+                (prev, 3 + bits.bits(2)? as usize)
+            }
+            17 => (0, 3 + bits.bits(3)? as usize),
+            _ => (0, 11 + bits.bits(7)? as usize),
+        };
+        all.extend(std::iter::repeat(value).take(repeat));
+    }
+    all.truncate(literals + distances);
+    Ok((Huffman::new(&all[..literals]), Huffman::new(&all[literals..])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_round_trip() {
+        let zip = write_stored(&[("a.txt", b"hello"), ("dir/b.xml", b"<x/>")]);
+        let entries = read(&zip).unwrap();
+        assert_eq!(entries, vec![("a.txt".to_string(), b"hello".to_vec()), ("dir/b.xml".to_string(), b"<x/>".to_vec())]);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn inflates_fixed_dynamic_and_stored_blocks() {
+        // Raw deflate of "hello hello hello hello"
+        let fixed = [0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x27, 0x01];
+        assert_eq!(inflate(&fixed, 23).unwrap(), b"hello hello hello hello");
+
+        // 200 bytes drawn from "abcd", skewed enough for a dynamic block
+        let dynamic = [
+            0x2D, 0x8E, 0xD1, 0x15, 0x00, 0x20, 0x08, 0x02, 0x67, 0xF5, 0x60, 0xFF, 0x19, 0x02, 0xAD, 0x0F, 0xE4,
+            0x01, 0xA1, 0x83, 0xA4, 0xC9, 0x0B, 0x30, 0xCB, 0x5C, 0x1A, 0x91, 0x8E, 0xA0, 0x2B, 0xD2, 0x59, 0xE5,
+            0x52, 0xCC, 0x8F, 0xC7, 0x94, 0x71, 0x1D, 0x56, 0x37, 0x90, 0xA4, 0xBC, 0x11, 0x8F, 0x68, 0xCB, 0x56,
+            0xED, 0x7F, 0x23, 0xB8, 0x42, 0x7D, 0x67, 0xFB, 0xA3, 0x72, 0x0B, 0xAA, 0x5B, 0x4B, 0x8B, 0x29, 0xAB,
+            0x35, 0xB9, 0xD3, 0xDD, 0x81, 0xB8, 0x7B, 0x2F, 0xAF, 0xBB, 0xEE, 0x01,
+        ];
+        let out = inflate(&dynamic, 200).unwrap();
+        assert_eq!((out.len(), crc32(&out)), (200, 0xEF13_FBC9));
+        assert!(out.starts_with(b"abcccaaaac"));
+
+        let stored = [0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'];
+        assert_eq!(inflate(&stored, 3).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn rejects_malformed_archives() {
+        for short in [&b""[..], b"PK", b"PK\x05\x06"] {
+            assert!(matches!(read(short), Err(FormatError::InvalidHeader(_))));
+        }
+        // Output beyond the declared size is refused, whatever the stream says
+        let fixed = [0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x27, 0x01];
+        assert!(matches!(inflate(&fixed, 10), Err(FormatError::InvalidHeader(_))));
+        let stored = [0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'];
+        assert!(matches!(inflate(&stored, 2), Err(FormatError::InvalidHeader(_))));
+    }
+}
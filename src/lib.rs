@@ -9,15 +9,24 @@
 //! See plans/imbolc-net.md for the full extraction plan.
 
 mod param;
+mod rng;
 pub mod state;
 pub mod action;
 mod audio;
 pub mod dispatch;
+pub mod scheduler;
+pub mod transport;
+pub mod formats;
 
 pub use audio::{AudioFeedback, ExportKind, ServerStatus};
 pub use param::{Param, ParamValue, adjust_freq_semitone, adjust_musical_step, is_freq_param};
+pub use rng::SeededRng;
 pub use action::*;
 pub use dispatch::Dispatcher;
+pub use scheduler::{
+    DrumStepPosition, ScheduledEvent, ScheduledEventKind, Scheduler, SchedulerCache, SongPosition,
+};
+pub use transport::Transport;
 
 // Re-export all state types at crate root for convenience
 pub use state::*;
@@ -0,0 +1,104 @@
+//! Small deterministic random number generator.
+//!
+//! Everything in this crate that needs randomness (probability, generators,
+//! humanization helpers) goes through `SeededRng` so that the same seed always
+//! produces the same result on every machine and in every export.
+
+/// SplitMix64-based generator. Cheap, seedable and stable across platforms.
+#[derive(Debug, Clone)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Build a generator from several values (seed, iteration, position, ...).
+    /// The same parts always yield the same stream.
+    pub fn from_parts(parts: &[u64]) -> Self {
+        let mut h: u64 = 0x9E37_79B9_7F4A_7C15;
+        for &part in parts {
+            h = mix64(h ^ part);
+        }
+        Self::new(h)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        mix64(self.state)
+    }
+
+    /// Uniform value in [0.0, 1.0)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform integer in [0, n). Returns 0 when n is 0.
+    pub fn below(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        (((self.next_u64() >> 32) * n as u64) >> 32) as u32
+    }
+
+    /// Uniform integer in [lo, hi] (inclusive). Bounds may be given in either order.
+    pub fn range_i32(&mut self, lo: i32, hi: i32) -> i32 {
+        let (lo, hi) = if lo <= hi { (lo, hi) } else { (hi, lo) };
+        let span = (hi as i64 - lo as i64 + 1) as u64;
+        lo + ((self.next_u64() % span) as i64) as i32
+    }
+
+    /// Returns true with the given probability (0.0-1.0)
+    pub fn chance(&mut self, probability: f32) -> bool {
+        if probability >= 1.0 {
+            return true;
+        }
+        if probability <= 0.0 {
+            return false;
+        }
+        self.next_f32() < probability
+    }
+}
+
+fn mix64(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_stream() {
+        let mut a = SeededRng::new(42);
+        let mut b = SeededRng::new(42);
+        for _ in 0..16 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn from_parts_depends_on_every_part() {
+        let a = SeededRng::from_parts(&[1, 2, 3]).next_u64();
+        let b = SeededRng::from_parts(&[1, 2, 4]).next_u64();
+        let c = SeededRng::from_parts(&[1, 2, 3]).next_u64();
+        assert_ne!(a, b);
+        assert_eq!(a, c);
+    }
+
+    #[test]
+    fn values_stay_in_range() {
+        let mut rng = SeededRng::new(7);
+        for _ in 0..1000 {
+            let f = rng.next_f32();
+            assert!((0.0..1.0).contains(&f));
+            assert!(rng.below(5) < 5);
+            let r = rng.range_i32(-3, 3);
+            assert!((-3..=3).contains(&r));
+        }
+    }
+}
@@ -0,0 +1,1191 @@
+//! Sequencer event scheduler.
+//!
+//! Turns the project (piano roll tracks in Pattern mode, arrangement clips in
+//! Song mode, plus any attached drum machines) into a time-ordered stream of
+//! note, drum and automation events for any
+//! window of playback. Loop wrapping, swing, mono voice handling and seeded
+//! probability are all applied here, so playback can be derived (and tested)
+//! without an audio server.
+//!
+//! Time is measured in *stream ticks*: ticks elapsed since playback started.
+//! Stream ticks only ever increase; the song position they map to wraps when
+//! the loop is enabled.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::OnceLock;
+
+use crate::state::arrangement::PlayMode;
+use crate::state::automation::{AutomationLane, AutomationTarget};
+use crate::state::drum_sequencer::{
+    DrumSequencerState, PadMode, PadPosition, ParamLocks, StepResolution, MAX_STEPS,
+};
+use crate::state::piano_roll::{Note, NoteId};
+use crate::state::probability::{HitKey, HitResult};
+use crate::state::session::SessionState;
+use crate::state::trig::TrigContext;
+use crate::state::voice::{resolve_mono_legato, resolve_mono_overlaps, MonoOverlapMode};
+use crate::InstrumentId;
+
+/// Beats after which a one-shot drum voice is assumed to have finished, for
+/// polyphony limits
+pub const VOICE_HORIZON_BEATS: u32 = 4;
+
+/// What happens at a scheduled point in time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScheduledEventKind {
+    NoteOff {
+        instrument_id: InstrumentId,
+        note_id: NoteId,
+        pitch: u8,
+    },
+    Automation {
+        target: AutomationTarget,
+        value: f32,
+    },
+    NoteOn {
+        instrument_id: InstrumentId,
+        note_id: NoteId,
+        pitch: u8,
+        velocity: u8,
+        /// Length in ticks (the matching NoteOff is also scheduled)
+        duration: u32,
+        /// Mono legato: glide from the note sounding before this one
+        /// instead of retriggering the envelope
+        legato: bool,
+    },
+    DrumHit {
+        instrument_id: InstrumentId,
+        pad: usize,
+        step: usize,
+        velocity: u8,
+        pitch_offset: i8,
+        /// Length in ticks (gate plus tied steps)
+        duration: u32,
+        /// Parameter values that apply to this hit only
+        locks: ParamLocks,
+    },
+    /// Silence a drum pad's voice: the one started by the hit at stream tick
+    /// `hit`, or all of them (a choke) when None
+    DrumStop {
+        instrument_id: InstrumentId,
+        pad: usize,
+        hit: Option<u64>,
+    },
+}
+
+impl ScheduledEventKind {
+    /// Ordering of simultaneous events: releases first, then parameter changes, then attacks
+    fn order(&self) -> u8 {
+        match self {
+            ScheduledEventKind::NoteOff { .. } | ScheduledEventKind::DrumStop { .. } => 0,
+            ScheduledEventKind::Automation { .. } => 1,
+            ScheduledEventKind::NoteOn { .. } | ScheduledEventKind::DrumHit { .. } => 2,
+        }
+    }
+}
+
+/// An event with its position in stream time and song time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledEvent {
+    /// Ticks since playback started (monotonic)
+    pub stream_tick: u64,
+    /// Song position the event belongs to
+    pub song_tick: u32,
+    /// Loop iteration the event belongs to
+    pub iteration: u32,
+    pub kind: ScheduledEventKind,
+}
+
+/// Where an attached drum sequencer is, from `stream_tick` until its next entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrumStepPosition {
+    pub stream_tick: u64,
+    pub instrument_id: InstrumentId,
+    /// Pattern step, in sixteenths
+    pub step: usize,
+    /// Step each pad's row is on (see [`DrumSequencerState::pad_position`])
+    pub pad_steps: Vec<usize>,
+}
+
+/// A point in song time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SongPosition {
+    pub tick: u32,
+    pub iteration: u32,
+}
+
+/// One uninterrupted stretch of song time: song [song_start, song_end) plays
+/// at stream [stream_start, stream_start + len).
+#[derive(Debug, Clone, Copy)]
+struct Pass {
+    stream_start: u64,
+    song_start: u32,
+    song_end: u32,
+    iteration: u32,
+}
+
+/// Pure scheduler over a borrowed session.
+#[derive(Debug, Clone)]
+pub struct Scheduler<'a> {
+    session: &'a SessionState,
+    start_tick: u32,
+    start_iteration: u32,
+    /// Spacing of automation evaluation in ticks
+    pub automation_resolution: u32,
+    /// Drum machines played alongside the session
+    drums: Vec<(InstrumentId, &'a DrumSequencerState)>,
+    /// Derived from the session on first use and kept for the scheduler's lifetime
+    cache: SchedulerCache,
+}
+
+/// Session data the scheduler derives once rather than on every window. It
+/// can be handed to the next scheduler over the same session (see
+/// [`Scheduler::with_cache`]) and must be dropped once the session is edited.
+#[derive(Debug, Clone, Default)]
+pub struct SchedulerCache {
+    /// Pattern mode: notes of mono or auditioning tracks as they will sound
+    playable: OnceLock<HashMap<InstrumentId, Vec<(Note, bool)>>>,
+    automation_lanes: OnceLock<Vec<AutomationLane>>,
+}
+
+impl<'a> Scheduler<'a> {
+    /// Scheduler starting at the piano roll's current playhead and loop iteration
+    pub fn new(session: &'a SessionState) -> Self {
+        let pr = &session.piano_roll;
+        Self {
+            session,
+            start_tick: pr.playhead,
+            start_iteration: pr.loop_iteration,
+            automation_resolution: (pr.ticks_per_beat / 16).max(1),
+            drums: Vec::new(),
+            cache: SchedulerCache::default(),
+        }
+    }
+
+    /// Also play a drum machine instrument's sequencer
+    pub fn with_drums(mut self, instrument_id: InstrumentId, sequencer: &'a DrumSequencerState) -> Self {
+        self.drums.push((instrument_id, sequencer));
+        self
+    }
+
+    /// Reuse what an earlier scheduler derived from this (unchanged) session
+    pub fn with_cache(mut self, cache: SchedulerCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// What this scheduler derived from the session, for the next one
+    pub fn into_cache(self) -> SchedulerCache {
+        self.cache
+    }
+
+    /// Start playback from a different song position
+    pub fn starting_at(mut self, tick: u32, iteration: u32) -> Self {
+        self.start_tick = tick;
+        self.start_iteration = iteration;
+        self
+    }
+
+    fn loop_range(&self) -> Option<(u32, u32)> {
+        let pr = &self.session.piano_roll;
+        (pr.looping && pr.loop_end > pr.loop_start).then_some((pr.loop_start, pr.loop_end))
+    }
+
+    /// Song position at which playback effectively starts
+    fn first_tick(&self) -> u32 {
+        match self.loop_range() {
+            Some((start, end)) if self.start_tick >= end => start,
+            _ => self.start_tick,
+        }
+    }
+
+    /// Map a stream tick to song time
+    pub fn position(&self, stream_tick: u64) -> SongPosition {
+        let first = self.first_tick() as u64;
+        match self.loop_range() {
+            Some((start, end)) if first + stream_tick >= end as u64 => {
+                let len = (end - start) as u64;
+                let over = first + stream_tick - end as u64;
+                SongPosition {
+                    tick: start + (over % len) as u32,
+                    iteration: self.start_iteration + 1 + (over / len) as u32,
+                }
+            }
+            _ => SongPosition {
+                tick: (first + stream_tick).min(u32::MAX as u64) as u32,
+                iteration: self.start_iteration,
+            },
+        }
+    }
+
+    /// Song passes overlapping stream range [from, to)
+    fn passes(&self, from: u64, to: u64) -> Vec<Pass> {
+        let first = self.first_tick();
+        let Some((loop_start, loop_end)) = self.loop_range() else {
+            return vec![Pass {
+                stream_start: 0,
+                song_start: first,
+                song_end: u32::MAX,
+                iteration: self.start_iteration,
+            }];
+        };
+        let mut passes = Vec::new();
+        let first_len = (loop_end - first) as u64;
+        if from < first_len {
+            passes.push(Pass {
+                stream_start: 0,
+                song_start: first,
+                song_end: loop_end,
+                iteration: self.start_iteration,
+            });
+        }
+        let len = (loop_end - loop_start) as u64;
+        let mut k = from.saturating_sub(first_len) / len;
+        loop {
+            let stream_start = first_len + k * len;
+            if stream_start >= to {
+                break;
+            }
+            passes.push(Pass {
+                stream_start,
+                song_start: loop_start,
+                song_end: loop_end,
+                iteration: self.start_iteration + 1 + k as u32,
+            });
+            k += 1;
+        }
+        passes
+    }
+
+    /// Instruments that can play in the current mode, in a stable order
+    fn instruments(&self) -> Vec<InstrumentId> {
+        match self.session.arrangement.play_mode {
+            PlayMode::Pattern => self.session.piano_roll.track_order.clone(),
+            PlayMode::Song => {
+                let mut ids: Vec<InstrumentId> = self
+                    .session
+                    .arrangement
+                    .placements()
+                    .iter()
+                    .map(|p| p.instrument_id)
+                    .collect();
+                ids.sort_unstable();
+                ids.dedup();
+                ids
+            }
+        }
+    }
+
+    /// Notes starting in song range [start, end) for one instrument, mono-resolved,
+    /// with whether each one glides in (mono legato) instead of retriggering
+    fn notes_starting(&self, instrument_id: InstrumentId, start: u32, end: u32) -> Vec<(Note, bool)> {
+        let track = self.session.piano_roll.tracks.get(&instrument_id);
+        match self.session.arrangement.play_mode {
+            PlayMode::Pattern => match track {
+                Some(track) if !track.polyphonic || track.takes.auditioning.is_some() => self
+                    .playable_notes()
+                    .get(&instrument_id)
+                    .into_iter()
+                    .flatten()
+                    .filter(|(n, _)| n.tick >= start && n.tick < end)
+                    .cloned()
+                    .collect(),
+                Some(track) => {
+                    track.notes.starting_in(start, end).iter().map(|n| (n.clone(), false)).collect()
+                }
+                None => Vec::new(),
+            },
+            PlayMode::Song => {
+                let notes = self.session.arrangement.notes_in_window(instrument_id, start, end);
+                match track {
+                    Some(track) if !track.polyphonic => match track.voice.mono_mode() {
+                        MonoOverlapMode::Legato => resolve_mono_legato(notes),
+                        mode => resolve_mono_overlaps(notes, mode).into_iter().map(|n| (n, false)).collect(),
+                    },
+                    _ => notes.into_iter().map(|n| (n, false)).collect(),
+                }
+            }
+        }
+    }
+
+    /// Pattern mode notes of every mono or auditioning track, resolved once
+    fn playable_notes(&self) -> &HashMap<InstrumentId, Vec<(Note, bool)>> {
+        self.cache.playable.get_or_init(|| {
+            self.session
+                .piano_roll
+                .tracks
+                .iter()
+                .filter(|(_, t)| !t.polyphonic || t.takes.auditioning.is_some())
+                .map(|(&id, t)| (id, t.playable_notes_legato()))
+                .collect()
+        })
+    }
+
+    /// Notes starting in song range [start, end) with whether each plays on
+    /// this pass: trig condition first, then probability. Conditions on the
+    /// previous hit need every earlier note of the pass, so those are walked too.
+    fn resolved_notes(
+        &self,
+        instrument_id: InstrumentId,
+        pass: &Pass,
+        start: u32,
+        end: u32,
+    ) -> Vec<(Note, bool, HitResult)> {
+        let notes = self.notes_starting(instrument_id, start, end);
+        let earlier = if notes.iter().any(|(n, _)| n.condition.uses_previous()) {
+            self.notes_starting(instrument_id, pass.song_start, start)
+        } else {
+            Vec::new()
+        };
+        let probability = &self.session.probability;
+        let fill = self.session.piano_roll.fill;
+        let song_mode = self.session.arrangement.play_mode == PlayMode::Song;
+        let mut previous = false;
+        let mut resolved = Vec::new();
+        for (i, (note, legato)) in earlier.iter().chain(&notes).enumerate() {
+            let ctx = TrigContext { iteration: pass.iteration, fill, previous };
+            let placement = song_mode
+                .then(|| self.session.arrangement.placement_at(instrument_id, note.tick).map(|p| p.id))
+                .flatten();
+            let key = HitKey::Note { instrument_id, note_id: note.id, placement };
+            let mut result = probability.result(key, note.probability, pass.iteration);
+            result.fired &= note.condition.evaluate(&ctx);
+            previous = result.fired;
+            if i >= earlier.len() {
+                resolved.push((note.clone(), *legato, result));
+            }
+        }
+        resolved
+    }
+
+    /// Swing delay for a note starting at `tick`: off-beat eighths are pushed
+    /// late, up to a triplet feel at swing 1.0
+    fn swing_delay(&self, tick: u32) -> u32 {
+        let pr = &self.session.piano_roll;
+        let eighth = pr.ticks_per_beat / 2;
+        if pr.swing_amount <= 0.0
+            || eighth == 0
+            || tick % eighth != 0
+            || (tick / eighth) % 2 == 0
+        {
+            return 0;
+        }
+        (pr.swing_amount.min(1.0) * pr.ticks_per_beat as f32 / 6.0).round() as u32
+    }
+
+    /// Earliest start among one instrument's notes that began before `tick`
+    /// and still sound at it (or `tick` itself), so a window reaches back just
+    /// far enough to release them
+    fn sounding_since(&self, instrument_id: InstrumentId, tick: u32) -> u32 {
+        let track = self.session.piano_roll.tracks.get(&instrument_id);
+        let earliest = match self.session.arrangement.play_mode {
+            PlayMode::Pattern => match track {
+                Some(track) if !track.polyphonic || track.takes.auditioning.is_some() => self
+                    .playable_notes()
+                    .get(&instrument_id)
+                    .into_iter()
+                    .flatten()
+                    .filter(|(n, _)| n.tick < tick && n.end_tick() > tick)
+                    .map(|(n, _)| n.tick)
+                    .min(),
+                Some(track) => track.notes.sounding_at(tick).map(|n| n.tick).filter(|&t| t < tick).min(),
+                None => None,
+            },
+            PlayMode::Song => {
+                self.session.arrangement.notes_sounding_at(instrument_id, tick).first().map(|n| n.tick)
+            }
+        };
+        earliest.unwrap_or(tick)
+    }
+
+    fn note_events(
+        &self,
+        from: u64,
+        to: u64,
+        out: &mut Vec<ScheduledEvent>,
+        results: &mut Vec<HitResult>,
+    ) {
+        // Notes starting up to a beat before the window can be swung into it;
+        // notes still sounding then can release inside it
+        let lookback = self.session.piano_roll.ticks_per_beat as u64;
+        for pass in self.passes(from.saturating_sub(lookback), to) {
+            let song_at = |stream: u64| {
+                let offset = stream.saturating_sub(pass.stream_start);
+                (pass.song_start as u64 + offset).min(pass.song_end as u64) as u32
+            };
+            let lo = song_at(from.saturating_sub(lookback).max(pass.stream_start));
+            let hi = song_at(to);
+            for instrument_id in self.instruments() {
+                let lo = self.sounding_since(instrument_id, lo).max(pass.song_start);
+                for (note, legato, result) in self.resolved_notes(instrument_id, &pass, lo, hi.max(lo)) {
+                    let on_song = note.tick;
+                    let off_song = note.end_tick().min(pass.song_end);
+                    // Swing moves the whole note, so its length is kept
+                    let swing = self.swing_delay(on_song) as u64;
+                    let on = pass.stream_start + (on_song - pass.song_start) as u64 + swing;
+                    if on >= from && on < to {
+                        results.push(result);
+                    }
+                    if !result.fired {
+                        continue;
+                    }
+                    let off = (pass.stream_start + (off_song - pass.song_start) as u64 + swing).max(on + 1);
+                    if on >= from && on < to {
+                        out.push(ScheduledEvent {
+                            stream_tick: on,
+                            song_tick: on_song,
+                            iteration: pass.iteration,
+                            kind: ScheduledEventKind::NoteOn {
+                                instrument_id,
+                                note_id: note.id,
+                                pitch: note.pitch,
+                                velocity: note.velocity,
+                                duration: (off - on) as u32,
+                                legato,
+                            },
+                        });
+                    }
+                    if off >= from && off < to {
+                        out.push(ScheduledEvent {
+                            stream_tick: off,
+                            song_tick: off_song,
+                            iteration: pass.iteration,
+                            kind: ScheduledEventKind::NoteOff {
+                                instrument_id,
+                                note_id: note.id,
+                                pitch: note.pitch,
+                            },
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drum hits for every attached sequencer. Each pad's row runs on its
+    /// own grid (see [`DrumSequencerState::pad_position`]); the pass counter
+    /// for trig conditions and probability is the number of times the row has
+    /// repeated since playback started, continuing across loop repeats. Each
+    /// step is swung, nudged and ratcheted, and lasts its gate plus any tied
+    /// steps. The outcome of every active step is kept with its stream tick.
+    fn drum_hits(
+        &self,
+        from: u64,
+        to: u64,
+        out: &mut Vec<ScheduledEvent>,
+        results: &mut Vec<(u64, HitResult)>,
+    ) {
+        if self.drums.is_empty() {
+            return;
+        }
+        let tpb = self.session.piano_roll.ticks_per_beat;
+        let probability = &self.session.probability;
+        // Swing, a late nudge and ratchets keep hits within two steps of
+        // their grid position; an early nudge moves them back half a step
+        let longest_step = StepResolution::ALL.iter().map(|r| r.ticks(tpb)).max().unwrap_or(1) as u64;
+        let lookback = 2 * longest_step;
+        let lookahead = longest_step;
+        for pass in self.passes(from.saturating_sub(lookback), to + lookahead) {
+            let song_at = |stream: u64| {
+                let offset = stream.saturating_sub(pass.stream_start);
+                (pass.song_start as u64 + offset).min(pass.song_end as u64) as u32
+            };
+            let lo = song_at(from.saturating_sub(lookback).max(pass.stream_start));
+            let hi = song_at(to + lookahead);
+            for &(instrument_id, seq) in &self.drums {
+                let mut fired: HashMap<(usize, usize, u32), Vec<usize>> = HashMap::new();
+                for pad in 0..seq.pads.len() {
+                    let origin_cycle = seq.pad_position(pad, pass.song_start, tpb).cycle;
+                    let cycles_per_pass = match self.loop_range() {
+                        Some((start, end)) => {
+                            let first = seq.pad_position(pad, start, tpb).cycle;
+                            seq.pad_position(pad, end - 1, tpb).cycle - first + 1
+                        }
+                        None => 0,
+                    };
+                    let first = seq.pad_position(pad, lo, tpb);
+                    let mut tick = if first.start < lo { first.next } else { lo };
+                    while tick < hi {
+                        let pos = seq.pad_position(pad, tick, tpb);
+                        tick = pos.next;
+                        let Some(pattern) = seq.patterns.get(pos.pattern) else {
+                            continue;
+                        };
+                        let Some(s) = pattern.step(pad, pos.step).filter(|s| s.active) else {
+                            continue;
+                        };
+                        let iteration = pass.iteration * cycles_per_pass + pos.cycle.saturating_sub(origin_cycle);
+                        let steps = fired.entry((pos.pattern, pad, iteration)).or_insert_with(|| {
+                            pattern.fired_steps(instrument_id, pad, iteration, seq.fill, probability)
+                        });
+                        let step_ticks = pattern.pad_step_ticks(pad, tpb);
+                        let grid = (pass.stream_start + (pos.start - pass.song_start) as u64) as i64;
+                        let mut start = grid + (s.nudge * step_ticks as f32).round() as i64;
+                        if pos.step % 2 == 1 {
+                            let swing = seq.swing_amount.clamp(0.0, 1.0) * step_ticks as f32 / 3.0;
+                            start += swing.round() as i64;
+                        }
+                        let key = HitKey::Step { instrument_id, pad, step: pos.step };
+                        let mut result = probability.result(key, s.probability, iteration);
+                        result.fired = steps.contains(&pos.step);
+                        if let Ok(at) = u64::try_from(start) {
+                            if at >= from && at < to {
+                                results.push((at, result));
+                            }
+                        }
+                        if !result.fired {
+                            continue;
+                        }
+                        let velocities = s.ratchet_velocities();
+                        let spacing = step_ticks as f32 / velocities.len() as f32;
+                        let gate = ((spacing * s.gate_fraction()).round() as u32).max(1);
+                        let tied = pattern.tied_after(pad, pos.step) as u32 * step_ticks;
+                        for (r, &velocity) in velocities.iter().enumerate() {
+                            let at = start + (r as f32 * spacing).round() as i64;
+                            let Ok(stream) = u64::try_from(at) else {
+                                continue;
+                            };
+                            if stream < from || stream >= to {
+                                continue;
+                            }
+                            let last = r + 1 == velocities.len();
+                            out.push(ScheduledEvent {
+                                stream_tick: stream,
+                                song_tick: pos.start,
+                                iteration: pass.iteration,
+                                kind: ScheduledEventKind::DrumHit {
+                                    instrument_id,
+                                    pad,
+                                    step: pos.step,
+                                    velocity,
+                                    pitch_offset: s.pitch_offset,
+                                    duration: if last { gate + tied } else { gate },
+                                    locks: s.locks.clone(),
+                                },
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Positions of every attached drum sequencer in stream range [from, to):
+    /// one entry at `from` and one wherever the pattern or a pad's row moves
+    /// to another step, in stream order
+    pub fn drum_steps(&self, from: u64, to: u64) -> Vec<DrumStepPosition> {
+        let mut out = Vec::new();
+        if self.drums.is_empty() || to <= from {
+            return out;
+        }
+        let tpb = self.session.piano_roll.ticks_per_beat;
+        let sixteenth = StepResolution::Sixteenth.ticks(tpb);
+        for pass in self.passes(from, to) {
+            let song_at = |stream: u64| {
+                let offset = stream.saturating_sub(pass.stream_start);
+                (pass.song_start as u64 + offset).min(pass.song_end as u64) as u32
+            };
+            let lo = song_at(from.max(pass.stream_start));
+            let hi = song_at(to);
+            for &(instrument_id, seq) in &self.drums {
+                let mut tick = lo;
+                while tick < hi {
+                    let positions: Vec<PadPosition> =
+                        (0..seq.pads.len()).map(|pad| seq.pad_position(pad, tick, tpb)).collect();
+                    out.push(DrumStepPosition {
+                        stream_tick: pass.stream_start + (tick - pass.song_start) as u64,
+                        instrument_id,
+                        step: seq.position_at((tick / sixteenth) as u64).1,
+                        pad_steps: positions.iter().map(|p| p.step).collect(),
+                    });
+                    let next_sixteenth = (tick / sixteenth + 1).saturating_mul(sixteenth);
+                    tick = positions.iter().map(|p| p.next).fold(next_sixteenth, u32::min);
+                }
+            }
+        }
+        out.sort_by_key(|p| p.stream_tick);
+        out
+    }
+
+    /// Drum hits plus the stops they cause: chokes within a pad's group, the
+    /// end of gate-mode hits, and the oldest voices over a pad's polyphony
+    /// limit. One-shot voices are assumed to have finished after
+    /// [`VOICE_HORIZON_BEATS`].
+    fn drum_events(
+        &self,
+        from: u64,
+        to: u64,
+        out: &mut Vec<ScheduledEvent>,
+        results: &mut Vec<HitResult>,
+    ) {
+        if self.drums.is_empty() {
+            return;
+        }
+        let tpb = self.session.piano_roll.ticks_per_beat;
+        let horizon = VOICE_HORIZON_BEATS as u64 * tpb as u64;
+        let longest_step = StepResolution::ALL.iter().map(|r| r.ticks(tpb)).max().unwrap_or(1) as u64;
+        // Gate-mode hits can end (and voices be stolen) well after they start
+        let lookback = horizon.max(MAX_STEPS as u64 * longest_step);
+        let mut hits = Vec::new();
+        let mut outcomes = Vec::new();
+        self.drum_hits(from.saturating_sub(lookback), to, &mut hits, &mut outcomes);
+        results.extend(outcomes.into_iter().filter(|&(at, _)| at >= from).map(|(_, result)| result));
+        hits.sort_by_key(|e| e.stream_tick);
+
+        let stop = |stream_tick: u64, song_tick: u32, iteration: u32, instrument_id, pad, hit| {
+            ScheduledEvent {
+                stream_tick,
+                song_tick,
+                iteration,
+                kind: ScheduledEventKind::DrumStop { instrument_id, pad, hit },
+            }
+        };
+        // Voices sounding per (instrument, pad): (hit stream tick, end)
+        let mut voices: HashMap<(InstrumentId, usize), VecDeque<(u64, u64)>> = HashMap::new();
+        for event in &hits {
+            let ScheduledEventKind::DrumHit { instrument_id, pad, duration, .. } = event.kind else {
+                continue;
+            };
+            let Some(seq) = self.drums.iter().find(|(id, _)| *id == instrument_id).map(|(_, seq)| *seq) else {
+                continue;
+            };
+            let Some(drum_pad) = seq.pad(pad) else {
+                continue;
+            };
+            let at = event.stream_tick;
+            let release = match drum_pad.mode {
+                PadMode::Gate => at + duration as u64,
+                PadMode::OneShot => at + horizon,
+            };
+            if drum_pad.mode == PadMode::Gate && release >= from && release < to {
+                let end = self.position(release);
+                out.push(stop(release, end.tick, end.iteration, instrument_id, pad, Some(at)));
+            }
+            let in_window = at >= from;
+            for (other, other_pad) in seq.pads.iter().enumerate() {
+                if other == pad || !drum_pad.chokes(other_pad) {
+                    continue;
+                }
+                // Only pads with a voice still sounding need cutting off
+                let choked = voices
+                    .remove(&(instrument_id, other))
+                    .is_some_and(|sounding| sounding.iter().any(|&(_, end)| end > at));
+                if choked && in_window {
+                    out.push(stop(at, event.song_tick, event.iteration, instrument_id, other, None));
+                }
+            }
+            let sounding = voices.entry((instrument_id, pad)).or_default();
+            sounding.retain(|&(_, end)| end > at);
+            if let Some(max_voices) = drum_pad.max_voices {
+                while sounding.len() >= max_voices as usize {
+                    let Some((oldest, _)) = sounding.pop_front() else {
+                        break;
+                    };
+                    if in_window {
+                        out.push(stop(at, event.song_tick, event.iteration, instrument_id, pad, Some(oldest)));
+                    }
+                }
+            }
+            sounding.push_back((at, release));
+            if in_window {
+                out.push(event.clone());
+            }
+        }
+    }
+
+    /// Lanes active in the current mode
+    fn automation_lanes(&self) -> &[AutomationLane] {
+        self.cache.automation_lanes.get_or_init(|| {
+            let mut lanes: Vec<AutomationLane> = self
+                .session
+                .automation
+                .lanes
+                .iter()
+                .filter(|l| l.enabled)
+                .cloned()
+                .collect();
+            if self.session.arrangement.play_mode == PlayMode::Song {
+                lanes.extend(self.session.arrangement.flatten_automation());
+            }
+            lanes
+        })
+    }
+
+    fn automation_events(&self, from: u64, to: u64, out: &mut Vec<ScheduledEvent>) {
+        let lanes = self.automation_lanes();
+        if lanes.is_empty() {
+            return;
+        }
+        let step = self.automation_resolution.max(1) as u64;
+        let mut last: HashMap<&AutomationTarget, f32> = HashMap::new();
+        // Seed with the value just before the window so unchanged values aren't resent
+        if from > 0 {
+            let prev = self.position((from - 1) / step * step);
+            for lane in lanes {
+                if let Some(v) = lane.value_at(prev.tick) {
+                    last.insert(&lane.target, v);
+                }
+            }
+        }
+        let mut t = from.div_ceil(step) * step;
+        while t < to {
+            let pos = self.position(t);
+            for lane in lanes {
+                let Some(value) = lane.value_at(pos.tick) else {
+                    continue;
+                };
+                if last.get(&lane.target) == Some(&value) {
+                    continue;
+                }
+                last.insert(&lane.target, value);
+                out.push(ScheduledEvent {
+                    stream_tick: t,
+                    song_tick: pos.tick,
+                    iteration: pos.iteration,
+                    kind: ScheduledEventKind::Automation { target: lane.target.clone(), value },
+                });
+            }
+            t += step;
+        }
+    }
+
+    /// All events with stream tick in [from, to), time-ordered
+    pub fn events(&self, from: u64, to: u64) -> Vec<ScheduledEvent> {
+        self.events_with_results(from, to).0
+    }
+
+    /// Events with stream tick in [from, to), plus the outcome of every note
+    /// and active drum step due in the window (played or skipped)
+    pub fn events_with_results(&self, from: u64, to: u64) -> (Vec<ScheduledEvent>, Vec<HitResult>) {
+        let mut out = Vec::new();
+        let mut results = Vec::new();
+        if to <= from {
+            return (out, results);
+        }
+        self.note_events(from, to, &mut out, &mut results);
+        self.drum_events(from, to, &mut out, &mut results);
+        self.automation_events(from, to, &mut out);
+        out.sort_by_key(|e| (e.stream_tick, e.kind.order()));
+        (out, results)
+    }
+
+    /// Events for the next `lookahead` ticks after `from`
+    pub fn lookahead(&self, from: u64, lookahead: u32) -> Vec<ScheduledEvent> {
+        self.events(from, from + lookahead as u64)
+    }
+
+    /// Convert seconds of playback to stream ticks at the current tempo
+    pub fn secs_to_ticks(&self, secs: f64) -> u64 {
+        let pr = &self.session.piano_roll;
+        (secs * pr.bpm as f64 / 60.0 * pr.ticks_per_beat as f64).max(0.0) as u64
+    }
+
+    /// Convert stream ticks to seconds at the current tempo
+    pub fn ticks_to_secs(&self, ticks: u64) -> f64 {
+        let pr = &self.session.piano_roll;
+        ticks as f64 * 60.0 / (pr.bpm as f64 * pr.ticks_per_beat as f64)
+    }
+
+    /// Events in a wall-clock window [from_secs, from_secs + secs)
+    pub fn events_in_time(&self, from_secs: f64, secs: f64) -> Vec<ScheduledEvent> {
+        self.events(self.secs_to_ticks(from_secs), self.secs_to_ticks(from_secs + secs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::automation::AutomationTarget;
+    use crate::state::drum_sequencer::DrumPattern;
+    use crate::state::trig::TrigCondition;
+
+    fn session_with_notes(notes: &[(u32, u32, u8)]) -> SessionState {
+        let mut session = SessionState::new();
+        session.piano_roll.add_track(1);
+        for &(tick, duration, pitch) in notes {
+            session.piano_roll.insert_note(0, Note::new(tick, duration, pitch, 100));
+        }
+        session
+    }
+
+    fn ons(events: &[ScheduledEvent]) -> Vec<(u64, u8)> {
+        events
+            .iter()
+            .filter_map(|e| match e.kind {
+                ScheduledEventKind::NoteOn { pitch, .. } => Some((e.stream_tick, pitch)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pattern_mode_wraps_loop() {
+        let mut session = session_with_notes(&[(0, 240, 60), (480, 240, 62)]);
+        session.piano_roll.loop_start = 0;
+        session.piano_roll.loop_end = 960;
+        let sched = Scheduler::new(&session);
+        let events = sched.events(0, 1920);
+        assert_eq!(ons(&events), vec![(0, 60), (480, 62), (960, 60), (1440, 62)]);
+        assert_eq!(events.iter().filter(|e| e.iteration == 1).count(), 4);
+        assert_eq!(sched.position(1000), SongPosition { tick: 40, iteration: 1 });
+    }
+
+    #[test]
+    fn windows_concatenate_to_whole() {
+        let mut session = session_with_notes(&[(0, 300, 60), (200, 500, 62), (900, 200, 64)]);
+        session.piano_roll.loop_end = 1000;
+        let sched = Scheduler::new(&session);
+        let whole = sched.events(0, 4000);
+        let mut pieces = Vec::new();
+        for w in 0..40 {
+            pieces.extend(sched.lookahead(w * 100, 100));
+        }
+        assert_eq!(whole, pieces);
+    }
+
+    #[test]
+    fn notes_are_cut_at_loop_end_and_offs_precede_ons() {
+        let mut session = session_with_notes(&[(0, 2000, 60)]);
+        session.piano_roll.loop_end = 960;
+        let events = Scheduler::new(&session).events(0, 961);
+        assert!(matches!(events[0].kind, ScheduledEventKind::NoteOn { duration: 960, .. }));
+        assert!(matches!(events[1].kind, ScheduledEventKind::NoteOff { .. }));
+        assert!(matches!(events[2].kind, ScheduledEventKind::NoteOn { .. }));
+        assert_eq!(events[1].stream_tick, 960);
+    }
+
+    #[test]
+    fn swing_delays_offbeats() {
+        let mut session = session_with_notes(&[(0, 100, 60), (240, 100, 62)]);
+        session.piano_roll.swing_amount = 1.0;
+        let events = Scheduler::new(&session).events(0, 480);
+        assert_eq!(ons(&events), vec![(0, 60), (320, 62)]);
+        let durations: Vec<u32> = events
+            .iter()
+            .filter_map(|e| match e.kind {
+                ScheduledEventKind::NoteOn { duration, .. } => Some(duration),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(durations, vec![100, 100]);
+        let offs: Vec<(u64, u8)> = events
+            .iter()
+            .filter_map(|e| match e.kind {
+                ScheduledEventKind::NoteOff { pitch, .. } => Some((e.stream_tick, pitch)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(offs, vec![(100, 60), (420, 62)]);
+    }
+
+    #[test]
+    fn mono_legato_marks_gliding_notes() {
+        let mut session = session_with_notes(&[(0, 480, 60), (240, 480, 64)]);
+        let track = session.piano_roll.track_at_mut(0).unwrap();
+        track.polyphonic = false;
+        track.voice.legato = true;
+        let events = Scheduler::new(&session).events(0, 960);
+        let legato: Vec<(u64, bool)> = events
+            .iter()
+            .filter_map(|e| match e.kind {
+                ScheduledEventKind::NoteOn { legato, .. } => Some((e.stream_tick, legato)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(legato, vec![(0, false), (240, true)]);
+    }
+
+    #[test]
+    fn probability_is_applied_per_iteration() {
+        let mut session = SessionState::new();
+        session.piano_roll.add_track(1);
+        for i in 0..16 {
+            let mut note = Note::new(i * 60, 30, 60, 100);
+            note.probability = 0.5;
+            session.piano_roll.insert_note(0, note);
+        }
+        session.piano_roll.loop_end = 960;
+        let sched = Scheduler::new(&session);
+        let first: Vec<u64> = ons(&sched.events(0, 960)).iter().map(|e| e.0).collect();
+        let second: Vec<u64> = ons(&sched.events(960, 1920)).iter().map(|e| e.0 - 960).collect();
+        assert!(!first.is_empty() && first.len() < 16);
+        assert_ne!(first, second);
+        let again: Vec<u64> = ons(&Scheduler::new(&session).events(0, 960)).iter().map(|e| e.0).collect();
+        assert_eq!(first, again);
+    }
+
+    #[test]
+    fn trig_conditions_vary_notes_over_repeats() {
+        let mut session = session_with_notes(&[(0, 100, 60), (240, 100, 62), (480, 100, 64)]);
+        session.piano_roll.loop_end = 960;
+        let notes = &mut session.piano_roll.tracks.get_mut(&1).unwrap().notes;
+        let mut edited = notes.to_vec();
+        edited[0].condition = TrigCondition::Ratio { a: 1, b: 2 };
+        edited[1].condition = TrigCondition::Previous;
+        edited[2].condition = TrigCondition::Fill;
+        notes.replace(edited);
+
+        let events = Scheduler::new(&session).events(0, 1920);
+        assert_eq!(ons(&events), vec![(0, 60), (240, 62)]);
+        // Later windows still see the earlier note for the Previous check
+        let events = Scheduler::new(&session).events(200, 960);
+        assert_eq!(ons(&events), vec![(240, 62)]);
+
+        session.piano_roll.fill = true;
+        let events = Scheduler::new(&session).events(960, 1920);
+        assert_eq!(ons(&events), vec![(1440, 64)]);
+    }
+
+    #[test]
+    fn drum_steps_follow_the_chain() {
+        let session = SessionState::new();
+        let mut seq = DrumSequencerState::new();
+        seq.patterns = vec![DrumPattern::new(4), DrumPattern::new(2)];
+        seq.add_chain_step(0);
+        seq.add_chain_step(1);
+        seq.toggle_chain();
+        let sched = Scheduler::new(&session).with_drums(7, &seq);
+        let steps: Vec<(u64, usize, usize)> =
+            sched.drum_steps(0, 840).iter().map(|p| (p.stream_tick, p.step, p.pad_steps[0])).collect();
+        assert_eq!(
+            steps,
+            vec![(0, 0, 0), (120, 1, 1), (240, 2, 2), (360, 3, 3), (480, 0, 0), (600, 1, 1), (720, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn drum_hits_carry_locks_and_conditions() {
+        let mut session = SessionState::new();
+        session.piano_roll.loop_end = 1920;
+        let mut seq = DrumSequencerState::new();
+        seq.swing_amount = 1.0;
+        let pattern = seq.pattern_mut();
+        for step in [0, 4, 8] {
+            pattern.toggle_step(0, step);
+        }
+        pattern.toggle_step(1, 1);
+        let cutoff = AutomationTarget::FilterCutoff(7);
+        pattern.set_lock(0, 4, cutoff.clone(), 800.0);
+        pattern.set_lock(0, 4, AutomationTarget::DrumPadParam(7, 0, 1), 0.25);
+        pattern.set_condition(0, 8, TrigCondition::Ratio { a: 1, b: 2 });
+
+        let sched = Scheduler::new(&session).with_drums(7, &seq);
+        let events = sched.events(0, 3840);
+        let hits: Vec<(u64, usize, usize, usize)> = events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                ScheduledEventKind::DrumHit { pad, step, locks, .. } => {
+                    Some((e.stream_tick, *pad, *step, locks.len()))
+                }
+                _ => None,
+            })
+            .collect();
+        // Step 8 plays on the first pass only; the odd step is swung
+        assert_eq!(
+            hits,
+            vec![
+                (0, 0, 0, 0),
+                (160, 1, 1, 0),
+                (480, 0, 4, 2),
+                (960, 0, 8, 0),
+                (1920, 0, 0, 0),
+                (2080, 1, 1, 0),
+                (2400, 0, 4, 2),
+            ]
+        );
+        let locked = events.iter().find_map(|e| match &e.kind {
+            ScheduledEventKind::DrumHit { step: 4, locks, .. } => locks.get(&cutoff),
+            _ => None,
+        });
+        assert_eq!(locked, Some(800.0));
+
+        let mut pieces = Vec::new();
+        for w in 0..32 {
+            pieces.extend(sched.lookahead(w * 120, 120));
+        }
+        assert_eq!(events, pieces);
+    }
+
+    #[test]
+    fn drum_steps_are_nudged_ratcheted_and_tied() {
+        let mut session = SessionState::new();
+        session.piano_roll.loop_end = 1920;
+        let mut seq = DrumSequencerState::new();
+        let pattern = seq.pattern_mut();
+        pattern.toggle_step(0, 0);
+        pattern.adjust_gate(0, 0, -0.5);
+        pattern.toggle_tie(0, 1);
+        pattern.toggle_step(0, 4);
+        pattern.adjust_nudge(0, 4, -0.25);
+        pattern.adjust_retrigs(0, 4, 2);
+        pattern.adjust_ratchet_ramp(0, 4, 1.0);
+        pattern.adjust_gate(0, 4, -0.5);
+
+        let sched = Scheduler::new(&session).with_drums(2, &seq);
+        let events = sched.events(0, 1920);
+        let hits: Vec<(u64, u8, u32)> = events
+            .iter()
+            .filter_map(|e| match e.kind {
+                ScheduledEventKind::DrumHit { velocity, duration, .. } => {
+                    Some((e.stream_tick, velocity, duration))
+                }
+                _ => None,
+            })
+            .collect();
+        // Half-step gate plus one tied step; three ratchets starting a
+        // quarter step early
+        assert_eq!(hits, vec![(0, 100, 180), (450, 100, 20), (490, 127, 20), (530, 127, 20)]);
+
+        let mut pieces = Vec::new();
+        for w in 0..64 {
+            pieces.extend(sched.lookahead(w * 30, 30));
+        }
+        assert_eq!(events, pieces);
+    }
+
+    #[test]
+    fn drum_pads_play_polymetric_rows() {
+        let mut session = SessionState::new();
+        session.piano_roll.loop_end = 3840;
+        let mut seq = DrumSequencerState::new();
+        let pattern = seq.pattern_mut();
+        pattern.toggle_step(0, 0);
+        pattern.adjust_pad_length(1, -11);
+        pattern.toggle_step(1, 0);
+        pattern.set_condition(1, 0, TrigCondition::Ratio { a: 1, b: 2 });
+        pattern.cycle_pad_resolution(2);
+        pattern.adjust_pad_length(2, -13);
+        pattern.toggle_step(2, 2);
+
+        let sched = Scheduler::new(&session).with_drums(3, &seq);
+        let hits = |from, to| -> Vec<(u64, usize, usize)> {
+            sched
+                .events(from, to)
+                .iter()
+                .filter_map(|e| match e.kind {
+                    ScheduledEventKind::DrumHit { pad, step, .. } => Some((e.stream_tick, pad, step)),
+                    _ => None,
+                })
+                .collect()
+        };
+        // Kick every bar, a five-step hat on every other repeat, and a
+        // three-step eighth-triplet row
+        assert_eq!(
+            hits(0, 1920),
+            vec![(0, 0, 0), (0, 1, 0), (320, 2, 2), (800, 2, 2), (1200, 1, 0), (1280, 2, 2), (1760, 2, 2)]
+        );
+        // The loop restarts every row and carries the repeat count on
+        assert_eq!(hits(3840, 3841), vec![(3840, 0, 0)]);
+    }
+
+    #[test]
+    fn drum_pads_choke_release_and_steal_voices() {
+        let mut session = SessionState::new();
+        session.piano_roll.loop_end = 1920;
+        let mut seq = DrumSequencerState::new();
+        // Open and closed hats in one choke group
+        seq.pads[0].choke_group = Some(1);
+        seq.pads[1].choke_group = Some(1);
+        // A gated pad, and a one-shot pad limited to one voice
+        seq.pads[2].toggle_mode();
+        seq.pads[3].adjust_max_voices(1);
+        let pattern = seq.pattern_mut();
+        pattern.toggle_step(0, 0);
+        pattern.toggle_step(1, 2);
+        pattern.toggle_step(2, 0);
+        pattern.adjust_gate(2, 0, -0.5);
+        pattern.toggle_step(3, 0);
+        pattern.toggle_step(3, 1);
+
+        let sched = Scheduler::new(&session).with_drums(4, &seq);
+        let events = sched.events(0, 1920);
+        let stops: Vec<(u64, usize, Option<u64>)> = events
+            .iter()
+            .filter_map(|e| match e.kind {
+                ScheduledEventKind::DrumStop { pad, hit, .. } => Some((e.stream_tick, pad, hit)),
+                _ => None,
+            })
+            .collect();
+        // Nothing sounds on the closed hat yet, so the first open hat chokes nothing
+        assert_eq!(stops, vec![(60, 2, Some(0)), (120, 3, Some(0)), (240, 0, None)]);
+        // Stops come before the hits that cause them
+        let choke = events.iter().position(|e| e.stream_tick == 240).unwrap();
+        assert!(matches!(events[choke].kind, ScheduledEventKind::DrumStop { .. }));
+        assert!(matches!(events[choke + 1].kind, ScheduledEventKind::DrumHit { pad: 1, .. }));
+
+        // The next loop pass chokes the closed hat and steals the one-shot
+        // voice, both left sounding from the last pass
+        let next: Vec<(u64, usize, Option<u64>)> = sched
+            .events(1920, 1921)
+            .iter()
+            .filter_map(|e| match e.kind {
+                ScheduledEventKind::DrumStop { pad, hit, .. } => Some((e.stream_tick, pad, hit)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(next, vec![(1920, 1, None), (1920, 3, Some(120))]);
+
+        let mut pieces = Vec::new();
+        for w in 0..16 {
+            pieces.extend(sched.lookahead(w * 120, 120));
+        }
+        assert_eq!(events, pieces);
+    }
+
+    #[test]
+    fn long_notes_release_across_small_windows() {
+        let mut session = session_with_notes(&[(0, 9000, 60), (4800, 100, 64)]);
+        session.piano_roll.looping = false;
+        session.arrangement.play_mode = PlayMode::Pattern;
+        let sched = Scheduler::new(&session);
+        let offs: Vec<u64> = (0..40)
+            .flat_map(|w| sched.events(w * 250, (w + 1) * 250))
+            .filter(|e| matches!(e.kind, ScheduledEventKind::NoteOff { .. }))
+            .map(|e| e.stream_tick)
+            .collect();
+        assert_eq!(offs, vec![4900, 9000]);
+
+        let cid = session.arrangement.add_clip("A".to_string(), 3, 9600);
+        session.arrangement.add_clip_note(cid, Note::new(0, 9000, 48, 100));
+        session.arrangement.add_placement(cid, 3, 480);
+        session.arrangement.play_mode = PlayMode::Song;
+        let sched = Scheduler::new(&session);
+        let events = sched.events(9000, 9600);
+        let offs = events.iter().filter(|e| matches!(e.kind, ScheduledEventKind::NoteOff { .. }));
+        assert_eq!(offs.map(|e| e.stream_tick).collect::<Vec<_>>(), vec![9480]);
+    }
+
+    #[test]
+    fn song_mode_plays_placements() {
+        let mut session = SessionState::new();
+        session.piano_roll.looping = false;
+        session.arrangement.play_mode = PlayMode::Song;
+        let cid = session.arrangement.add_clip("A".to_string(), 3, 480);
+        session.arrangement.add_clip_note(cid, Note::new(0, 120, 48, 100));
+        session.arrangement.add_placement(cid, 3, 0);
+        session.arrangement.add_placement(cid, 3, 960);
+        let events = Scheduler::new(&session).events(0, 2000);
+        assert_eq!(ons(&events), vec![(0, 48), (960, 48)]);
+    }
+
+    #[test]
+    fn automation_emits_changes_only() {
+        let mut session = SessionState::new();
+        session.piano_roll.looping = false;
+        let id = session.automation.add_lane(AutomationTarget::InstrumentLevel(1));
+        let lane = session.automation.lane_mut(id).unwrap();
+        lane.add_point(0, 0.0);
+        lane.add_point(60, 1.0);
+        let mut sched = Scheduler::new(&session);
+        sched.automation_resolution = 30;
+        let values: Vec<f32> = sched
+            .events(0, 240)
+            .iter()
+            .filter_map(|e| match e.kind {
+                ScheduledEventKind::Automation { value, .. } => Some(value),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(values, vec![0.0, 0.5, 1.0]);
+    }
+}
@@ -3,7 +3,13 @@
 use std::collections::HashMap;
 
 use super::automation::{AutomationLane, AutomationLaneId, AutomationPoint, AutomationTarget};
-use super::piano_roll::Note;
+use super::interval_index::IntervalIndex;
+use super::mini_notation::{MiniPattern, NotationError};
+use super::note_index::NoteIndex;
+use super::piano_roll::{Note, NoteId, NoteIdAllocator};
+use super::generator::{GeneratorRegion, NoteGenerator};
+use super::take::TakeLanes;
+use super::tracker::TrackerPattern;
 use crate::InstrumentId;
 use serde::{Deserialize, Serialize};
 
@@ -13,18 +19,13 @@ pub type ClipId = u32;
 /// Unique identifier for a clip placement on the timeline.
 pub type PlacementId = u32;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub enum PlayMode {
+    #[default]
     Pattern,
     Song,
 }
 
-impl Default for PlayMode {
-    fn default() -> Self {
-        Self::Pattern
-    }
-}
-
 /// Reusable pattern of notes for a single instrument.
 /// Notes use tick positions relative to clip start (0-based).
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,11 +33,21 @@ pub struct Clip {
     pub id: ClipId,
     pub name: String,
     pub instrument_id: InstrumentId,
-    pub length_ticks: u32,
-    pub notes: Vec<Note>,
+    /// Changed through `ArrangementState::set_clip_length` so placements stay indexed
+    length_ticks: u32,
+    pub notes: NoteIndex,
     /// Per-clip automation lanes (0-based tick positions, like notes)
     #[serde(default)]
     pub automation_lanes: Vec<AutomationLane>,
+    /// Recorded takes and the comp built from them
+    #[serde(default)]
+    pub takes: TakeLanes,
+}
+
+impl Clip {
+    pub fn length_ticks(&self) -> u32 {
+        self.length_ticks
+    }
 }
 
 /// A placement of a clip on the timeline. Multiple placements can share a clip.
@@ -80,7 +91,8 @@ pub struct ClipEditContext {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ArrangementState {
     pub clips: Vec<Clip>,
-    pub placements: Vec<ClipPlacement>,
+    /// Edited only through the placement methods, which keep the index current
+    placements: Vec<ClipPlacement>,
     pub play_mode: PlayMode,
     #[serde(skip)]
     pub editing_clip: Option<ClipEditContext>,
@@ -95,6 +107,19 @@ pub struct ArrangementState {
     pub(crate) next_clip_id: ClipId,
     pub(crate) next_placement_id: PlacementId,
     pub(crate) next_clip_automation_lane_id: AutomationLaneId,
+    #[serde(default)]
+    pub(crate) note_ids: NoteIdAllocator,
+
+    /// Per-instrument interval index over placements (derived, rebuilt on load)
+    #[serde(skip)]
+    placement_index: HashMap<InstrumentId, IntervalIndex<PlacementId>>,
+    /// Position of each placement in `placements` (derived)
+    #[serde(skip)]
+    placement_pos: HashMap<PlacementId, usize>,
+    /// Whether the two maps above cover `placements`. Only
+    /// `rebuild_placement_index` sets it; deserialized state starts without.
+    #[serde(skip)]
+    index_built: bool,
 }
 
 impl Default for ArrangementState {
@@ -118,6 +143,10 @@ impl ArrangementState {
             next_clip_id: 1,
             next_placement_id: 1,
             next_clip_automation_lane_id: 0,
+            note_ids: NoteIdAllocator::new(),
+            placement_index: HashMap::new(),
+            placement_pos: HashMap::new(),
+            index_built: true,
         }
     }
 
@@ -134,8 +163,9 @@ impl ArrangementState {
             name,
             instrument_id,
             length_ticks,
-            notes: Vec::new(),
+            notes: NoteIndex::new(),
             automation_lanes: Vec::new(),
+            takes: TakeLanes::new(),
         });
         id
     }
@@ -148,16 +178,117 @@ impl ArrangementState {
         self.clips.iter_mut().find(|c| c.id == id)
     }
 
+    /// Insert a note into a clip in tick order, assigning a fresh id
+    pub fn add_clip_note(&mut self, clip_id: ClipId, mut note: Note) -> Option<NoteId> {
+        note.id = self.note_ids.alloc();
+        let note_id = note.id;
+        let clip = self.clips.iter_mut().find(|c| c.id == clip_id)?;
+        clip.notes.insert(note);
+        Some(note_id)
+    }
+
+    /// Fill a clip with generated notes, using its current notes as the source
+    pub fn generate_clip_notes(
+        &mut self,
+        clip_id: ClipId,
+        step_ticks: u32,
+        generator: &NoteGenerator,
+    ) -> bool {
+        let Some(clip) = self.clips.iter_mut().find(|c| c.id == clip_id) else {
+            return false;
+        };
+        let region = GeneratorRegion::new(0, clip.length_ticks, step_ticks);
+        let mut notes = generator.generate(&region, &clip.notes);
+        for note in &mut notes {
+            note.id = self.note_ids.alloc();
+        }
+        clip.notes.replace(notes);
+        true
+    }
+
+    /// Replace a clip's notes with those of a tracker pattern. Notes keep
+    /// their ids; cells typed in the tracker get new ones.
+    pub fn apply_clip_tracker(&mut self, clip_id: ClipId, pattern: &TrackerPattern) -> bool {
+        let Some(clip) = self.clips.iter_mut().find(|c| c.id == clip_id) else {
+            return false;
+        };
+        let mut notes = pattern.to_notes();
+        for note in &mut notes {
+            if note.id == 0 || clip.notes.find_id(note.id).is_none() {
+                note.id = self.note_ids.alloc();
+            }
+        }
+        clip.notes.replace(notes);
+        true
+    }
+
+    /// Replace a clip's notes with a mini-notation pattern, one cycle every
+    /// `ticks_per_cycle` for the length of the clip. Returns the note count.
+    pub fn set_clip_notation(
+        &mut self,
+        clip_id: ClipId,
+        src: &str,
+        ticks_per_cycle: u32,
+    ) -> Result<usize, NotationError> {
+        let pattern = MiniPattern::parse(src)?;
+        let Some(clip) = self.clips.iter_mut().find(|c| c.id == clip_id) else {
+            return Ok(0);
+        };
+        let cycles = clip.length_ticks.div_ceil(ticks_per_cycle.max(1)).max(1);
+        let mut notes = pattern.notes(cycles, ticks_per_cycle.max(1), 100)?;
+        notes.retain(|n| n.tick < clip.length_ticks);
+        for note in &mut notes {
+            note.id = self.note_ids.alloc();
+        }
+        let count = notes.len();
+        clip.notes.replace(notes);
+        Ok(count)
+    }
+
+    /// Replace a clip's notes in the comp range with the comp
+    pub fn flatten_clip_comp(&mut self, clip_id: ClipId) -> bool {
+        let Some(clip) = self.clips.iter_mut().find(|c| c.id == clip_id) else {
+            return false;
+        };
+        let mut notes = clip.notes.take();
+        let flattened = clip.takes.flatten_into(&mut notes, &mut self.note_ids);
+        clip.notes.replace(notes);
+        flattened
+    }
+
+    /// Allocate a fresh note ID for use in clips
+    pub fn next_note_id(&mut self) -> NoteId {
+        self.note_ids.alloc()
+    }
+
     pub fn remove_clip(&mut self, id: ClipId) {
         if let Some(pos) = self.clips.iter().position(|c| c.id == id) {
             self.clips.remove(pos);
             // Cascade delete placements
             self.placements.retain(|p| p.clip_id != id);
+            self.rebuild_placement_index();
             // Clear selection if it was a placement of this clip (simplified: just clear selection)
             self.selected_placement = None;
         }
     }
 
+    /// Change a clip's length and re-index the placements that use it
+    pub fn set_clip_length(&mut self, id: ClipId, length_ticks: u32) {
+        self.ensure_placement_index();
+        if let Some(clip) = self.clip_mut(id) {
+            clip.length_ticks = length_ticks;
+            let ids: Vec<PlacementId> = self
+                .placements
+                .iter()
+                .filter(|p| p.clip_id == id)
+                .map(|p| p.id)
+                .collect();
+            for pid in ids {
+                self.reindex_placement(pid);
+            }
+        }
+    }
+
     pub fn clips_for_instrument(&self, instrument_id: InstrumentId) -> Vec<&Clip> {
         self.clips
             .iter()
@@ -171,6 +302,7 @@ impl ArrangementState {
         instrument_id: InstrumentId,
         start_tick: u32,
     ) -> PlacementId {
+        self.ensure_placement_index();
         let id = self.next_placement_id;
         self.next_placement_id += 1;
         self.placements.push(ClipPlacement {
@@ -180,26 +312,109 @@ impl ArrangementState {
             start_tick,
             length_override: None,
         });
+        self.placement_pos.insert(id, self.placements.len() - 1);
+        self.reindex_placement(id);
         id
     }
 
     pub fn remove_placement(&mut self, id: PlacementId) {
+        self.ensure_placement_index();
         if let Some(pos) = self.placements.iter().position(|p| p.id == id) {
-            self.placements.remove(pos);
+            let removed = self.placements.remove(pos);
+            if let Some(index) = self.placement_index.get_mut(&removed.instrument_id) {
+                index.remove(id);
+            }
+            self.placement_pos.remove(&id);
+            for (i, p) in self.placements.iter().enumerate().skip(pos) {
+                self.placement_pos.insert(p.id, i);
+            }
             self.selected_placement = None;
         }
     }
 
     pub fn move_placement(&mut self, id: PlacementId, new_start_tick: u32) {
+        self.ensure_placement_index();
         if let Some(p) = self.placements.iter_mut().find(|p| p.id == id) {
             p.start_tick = new_start_tick;
+            self.reindex_placement(id);
         }
     }
 
     pub fn resize_placement(&mut self, id: PlacementId, new_length: Option<u32>) {
+        self.ensure_placement_index();
         if let Some(p) = self.placements.iter_mut().find(|p| p.id == id) {
             p.length_override = new_length;
+            self.reindex_placement(id);
+        }
+    }
+
+    /// Every placement, in the order they were added
+    pub fn placements(&self) -> &[ClipPlacement] {
+        &self.placements
+    }
+
+    /// Direct access to the placement list. Queries fall back to linear scans
+    /// until the next placement method (or `rebuild_placement_index`) re-indexes.
+    pub fn placements_mut(&mut self) -> &mut Vec<ClipPlacement> {
+        self.index_built = false;
+        &mut self.placements
+    }
+
+    /// Look up a placement by id
+    pub fn placement(&self, id: PlacementId) -> Option<&ClipPlacement> {
+        self.placement_pos
+            .get(&id)
+            .and_then(|&pos| self.placements.get(pos))
+            .filter(|p| p.id == id)
+            .or_else(|| self.placements.iter().find(|p| p.id == id))
+    }
+
+    /// Update one placement's entry in the interval index
+    fn reindex_placement(&mut self, id: PlacementId) {
+        let Some(&pos) = self.placement_pos.get(&id) else {
+            return;
+        };
+        let Some(p) = self.placements.get(pos) else {
+            return;
+        };
+        let (instrument_id, start) = (p.instrument_id, p.start_tick);
+        let end = self.clip(p.clip_id).map(|clip| p.end_tick(clip));
+        let index = self.placement_index.entry(instrument_id).or_default();
+        match end {
+            Some(end) => index.update(id, start, end),
+            None => {
+                index.remove(id);
+            }
+        }
+    }
+
+    /// Rebuild the placement index from scratch (after load or direct edits)
+    pub fn rebuild_placement_index(&mut self) {
+        self.placement_index.clear();
+        self.placement_pos.clear();
+        for (pos, p) in self.placements.iter().enumerate() {
+            self.placement_pos.insert(p.id, pos);
+            if let Some(clip) = self.clips.iter().find(|c| c.id == p.clip_id) {
+                self.placement_index
+                    .entry(p.instrument_id)
+                    .or_default()
+                    .insert(p.start_tick, p.end_tick(clip), p.id);
+            }
         }
+        self.index_built = true;
+    }
+
+    /// Build the index before an incremental update if it isn't built yet
+    fn ensure_placement_index(&mut self) {
+        if !self.index_built {
+            self.rebuild_placement_index();
+        }
+    }
+
+    /// Queries use the index once built; until then (freshly deserialized
+    /// state, or after `placements_mut`) they fall back to a linear scan.
+    fn index_is_current(&self) -> bool {
+        self.index_built
     }
 
     pub fn placements_for_instrument(&self, instrument_id: InstrumentId) -> Vec<&ClipPlacement> {
@@ -213,6 +428,13 @@ impl ArrangementState {
     }
 
     pub fn placement_at(&self, instrument_id: InstrumentId, tick: u32) -> Option<&ClipPlacement> {
+        if self.index_is_current() {
+            return self
+                .placement_index
+                .get(&instrument_id)?
+                .at(tick)
+                .find_map(|e| self.placement(e.key));
+        }
         for placement in self.placements_for_instrument(instrument_id) {
             if let Some(clip) = self.clip(placement.clip_id) {
                 if tick >= placement.start_tick && tick < placement.end_tick(clip) {
@@ -223,6 +445,95 @@ impl ArrangementState {
         None
     }
 
+    /// Placements of an instrument that overlap [start_tick, end_tick), in start order
+    pub fn placements_overlapping(
+        &self,
+        instrument_id: InstrumentId,
+        start_tick: u32,
+        end_tick: u32,
+    ) -> Vec<&ClipPlacement> {
+        if self.index_is_current() {
+            return self
+                .placement_index
+                .get(&instrument_id)
+                .map(|index| {
+                    index
+                        .overlapping(start_tick, end_tick)
+                        .filter_map(|e| self.placement(e.key))
+                        .collect()
+                })
+                .unwrap_or_default();
+        }
+        self.placements_for_instrument(instrument_id)
+            .into_iter()
+            .filter(|p| {
+                self.clip(p.clip_id)
+                    .is_some_and(|clip| p.start_tick < end_tick && p.end_tick(clip) > start_tick)
+            })
+            .collect()
+    }
+
+    /// Notes of one instrument starting in [start_tick, end_tick), in absolute ticks.
+    /// Same trimming rules as `flatten_to_notes`, without flattening the whole song.
+    pub fn notes_in_window(
+        &self,
+        instrument_id: InstrumentId,
+        start_tick: u32,
+        end_tick: u32,
+    ) -> Vec<Note> {
+        let mut notes = Vec::new();
+        for placement in self.placements_overlapping(instrument_id, start_tick, end_tick) {
+            let Some(clip) = self.clip(placement.clip_id) else {
+                continue;
+            };
+            let effective_len = placement.effective_length(clip);
+            let rel_start = start_tick.saturating_sub(placement.start_tick);
+            let rel_end = (end_tick - placement.start_tick.min(end_tick)).min(effective_len);
+            let auditioned = clip.takes.audition_notes(&clip.notes);
+            let source: Vec<&Note> = match &auditioned {
+                Some(notes) => notes.iter().filter(|n| n.tick >= rel_start && n.tick < rel_end).collect(),
+                None => clip.notes.starting_in(rel_start, rel_end).iter().collect(),
+            };
+            for note in source {
+                let duration = note.duration.min(effective_len - note.tick);
+                if duration > 0 {
+                    let mut new_note = note.clone();
+                    new_note.tick = placement.start_tick + note.tick;
+                    new_note.duration = duration;
+                    notes.push(new_note);
+                }
+            }
+        }
+        notes.sort_by_key(|n| n.tick);
+        notes
+    }
+
+    /// Notes of one instrument that started before `tick` and still sound at
+    /// it, in absolute ticks, trimmed like `notes_in_window`
+    pub fn notes_sounding_at(&self, instrument_id: InstrumentId, tick: u32) -> Vec<Note> {
+        let mut notes = Vec::new();
+        for placement in self.placements_overlapping(instrument_id, tick, tick.saturating_add(1)) {
+            let Some(clip) = self.clip(placement.clip_id) else {
+                continue;
+            };
+            let effective_len = placement.effective_length(clip);
+            let rel = tick - placement.start_tick;
+            let auditioned = clip.takes.audition_notes(&clip.notes);
+            let source: Vec<&Note> = match &auditioned {
+                Some(notes) => notes.iter().filter(|n| n.tick < rel && n.end_tick() > rel).collect(),
+                None => clip.notes.sounding_at(rel).filter(|n| n.tick < rel).collect(),
+            };
+            for note in source {
+                let mut new_note = note.clone();
+                new_note.tick = placement.start_tick + note.tick;
+                new_note.duration = note.duration.min(effective_len - note.tick);
+                notes.push(new_note);
+            }
+        }
+        notes.sort_by_key(|n| n.tick);
+        notes
+    }
+
     pub fn flatten_to_notes(&self) -> HashMap<InstrumentId, Vec<Note>> {
         let mut result: HashMap<InstrumentId, Vec<Note>> = HashMap::new();
 
@@ -241,7 +552,7 @@ impl ArrangementState {
                         let mut new_duration = note.duration;
 
                         // Clamp duration if it extends past effective length
-                        if note.tick + new_duration > effective_len {
+                        if note.tick.saturating_add(new_duration) > effective_len {
                             new_duration = effective_len - note.tick;
                         }
 
@@ -343,6 +654,7 @@ impl ArrangementState {
         self.placements.retain(|p| {
             p.instrument_id != instrument_id && !clip_ids_to_remove.contains(&p.clip_id)
         });
+        self.rebuild_placement_index();
 
         self.selected_placement = None;
     }
@@ -356,6 +668,15 @@ impl ArrangementState {
             .flat_map(|c| c.automation_lanes.iter().map(|l| l.id))
             .max()
             .map_or(0, |m| m + 1);
+        self.note_ids = NoteIdAllocator::new();
+        for clip in &self.clips {
+            self.note_ids.reserve_past(&clip.notes);
+        }
+        for clip in &mut self.clips {
+            let note_ids = &mut self.note_ids;
+            clip.notes.modify(|notes| note_ids.assign_missing(notes));
+        }
+        self.rebuild_placement_index();
     }
 
     /// Allocate a fresh automation lane ID for use in clips
@@ -369,6 +690,7 @@ impl ArrangementState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::state::trig::TrigCondition;
 
     #[test]
     fn test_add_remove_clip() {
@@ -428,18 +750,22 @@ mod tests {
 
         if let Some(clip) = arr.clip_mut(cid) {
             clip.notes.push(Note {
+                id: 0,
                 tick: 0,
                 pitch: 60,
                 velocity: 100,
                 duration: 48,
                 probability: 1.0,
+                condition: TrigCondition::Always,
             });
             clip.notes.push(Note {
+                id: 0,
                 tick: 96,
                 pitch: 62,
                 velocity: 100,
                 duration: 48,
                 probability: 1.0,
+                condition: TrigCondition::Always,
             });
         }
 
@@ -467,19 +793,23 @@ mod tests {
         if let Some(clip) = arr.clip_mut(cid) {
             // Note at 0, duration 50
             clip.notes.push(Note {
+                id: 0,
                 tick: 0,
                 pitch: 60,
                 velocity: 100,
                 duration: 50,
                 probability: 1.0,
+                condition: TrigCondition::Always,
             });
             // Note at 60, duration 50 (extends past 100)
             clip.notes.push(Note {
+                id: 0,
                 tick: 60,
                 pitch: 62,
                 velocity: 100,
                 duration: 50,
                 probability: 1.0,
+                condition: TrigCondition::Always,
             });
         }
 
@@ -497,6 +827,24 @@ mod tests {
         assert_eq!(notes[1].duration, 20);
     }
 
+    #[test]
+    fn test_clip_note_ids() {
+        let mut arr = ArrangementState::new();
+        let cid = arr.add_clip("Test".to_string(), 1, 384);
+        let a = arr.add_clip_note(cid, Note::new(96, 48, 60, 100)).unwrap();
+        let b = arr.add_clip_note(cid, Note::new(0, 48, 62, 100)).unwrap();
+        assert_ne!(a, b);
+        let clip = arr.clip(cid).unwrap();
+        assert_eq!(clip.notes[0].id, b);
+        assert!(arr.add_clip_note(999, Note::new(0, 48, 60, 100)).is_none());
+
+        arr.clip_mut(cid).unwrap().notes.push(Note::new(200, 48, 64, 100));
+        arr.recalculate_next_ids();
+        let ids: Vec<NoteId> = arr.clip(cid).unwrap().notes.iter().map(|n| n.id).collect();
+        assert!(ids.iter().all(|&id| id != 0));
+        assert!(arr.next_note_id() > *ids.iter().max().unwrap());
+    }
+
     #[test]
     fn test_arrangement_length() {
         let mut arr = ArrangementState::new();
@@ -520,6 +868,86 @@ mod tests {
         assert!(arr.placement_at(1, 120).is_none());
     }
 
+    #[test]
+    fn test_placement_index_tracks_edits() {
+        let mut arr = ArrangementState::new();
+        let cid = arr.add_clip("Test".to_string(), 1, 100);
+        let a = arr.add_placement(cid, 1, 0);
+        let b = arr.add_placement(cid, 1, 200);
+        arr.add_placement(cid, 2, 0);
+
+        assert_eq!(arr.placement_at(1, 250).unwrap().id, b);
+        arr.move_placement(b, 1000);
+        assert!(arr.placement_at(1, 250).is_none());
+        assert_eq!(arr.placement_at(1, 1050).unwrap().id, b);
+
+        arr.resize_placement(a, Some(50));
+        assert!(arr.placement_at(1, 60).is_none());
+        arr.set_clip_length(cid, 40);
+        assert!(arr.placement_at(1, 1045).is_none());
+
+        arr.remove_placement(a);
+        assert_eq!(arr.placements_overlapping(1, 0, 2000).len(), 1);
+        assert_eq!(arr.placements_overlapping(2, 0, 10).len(), 1);
+    }
+
+    #[test]
+    fn test_placement_at_falls_back_before_rebuild() {
+        let mut arr = ArrangementState::new();
+        let cid = arr.add_clip("Test".to_string(), 1, 100);
+        arr.placements_mut().push(ClipPlacement {
+            id: 42,
+            clip_id: cid,
+            instrument_id: 1,
+            start_tick: 500,
+            length_override: None,
+        });
+        assert_eq!(arr.placement_at(1, 550).unwrap().id, 42);
+        arr.rebuild_placement_index();
+        assert_eq!(arr.placement_at(1, 550).unwrap().id, 42);
+    }
+
+    #[test]
+    fn test_placement_index_built_on_first_edit_after_load() {
+        let mut arr = ArrangementState::new();
+        let cid = arr.add_clip("Test".to_string(), 1, 480);
+        let ids: Vec<PlacementId> = (0..3).map(|i| arr.add_placement(cid, 1, i * 480)).collect();
+        // As deserialized: the derived index isn't saved
+        arr.placement_index.clear();
+        arr.placement_pos.clear();
+        arr.index_built = false;
+
+        arr.remove_placement(ids[0]);
+        assert_eq!(arr.placement_at(1, 1000).unwrap().id, ids[2]);
+        assert_eq!(arr.placements_overlapping(1, 0, 2000).len(), 2);
+
+        arr.placements_mut()[0].start_tick = 4800;
+        assert_eq!(arr.placement_at(1, 4900).unwrap().id, ids[1]);
+        arr.move_placement(ids[2], 0);
+        assert_eq!(arr.placement_at(1, 100).unwrap().id, ids[2]);
+        assert_eq!(arr.placement_at(1, 4900).unwrap().id, ids[1]);
+    }
+
+    #[test]
+    fn test_notes_in_window_matches_flatten() {
+        let mut arr = ArrangementState::new();
+        let cid = arr.add_clip("Test".to_string(), 1, 100);
+        arr.add_clip_note(cid, Note::new(0, 50, 60, 100));
+        arr.add_clip_note(cid, Note::new(60, 50, 62, 100));
+        arr.add_placement(cid, 1, 0);
+        let pid = arr.add_placement(cid, 1, 300);
+        arr.resize_placement(pid, Some(80));
+
+        let window = arr.notes_in_window(1, 50, 400);
+        let ticks: Vec<u32> = window.iter().map(|n| n.tick).collect();
+        assert_eq!(ticks, vec![60, 300, 360]);
+        assert_eq!(window[2].duration, 20);
+
+        let flat = arr.flatten_to_notes();
+        let expected: Vec<u32> = flat[&1].iter().map(|n| n.tick).filter(|&t| (50..400).contains(&t)).collect();
+        assert_eq!(ticks, expected);
+    }
+
     #[test]
     fn test_flatten_automation_single_clip() {
         let mut arr = ArrangementState::new();
@@ -632,4 +1060,14 @@ mod tests {
         assert_eq!(flat[0].points[0].tick, 0);
         assert_eq!(flat[0].points[1].tick, 50);
     }
+
+    #[test]
+    fn set_clip_notation_fills_clip() {
+        let mut arr = ArrangementState::new();
+        let cid = arr.add_clip("A".to_string(), 1, 1920);
+        assert_eq!(arr.set_clip_notation(cid, "c4 [e4 g4]", 960), Ok(6));
+        assert!(arr.clip(cid).unwrap().notes.iter().all(|n| n.id != 0));
+        assert_eq!(arr.set_clip_notation(cid, "c4 [e4", 960).unwrap_err().span, 3..4);
+    }
+
 }
@@ -8,9 +8,10 @@ use crate::{EffectId, InstrumentId};
 pub type AutomationLaneId = u32;
 
 /// Interpolation curve type between automation points.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub enum CurveType {
     /// Linear interpolation (default)
+    #[default]
     Linear,
     /// Exponential curve (good for volume, frequency)
     Exponential,
@@ -20,12 +21,6 @@ pub enum CurveType {
     SCurve,
 }
 
-impl Default for CurveType {
-    fn default() -> Self {
-        Self::Linear
-    }
-}
-
 /// A single automation point.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AutomationPoint {
@@ -94,6 +89,8 @@ pub enum AutomationTarget {
     VstParam(InstrumentId, u32),
     /// EQ band parameter (instrument_id, band_index 0-11, param: 0=freq 1=gain 2=q)
     EqBandParam(InstrumentId, usize, usize),
+    /// Drum pad parameter (instrument_id, pad_index, param: 0=level 1=slice start 2=slice end 3=pitch)
+    DrumPadParam(InstrumentId, usize, usize),
 }
 
 impl AutomationTarget {
@@ -116,6 +113,7 @@ impl AutomationTarget {
             AutomationTarget::SendLevel(id, _) => Some(*id),
             AutomationTarget::VstParam(id, _) => Some(*id),
             AutomationTarget::EqBandParam(id, _, _) => Some(*id),
+            AutomationTarget::DrumPadParam(id, _, _) => Some(*id),
             AutomationTarget::BusLevel(_) | AutomationTarget::Bpm => None,
         }
     }
@@ -150,6 +148,15 @@ impl AutomationTarget {
                 };
                 format!("EQ B{} {}", band + 1, param_name)
             }
+            AutomationTarget::DrumPadParam(_, pad, param) => {
+                let param_name = match param {
+                    0 => "Level",
+                    1 => "Start",
+                    2 => "End",
+                    _ => "Pitch",
+                };
+                format!("Pad{} {}", pad + 1, param_name)
+            }
         }
     }
 
@@ -174,6 +181,7 @@ impl AutomationTarget {
             AutomationTarget::Bpm => "BPM",
             AutomationTarget::VstParam(_, _) => "VstP",
             AutomationTarget::EqBandParam(_, _, _) => "EqBd",
+            AutomationTarget::DrumPadParam(_, _, _) => "Pad",
         }
     }
 
@@ -228,6 +236,10 @@ impl AutomationTarget {
                 1 => (-24.0, 24.0),    // gain
                 _ => (0.1, 10.0),      // Q
             },
+            AutomationTarget::DrumPadParam(_, _, param) => match param {
+                0..=2 => (0.0, 1.0),   // level, slice start/end
+                _ => (-24.0, 24.0),    // pitch
+            },
         }
     }
 }
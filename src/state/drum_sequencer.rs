@@ -1,7 +1,104 @@
 //! Drum sequencer types.
 
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
 use serde::{Deserialize, Serialize};
 
+use super::automation::AutomationTarget;
+use super::generator::euclidean_rhythm;
+use super::probability::{HitKey, ProbabilityResolver};
+use super::trig::{TrigCondition, TrigContext};
+use crate::{BufferId, InstrumentId};
+
+pub const NUM_PADS: usize = 12;
+pub const NUM_PATTERNS: usize = 4;
+pub const DEFAULT_STEPS: usize = 16;
+/// Steps stored per pad; shorter patterns keep the rest for when they grow again
+pub const MAX_STEPS: usize = 64;
+/// Pattern lengths visited by [`DrumPattern::cycle_length`]
+pub const PATTERN_LENGTHS: [usize; 4] = [8, 16, 32, 64];
+/// Most hits a ratcheted step can play
+pub const MAX_RATCHETS: u8 = 8;
+/// Furthest a step can be nudged off the grid, as a fraction of a step
+pub const MAX_NUDGE: f32 = 0.5;
+/// Shortest gate, as a fraction of a step
+pub const MIN_GATE: f32 = 0.05;
+/// Choke groups a pad can join (numbered from 1)
+pub const NUM_CHOKE_GROUPS: u8 = 8;
+/// Highest per-pad polyphony limit
+pub const MAX_PAD_VOICES: u8 = 16;
+
+/// Stands in for the current pattern when a loaded state has none
+fn empty_pattern() -> &'static DrumPattern {
+    static EMPTY: OnceLock<DrumPattern> = OnceLock::new();
+    EMPTY.get_or_init(DrumPattern::default)
+}
+
+/// Note value of one step.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StepResolution {
+    /// 1/16
+    #[default]
+    Sixteenth,
+    /// 1/8 triplet
+    EighthTriplet,
+    /// 1/32
+    ThirtySecond,
+}
+
+impl StepResolution {
+    pub const ALL: [StepResolution; 3] =
+        [StepResolution::Sixteenth, StepResolution::EighthTriplet, StepResolution::ThirtySecond];
+
+    /// Length of a step in ticks
+    pub fn ticks(&self, ticks_per_beat: u32) -> u32 {
+        let per_beat = match self {
+            StepResolution::Sixteenth => 4,
+            StepResolution::EighthTriplet => 3,
+            StepResolution::ThirtySecond => 8,
+        };
+        (ticks_per_beat / per_beat).max(1)
+    }
+
+    pub fn next(&self) -> StepResolution {
+        let i = Self::ALL.iter().position(|r| r == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+impl fmt::Display for StepResolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StepResolution::Sixteenth => write!(f, "1/16"),
+            StepResolution::EighthTriplet => write!(f, "1/8T"),
+            StepResolution::ThirtySecond => write!(f, "1/32"),
+        }
+    }
+}
+
+/// Length and resolution of one pad's row, for polymetric patterns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PadTiming {
+    /// Steps before the row repeats; None follows the pattern length
+    pub length: Option<usize>,
+    pub resolution: StepResolution,
+}
+
+/// Where a pad's row is at a song tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PadPosition {
+    pub pattern: usize,
+    pub step: usize,
+    /// Times the row has repeated (per chain round, when chaining)
+    pub cycle: u32,
+    /// Song tick the step began at
+    pub start: u32,
+    /// Song tick the next step begins at
+    pub next: u32,
+}
+
 /// A single step in a drum pattern.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DrumStep {
@@ -9,6 +106,27 @@ pub struct DrumStep {
     pub velocity: u8,       // 1-127, default 100
     pub probability: f32,   // 0.0-1.0, default 1.0 (always play)
     pub pitch_offset: i8,   // semitone offset per step, default 0
+    /// Pass condition, checked before probability
+    #[serde(default)]
+    pub condition: TrigCondition,
+    /// Parameters overridden for this hit only
+    #[serde(default)]
+    pub locks: ParamLocks,
+    /// Micro-timing offset as a fraction of a step (-0.5 to 0.5)
+    #[serde(default)]
+    pub nudge: f32,
+    /// Extra retriggers within the step (0 plays a single hit)
+    #[serde(default)]
+    pub retrigs: u8,
+    /// Velocity change across the retriggers, -1.0 (fade to silence) to 1.0 (double)
+    #[serde(default)]
+    pub ratchet_ramp: f32,
+    /// Hit length as a fraction of a step; None holds for the whole step
+    #[serde(default)]
+    pub gate: Option<f32>,
+    /// Continue the hit from the step before instead of triggering
+    #[serde(default)]
+    pub tie: bool,
 }
 
 impl Default for DrumStep {
@@ -18,6 +136,924 @@ impl Default for DrumStep {
             velocity: 100,
             probability: 1.0,
             pitch_offset: 0,
+            condition: TrigCondition::Always,
+            locks: ParamLocks::default(),
+            nudge: 0.0,
+            retrigs: 0,
+            ratchet_ramp: 0.0,
+            gate: None,
+            tie: false,
+        }
+    }
+}
+
+impl DrumStep {
+    /// Hits the step plays, counting retriggers
+    pub fn ratchet_count(&self) -> u8 {
+        self.retrigs.min(MAX_RATCHETS - 1) + 1
+    }
+
+    /// Velocity of each retrigger, ramping linearly from the step's velocity
+    pub fn ratchet_velocities(&self) -> Vec<u8> {
+        let count = self.ratchet_count() as usize;
+        (0..count)
+            .map(|i| {
+                let t = if count > 1 { i as f32 / (count - 1) as f32 } else { 0.0 };
+                let v = self.velocity as f32 * (1.0 + self.ratchet_ramp * t);
+                v.round().clamp(1.0, 127.0) as u8
+            })
+            .collect()
+    }
+
+    /// Gate as a fraction of a step
+    pub fn gate_fraction(&self) -> f32 {
+        self.gate.unwrap_or(1.0)
+    }
+}
+
+/// Sparse set of parameter locks on a step, in each target's actual units
+/// (the same values automation lanes produce).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ParamLocks(Vec<(AutomationTarget, f32)>);
+
+impl ParamLocks {
+    pub fn get(&self, target: &AutomationTarget) -> Option<f32> {
+        self.0.iter().find(|(t, _)| t == target).map(|(_, v)| *v)
+    }
+
+    /// Lock a target, clamping to its range and replacing any earlier value
+    pub fn set(&mut self, target: AutomationTarget, value: f32) {
+        let (min, max) = target.default_range();
+        let value = value.clamp(min, max);
+        match self.0.iter_mut().find(|(t, _)| *t == target) {
+            Some(lock) => lock.1 = value,
+            None => self.0.push((target, value)),
+        }
+    }
+
+    /// Remove one lock, returning whether it was set
+    pub fn clear(&mut self, target: &AutomationTarget) -> bool {
+        let before = self.0.len();
+        self.0.retain(|(t, _)| t != target);
+        self.0.len() != before
+    }
+
+    pub fn clear_all(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Locks in the order they were first set
+    pub fn iter(&self) -> impl Iterator<Item = (&AutomationTarget, f32)> {
+        self.0.iter().map(|(t, v)| (t, *v))
+    }
+}
+
+/// How long a pad's hits sound.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PadMode {
+    /// Play the whole sample (or note) regardless of the step's gate
+    #[default]
+    OneShot,
+    /// Stop when the step's gate (plus ties) ends
+    Gate,
+}
+
+/// A sample slot (or layered instrument) triggered by one row of the pattern.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DrumPad {
+    pub name: String,
+    pub path: Option<PathBuf>,
+    /// Loaded buffer on the audio server
+    #[serde(skip)]
+    pub buffer_id: Option<BufferId>,
+    pub level: f32,       // 0.0-1.0, default 0.8
+    pub slice_start: f32, // 0.0-1.0 of the sample
+    pub slice_end: f32,
+    pub reverse: bool,
+    pub pitch: i8,        // semitones, -24 to 24
+    /// Instrument played instead of the sample
+    pub instrument_id: Option<InstrumentId>,
+    /// Pads sharing a group cut each other off (e.g. open and closed hi-hats)
+    #[serde(default)]
+    pub choke_group: Option<u8>,
+    #[serde(default)]
+    pub mode: PadMode,
+    /// Most voices sounding at once, oldest stolen first; None is unlimited
+    #[serde(default)]
+    pub max_voices: Option<u8>,
+}
+
+impl Default for DrumPad {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            path: None,
+            buffer_id: None,
+            level: 0.8,
+            slice_start: 0.0,
+            slice_end: 1.0,
+            reverse: false,
+            pitch: 0,
+            instrument_id: None,
+            choke_group: None,
+            mode: PadMode::OneShot,
+            max_voices: None,
+        }
+    }
+}
+
+impl DrumPad {
+    pub fn is_loaded(&self) -> bool {
+        self.buffer_id.is_some() || self.instrument_id.is_some()
+    }
+
+    pub fn adjust_level(&mut self, delta: f32) {
+        self.level = (self.level + delta).clamp(0.0, 1.0);
+    }
+
+    pub fn adjust_pitch(&mut self, delta: i8) {
+        self.pitch = self.pitch.saturating_add(delta).clamp(-24, 24);
+    }
+
+    pub fn toggle_reverse(&mut self) {
+        self.reverse = !self.reverse;
+    }
+
+    /// Join a group, clamped to 1..=[`NUM_CHOKE_GROUPS`]; None leaves any group
+    pub fn set_choke_group(&mut self, group: Option<u8>) {
+        self.choke_group = group.map(|g| g.clamp(1, NUM_CHOKE_GROUPS));
+    }
+
+    /// Step through no group, then groups 1 to [`NUM_CHOKE_GROUPS`]
+    pub fn cycle_choke_group(&mut self) {
+        self.choke_group = match self.choke_group {
+            None => Some(1),
+            Some(g) if g < NUM_CHOKE_GROUPS => Some(g + 1),
+            Some(_) => None,
+        };
+    }
+
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            PadMode::OneShot => PadMode::Gate,
+            PadMode::Gate => PadMode::OneShot,
+        };
+    }
+
+    /// Change the polyphony limit; going below one voice removes the limit
+    pub fn adjust_max_voices(&mut self, delta: i8) {
+        let voices = self.max_voices.unwrap_or(0) as i16 + delta as i16;
+        let voices = voices.clamp(0, MAX_PAD_VOICES as i16) as u8;
+        self.max_voices = (voices > 0).then_some(voices);
+    }
+
+    /// Whether hitting this pad cuts off `other`
+    pub fn chokes(&self, other: &DrumPad) -> bool {
+        self.choke_group.is_some() && self.choke_group == other.choke_group
+    }
+
+    /// Forget the sample, keeping level, pitch and slice settings
+    pub fn clear_sample(&mut self) {
+        self.path = None;
+        self.buffer_id = None;
+        self.name.clear();
+    }
+}
+
+/// Steps for every pad, of which the first `length` play (or the pad's own
+/// length, if it has one).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DrumPattern {
+    /// `steps[pad][step]`, each pad holding [`MAX_STEPS`] steps
+    pub steps: Vec<Vec<DrumStep>>,
+    /// Length in sixteenths; also the span of the pattern when chaining
+    pub length: usize,
+    /// Per-pad length and resolution overrides
+    #[serde(default)]
+    pub pad_timing: Vec<PadTiming>,
+}
+
+impl Default for DrumPattern {
+    fn default() -> Self {
+        Self::new(DEFAULT_STEPS)
+    }
+}
+
+impl DrumPattern {
+    pub fn new(length: usize) -> Self {
+        Self {
+            steps: vec![vec![DrumStep::default(); MAX_STEPS]; NUM_PADS],
+            length: length.clamp(1, MAX_STEPS),
+            pad_timing: vec![PadTiming::default(); NUM_PADS],
+        }
+    }
+
+    pub fn pad_timing(&self, pad: usize) -> PadTiming {
+        self.pad_timing.get(pad).copied().unwrap_or_default()
+    }
+
+    fn pad_timing_mut(&mut self, pad: usize) -> Option<&mut PadTiming> {
+        if pad >= self.steps.len() {
+            return None;
+        }
+        if self.pad_timing.len() <= pad {
+            self.pad_timing.resize(pad + 1, PadTiming::default());
+        }
+        self.pad_timing.get_mut(pad)
+    }
+
+    /// Steps that play on a pad before its row repeats
+    pub fn pad_length(&self, pad: usize) -> usize {
+        self.pad_timing(pad).length.unwrap_or(self.length).clamp(1, MAX_STEPS)
+    }
+
+    /// Length of one of a pad's steps in ticks
+    pub fn pad_step_ticks(&self, pad: usize, ticks_per_beat: u32) -> u32 {
+        self.pad_timing(pad).resolution.ticks(ticks_per_beat)
+    }
+
+    /// Change a pad's own length, starting from the pattern length
+    pub fn adjust_pad_length(&mut self, pad: usize, delta: i8) {
+        let length = (self.pad_length(pad) as i16 + delta as i16).clamp(1, MAX_STEPS as i16) as usize;
+        if let Some(timing) = self.pad_timing_mut(pad) {
+            timing.length = Some(length);
+        }
+    }
+
+    /// Make a pad follow the pattern length again
+    pub fn reset_pad_length(&mut self, pad: usize) {
+        if let Some(timing) = self.pad_timing_mut(pad) {
+            timing.length = None;
+        }
+    }
+
+    pub fn cycle_pad_resolution(&mut self, pad: usize) {
+        if let Some(timing) = self.pad_timing_mut(pad) {
+            timing.resolution = timing.resolution.next();
         }
     }
+
+    /// (length, step ticks) of every pad
+    pub fn pad_layout(&self, ticks_per_beat: u32) -> Vec<(usize, u32)> {
+        (0..self.steps.len())
+            .map(|pad| (self.pad_length(pad), self.pad_step_ticks(pad, ticks_per_beat)))
+            .collect()
+    }
+
+    pub fn step(&self, pad: usize, step: usize) -> Option<&DrumStep> {
+        self.steps.get(pad)?.get(step)
+    }
+
+    pub fn step_mut(&mut self, pad: usize, step: usize) -> Option<&mut DrumStep> {
+        self.steps.get_mut(pad)?.get_mut(step)
+    }
+
+    pub fn toggle_step(&mut self, pad: usize, step: usize) {
+        if let Some(s) = self.step_mut(pad, step) {
+            s.active = !s.active;
+            if s.active {
+                s.tie = false;
+            }
+        }
+    }
+
+    pub fn adjust_velocity(&mut self, pad: usize, step: usize, delta: i8) {
+        if let Some(s) = self.step_mut(pad, step) {
+            s.velocity = (s.velocity as i16 + delta as i16).clamp(1, 127) as u8;
+        }
+    }
+
+    pub fn adjust_probability(&mut self, pad: usize, step: usize, delta: f32) {
+        if let Some(s) = self.step_mut(pad, step) {
+            s.probability = (s.probability + delta).clamp(0.0, 1.0);
+        }
+    }
+
+    pub fn adjust_step_pitch(&mut self, pad: usize, step: usize, delta: i8) {
+        if let Some(s) = self.step_mut(pad, step) {
+            s.pitch_offset = s.pitch_offset.saturating_add(delta).clamp(-24, 24);
+        }
+    }
+
+    pub fn adjust_nudge(&mut self, pad: usize, step: usize, delta: f32) {
+        if let Some(s) = self.step_mut(pad, step) {
+            s.nudge = (s.nudge + delta).clamp(-MAX_NUDGE, MAX_NUDGE);
+        }
+    }
+
+    pub fn adjust_retrigs(&mut self, pad: usize, step: usize, delta: i8) {
+        if let Some(s) = self.step_mut(pad, step) {
+            s.retrigs = (s.retrigs as i16 + delta as i16).clamp(0, MAX_RATCHETS as i16 - 1) as u8;
+        }
+    }
+
+    pub fn adjust_ratchet_ramp(&mut self, pad: usize, step: usize, delta: f32) {
+        if let Some(s) = self.step_mut(pad, step) {
+            s.ratchet_ramp = (s.ratchet_ramp + delta).clamp(-1.0, 1.0);
+        }
+    }
+
+    /// Lengthen or shorten the gate; reaching a full step goes back to the default
+    pub fn adjust_gate(&mut self, pad: usize, step: usize, delta: f32) {
+        if let Some(s) = self.step_mut(pad, step) {
+            let gate = (s.gate_fraction() + delta).clamp(MIN_GATE, 1.0);
+            s.gate = (gate < 1.0).then_some(gate);
+        }
+    }
+
+    /// Tie a step to the one before it. A tied step never triggers; the
+    /// first step can't be tied.
+    pub fn toggle_tie(&mut self, pad: usize, step: usize) {
+        if step == 0 {
+            return;
+        }
+        if let Some(s) = self.step_mut(pad, step) {
+            s.tie = !s.tie;
+            if s.tie {
+                s.active = false;
+            }
+        }
+    }
+
+    /// Tied steps directly after `step` (within the pad's length)
+    pub fn tied_after(&self, pad: usize, step: usize) -> usize {
+        self.steps.get(pad).map_or(0, |row| {
+            row.iter().take(self.pad_length(pad)).skip(step + 1).take_while(|s| s.tie).count()
+        })
+    }
+
+    pub fn set_condition(&mut self, pad: usize, step: usize, condition: TrigCondition) {
+        if let Some(s) = self.step_mut(pad, step) {
+            s.condition = condition;
+        }
+    }
+
+    pub fn cycle_condition(&mut self, pad: usize, step: usize) {
+        if let Some(s) = self.step_mut(pad, step) {
+            s.condition = s.condition.next();
+        }
+    }
+
+    pub fn set_lock(&mut self, pad: usize, step: usize, target: AutomationTarget, value: f32) {
+        if let Some(s) = self.step_mut(pad, step) {
+            s.locks.set(target, value);
+        }
+    }
+
+    pub fn clear_lock(&mut self, pad: usize, step: usize, target: &AutomationTarget) {
+        if let Some(s) = self.step_mut(pad, step) {
+            s.locks.clear(target);
+        }
+    }
+
+    pub fn clear_locks(&mut self, pad: usize, step: usize) {
+        if let Some(s) = self.step_mut(pad, step) {
+            s.locks.clear_all();
+        }
+    }
+
+    /// Active steps on a pad that play on pass `iteration`: each step's
+    /// condition is checked (with the outcome of the previous active step),
+    /// then its probability is rolled
+    pub fn fired_steps(
+        &self,
+        instrument_id: InstrumentId,
+        pad: usize,
+        iteration: u32,
+        fill: bool,
+        resolver: &ProbabilityResolver,
+    ) -> Vec<usize> {
+        let mut fired = Vec::new();
+        let mut previous = false;
+        for step in self.active_steps(pad) {
+            let s = &self.steps[pad][step];
+            let ctx = TrigContext { iteration, fill, previous };
+            previous = s.condition.evaluate(&ctx)
+                && resolver.fires(HitKey::Step { instrument_id, pad, step }, s.probability, iteration);
+            if previous {
+                fired.push(step);
+            }
+        }
+        fired
+    }
+
+    /// Reset every step on a pad
+    pub fn clear_pad(&mut self, pad: usize) {
+        if let Some(steps) = self.steps.get_mut(pad) {
+            steps.fill(DrumStep::default());
+        }
+    }
+
+    /// Reset every step, keeping the length
+    pub fn clear(&mut self) {
+        for pad in 0..self.steps.len() {
+            self.clear_pad(pad);
+        }
+    }
+
+    /// Reset steps in a rectangular region (inclusive bounds)
+    pub fn clear_region(&mut self, pads: (usize, usize), steps: (usize, usize)) {
+        for pad in pads.0.min(pads.1)..=pads.0.max(pads.1) {
+            for step in steps.0.min(steps.1)..=steps.0.max(steps.1) {
+                if let Some(s) = self.step_mut(pad, step) {
+                    *s = DrumStep::default();
+                }
+            }
+        }
+    }
+
+    /// Move to the next of [`PATTERN_LENGTHS`], wrapping around
+    pub fn cycle_length(&mut self) {
+        let next = PATTERN_LENGTHS.iter().position(|&l| l > self.length).unwrap_or(0);
+        self.length = PATTERN_LENGTHS[next];
+    }
+
+    /// Set a pad's active steps across the pattern from a Euclidean rhythm of
+    /// `steps` steps, repeated. Velocity, probability and pitch are kept.
+    pub fn apply_euclidean(&mut self, pad: usize, pulses: usize, steps: usize, rotation: usize) {
+        let rhythm = euclidean_rhythm(pulses, steps, rotation);
+        let length = self.pad_length(pad);
+        let Some(row) = self.steps.get_mut(pad) else {
+            return;
+        };
+        if rhythm.is_empty() {
+            return;
+        }
+        for (i, step) in row.iter_mut().take(length).enumerate() {
+            step.active = rhythm[i % rhythm.len()];
+        }
+    }
+
+    /// Active step indices within the pad's length
+    pub fn active_steps(&self, pad: usize) -> Vec<usize> {
+        self.steps.get(pad).map_or_else(Vec::new, |row| {
+            row.iter().take(self.pad_length(pad)).enumerate().filter(|(_, s)| s.active).map(|(i, _)| i).collect()
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..self.steps.len()).all(|pad| self.active_steps(pad).is_empty())
+    }
+}
+
+/// Order patterns play in when chaining is on.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PatternChain {
+    /// Pattern indices, in play order
+    pub patterns: Vec<usize>,
+    pub enabled: bool,
+    /// Position of the pattern now playing
+    #[serde(skip)]
+    pub position: usize,
+}
+
+impl PatternChain {
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.position = 0;
+    }
+
+    pub fn add(&mut self, pattern: usize) {
+        self.patterns.push(pattern);
+    }
+
+    pub fn remove(&mut self, position: usize) {
+        if position < self.patterns.len() {
+            self.patterns.remove(position);
+            if self.position >= self.patterns.len() {
+                self.position = 0;
+            }
+        }
+    }
+
+    /// Move an entry, clamping the destination to the end of the chain
+    pub fn move_step(&mut self, from: usize, to: usize) {
+        if from < self.patterns.len() {
+            let pattern = self.patterns.remove(from);
+            self.patterns.insert(to.min(self.patterns.len()), pattern);
+        }
+    }
+
+    /// Pattern at the current position, if chaining applies
+    pub fn current(&self) -> Option<usize> {
+        if !self.enabled {
+            return None;
+        }
+        self.patterns.get(self.position).copied()
+    }
+
+    /// Step to the next entry (wrapping) and return its pattern
+    pub fn advance(&mut self) -> Option<usize> {
+        if !self.enabled || self.patterns.is_empty() {
+            return None;
+        }
+        self.position = (self.position + 1) % self.patterns.len();
+        self.current()
+    }
+}
+
+/// Pads, patterns and chain of one drum machine instrument.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DrumSequencerState {
+    pub pads: Vec<DrumPad>,
+    pub patterns: Vec<DrumPattern>,
+    pub current_pattern: usize,
+    pub swing_amount: f32, // 0.0-1.0, delay applied to every other step
+    pub chain: PatternChain,
+    /// Fill mode held (enables Fill trig conditions)
+    #[serde(skip)]
+    pub fill: bool,
+}
+
+impl Default for DrumSequencerState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DrumSequencerState {
+    pub fn new() -> Self {
+        Self {
+            pads: vec![DrumPad::default(); NUM_PADS],
+            patterns: vec![DrumPattern::default(); NUM_PATTERNS],
+            current_pattern: 0,
+            swing_amount: 0.0,
+            chain: PatternChain::default(),
+            fill: false,
+        }
+    }
+
+    /// `current_pattern`, clamped to the pattern list (loaded state may not be)
+    fn current_index(&self) -> usize {
+        self.current_pattern.min(self.patterns.len().saturating_sub(1))
+    }
+
+    pub fn pattern(&self) -> &DrumPattern {
+        self.patterns.get(self.current_index()).unwrap_or(empty_pattern())
+    }
+
+    /// The current pattern, adding one first if there are none
+    pub fn pattern_mut(&mut self) -> &mut DrumPattern {
+        if self.patterns.is_empty() {
+            self.patterns.push(DrumPattern::default());
+        }
+        let index = self.current_index();
+        &mut self.patterns[index]
+    }
+
+    pub fn pad(&self, index: usize) -> Option<&DrumPad> {
+        self.pads.get(index)
+    }
+
+    pub fn pad_mut(&mut self, index: usize) -> Option<&mut DrumPad> {
+        self.pads.get_mut(index)
+    }
+
+    pub fn next_pattern(&mut self) {
+        if !self.patterns.is_empty() {
+            self.current_pattern = (self.current_index() + 1) % self.patterns.len();
+        }
+    }
+
+    pub fn prev_pattern(&mut self) {
+        let count = self.patterns.len();
+        if count > 0 {
+            self.current_pattern = (self.current_index() + count - 1) % count;
+        }
+    }
+
+    pub fn adjust_swing(&mut self, delta: f32) {
+        self.swing_amount = (self.swing_amount + delta).clamp(0.0, 1.0);
+    }
+
+    /// Chain entries pointing past the pattern list are ignored
+    pub fn add_chain_step(&mut self, pattern: usize) {
+        if pattern < self.patterns.len() {
+            self.chain.add(pattern);
+        }
+    }
+
+    pub fn toggle_chain(&mut self) {
+        self.chain.toggle();
+        if let Some(pattern) = self.chain.current() {
+            self.current_pattern = pattern;
+        }
+    }
+
+    /// Called when the current pattern finishes a pass: follows the chain
+    /// if it is enabled, otherwise keeps the current pattern
+    pub fn pattern_finished(&mut self) -> usize {
+        if let Some(pattern) = self.chain.advance().filter(|&p| p < self.patterns.len()) {
+            self.current_pattern = pattern;
+        }
+        self.current_index()
+    }
+
+    /// Whether playback follows the chain
+    fn chained(&self) -> bool {
+        self.chain.enabled && self.chain.patterns.iter().any(|&p| p < self.patterns.len())
+    }
+
+    /// Patterns played in order: the chain's valid entries when it is
+    /// enabled, otherwise just the current pattern
+    fn play_order(&self) -> Vec<usize> {
+        let chain: Vec<usize> = if self.chain.enabled {
+            self.chain.patterns.iter().copied().filter(|&p| p < self.patterns.len()).collect()
+        } else {
+            Vec::new()
+        };
+        if chain.is_empty() {
+            vec![self.current_index()]
+        } else {
+            chain
+        }
+    }
+
+    /// Length of a pattern in sixteenths, at least one
+    fn pattern_length(&self, pattern: usize) -> u64 {
+        self.patterns.get(pattern).unwrap_or(empty_pattern()).length.max(1) as u64
+    }
+
+    /// Where playback is `step` steps after the sequence started, following
+    /// the chain from its first entry: (pattern, step within it, round),
+    /// where a round is one pass through the whole chain
+    pub fn position_at(&self, step: u64) -> (usize, usize, u32) {
+        let order = self.play_order();
+        let total: u64 = order.iter().map(|&p| self.pattern_length(p)).sum();
+        let round = (step / total) as u32;
+        let mut rem = step % total;
+        for &pattern in &order {
+            let length = self.pattern_length(pattern);
+            if rem < length {
+                return (pattern, rem as usize, round);
+            }
+            rem -= length;
+        }
+        (order[0], 0, round)
+    }
+
+    /// Where a pad's row is at song tick `tick`. Rows run freely from tick 0
+    /// at their own length and resolution; with the chain enabled, each
+    /// pattern play (its length in sixteenths) restarts them.
+    pub fn pad_position(&self, pad: usize, tick: u32, ticks_per_beat: u32) -> PadPosition {
+        let sixteenth = StepResolution::Sixteenth.ticks(ticks_per_beat);
+        let (pattern, segment, round) = if self.chained() {
+            let index = tick / sixteenth;
+            let (pattern, step, round) = self.position_at(index as u64);
+            let start = (index - step as u32) * sixteenth;
+            let end = start + self.pattern_length(pattern) as u32 * sixteenth;
+            (pattern, Some((start, end)), round)
+        } else {
+            (self.current_index(), None, 0)
+        };
+        let p = self.patterns.get(pattern).unwrap_or(empty_pattern());
+        let step_ticks = p.pad_step_ticks(pad, ticks_per_beat);
+        let length = p.pad_length(pad) as u32;
+        let origin = segment.map_or(0, |(start, _)| start);
+        let local = (tick - origin) / step_ticks;
+        let start = origin + local * step_ticks;
+        let (next, cycle) = match segment {
+            Some((seg_start, seg_end)) => {
+                let per_play = (seg_end - seg_start).div_ceil(step_ticks * length);
+                ((start + step_ticks).min(seg_end), round * per_play + local / length)
+            }
+            None => (start + step_ticks, local / length),
+        };
+        PadPosition { pattern, step: (local % length) as usize, cycle, start, next }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn euclidean_fill_clear_and_length_cycling() {
+        let mut pattern = DrumPattern::default();
+        pattern.adjust_velocity(0, 4, 20);
+        pattern.apply_euclidean(0, 3, 8, 0);
+        assert_eq!(pattern.active_steps(0), vec![0, 3, 6, 8, 11, 14]);
+        // Velocity survives the fill
+        assert_eq!(pattern.step(0, 4).map(|s| s.velocity), Some(120));
+
+        pattern.cycle_length();
+        assert_eq!(pattern.length, 32);
+        pattern.length = 64;
+        pattern.cycle_length();
+        assert_eq!(pattern.length, 8);
+        assert_eq!(pattern.active_steps(0), vec![0, 3, 6]);
+        pattern.length = 16;
+
+        pattern.toggle_step(1, 2);
+        pattern.clear_pad(0);
+        assert!(pattern.active_steps(0).is_empty());
+        assert!(!pattern.is_empty());
+        pattern.clear();
+        assert!(pattern.is_empty());
+    }
+
+    #[test]
+    fn conditions_and_probability_pick_fired_steps() {
+        let mut pattern = DrumPattern::default();
+        for step in [0, 4, 8, 12] {
+            pattern.toggle_step(0, step);
+        }
+        pattern.set_condition(0, 4, TrigCondition::Ratio { a: 2, b: 2 });
+        pattern.set_condition(0, 8, TrigCondition::Previous);
+        pattern.set_condition(0, 12, TrigCondition::Fill);
+        let resolver = ProbabilityResolver::new(1);
+
+        assert_eq!(pattern.fired_steps(1, 0, 0, false, &resolver), vec![0]);
+        assert_eq!(pattern.fired_steps(1, 0, 1, false, &resolver), vec![0, 4, 8]);
+        assert_eq!(pattern.fired_steps(1, 0, 2, true, &resolver), vec![0, 12]);
+
+        // A step that never fires blocks the Previous one after it
+        pattern.cycle_condition(0, 4);
+        assert_eq!(pattern.step(0, 4).map(|s| s.condition), Some(TrigCondition::Ratio { a: 1, b: 4 }));
+        pattern.set_condition(0, 4, TrigCondition::Always);
+        pattern.adjust_probability(0, 4, -1.0);
+        assert_eq!(pattern.fired_steps(1, 0, 1, false, &resolver), vec![0]);
+    }
+
+    #[test]
+    fn chain_follows_pattern_order() {
+        let mut seq = DrumSequencerState::new();
+        seq.add_chain_step(2);
+        seq.add_chain_step(0);
+        seq.add_chain_step(9);
+        seq.add_chain_step(3);
+        assert_eq!(seq.chain.patterns, vec![2, 0, 3]);
+
+        // Without the chain, patterns repeat
+        assert_eq!(seq.pattern_finished(), 0);
+        seq.toggle_chain();
+        assert_eq!(seq.current_pattern, 2);
+        assert_eq!(seq.pattern_finished(), 0);
+        assert_eq!(seq.pattern_finished(), 3);
+        assert_eq!(seq.pattern_finished(), 2);
+
+        seq.chain.move_step(0, 5);
+        assert_eq!(seq.chain.patterns, vec![0, 3, 2]);
+        seq.chain.remove(1);
+        assert_eq!(seq.chain.patterns, vec![0, 2]);
+
+        seq.prev_pattern();
+        assert_eq!(seq.current_pattern, 1);
+        seq.pads[0].adjust_pitch(30);
+        assert_eq!(seq.pads[0].pitch, 24);
+    }
+
+    #[test]
+    fn tolerates_missing_or_out_of_range_patterns() {
+        let mut seq = DrumSequencerState::new();
+        seq.current_pattern = 9;
+        assert_eq!(seq.pad_position(0, 0, 480).pattern, NUM_PATTERNS - 1);
+        seq.next_pattern();
+        assert_eq!(seq.current_pattern, 0);
+
+        seq.patterns.clear();
+        seq.current_pattern = 2;
+        assert_eq!(seq.pattern().length, DEFAULT_STEPS);
+        seq.next_pattern();
+        seq.prev_pattern();
+        assert_eq!(seq.position_at(20), (0, 4, 1));
+        assert_eq!(seq.pad_position(0, 480, 480).step, 4);
+        seq.pattern_mut().toggle_step(0, 0);
+        assert_eq!(seq.patterns.len(), 1);
+        assert_eq!(seq.pattern().active_steps(0), vec![0]);
+    }
+
+    #[test]
+    fn param_locks_and_chained_positions() {
+        let mut pattern = DrumPattern::default();
+        let cutoff = AutomationTarget::FilterCutoff(3);
+        pattern.set_lock(2, 5, cutoff.clone(), 50_000.0);
+        pattern.set_lock(2, 5, AutomationTarget::SendLevel(3, 0), 0.5);
+        pattern.set_lock(2, 5, cutoff.clone(), 1200.0);
+        let locks = &pattern.step(2, 5).unwrap().locks;
+        assert_eq!(locks.len(), 2);
+        assert_eq!(locks.get(&cutoff), Some(1200.0));
+        pattern.clear_lock(2, 5, &cutoff);
+        assert_eq!(pattern.step(2, 5).unwrap().locks.get(&cutoff), None);
+        pattern.clear_locks(2, 5);
+        assert!(pattern.step(2, 5).unwrap().locks.is_empty());
+
+        // Values are clamped to the target's range
+        let mut locks = ParamLocks::default();
+        locks.set(cutoff.clone(), 50_000.0);
+        assert_eq!(locks.get(&cutoff), Some(20000.0));
+
+        let mut seq = DrumSequencerState::new();
+        assert_eq!(seq.position_at(17), (0, 1, 1));
+        seq.patterns[1].length = 8;
+        seq.add_chain_step(1);
+        seq.add_chain_step(0);
+        seq.toggle_chain();
+        assert_eq!(seq.position_at(7), (1, 7, 0));
+        assert_eq!(seq.position_at(8), (0, 0, 0));
+        assert_eq!(seq.position_at(25), (1, 1, 1));
+    }
+
+    #[test]
+    fn ratchets_gates_and_ties() {
+        let mut pattern = DrumPattern::default();
+        pattern.toggle_step(0, 0);
+        pattern.adjust_retrigs(0, 0, 3);
+        pattern.adjust_velocity(0, 0, -20);
+        pattern.adjust_ratchet_ramp(0, 0, -0.5);
+        let step = pattern.step(0, 0).unwrap();
+        assert_eq!(step.ratchet_count(), 4);
+        assert_eq!(step.ratchet_velocities(), vec![80, 67, 53, 40]);
+        pattern.adjust_retrigs(0, 0, 20);
+        assert_eq!(pattern.step(0, 0).unwrap().ratchet_count(), MAX_RATCHETS);
+
+        pattern.adjust_nudge(0, 0, -0.8);
+        assert_eq!(pattern.step(0, 0).unwrap().nudge, -MAX_NUDGE);
+        pattern.adjust_gate(0, 0, -0.5);
+        assert_eq!(pattern.step(0, 0).unwrap().gate, Some(0.5));
+        pattern.adjust_gate(0, 0, 0.7);
+        assert_eq!(pattern.step(0, 0).unwrap().gate, None);
+
+        pattern.toggle_tie(0, 0);
+        assert!(!pattern.step(0, 0).unwrap().tie);
+        pattern.toggle_step(0, 2);
+        pattern.toggle_tie(0, 1);
+        pattern.toggle_tie(0, 2);
+        assert_eq!(pattern.tied_after(0, 0), 2);
+        assert_eq!(pattern.active_steps(0), vec![0]);
+        pattern.toggle_step(0, 2);
+        assert_eq!(pattern.tied_after(0, 0), 1);
+    }
+
+    #[test]
+    fn pads_run_at_their_own_length_and_resolution() {
+        let mut seq = DrumSequencerState::new();
+        let pattern = seq.pattern_mut();
+        pattern.adjust_pad_length(1, -11);
+        assert_eq!(pattern.pad_length(1), 5);
+        pattern.cycle_pad_resolution(2);
+        pattern.cycle_pad_resolution(2);
+        assert_eq!(pattern.pad_timing(2).resolution, StepResolution::ThirtySecond);
+        pattern.toggle_step(1, 7);
+        assert!(pattern.active_steps(1).is_empty());
+
+        // 120 ticks per sixteenth at 480 tpb
+        let at = |seq: &DrumSequencerState, pad, tick| {
+            let p = seq.pad_position(pad, tick, 480);
+            (p.step, p.cycle, p.start)
+        };
+        assert_eq!(at(&seq, 0, 1930), (0, 1, 1920));
+        assert_eq!(at(&seq, 1, 1930), (1, 3, 1920));
+        assert_eq!(at(&seq, 2, 1930), (0, 2, 1920));
+
+        // Chained patterns restart rows at each pattern boundary
+        seq.patterns[1].length = 6;
+        seq.add_chain_step(0);
+        seq.add_chain_step(1);
+        seq.toggle_chain();
+        let p = seq.pad_position(1, 1800, 480);
+        assert_eq!((p.step, p.next), (0, 1920));
+        assert_eq!(at(&seq, 1, 1920), (0, 0, 1920));
+        assert_eq!(seq.pad_position(1, 1920, 480).pattern, 1);
+        seq.pattern_mut().reset_pad_length(1);
+        assert_eq!(seq.pattern().pad_length(1), 16);
+    }
+
+    #[test]
+    fn pad_choke_groups_modes_and_voices() {
+        let mut pads = vec![DrumPad::default(); 3];
+        pads[0].cycle_choke_group();
+        pads[1].cycle_choke_group();
+        assert!(pads[0].chokes(&pads[1]));
+        assert!(!pads[0].chokes(&pads[2]));
+        assert!(!pads[2].chokes(&DrumPad::default()));
+        for _ in 0..NUM_CHOKE_GROUPS {
+            pads[1].cycle_choke_group();
+        }
+        assert_eq!(pads[1].choke_group, None);
+        pads[1].set_choke_group(Some(40));
+        assert_eq!(pads[1].choke_group, Some(NUM_CHOKE_GROUPS));
+        pads[1].set_choke_group(Some(0));
+        assert_eq!(pads[1].choke_group, Some(1));
+        pads[1].set_choke_group(None);
+        assert_eq!(pads[1].choke_group, None);
+
+        pads[2].toggle_mode();
+        assert_eq!(pads[2].mode, PadMode::Gate);
+        pads[2].adjust_max_voices(2);
+        assert_eq!(pads[2].max_voices, Some(2));
+        pads[2].adjust_max_voices(-5);
+        assert_eq!(pads[2].max_voices, None);
+        pads[2].adjust_max_voices(100);
+        assert_eq!(pads[2].max_voices, Some(MAX_PAD_VOICES));
+    }
 }
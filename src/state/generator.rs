@@ -0,0 +1,354 @@
+//! Seeded generative note generators.
+//!
+//! Every generator is a pure function of its parameters and seed, filling a
+//! region of a track or clip with notes. Generated notes have id 0; callers
+//! assign ids when inserting them.
+
+use std::collections::HashMap;
+
+use super::music::{Key, Scale};
+use super::piano_roll::Note;
+use crate::SeededRng;
+
+/// Where and at what grid a generator writes notes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GeneratorRegion {
+    pub start_tick: u32,
+    pub length_ticks: u32,
+    /// Grid spacing of generated steps
+    pub step_ticks: u32,
+    pub velocity: u8,
+}
+
+impl GeneratorRegion {
+    pub fn new(start_tick: u32, length_ticks: u32, step_ticks: u32) -> Self {
+        Self { start_tick, length_ticks, step_ticks: step_ticks.max(1), velocity: 100 }
+    }
+
+    pub fn end_tick(&self) -> u32 {
+        self.start_tick + self.length_ticks
+    }
+
+    /// Number of whole steps in the region
+    pub fn steps(&self) -> usize {
+        (self.length_ticks / self.step_ticks.max(1)) as usize
+    }
+
+    fn step_tick(&self, step: usize) -> u32 {
+        self.start_tick + step as u32 * self.step_ticks.max(1)
+    }
+}
+
+/// A generator with its parameters, carried by generate actions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoteGenerator {
+    /// Random pitches from the scale within [low, high]; `density` is the chance of a note per step
+    RandomInScale { density: f32, low: u8, high: u8, key: Key, scale: Scale, seed: u64 },
+    /// First-order Markov chain trained on the source notes
+    Markov { seed: u64 },
+    /// Euclidean rhythm cycling through `pitches` on each hit
+    Euclidean { pulses: usize, steps: usize, rotation: usize, pitches: Vec<u8> },
+    /// L-system rhythm: uppercase symbols are hits, everything else rests
+    LSystem { axiom: String, rules: Vec<(char, String)>, iterations: u8, pitch: u8 },
+    /// Elementary cellular automaton: live cells of the final generation are hits
+    CellularAutomaton { rule: u8, generations: u32, pitch: u8, seed: u64 },
+    /// Vary the source notes; `amount` (0.0-1.0) is the chance each note changes
+    Mutate { amount: f32, key: Key, scale: Scale, seed: u64 },
+}
+
+impl NoteGenerator {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoteGenerator::RandomInScale { .. } => "Random",
+            NoteGenerator::Markov { .. } => "Markov",
+            NoteGenerator::Euclidean { .. } => "Euclidean",
+            NoteGenerator::LSystem { .. } => "L-System",
+            NoteGenerator::CellularAutomaton { .. } => "Automaton",
+            NoteGenerator::Mutate { .. } => "Mutate",
+        }
+    }
+
+    /// Generate notes for the region. `source` is what Markov trains on and
+    /// what Mutate varies (usually the notes currently in the region).
+    pub fn generate(&self, region: &GeneratorRegion, source: &[Note]) -> Vec<Note> {
+        match self {
+            NoteGenerator::RandomInScale { density, low, high, key, scale, seed } => {
+                random_in_scale(region, *density, *low, *high, *key, *scale, *seed)
+            }
+            NoteGenerator::Markov { seed } => markov(region, source, *seed),
+            NoteGenerator::Euclidean { pulses, steps, rotation, pitches } => {
+                euclidean(region, *pulses, *steps, *rotation, pitches)
+            }
+            NoteGenerator::LSystem { axiom, rules, iterations, pitch } => {
+                let pattern = lsystem_rhythm(axiom, rules, *iterations);
+                rhythm_notes(region, &pattern, &[*pitch])
+            }
+            NoteGenerator::CellularAutomaton { rule, generations, pitch, seed } => {
+                let pattern = automaton_rhythm(*rule, region.steps(), *generations, *seed);
+                rhythm_notes(region, &pattern, &[*pitch])
+            }
+            NoteGenerator::Mutate { amount, key, scale, seed } => {
+                mutate(source, region, *amount, *key, *scale, *seed)
+            }
+        }
+    }
+}
+
+/// Every pitch of the scale in [low, high]
+pub fn scale_pitches(key: Key, scale: Scale, low: u8, high: u8) -> Vec<u8> {
+    let root = key.semitone();
+    (low.min(high)..=high.max(low))
+        .filter(|&p| scale.intervals().contains(&(p as i32 - root).rem_euclid(12)))
+        .collect()
+}
+
+/// Distribute `pulses` hits as evenly as possible over `steps`, rotated right by `rotation`
+pub fn euclidean_rhythm(pulses: usize, steps: usize, rotation: usize) -> Vec<bool> {
+    if steps == 0 {
+        return Vec::new();
+    }
+    let pulses = pulses.min(steps);
+    let pattern: Vec<bool> = (0..steps).map(|i| (i * pulses) % steps < pulses).collect();
+    let mut rotated = pattern.clone();
+    for (i, hit) in pattern.into_iter().enumerate() {
+        rotated[(i + rotation) % steps] = hit;
+    }
+    rotated
+}
+
+/// Notes on the region grid wherever `pattern` (cycled) has a hit, cycling through `pitches`
+pub fn rhythm_notes(region: &GeneratorRegion, pattern: &[bool], pitches: &[u8]) -> Vec<Note> {
+    if pattern.is_empty() || pitches.is_empty() {
+        return Vec::new();
+    }
+    let mut notes = Vec::new();
+    for step in 0..region.steps() {
+        if pattern[step % pattern.len()] {
+            let pitch = pitches[notes.len() % pitches.len()];
+            notes.push(Note::new(region.step_tick(step), region.step_ticks, pitch, region.velocity));
+        }
+    }
+    notes
+}
+
+pub fn random_in_scale(
+    region: &GeneratorRegion,
+    density: f32,
+    low: u8,
+    high: u8,
+    key: Key,
+    scale: Scale,
+    seed: u64,
+) -> Vec<Note> {
+    let pitches = scale_pitches(key, scale, low, high);
+    if pitches.is_empty() {
+        return Vec::new();
+    }
+    let mut rng = SeededRng::new(seed);
+    let mut notes = Vec::new();
+    for step in 0..region.steps() {
+        if rng.chance(density) {
+            let pitch = pitches[rng.below(pitches.len() as u32) as usize];
+            notes.push(Note::new(region.step_tick(step), region.step_ticks, pitch, region.velocity));
+        }
+    }
+    notes
+}
+
+/// Markov state: a note and the gap to the note after it
+type MarkovToken = (u8, u32, u8, u32); // (pitch, duration, velocity, gap)
+
+pub fn markov(region: &GeneratorRegion, source: &[Note], seed: u64) -> Vec<Note> {
+    let mut sorted: Vec<&Note> = source.iter().collect();
+    sorted.sort_by_key(|n| (n.tick, n.pitch));
+    if sorted.is_empty() {
+        return Vec::new();
+    }
+    let tokens: Vec<MarkovToken> = sorted
+        .iter()
+        .enumerate()
+        .map(|(i, n)| {
+            let gap = sorted.get(i + 1).map_or(region.step_ticks, |next| next.tick - n.tick);
+            (n.pitch, n.duration, n.velocity, gap)
+        })
+        .collect();
+    let mut transitions: HashMap<MarkovToken, Vec<MarkovToken>> = HashMap::new();
+    for pair in tokens.windows(2) {
+        transitions.entry(pair[0]).or_default().push(pair[1]);
+    }
+
+    let mut rng = SeededRng::new(seed);
+    let mut notes = Vec::new();
+    let mut tick = region.start_tick;
+    let mut token = tokens[rng.below(tokens.len() as u32) as usize];
+    // Chords (gap 0) can't stall generation forever
+    let max_notes = region.length_ticks as usize / region.step_ticks.max(1) as usize * 8 + 8;
+    while tick < region.end_tick() && notes.len() < max_notes {
+        let (pitch, duration, velocity, gap) = token;
+        notes.push(Note::new(tick, duration.min(region.end_tick() - tick).max(1), pitch, velocity));
+        tick += gap;
+        token = match transitions.get(&token) {
+            Some(next) => next[rng.below(next.len() as u32) as usize],
+            None => tokens[rng.below(tokens.len() as u32) as usize],
+        };
+    }
+    notes
+}
+
+pub fn euclidean(
+    region: &GeneratorRegion,
+    pulses: usize,
+    steps: usize,
+    rotation: usize,
+    pitches: &[u8],
+) -> Vec<Note> {
+    rhythm_notes(region, &euclidean_rhythm(pulses, steps, rotation), pitches)
+}
+
+/// Expand an L-system and read it as a rhythm (uppercase = hit)
+pub fn lsystem_rhythm(axiom: &str, rules: &[(char, String)], iterations: u8) -> Vec<bool> {
+    const MAX_LEN: usize = 4096;
+    let mut current: String = axiom.to_string();
+    for _ in 0..iterations {
+        let mut next = String::with_capacity(current.len() * 2);
+        for c in current.chars() {
+            match rules.iter().find(|(from, _)| *from == c) {
+                Some((_, to)) => next.push_str(to),
+                None => next.push(c),
+            }
+            if next.len() >= MAX_LEN {
+                break;
+            }
+        }
+        current = next;
+    }
+    current.chars().take(MAX_LEN).map(|c| c.is_ascii_uppercase()).collect()
+}
+
+/// Run an elementary cellular automaton over `width` wrapping cells from a seeded row
+pub fn automaton_rhythm(rule: u8, width: usize, generations: u32, seed: u64) -> Vec<bool> {
+    if width == 0 {
+        return Vec::new();
+    }
+    let mut rng = SeededRng::new(seed);
+    let mut row: Vec<bool> = (0..width).map(|_| rng.chance(0.5)).collect();
+    for _ in 0..generations {
+        row = (0..width)
+            .map(|i| {
+                let l = row[(i + width - 1) % width] as u8;
+                let c = row[i] as u8;
+                let r = row[(i + 1) % width] as u8;
+                rule >> ((l << 2) | (c << 1) | r) & 1 == 1
+            })
+            .collect();
+    }
+    row
+}
+
+/// Vary notes: each note changes with probability `amount` by moving a scale
+/// degree, shifting a step, changing velocity, or being dropped
+pub fn mutate(
+    notes: &[Note],
+    region: &GeneratorRegion,
+    amount: f32,
+    key: Key,
+    scale: Scale,
+    seed: u64,
+) -> Vec<Note> {
+    let pitches = scale_pitches(key, scale, 0, 127);
+    let mut rng = SeededRng::new(seed);
+    let mut out = Vec::with_capacity(notes.len());
+    for note in notes {
+        let mut note = note.clone();
+        if !rng.chance(amount) {
+            out.push(note);
+            continue;
+        }
+        match rng.below(4) {
+            0 => {
+                let idx = pitches.partition_point(|&p| p < note.pitch);
+                let idx = if rng.chance(0.5) { idx + 1 } else { idx.saturating_sub(1) };
+                if let Some(&p) = pitches.get(idx.min(pitches.len().saturating_sub(1))) {
+                    note.pitch = p;
+                }
+            }
+            1 => {
+                let step = region.step_ticks as i64 * if rng.chance(0.5) { 1 } else { -1 };
+                let tick = (note.tick as i64 + step)
+                    .clamp(region.start_tick as i64, region.end_tick().saturating_sub(1) as i64);
+                note.tick = tick as u32;
+            }
+            2 => note.velocity = (note.velocity as i32 + rng.range_i32(-24, 24)).clamp(1, 127) as u8,
+            _ => continue,
+        }
+        out.push(note);
+    }
+    out.sort_by_key(|n| (n.tick, n.pitch));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region() -> GeneratorRegion {
+        GeneratorRegion::new(0, 1920, 120)
+    }
+
+    #[test]
+    fn euclidean_spreads_hits() {
+        let r = euclidean_rhythm(3, 8, 0);
+        assert_eq!(r.iter().filter(|h| **h).count(), 3);
+        assert_eq!(r, vec![true, false, false, true, false, false, true, false]);
+        assert!(euclidean_rhythm(3, 8, 1)[1]);
+        let notes = euclidean(&region(), 4, 16, 0, &[36, 38]);
+        let pitches: Vec<u8> = notes.iter().map(|n| n.pitch).collect();
+        assert_eq!(pitches, vec![36, 38, 36, 38]);
+    }
+
+    #[test]
+    fn random_in_scale_is_seeded_and_in_scale() {
+        let gen = NoteGenerator::RandomInScale {
+            density: 0.5,
+            low: 48,
+            high: 72,
+            key: Key::C,
+            scale: Scale::Major,
+            seed: 7,
+        };
+        let a = gen.generate(&region(), &[]);
+        let b = gen.generate(&region(), &[]);
+        assert!(!a.is_empty() && a.len() < 16);
+        assert_eq!(a.iter().map(|n| (n.tick, n.pitch)).collect::<Vec<_>>(), b.iter().map(|n| (n.tick, n.pitch)).collect::<Vec<_>>());
+        assert!(a.iter().all(|n| (48..=72).contains(&n.pitch) && ![1, 3, 6, 8, 10].contains(&(n.pitch % 12))));
+    }
+
+    #[test]
+    fn markov_only_uses_trained_pitches() {
+        let source = vec![Note::new(0, 120, 60, 100), Note::new(240, 120, 64, 100), Note::new(480, 120, 67, 100)];
+        let notes = markov(&region(), &source, 3);
+        assert!(!notes.is_empty());
+        assert!(notes.iter().all(|n| [60, 64, 67].contains(&n.pitch) && n.tick < 1920));
+    }
+
+    #[test]
+    fn lsystem_and_automaton_rhythms() {
+        let rules = vec![('A', "AB".to_string()), ('B', "A".to_string())];
+        assert_eq!(lsystem_rhythm("A", &rules, 3).len(), 5);
+        let rules = vec![('A', "Ab".to_string())];
+        assert_eq!(lsystem_rhythm("A", &rules, 2), vec![true, false, false]);
+        let row = automaton_rhythm(90, 16, 4, 1);
+        assert_eq!(row.len(), 16);
+        assert_eq!(row, automaton_rhythm(90, 16, 4, 1));
+    }
+
+    #[test]
+    fn mutate_zero_amount_is_identity() {
+        let source = euclidean(&region(), 5, 16, 0, &[60]);
+        let same = mutate(&source, &region(), 0.0, Key::C, Scale::Major, 1);
+        assert_eq!(same.len(), source.len());
+        let changed = mutate(&source, &region(), 1.0, Key::C, Scale::Major, 1);
+        let key = |n: &Note| (n.tick, n.pitch, n.velocity);
+        assert!(changed.iter().map(key).ne(source.iter().map(key)));
+    }
+}
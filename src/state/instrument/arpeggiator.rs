@@ -0,0 +1,369 @@
+use serde::{Serialize, Deserialize};
+
+use crate::state::piano_roll::Note;
+use crate::SeededRng;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ArpDirection {
+    #[default]
+    Up,
+    Down,
+    UpDown,
+    Random,
+    /// Order the keys were pressed in
+    AsPlayed,
+    /// All held notes together on every step
+    Chord,
+}
+
+impl ArpDirection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArpDirection::Up => "Up",
+            ArpDirection::Down => "Down",
+            ArpDirection::UpDown => "Up/Down",
+            ArpDirection::Random => "Random",
+            ArpDirection::AsPlayed => "Played",
+            ArpDirection::Chord => "Chord",
+        }
+    }
+
+    pub fn next(&self) -> ArpDirection {
+        match self {
+            ArpDirection::Up => ArpDirection::Down,
+            ArpDirection::Down => ArpDirection::UpDown,
+            ArpDirection::UpDown => ArpDirection::Random,
+            ArpDirection::Random => ArpDirection::AsPlayed,
+            ArpDirection::AsPlayed => ArpDirection::Chord,
+            ArpDirection::Chord => ArpDirection::Up,
+        }
+    }
+}
+
+/// Arp step length as a musical division (relative to a quarter-note beat)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ArpRate {
+    Quarter,
+    Eighth,
+    EighthTriplet,
+    #[default]
+    Sixteenth,
+    SixteenthTriplet,
+    ThirtySecond,
+}
+
+impl ArpRate {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArpRate::Quarter => "1/4",
+            ArpRate::Eighth => "1/8",
+            ArpRate::EighthTriplet => "1/8T",
+            ArpRate::Sixteenth => "1/16",
+            ArpRate::SixteenthTriplet => "1/16T",
+            ArpRate::ThirtySecond => "1/32",
+        }
+    }
+
+    pub fn next(&self) -> ArpRate {
+        match self {
+            ArpRate::Quarter => ArpRate::Eighth,
+            ArpRate::Eighth => ArpRate::EighthTriplet,
+            ArpRate::EighthTriplet => ArpRate::Sixteenth,
+            ArpRate::Sixteenth => ArpRate::SixteenthTriplet,
+            ArpRate::SixteenthTriplet => ArpRate::ThirtySecond,
+            ArpRate::ThirtySecond => ArpRate::Quarter,
+        }
+    }
+
+    pub fn ticks(&self, ticks_per_beat: u32) -> u32 {
+        match self {
+            ArpRate::Quarter => ticks_per_beat,
+            ArpRate::Eighth => ticks_per_beat / 2,
+            ArpRate::EighthTriplet => ticks_per_beat / 3,
+            ArpRate::Sixteenth => ticks_per_beat / 4,
+            ArpRate::SixteenthTriplet => ticks_per_beat / 6,
+            ArpRate::ThirtySecond => ticks_per_beat / 8,
+        }
+        .max(1)
+    }
+}
+
+/// One step of the arp rhythm pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArpStep {
+    /// false = rest
+    pub active: bool,
+    pub accent: bool,
+}
+
+impl Default for ArpStep {
+    fn default() -> Self {
+        Self { active: true, accent: false }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArpConfig {
+    pub enabled: bool,
+    pub direction: ArpDirection,
+    pub rate: ArpRate,
+    /// Octave range (1-4)
+    pub octaves: u8,
+    /// Fraction of the step each note sounds (0.1-1.0)
+    pub gate: f32,
+    /// Delay of odd steps: 0.0 = straight, 1.0 = triplet feel
+    pub swing: f32,
+    /// Rhythm pattern cycled over the steps (empty = every step plays)
+    pub pattern: Vec<ArpStep>,
+    /// Velocity added on accented steps
+    pub accent_boost: u8,
+    /// Keep arpeggiating after the keys are released
+    pub latch: bool,
+    /// Seed for the Random direction
+    pub seed: u64,
+}
+
+impl Default for ArpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            direction: ArpDirection::Up,
+            rate: ArpRate::Sixteenth,
+            octaves: 1,
+            gate: 0.5,
+            swing: 0.0,
+            pattern: Vec::new(),
+            accent_boost: 24,
+            latch: false,
+            seed: 0,
+        }
+    }
+}
+
+impl ArpConfig {
+    pub const MAX_OCTAVES: u8 = 4;
+
+    pub fn adjust_octaves(&mut self, delta: i8) {
+        self.octaves = (self.octaves as i16 + delta as i16).clamp(1, Self::MAX_OCTAVES as i16) as u8;
+    }
+
+    pub fn adjust_gate(&mut self, delta: f32) {
+        self.gate = (self.gate + delta).clamp(0.1, 1.0);
+    }
+
+    /// Resize the rhythm pattern (new steps play, unaccented)
+    pub fn set_pattern_length(&mut self, len: usize) {
+        self.pattern.resize(len, ArpStep::default());
+    }
+
+    pub fn toggle_step(&mut self, index: usize) {
+        if let Some(step) = self.pattern.get_mut(index) {
+            step.active = !step.active;
+        }
+    }
+
+    pub fn toggle_accent(&mut self, index: usize) {
+        if let Some(step) = self.pattern.get_mut(index) {
+            step.accent = !step.accent;
+        }
+    }
+
+    fn step(&self, index: u64) -> ArpStep {
+        if self.pattern.is_empty() {
+            ArpStep::default()
+        } else {
+            self.pattern[(index % self.pattern.len() as u64) as usize]
+        }
+    }
+}
+
+/// Keys feeding the arp, with latch handling.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArpHeldNotes {
+    /// Pitches in the order they were pressed
+    pitches: Vec<u8>,
+    /// Pitches physically held (differs from `pitches` while latched)
+    down: Vec<u8>,
+}
+
+impl ArpHeldNotes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pitches the arp should play, in press order
+    pub fn pitches(&self) -> &[u8] {
+        &self.pitches
+    }
+
+    pub fn note_on(&mut self, pitch: u8, latch: bool) {
+        // With latch, the first key after a full release starts a new chord
+        if latch && self.down.is_empty() {
+            self.pitches.clear();
+        }
+        if !self.down.contains(&pitch) {
+            self.down.push(pitch);
+        }
+        if !self.pitches.contains(&pitch) {
+            self.pitches.push(pitch);
+        }
+    }
+
+    pub fn note_off(&mut self, pitch: u8, latch: bool) {
+        self.down.retain(|&p| p != pitch);
+        if !latch {
+            self.pitches.retain(|&p| p != pitch);
+        }
+    }
+
+    /// Drop latched notes (e.g. when latch is switched off)
+    pub fn release_latched(&mut self) {
+        let down = &self.down;
+        self.pitches.retain(|p| down.contains(p));
+    }
+}
+
+/// The pitch order one arp cycle walks through (before Random/Chord handling)
+fn arp_order(held: &[u8], config: &ArpConfig) -> Vec<u8> {
+    let mut base: Vec<u8> = held.to_vec();
+    match config.direction {
+        ArpDirection::AsPlayed => {}
+        ArpDirection::Down => {
+            base.sort_unstable_by(|a, b| b.cmp(a));
+        }
+        _ => base.sort_unstable(),
+    }
+    base.dedup();
+    let octaves = config.octaves.clamp(1, ArpConfig::MAX_OCTAVES);
+    let mut order = Vec::with_capacity(base.len() * octaves as usize);
+    for o in 0..octaves {
+        let o = match config.direction {
+            ArpDirection::Down => octaves - 1 - o,
+            _ => o,
+        };
+        order.extend(base.iter().filter_map(|&p| {
+            let pitch = p as u16 + o as u16 * 12;
+            (pitch <= 127).then_some(pitch as u8)
+        }));
+    }
+    if config.direction == ArpDirection::UpDown && order.len() > 2 {
+        let down: Vec<u8> = order[1..order.len() - 1].iter().rev().copied().collect();
+        order.extend(down);
+    }
+    order
+}
+
+/// Arpeggiate held pitches over [start_tick, end_tick).
+///
+/// Steps sit on a fixed grid from tick 0, so the same transport position
+/// always yields the same notes regardless of where the window starts; this is
+/// what lets the arp be scheduled live and bounced to a clip identically.
+pub fn arpeggiate(
+    held: &[u8],
+    velocity: u8,
+    config: &ArpConfig,
+    start_tick: u32,
+    end_tick: u32,
+    ticks_per_beat: u32,
+) -> Vec<Note> {
+    let order = arp_order(held, config);
+    if order.is_empty() || end_tick <= start_tick {
+        return Vec::new();
+    }
+    let step_ticks = config.rate.ticks(ticks_per_beat);
+    let duration = ((step_ticks as f32 * config.gate.clamp(0.1, 1.0)) as u32).max(1);
+    let swing_delay = (config.swing.clamp(0.0, 1.0) * step_ticks as f32 / 3.0) as u32;
+
+    let mut notes = Vec::new();
+    // Start one step early so a swung step from before the window is included
+    let first = (start_tick / step_ticks).saturating_sub(1) as u64;
+    let last = end_tick.div_ceil(step_ticks) as u64;
+    for index in first..last {
+        let step = config.step(index);
+        if !step.active {
+            continue;
+        }
+        let tick = index as u32 * step_ticks + if index % 2 == 1 { swing_delay } else { 0 };
+        if tick < start_tick || tick >= end_tick {
+            continue;
+        }
+        let velocity = if step.accent {
+            velocity.saturating_add(config.accent_boost).min(127)
+        } else {
+            velocity
+        };
+        let pitches: Vec<u8> = match config.direction {
+            ArpDirection::Chord => order.clone(),
+            ArpDirection::Random => {
+                let mut rng = SeededRng::from_parts(&[config.seed, index]);
+                vec![order[rng.below(order.len() as u32) as usize]]
+            }
+            _ => vec![order[(index % order.len() as u64) as usize]],
+        };
+        notes.extend(pitches.into_iter().map(|p| Note::new(tick, duration, p, velocity)));
+    }
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pitches(notes: &[Note]) -> Vec<u8> {
+        notes.iter().map(|n| n.pitch).collect()
+    }
+
+    #[test]
+    fn directions_and_octaves() {
+        let mut config = ArpConfig { rate: ArpRate::Quarter, ..ArpConfig::default() };
+        let held = [64, 60, 67];
+        assert_eq!(pitches(&arpeggiate(&held, 100, &config, 0, 1920, 480)), vec![60, 64, 67, 60]);
+        config.direction = ArpDirection::AsPlayed;
+        assert_eq!(pitches(&arpeggiate(&held, 100, &config, 0, 1440, 480)), vec![64, 60, 67]);
+        config.direction = ArpDirection::UpDown;
+        config.octaves = 2;
+        let notes = arpeggiate(&held, 100, &config, 0, 480 * 10, 480);
+        assert_eq!(pitches(&notes), vec![60, 64, 67, 72, 76, 79, 76, 72, 67, 64]);
+        config.direction = ArpDirection::Chord;
+        assert_eq!(arpeggiate(&held, 100, &config, 0, 480, 480).len(), 6);
+    }
+
+    #[test]
+    fn windows_are_position_stable() {
+        let config = ArpConfig { direction: ArpDirection::Random, swing: 0.5, ..ArpConfig::default() };
+        let held = [60, 62, 65, 69];
+        let whole = arpeggiate(&held, 100, &config, 0, 1920, 480);
+        let mut pieces = arpeggiate(&held, 100, &config, 0, 700, 480);
+        pieces.extend(arpeggiate(&held, 100, &config, 700, 1920, 480));
+        let key = |notes: &[Note]| -> Vec<(u32, u8)> { notes.iter().map(|n| (n.tick, n.pitch)).collect() };
+        assert_eq!(key(&whole), key(&pieces));
+        assert_eq!(whole[1].tick, 120 + 20);
+    }
+
+    #[test]
+    fn pattern_rests_and_accents() {
+        let mut config = ArpConfig { rate: ArpRate::Eighth, gate: 1.0, ..ArpConfig::default() };
+        config.set_pattern_length(3);
+        config.toggle_step(1);
+        config.toggle_accent(2);
+        let notes = arpeggiate(&[60], 100, &config, 0, 1440, 480);
+        let ticks: Vec<u32> = notes.iter().map(|n| n.tick).collect();
+        assert_eq!(ticks, vec![0, 480, 720, 1200]);
+        assert_eq!(notes[1].velocity, 124);
+        assert_eq!(notes[0].duration, 240);
+    }
+
+    #[test]
+    fn latch_keeps_released_notes() {
+        let mut held = ArpHeldNotes::new();
+        held.note_on(60, true);
+        held.note_on(64, true);
+        held.note_off(60, true);
+        held.note_off(64, true);
+        assert_eq!(held.pitches(), &[60, 64]);
+        held.note_on(67, true);
+        assert_eq!(held.pitches(), &[67]);
+        held.note_off(67, false);
+        assert!(held.pitches().is_empty());
+    }
+}
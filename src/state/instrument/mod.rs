@@ -3,29 +3,26 @@ mod filter;
 mod effect;
 mod lfo;
 mod envelope;
+mod arpeggiator;
 
 pub use source_type::*;
 pub use filter::*;
 pub use effect::*;
 pub use lfo::*;
 pub use envelope::*;
+pub use arpeggiator::*;
 
 use serde::{Serialize, Deserialize};
 
 use crate::InstrumentId;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub enum OutputTarget {
+    #[default]
     Master,
     Bus(u8), // 1-8
 }
 
-impl Default for OutputTarget {
-    fn default() -> Self {
-        Self::Master
-    }
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MixerSend {
     pub bus_id: u8,
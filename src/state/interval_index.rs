@@ -0,0 +1,253 @@
+//! Generic interval index for timeline objects (clip placements).
+
+use std::sync::OnceLock;
+
+/// One indexed interval [start, end) with its key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval<K> {
+    pub start: u32,
+    pub end: u32,
+    pub key: K,
+}
+
+/// Intervals sorted by start, with a max-end tree over them.
+///
+/// Point and overlap queries binary-search the start positions for the upper
+/// bound and let the tree skip every run of intervals that ends before the
+/// query, so they cost O((k + 1) log n) however long the longest interval is.
+#[derive(Debug, Clone)]
+pub struct IntervalIndex<K> {
+    entries: Vec<Interval<K>>,
+    /// Built on the first query, then updated along with `entries`
+    ends: OnceLock<MaxEnds>,
+}
+
+impl<K> Default for IntervalIndex<K> {
+    fn default() -> Self {
+        Self { entries: Vec::new(), ends: OnceLock::new() }
+    }
+}
+
+impl<K: Copy + PartialEq> IntervalIndex<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Interval<K>> {
+        self.entries.iter()
+    }
+
+    pub fn insert(&mut self, start: u32, end: u32, key: K) {
+        let end = end.max(start);
+        let pos = self.entries.partition_point(|e| e.start <= start);
+        self.entries.insert(pos, Interval { start, end, key });
+        if let Some(ends) = self.ends.get_mut() {
+            ends.insert(pos, end);
+        }
+    }
+
+    /// Remove the interval with this key. Returns true if it was present.
+    pub fn remove(&mut self, key: K) -> bool {
+        match self.entries.iter().position(|e| e.key == key) {
+            Some(pos) => {
+                self.entries.remove(pos);
+                if let Some(ends) = self.ends.get_mut() {
+                    ends.remove(pos);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move or resize the interval with this key
+    pub fn update(&mut self, key: K, start: u32, end: u32) {
+        self.remove(key);
+        self.insert(start, end, key);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.ends = OnceLock::new();
+    }
+
+    /// Intervals overlapping [start, end), in start order
+    pub fn overlapping(&self, start: u32, end: u32) -> impl Iterator<Item = &Interval<K>> {
+        let hi = self.entries.partition_point(|e| e.start < end);
+        let ends = self.ends.get_or_init(|| MaxEnds::build(self.entries.iter().map(|e| e.end)));
+        ends.ending_after(start, hi).into_iter().map(|i| &self.entries[i])
+    }
+
+    /// Intervals containing `tick`, in start order
+    pub fn at(&self, tick: u32) -> impl Iterator<Item = &Interval<K>> {
+        self.overlapping(tick, tick.saturating_add(1))
+    }
+}
+
+/// Max-end tree over spans sorted by start: an implicit segment tree whose
+/// nodes hold the latest end below them, so a query can skip whole runs of
+/// spans that end before it starts.
+///
+/// Edits mirror the span list: changing a span refreshes its path to the
+/// root, and inserting or removing one refreshes only the leaves that shift.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MaxEnds {
+    /// Spans held
+    len: usize,
+    /// Leaf count, rounded up to a power of two
+    size: usize,
+    /// Node `i` has children `2i` and `2i + 1`; leaves start at `size`
+    tree: Vec<u32>,
+}
+
+impl MaxEnds {
+    /// Build from span ends, in the spans' start order
+    pub(crate) fn build(ends: impl ExactSizeIterator<Item = u32>) -> Self {
+        let len = ends.len();
+        let size = len.next_power_of_two();
+        let mut tree = vec![0; 2 * size];
+        for (i, end) in ends.enumerate() {
+            tree[size + i] = end;
+        }
+        for i in (1..size).rev() {
+            tree[i] = tree[2 * i].max(tree[2 * i + 1]);
+        }
+        Self { len, size, tree }
+    }
+
+    /// A span was inserted at `pos`
+    pub(crate) fn insert(&mut self, pos: usize, end: u32) {
+        if self.len == self.size {
+            // Full: double the leaves, with the new span's slot spare
+            let mut leaves = self.tree[self.size..].to_vec();
+            leaves.push(0);
+            *self = Self::build(leaves.into_iter());
+            self.len -= 1;
+        }
+        let leaf = self.size + pos;
+        self.tree.copy_within(leaf..self.size + self.len, leaf + 1);
+        self.tree[leaf] = end;
+        self.len += 1;
+        self.refresh(pos, self.len);
+    }
+
+    /// The span at `pos` was removed
+    pub(crate) fn remove(&mut self, pos: usize) {
+        let leaf = self.size + pos;
+        self.tree.copy_within(leaf + 1..self.size + self.len, leaf);
+        self.len -= 1;
+        self.tree[self.size + self.len] = 0;
+        self.refresh(pos, self.len + 1);
+    }
+
+    /// The span at `from` changed its end to `end` and moved to `to`
+    pub(crate) fn relocate(&mut self, from: usize, to: usize, end: u32) {
+        let (lo, hi) = (from.min(to), from.max(to));
+        if from < to {
+            self.tree.copy_within(self.size + from + 1..=self.size + to, self.size + from);
+        } else {
+            self.tree.copy_within(self.size + to..self.size + from, self.size + to + 1);
+        }
+        self.tree[self.size + to] = end;
+        self.refresh(lo, hi + 1);
+    }
+
+    /// Recompute the ancestors of leaves [lo, hi)
+    fn refresh(&mut self, lo: usize, hi: usize) {
+        let (mut lo, mut hi) = (self.size + lo, self.size + hi.max(lo + 1) - 1);
+        while lo > 1 {
+            lo /= 2;
+            hi /= 2;
+            for i in lo..=hi {
+                self.tree[i] = self.tree[2 * i].max(self.tree[2 * i + 1]);
+            }
+        }
+    }
+
+    /// Positions below `hi` whose end is after `start`, ascending
+    pub(crate) fn ending_after(&self, start: u32, hi: usize) -> Vec<usize> {
+        let mut out = Vec::new();
+        if !self.tree.is_empty() {
+            self.collect(1, 0, self.size, start, hi, &mut out);
+        }
+        out
+    }
+
+    fn collect(&self, node: usize, lo: usize, width: usize, start: u32, hi: usize, out: &mut Vec<usize>) {
+        if lo >= hi || self.tree[node] <= start {
+            return;
+        }
+        if width == 1 {
+            out.push(lo);
+            return;
+        }
+        let half = width / 2;
+        self.collect(2 * node, lo, half, start, hi, out);
+        self.collect(2 * node + 1, lo + half, half, start, hi, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_and_overlap_queries() {
+        let mut idx = IntervalIndex::new();
+        idx.insert(100, 200, 'a');
+        idx.insert(0, 50, 'b');
+        idx.insert(150, 400, 'c');
+        assert_eq!(idx.at(160).map(|e| e.key).collect::<Vec<_>>(), vec!['a', 'c']);
+        assert_eq!(idx.at(50).count(), 0);
+        assert_eq!(idx.overlapping(40, 110).map(|e| e.key).collect::<Vec<_>>(), vec!['b', 'a']);
+    }
+
+    #[test]
+    fn long_interval_does_not_widen_queries() {
+        let mut idx = IntervalIndex::new();
+        idx.insert(0, u32::MAX, 0u32);
+        for i in 1..1000 {
+            idx.insert(i * 10, i * 10 + 5, i);
+        }
+        assert_eq!(idx.at(5002).map(|e| e.key).collect::<Vec<_>>(), vec![0, 500]);
+        assert_eq!(idx.at(5007).map(|e| e.key).collect::<Vec<_>>(), vec![0]);
+    }
+
+    #[test]
+    fn tree_follows_edits() {
+        let mut idx = IntervalIndex::new();
+        idx.insert(0, 10, 0u32);
+        assert_eq!(idx.at(5).count(), 1);
+        // Edits after the first query update the built tree, growing it as needed
+        for i in 1..40 {
+            idx.insert(i * 100, i * 100 + 10, i);
+        }
+        idx.insert(50, 5000, 99);
+        assert_eq!(idx.at(3905).map(|e| e.key).collect::<Vec<_>>(), vec![99, 39]);
+        idx.remove(99);
+        assert_eq!(idx.at(3905).map(|e| e.key).collect::<Vec<_>>(), vec![39]);
+        idx.update(39, 0, 4000);
+        assert_eq!(idx.at(3995).map(|e| e.key).collect::<Vec<_>>(), vec![39]);
+        let rebuilt = MaxEnds::build(idx.entries.iter().map(|e| e.end));
+        assert_eq!(idx.ends.get().unwrap().tree, rebuilt.tree);
+    }
+
+    #[test]
+    fn update_and_remove() {
+        let mut idx = IntervalIndex::new();
+        idx.insert(0, 100, 1u32);
+        idx.update(1, 500, 600);
+        assert_eq!(idx.at(50).count(), 0);
+        assert_eq!(idx.at(550).count(), 1);
+        assert!(idx.remove(1));
+        assert!(idx.is_empty());
+    }
+}
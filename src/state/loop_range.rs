@@ -0,0 +1,66 @@
+//! Saved loop ranges and note recording modes.
+
+use serde::{Deserialize, Serialize};
+
+/// A named loop range that can be recalled into the piano roll.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoopRange {
+    pub name: String,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl LoopRange {
+    pub fn new(name: impl Into<String>, start: u32, end: u32) -> Self {
+        Self {
+            name: name.into(),
+            start: start.min(end),
+            end: start.max(end),
+        }
+    }
+
+    pub fn len(&self) -> u32 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end <= self.start
+    }
+
+    pub fn contains(&self, tick: u32) -> bool {
+        tick >= self.start && tick < self.end
+    }
+}
+
+/// What happens to existing notes when recording notes over them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum NoteRecordMode {
+    /// Add recorded notes to what is already there
+    #[default]
+    Overdub,
+    /// Clear the recording region at the start of each pass
+    Replace,
+    /// Set the existing notes aside and record each pass as a new take
+    NewTake,
+}
+
+impl NoteRecordMode {
+    pub const ALL: [NoteRecordMode; 3] =
+        [NoteRecordMode::Overdub, NoteRecordMode::Replace, NoteRecordMode::NewTake];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoteRecordMode::Overdub => "Overdub",
+            NoteRecordMode::Replace => "Replace",
+            NoteRecordMode::NewTake => "New Take",
+        }
+    }
+
+    pub fn next(&self) -> NoteRecordMode {
+        match self {
+            NoteRecordMode::Overdub => NoteRecordMode::Replace,
+            NoteRecordMode::Replace => NoteRecordMode::NewTake,
+            NoteRecordMode::NewTake => NoteRecordMode::Overdub,
+        }
+    }
+}
@@ -5,9 +5,10 @@ use crate::InstrumentId;
 use serde::{Deserialize, Serialize};
 
 /// Recording mode for MIDI automation
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub enum RecordMode {
     /// Not recording
+    #[default]
     Off,
     /// Armed for recording (waiting for play to start)
     Armed,
@@ -15,12 +16,6 @@ pub enum RecordMode {
     Recording,
 }
 
-impl Default for RecordMode {
-    fn default() -> Self {
-        Self::Off
-    }
-}
-
 /// Mapping of a MIDI CC to an automation target
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MidiCcMapping {
@@ -193,7 +188,7 @@ impl MidiRecordingState {
 
     /// Check if a MIDI channel should be processed
     pub fn should_process_channel(&self, channel: u8) -> bool {
-        self.channel_filter.map_or(true, |f| f == channel)
+        self.channel_filter.is_none() || self.channel_filter == Some(channel)
     }
 }
 
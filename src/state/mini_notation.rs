@@ -0,0 +1,762 @@
+//! TidalCycles-style mini-notation for typing patterns.
+//!
+//! Supported syntax:
+//! - `c4 e4 g4` sequence, each step an equal share of the cycle
+//! - `~` rest
+//! - `[a b]` subdivision, `[a, b]` stack (play together)
+//! - `<a b>` alternate one item per cycle
+//! - `a*2` faster, `a/2` slower, `a!3` or `a !` replicate, `a@3` weight
+//! - `{a b c, d e}` polymeter (steps of the first sequence per cycle), `{a b}%4` explicit steps
+//! - `a(3,8)` / `a(3,8,2)` euclidean rhythm with optional rotation
+//!
+//! Words are note names (`c4`, `eb3`, `f#5`, `cs2`) or MIDI numbers for
+//! notes, and pad names or indices for drum grids.
+//!
+//! Counts and factors are capped at [`MAX_REPEAT`], and patterns too large to
+//! build or too dense to render are errors rather than hangs.
+
+use std::fmt;
+use std::ops::Range;
+
+use super::drum_sequencer::DrumStep;
+use super::generator::euclidean_rhythm;
+use super::piano_roll::Note;
+
+/// A parse or evaluation error pointing at a byte span of the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotationError {
+    pub message: String,
+    pub span: Range<usize>,
+}
+
+impl NotationError {
+    fn new(message: impl Into<String>, span: Range<usize>) -> Self {
+        Self { message: message.into(), span }
+    }
+
+    /// The input with a caret line under the offending span
+    pub fn render(&self, src: &str) -> String {
+        let start = src[..self.span.start.min(src.len())].chars().count();
+        let width = src
+            .get(self.span.clone())
+            .map_or(1, |s| s.chars().count())
+            .max(1);
+        format!("{}\n{}{} {}", src, " ".repeat(start), "^".repeat(width), self.message)
+    }
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}..{}", self.message, self.span.start, self.span.end)
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+/// Largest euclid step count, `!` repeat count, `*` or `/` factor, `@` weight
+/// and `%` step count a pattern may use
+pub const MAX_REPEAT: usize = 1024;
+
+/// Most pattern nodes a parse may build (repeats and euclid rhythms copy their step)
+const MAX_NODES: usize = 1 << 16;
+
+/// Deepest bracket nesting a pattern may use
+const MAX_DEPTH: usize = 64;
+
+/// Most query steps (cycles walked plus events produced) one render may take
+const MAX_WORK: usize = 1 << 18;
+
+/// Exact rational time in cycles. Arithmetic is checked: None means the
+/// value no longer fits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Frac {
+    num: i64,
+    den: i64,
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 { a.abs().max(1) } else { gcd(b, a % b) }
+}
+
+impl Frac {
+    fn new(num: i128, den: i128) -> Option<Self> {
+        if den == 0 {
+            return None;
+        }
+        let g = gcd(num, den);
+        let sign = if den < 0 { -1 } else { 1 };
+        Some(Self { num: i64::try_from(sign * num / g).ok()?, den: i64::try_from(sign * den / g).ok()? })
+    }
+
+    fn int(n: i64) -> Self {
+        Self { num: n, den: 1 }
+    }
+
+    fn floor(self) -> i64 {
+        self.num.div_euclid(self.den)
+    }
+
+    fn ceil(self) -> i64 {
+        -(-self.num).div_euclid(self.den)
+    }
+
+    fn to_ticks(self, ticks_per_cycle: u32) -> u32 {
+        (self.num as i128 * ticks_per_cycle as i128 / self.den as i128).clamp(0, u32::MAX as i128) as u32
+    }
+
+    fn checked_add(self, o: Frac) -> Option<Frac> {
+        let (a, b) = (self.wide(), o.wide());
+        Frac::new(a.0 * b.1 + b.0 * a.1, a.1 * b.1)
+    }
+
+    fn checked_sub(self, o: Frac) -> Option<Frac> {
+        let (a, b) = (self.wide(), o.wide());
+        Frac::new(a.0 * b.1 - b.0 * a.1, a.1 * b.1)
+    }
+
+    fn checked_mul(self, o: Frac) -> Option<Frac> {
+        let (a, b) = (self.wide(), o.wide());
+        Frac::new(a.0 * b.0, a.1 * b.1)
+    }
+
+    fn checked_div(self, o: Frac) -> Option<Frac> {
+        let (a, b) = (self.wide(), o.wide());
+        Frac::new(a.0 * b.1, a.1 * b.0)
+    }
+
+    fn wide(self) -> (i128, i128) {
+        (self.num as i128, self.den as i128)
+    }
+}
+
+impl PartialOrd for Frac {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Frac {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.num as i128 * other.den as i128).cmp(&(other.num as i128 * self.den as i128))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Word { text: String, span: Range<usize> },
+    Rest,
+    /// Items with their relative weights
+    Seq(Vec<(Node, Frac)>),
+    Stack(Vec<Node>),
+    Alt(Vec<Node>),
+    Fast(Box<Node>, Frac),
+}
+
+/// One sounding event of a pattern, in cycles.
+#[derive(Debug, Clone, PartialEq)]
+struct Event {
+    start: Frac,
+    end: Frac,
+    text: String,
+    span: Range<usize>,
+}
+
+impl Node {
+    /// Events whose onset lies in [b, e), in this node's own timeline. Each
+    /// cycle walked and event produced spends one unit of `work`; None once
+    /// it runs out or a time no longer fits.
+    fn query(&self, b: Frac, e: Frac, out: &mut Vec<Event>, work: &mut usize) -> Option<()> {
+        match self {
+            Node::Rest => {}
+            Node::Word { text, span } => {
+                for c in b.floor()..e.ceil() {
+                    *work = work.checked_sub(1)?;
+                    let start = Frac::int(c);
+                    if start >= b && start < e {
+                        out.push(Event { start, end: Frac::int(c + 1), text: text.clone(), span: span.clone() });
+                    }
+                }
+            }
+            Node::Seq(items) => {
+                let mut total = Frac::int(0);
+                for (_, weight) in items {
+                    total = total.checked_add(*weight)?;
+                }
+                for c in b.floor()..e.ceil() {
+                    *work = work.checked_sub(1)?;
+                    let cycle = Frac::int(c);
+                    let mut offset = Frac::int(0);
+                    for (node, weight) in items {
+                        let width = weight.checked_div(total)?;
+                        let slot = cycle.checked_add(offset.checked_div(total)?)?;
+                        offset = offset.checked_add(*weight)?;
+                        let mut child = Vec::new();
+                        node.query(cycle, cycle.checked_add(Frac::int(1))?, &mut child, work)?;
+                        for ev in child {
+                            *work = work.checked_sub(1)?;
+                            let start = slot.checked_add(ev.start.checked_sub(cycle)?.checked_mul(width)?)?;
+                            if start >= b && start < e {
+                                let end = slot.checked_add(ev.end.checked_sub(cycle)?.checked_mul(width)?)?;
+                                out.push(Event { start, end, ..ev });
+                            }
+                        }
+                    }
+                }
+            }
+            Node::Stack(nodes) => {
+                for node in nodes {
+                    node.query(b, e, out, work)?;
+                }
+            }
+            Node::Alt(nodes) => {
+                let n = nodes.len() as i64;
+                for c in b.floor()..e.ceil() {
+                    *work = work.checked_sub(1)?;
+                    let node = &nodes[c.rem_euclid(n) as usize];
+                    // Each item only advances on the cycles it plays
+                    let inner = Frac::int(c.div_euclid(n));
+                    let shift = Frac::int(c).checked_sub(inner)?;
+                    let mut child = Vec::new();
+                    node.query(inner, inner.checked_add(Frac::int(1))?, &mut child, work)?;
+                    for ev in child {
+                        *work = work.checked_sub(1)?;
+                        let start = ev.start.checked_add(shift)?;
+                        if start >= b && start < e {
+                            out.push(Event { start, end: ev.end.checked_add(shift)?, ..ev });
+                        }
+                    }
+                }
+            }
+            Node::Fast(node, factor) => {
+                let mut child = Vec::new();
+                node.query(b.checked_mul(*factor)?, e.checked_mul(*factor)?, &mut child, work)?;
+                for ev in child {
+                    *work = work.checked_sub(1)?;
+                    let (start, end) = (ev.start.checked_div(*factor)?, ev.end.checked_div(*factor)?);
+                    out.push(Event { start, end, ..ev });
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Nodes in this tree (what a copy of it costs)
+    fn size(&self) -> usize {
+        match self {
+            Node::Word { .. } | Node::Rest => 1,
+            Node::Seq(items) => 1 + items.iter().map(|(n, _)| n.size()).sum::<usize>(),
+            Node::Stack(nodes) | Node::Alt(nodes) => 1 + nodes.iter().map(Node::size).sum::<usize>(),
+            Node::Fast(node, _) => 1 + node.size(),
+        }
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    /// Nodes built so far, against `MAX_NODES`
+    nodes: usize,
+    /// Brackets open at the current position, against `MAX_DEPTH`
+    depth: usize,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '#' | '.' | '-' | '_' | ':')
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn error_here(&self, message: impl Into<String>) -> NotationError {
+        let len = self.peek().map_or(0, char::len_utf8);
+        NotationError::new(message, self.pos..self.pos + len)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), NotationError> {
+        self.skip_ws();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error_here(match self.peek() {
+                Some(found) => format!("expected '{c}', found '{found}'"),
+                None => format!("expected '{c}' before end of input"),
+            }))
+        }
+    }
+
+    fn word(&mut self) -> (String, Range<usize>) {
+        let start = self.pos;
+        while self.peek().is_some_and(is_word_char) {
+            self.pos += 1;
+        }
+        (self.src[start..self.pos].to_string(), start..self.pos)
+    }
+
+    /// A positive number (integer or decimal) as an exact fraction
+    fn number(&mut self) -> Result<Frac, NotationError> {
+        let (text, span) = self.word();
+        let bad = || NotationError::new(format!("expected a number, found '{text}'"), span.clone());
+        let (int, frac) = text.split_once('.').unwrap_or((&text, ""));
+        if int.is_empty() && frac.is_empty() {
+            return Err(NotationError::new("expected a number", span.start..span.start + 1));
+        }
+        let digits = format!("{int}{frac}");
+        let num: i64 = digits.parse().map_err(|_| bad())?;
+        let scale = u32::try_from(frac.len()).ok().and_then(|places| 10i64.checked_pow(places));
+        let value = scale
+            .and_then(|scale| Frac::new(num as i128, scale as i128))
+            .ok_or_else(|| NotationError::new("too many decimal places", span.clone()))?;
+        if value.num <= 0 {
+            return Err(NotationError::new("number must be greater than zero", span));
+        }
+        Ok(value)
+    }
+
+    /// A speed factor, weight or step count: a number within 1/MAX_REPEAT..=MAX_REPEAT
+    fn factor(&mut self) -> Result<Frac, NotationError> {
+        let start = self.pos;
+        let value = self.number()?;
+        let max = Frac::int(MAX_REPEAT as i64);
+        if value > max || Frac::int(1) > value.checked_mul(max).unwrap_or(max) {
+            let message = format!("must be between 1/{MAX_REPEAT} and {MAX_REPEAT}");
+            return Err(NotationError::new(message, start..self.pos));
+        }
+        Ok(value)
+    }
+
+    fn integer(&mut self) -> Result<usize, NotationError> {
+        self.skip_ws();
+        let (text, span) = self.word();
+        text.parse()
+            .map_err(|_| NotationError::new(format!("expected a whole number, found '{text}'"), span))
+    }
+
+    /// A whole number no larger than MAX_REPEAT
+    fn count(&mut self) -> Result<usize, NotationError> {
+        self.skip_ws();
+        let start = self.pos;
+        let n = self.integer()?;
+        if n > MAX_REPEAT {
+            return Err(NotationError::new(format!("must be at most {MAX_REPEAT}"), start..self.pos));
+        }
+        Ok(n)
+    }
+
+    /// Account for `count` copies of `node`, failing once the pattern grows too large
+    fn spend(&mut self, node: &Node, count: usize, span: Range<usize>) -> Result<(), NotationError> {
+        self.nodes = node
+            .size()
+            .checked_mul(count)
+            .and_then(|n| n.checked_add(self.nodes))
+            .filter(|&n| n <= MAX_NODES)
+            .ok_or_else(|| NotationError::new("pattern is too large", span))?;
+        Ok(())
+    }
+
+    /// Sequences separated by commas, up to (not including) `close`
+    fn layers(&mut self, close: char) -> Result<Vec<Vec<(Node, Frac)>>, NotationError> {
+        let mut layers = vec![self.sequence(close)?];
+        while self.peek() == Some(',') {
+            self.pos += 1;
+            layers.push(self.sequence(close)?);
+        }
+        Ok(layers)
+    }
+
+    /// Weighted items until `close`, ',' or end of input
+    fn sequence(&mut self, close: char) -> Result<Vec<(Node, Frac)>, NotationError> {
+        let mut items: Vec<(Node, Frac)> = Vec::new();
+        loop {
+            self.skip_ws();
+            match self.peek() {
+                None => return Ok(items),
+                Some(c) if c == close || c == ',' => return Ok(items),
+                Some('!') => {
+                    // Bare '!' repeats the previous step
+                    let span = self.pos..self.pos + 1;
+                    self.pos += 1;
+                    let last = items
+                        .last()
+                        .cloned()
+                        .ok_or_else(|| NotationError::new("'!' has nothing to repeat", span.clone()))?;
+                    self.spend(&last.0, 1, span)?;
+                    items.push(last);
+                }
+                Some(_) => {
+                    let start = self.pos;
+                    let (node, weight, copies) = self.term()?;
+                    self.spend(&node, copies, start..self.pos)?;
+                    for _ in 0..copies {
+                        items.push((node.clone(), weight));
+                    }
+                }
+            }
+        }
+    }
+
+    fn group(&mut self, open: char, close: char, start: usize) -> Result<Vec<Vec<(Node, Frac)>>, NotationError> {
+        if self.depth == MAX_DEPTH {
+            return Err(NotationError::new("brackets nested too deeply", start..start + 1));
+        }
+        self.depth += 1;
+        let layers = self.layers(close);
+        self.depth -= 1;
+        let layers = layers?;
+        if self.peek() != Some(close) {
+            return Err(NotationError::new(format!("unclosed '{open}'"), start..start + 1));
+        }
+        self.pos += 1;
+        if layers.iter().all(|l| l.is_empty()) {
+            return Err(NotationError::new(format!("empty '{open}{close}'"), start..self.pos));
+        }
+        Ok(layers)
+    }
+
+    /// One step with its modifiers: (node, weight, replicate count)
+    fn term(&mut self) -> Result<(Node, Frac, usize), NotationError> {
+        let start = self.pos;
+        let mut node = match self.bump() {
+            Some('~') => Node::Rest,
+            Some('[') => {
+                let layers = self.group('[', ']', start)?;
+                stack(layers.into_iter().map(Node::Seq).collect())
+            }
+            Some('<') => {
+                let layers = self.group('<', '>', start)?;
+                stack(
+                    layers
+                        .into_iter()
+                        .filter(|l| !l.is_empty())
+                        .map(|l| Node::Alt(l.into_iter().map(|(n, _)| n).collect()))
+                        .collect(),
+                )
+            }
+            Some('{') => {
+                let layers = self.group('{', '}', start)?;
+                let steps = if self.peek() == Some('%') {
+                    self.pos += 1;
+                    self.factor()?
+                } else {
+                    Frac::int(layers[0].len().max(1) as i64)
+                };
+                stack(
+                    layers
+                        .into_iter()
+                        .filter(|l| !l.is_empty())
+                        .map(|l| {
+                            let len = Frac::int(l.len() as i64);
+                            // steps is at most MAX_REPEAT and len at most MAX_NODES
+                            let factor = steps.checked_div(len).unwrap_or(steps);
+                            Node::Fast(Box::new(Node::Seq(l)), factor)
+                        })
+                        .collect(),
+                )
+            }
+            Some(c) if is_word_char(c) => {
+                self.pos = start;
+                let (text, span) = self.word();
+                Node::Word { text, span }
+            }
+            Some(c) => {
+                return Err(NotationError::new(format!("unexpected '{c}'"), start..start + c.len_utf8()));
+            }
+            None => return Err(NotationError::new("unexpected end of input", start..start)),
+        };
+
+        let mut weight = Frac::int(1);
+        let mut copies = 1;
+        loop {
+            match self.peek() {
+                Some('*') => {
+                    self.pos += 1;
+                    let factor = self.factor()?;
+                    node = Node::Fast(Box::new(node), factor);
+                }
+                Some('/') => {
+                    self.pos += 1;
+                    let factor = self.factor()?;
+                    let inverse = Frac::new(factor.den as i128, factor.num as i128).unwrap_or(factor);
+                    node = Node::Fast(Box::new(node), inverse);
+                }
+                Some('@') => {
+                    self.pos += 1;
+                    weight = self.factor()?;
+                }
+                Some('!') if self.src[self.pos + 1..].starts_with(|c: char| c.is_ascii_digit()) => {
+                    self.pos += 1;
+                    copies = self.count()?.max(1);
+                }
+                Some('(') => {
+                    let open = self.pos;
+                    self.pos += 1;
+                    let pulses = self.integer()?;
+                    self.expect(',')?;
+                    let steps = self.count()?;
+                    self.skip_ws();
+                    let rotation = if self.peek() == Some(',') {
+                        self.pos += 1;
+                        self.integer()?
+                    } else {
+                        0
+                    };
+                    self.expect(')')?;
+                    if steps == 0 {
+                        return Err(NotationError::new("euclid needs at least one step", open..self.pos));
+                    }
+                    self.spend(&node, steps, open..self.pos)?;
+                    let hits = euclidean_rhythm(pulses, steps, rotation % steps);
+                    node = Node::Seq(
+                        hits.into_iter()
+                            .map(|hit| (if hit { node.clone() } else { Node::Rest }, Frac::int(1)))
+                            .collect(),
+                    );
+                }
+                _ => break,
+            }
+        }
+        Ok((node, weight, copies))
+    }
+}
+
+fn stack(mut nodes: Vec<Node>) -> Node {
+    if nodes.len() == 1 {
+        nodes.remove(0)
+    } else {
+        Node::Stack(nodes)
+    }
+}
+
+/// A parsed mini-notation pattern. One cycle is the span of the top-level sequence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MiniPattern {
+    root: Node,
+    /// Length of the source, for errors about the pattern as a whole
+    len: usize,
+}
+
+/// A pattern event resolved to ticks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MiniEvent {
+    pub tick: u32,
+    pub duration: u32,
+    pub word: String,
+    /// Where the word appears in the source
+    pub span: Range<usize>,
+}
+
+impl MiniPattern {
+    pub fn parse(src: &str) -> Result<Self, NotationError> {
+        let mut parser = Parser { src, pos: 0, nodes: 0, depth: 0 };
+        let layers = parser.layers('\0')?;
+        if let Some(c) = parser.peek() {
+            return Err(parser.error_here(format!("unexpected '{c}'")));
+        }
+        Ok(Self { root: stack(layers.into_iter().map(Node::Seq).collect()), len: src.len() })
+    }
+
+    /// Events starting in the first `cycles` cycles, in time order. Fails
+    /// when rendering them would take too long.
+    pub fn events(&self, cycles: u32, ticks_per_cycle: u32) -> Result<Vec<MiniEvent>, NotationError> {
+        let mut events = Vec::new();
+        let mut work = MAX_WORK;
+        self.root
+            .query(Frac::int(0), Frac::int(cycles as i64), &mut events, &mut work)
+            .ok_or_else(|| NotationError::new("pattern is too dense to render", 0..self.len))?;
+        events.sort_by_key(|e| e.start);
+        Ok(events
+            .into_iter()
+            .map(|ev| {
+                let tick = ev.start.to_ticks(ticks_per_cycle);
+                MiniEvent {
+                    tick,
+                    duration: ev.end.to_ticks(ticks_per_cycle).saturating_sub(tick).max(1),
+                    word: ev.text,
+                    span: ev.span,
+                }
+            })
+            .collect())
+    }
+
+    /// Notes for `cycles` cycles. Every word must be a note name or MIDI number.
+    pub fn notes(&self, cycles: u32, ticks_per_cycle: u32, velocity: u8) -> Result<Vec<Note>, NotationError> {
+        self.events(cycles, ticks_per_cycle)?
+            .into_iter()
+            .map(|ev| {
+                let pitch = parse_pitch(&ev.word)
+                    .ok_or_else(|| NotationError::new(format!("'{}' is not a note", ev.word), ev.span))?;
+                Ok(Note::new(ev.tick, ev.duration, pitch, velocity))
+            })
+            .collect()
+    }
+
+    /// A drum grid with one row per pad and `steps` steps for one cycle.
+    /// Words are pad indices or (case-insensitive) entries of `pad_names`.
+    pub fn drum_grid(&self, pad_names: &[&str], steps: usize) -> Result<Vec<Vec<DrumStep>>, NotationError> {
+        let mut grid = vec![vec![DrumStep::default(); steps]; pad_names.len()];
+        if steps == 0 {
+            return Ok(grid);
+        }
+        for ev in self.events(1, steps as u32)? {
+            let pad = ev
+                .word
+                .parse::<usize>()
+                .ok()
+                .filter(|&i| i < pad_names.len())
+                .or_else(|| pad_names.iter().position(|n| n.eq_ignore_ascii_case(&ev.word)))
+                .ok_or_else(|| NotationError::new(format!("unknown pad '{}'", ev.word), ev.span.clone()))?;
+            if let Some(step) = grid[pad].get_mut(ev.tick as usize) {
+                step.active = true;
+            }
+        }
+        Ok(grid)
+    }
+}
+
+/// Parse `c4`, `eb3`, `f#5`, `cs2` (octave defaults to 4, C4 = 60) or a MIDI number
+pub fn parse_pitch(word: &str) -> Option<u8> {
+    if let Ok(n) = word.parse::<u8>() {
+        return (n <= 127).then_some(n);
+    }
+    let lower = word.to_ascii_lowercase();
+    let mut chars = lower.chars().peekable();
+    let base = match chars.next()? {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    let mut semis = base;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '#' | 's' => semis += 1,
+            'b' | 'f' => semis -= 1,
+            _ => break,
+        }
+        chars.next();
+    }
+    let rest: String = chars.collect();
+    let octave: i32 = if rest.is_empty() { 4 } else { rest.parse().ok()? };
+    let pitch = (octave + 1) * 12 + semis;
+    (0..=127).contains(&pitch).then_some(pitch as u8)
+}
+
+/// Parse a pattern and render `cycles` cycles to notes
+pub fn parse_notes(src: &str, cycles: u32, ticks_per_cycle: u32) -> Result<Vec<Note>, NotationError> {
+    MiniPattern::parse(src)?.notes(cycles, ticks_per_cycle, 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(src: &str, cycles: u32) -> Vec<(u32, u32, String)> {
+        MiniPattern::parse(src)
+            .unwrap()
+            .events(cycles, 960)
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.tick, e.duration, e.word))
+            .collect()
+    }
+
+    fn s(t: u32, d: u32, w: &str) -> (u32, u32, String) {
+        (t, d, w.to_string())
+    }
+
+    #[test]
+    fn sequence_subdivision_and_rest() {
+        assert_eq!(
+            shape("c4 [e4 g4] ~", 1),
+            vec![s(0, 320, "c4"), s(320, 160, "e4"), s(480, 160, "g4")]
+        );
+    }
+
+    #[test]
+    fn alternation_and_fast() {
+        assert_eq!(shape("<a4 b4>*2", 1), vec![s(0, 480, "a4"), s(480, 480, "b4")]);
+        assert_eq!(shape("c4 <e4 g4>", 2), vec![s(0, 480, "c4"), s(480, 480, "e4"), s(960, 480, "c4"), s(1440, 480, "g4")]);
+        assert_eq!(shape("c4/2", 2), vec![s(0, 1920, "c4")]);
+    }
+
+    #[test]
+    fn replicate_weight_and_stack() {
+        assert_eq!(shape("a!3 b", 1).len(), 4);
+        assert_eq!(shape("a ! b", 1).len(), 3);
+        assert_eq!(shape("a@3 b", 1), vec![s(0, 720, "a"), s(720, 240, "b")]);
+        assert_eq!(shape("[c4, e4]", 1), vec![s(0, 960, "c4"), s(0, 960, "e4")]);
+    }
+
+    #[test]
+    fn polymeter_and_euclid() {
+        let ev = shape("{a b c, d e}", 1);
+        let words: Vec<&str> = ev.iter().map(|e| e.2.as_str()).collect();
+        assert_eq!(words, vec!["a", "d", "b", "e", "c", "d"]);
+        assert_eq!(shape("{a b c}%4", 1).last().unwrap().2, "a");
+        let ticks: Vec<u32> = shape("bd(3,8)", 1).iter().map(|e| e.0).collect();
+        assert_eq!(ticks, vec![0, 360, 720]);
+    }
+
+    #[test]
+    fn notes_and_drum_grid() {
+        let notes = parse_notes("c4 eb4 f#4 60", 1, 960).unwrap();
+        let pitches: Vec<u8> = notes.iter().map(|n| n.pitch).collect();
+        assert_eq!(pitches, vec![60, 63, 66, 60]);
+        let grid = MiniPattern::parse("[bd sn]*2, hh*4").unwrap().drum_grid(&["bd", "sn", "hh"], 8).unwrap();
+        let row = |p: usize| grid[p].iter().map(|s| s.active as u8).collect::<Vec<_>>();
+        assert_eq!(row(0), vec![1, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(row(1), vec![0, 0, 1, 0, 0, 0, 1, 0]);
+        assert_eq!(row(2), vec![1, 0, 1, 0, 1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn errors_point_at_spans() {
+        let err = MiniPattern::parse("c4 [e4 g4").unwrap_err();
+        assert_eq!(err.span, 3..4);
+        assert!(err.message.contains("unclosed"));
+        let err = parse_notes("c4 zz4", 1, 960).unwrap_err();
+        assert_eq!(err.span, 3..6);
+        assert_eq!(err.render("c4 zz4").lines().nth(1).unwrap(), "   ^^^ 'zz4' is not a note");
+        let err = MiniPattern::parse("a*x").unwrap_err();
+        assert_eq!(err.span, 2..3);
+        assert_eq!(MiniPattern::parse("a ]").unwrap_err().span, 2..3);
+        assert!(MiniPattern::parse("a(3 8)").is_err());
+    }
+
+    #[test]
+    fn oversized_patterns_are_errors() {
+        let err = |src: &str| MiniPattern::parse(src).unwrap_err();
+        assert_eq!(err("a*0.0000000000000000001").span, 2..23);
+        assert_eq!(err("a(1,99999999)").span, 4..12);
+        assert_eq!(err("a*100000000").span, 2..11);
+        assert_eq!(err("[a b]*4294967296").span, 6..16);
+        assert_eq!(err("a/0.0001").span, 2..8);
+        assert_eq!(err("a!99999999").span, 2..10);
+        assert!(err("[a!1024]!1024").message.contains("too large"));
+        assert!(err(&"[".repeat(100_000)).message.contains("nested"));
+        assert_eq!(shape("a(3,8,1000)", 1).len(), 3);
+
+        let dense = MiniPattern::parse("[[[a*1024]*1024]*1024]").unwrap();
+        let err = dense.events(1, 960).unwrap_err();
+        assert_eq!((err.span, err.message.contains("dense")), (0..22, true));
+        assert!(MiniPattern::parse("a").unwrap().notes(u32::MAX, 960, 100).is_err());
+    }
+}
@@ -3,16 +3,29 @@ pub mod automation;
 pub mod clipboard;
 pub mod custom_synthdef;
 pub mod drum_sequencer;
+pub mod generator;
 pub mod humanize;
 pub mod instrument;
+pub mod interval_index;
 pub mod io;
+pub mod loop_range;
 pub mod midi_recording;
+pub mod mini_notation;
 pub mod mixer;
 pub mod music;
+pub mod note_index;
+pub mod note_selection;
+pub mod note_transform;
 pub mod piano_roll;
+pub mod probability;
 pub mod project;
 pub mod recording;
 pub mod session;
+pub mod step_input;
+pub mod take;
+pub mod tracker;
+pub mod trig;
+pub mod voice;
 pub mod vst;
 
 pub use arrangement::*;
@@ -20,16 +33,29 @@ pub use automation::*;
 pub use clipboard::{Clipboard, ClipboardContents};
 pub use custom_synthdef::*;
 pub use drum_sequencer::*;
+pub use generator::{GeneratorRegion, NoteGenerator};
 pub use humanize::*;
 pub use instrument::*;
+pub use interval_index::{Interval, IntervalIndex};
 pub use io::*;
+pub use loop_range::*;
 pub use midi_recording::*;
+pub use mini_notation::{MiniEvent, MiniPattern, NotationError};
 pub use mixer::*;
 pub use music::*;
+pub use note_index::NoteIndex;
+pub use note_selection::*;
+pub use note_transform::NoteTransform;
 pub use piano_roll::*;
+pub use probability::*;
 pub use project::*;
 pub use recording::*;
 pub use session::*;
+pub use step_input::*;
+pub use take::*;
+pub use tracker::{TrackerCell, TrackerEffect, TrackerNote, TrackerPattern};
+pub use trig::{TrigCondition, TrigContext};
+pub use voice::*;
 pub use vst::*;
 
 use std::collections::VecDeque;
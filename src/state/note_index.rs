@@ -0,0 +1,340 @@
+//! Sorted note storage with logarithmic range queries.
+
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use super::interval_index::MaxEnds;
+use super::piano_roll::{Note, NoteId};
+
+/// Notes kept sorted by (tick, pitch, id).
+///
+/// Alongside the sorted vector it keeps a max-end tree over the notes, so
+/// "which notes sound during [a, b)" skips every run of notes that ends
+/// before `a`, however long the longest note is, and the sort key of every
+/// note with an id, so id lookups are a binary search. Reads go through
+/// `Deref<Target = [Note]>`; writes go through methods that keep the order intact.
+#[derive(Debug, Clone, Default)]
+pub struct NoteIndex {
+    notes: Vec<Note>,
+    /// (tick, pitch) of each note by id; notes without an id (0) aren't listed
+    keys: HashMap<NoteId, (u32, u8)>,
+    /// Note ends in sorted order, built on the first overlap query and then
+    /// updated with each edit
+    ends: OnceLock<MaxEnds>,
+}
+
+fn sort_key(note: &Note) -> (u32, u8, NoteId) {
+    (note.tick, note.pitch, note.id)
+}
+
+impl NoteIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build from unsorted notes
+    pub fn from_vec(mut notes: Vec<Note>) -> Self {
+        notes.sort_by_key(sort_key);
+        let mut index = Self { notes, keys: HashMap::new(), ends: OnceLock::new() };
+        index.rebuild_keys();
+        index
+    }
+
+    fn rebuild_keys(&mut self) {
+        self.keys = self.notes.iter().filter(|n| n.id != 0).map(|n| (n.id, (n.tick, n.pitch))).collect();
+    }
+
+    pub fn as_slice(&self) -> &[Note] {
+        &self.notes
+    }
+
+    pub fn into_vec(self) -> Vec<Note> {
+        self.notes
+    }
+
+    /// Insert a note in sorted position. Returns its index.
+    pub fn insert(&mut self, note: Note) -> usize {
+        let key = sort_key(&note);
+        let pos = self.notes.partition_point(|n| sort_key(n) <= key);
+        if note.id != 0 {
+            self.keys.insert(note.id, (note.tick, note.pitch));
+        }
+        if let Some(ends) = self.ends.get_mut() {
+            ends.insert(pos, note.end_tick());
+        }
+        self.notes.insert(pos, note);
+        pos
+    }
+
+    /// Alias for `insert`, so the storage can be filled like a `Vec`
+    pub fn push(&mut self, note: Note) {
+        self.insert(note);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Note {
+        let note = self.notes.remove(index);
+        if self.keys.get(&note.id) == Some(&(note.tick, note.pitch)) {
+            self.keys.remove(&note.id);
+        }
+        if let Some(ends) = self.ends.get_mut() {
+            ends.remove(index);
+        }
+        note
+    }
+
+    /// Remove a note by id
+    pub fn remove_id(&mut self, id: NoteId) -> Option<Note> {
+        let pos = self.position_of(id)?;
+        Some(self.remove(pos))
+    }
+
+    pub fn retain(&mut self, f: impl FnMut(&Note) -> bool) {
+        self.notes.retain(f);
+        self.rebuild_keys();
+        self.ends = OnceLock::new();
+    }
+
+    pub fn clear(&mut self) {
+        self.notes.clear();
+        self.keys.clear();
+        self.ends = OnceLock::new();
+    }
+
+    /// Take all notes out, leaving the index empty
+    pub fn take(&mut self) -> Vec<Note> {
+        self.keys.clear();
+        self.ends = OnceLock::new();
+        std::mem::take(&mut self.notes)
+    }
+
+    /// Replace the contents with (unsorted) notes
+    pub fn replace(&mut self, notes: Vec<Note>) {
+        *self = Self::from_vec(notes);
+    }
+
+    /// Edit one note by id, moving it to its new sorted position
+    pub fn update(&mut self, id: NoteId, f: impl FnOnce(&mut Note)) -> bool {
+        let Some(from) = self.position_of(id) else {
+            return false;
+        };
+        let mut note = self.notes[from].clone();
+        f(&mut note);
+        if note.id != id {
+            // A new id moves the note under a new key
+            self.remove(from);
+            self.insert(note);
+            return true;
+        }
+        // Slot among the other notes, then shift the notes in between
+        let key = sort_key(&note);
+        let slot = self.notes.partition_point(|n| sort_key(n) <= key);
+        let to = if slot > from { slot - 1 } else { slot };
+        if from < to {
+            self.notes[from..=to].rotate_left(1);
+        } else {
+            self.notes[to..=from].rotate_right(1);
+        }
+        if id != 0 {
+            self.keys.insert(id, (note.tick, note.pitch));
+        }
+        if let Some(ends) = self.ends.get_mut() {
+            ends.relocate(from, to, note.end_tick());
+        }
+        self.notes[to] = note;
+        true
+    }
+
+    /// Edit notes in place, then restore sorted order
+    pub fn modify(&mut self, f: impl FnOnce(&mut [Note])) {
+        f(&mut self.notes);
+        self.notes.sort_by_key(sort_key);
+        self.rebuild_keys();
+        self.ends = OnceLock::new();
+    }
+
+    /// Position of the note with this id: a binary search on its key, or a
+    /// scan for notes without an id
+    fn position_of(&self, id: NoteId) -> Option<usize> {
+        if id == 0 {
+            return self.notes.iter().position(|n| n.id == 0);
+        }
+        let &(tick, pitch) = self.keys.get(&id)?;
+        let pos = self.notes.partition_point(|n| sort_key(n) < (tick, pitch, id));
+        self.notes.get(pos).filter(|n| n.id == id).map(|_| pos)
+    }
+
+    /// Index range of notes starting in [start, end)
+    fn start_range(&self, start: u32, end: u32) -> std::ops::Range<usize> {
+        let lo = self.notes.partition_point(|n| n.tick < start);
+        let hi = self.notes.partition_point(|n| n.tick < end).max(lo);
+        lo..hi
+    }
+
+    /// Notes starting in [start, end), in O(log n)
+    pub fn starting_in(&self, start: u32, end: u32) -> &[Note] {
+        &self.notes[self.start_range(start, end)]
+    }
+
+    /// Notes sounding at any point in [start, end)
+    pub fn overlapping(&self, start: u32, end: u32) -> impl Iterator<Item = &Note> {
+        let hi = self.notes.partition_point(|n| n.tick < end);
+        let ends = self.ends.get_or_init(|| MaxEnds::build(self.notes.iter().map(|n| n.end_tick())));
+        ends.ending_after(start, hi).into_iter().map(|i| &self.notes[i])
+    }
+
+    /// Notes sounding at the given tick
+    pub fn sounding_at(&self, tick: u32) -> impl Iterator<Item = &Note> {
+        self.overlapping(tick, tick.saturating_add(1))
+    }
+
+    /// Position of the note at exactly this pitch and tick
+    pub fn position_at(&self, pitch: u8, tick: u32) -> Option<usize> {
+        let lo = self.notes.partition_point(|n| n.tick < tick);
+        let mut at_tick = self.notes[lo..].iter().take_while(|n| n.tick == tick);
+        at_tick.position(|n| n.pitch == pitch).map(|i| lo + i)
+    }
+
+    pub fn find_id(&self, id: NoteId) -> Option<&Note> {
+        self.position_of(id).map(|pos| &self.notes[pos])
+    }
+}
+
+impl Deref for NoteIndex {
+    type Target = [Note];
+
+    fn deref(&self) -> &[Note] {
+        &self.notes
+    }
+}
+
+impl<'a> IntoIterator for &'a NoteIndex {
+    type Item = &'a Note;
+    type IntoIter = std::slice::Iter<'a, Note>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.notes.iter()
+    }
+}
+
+impl From<Vec<Note>> for NoteIndex {
+    fn from(notes: Vec<Note>) -> Self {
+        Self::from_vec(notes)
+    }
+}
+
+impl From<NoteIndex> for Vec<Note> {
+    fn from(index: NoteIndex) -> Self {
+        index.notes
+    }
+}
+
+impl FromIterator<Note> for NoteIndex {
+    fn from_iter<I: IntoIterator<Item = Note>>(iter: I) -> Self {
+        Self::from_vec(iter.into_iter().collect())
+    }
+}
+
+// Persisted as a plain list of notes so existing projects load unchanged
+impl Serialize for NoteIndex {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.notes.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for NoteIndex {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<Note>::deserialize(deserializer).map(Self::from_vec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn n(id: u32, tick: u32, duration: u32, pitch: u8) -> Note {
+        Note { id, ..Note::new(tick, duration, pitch, 100) }
+    }
+
+    #[test]
+    fn insert_keeps_order() {
+        let mut idx = NoteIndex::new();
+        idx.insert(n(1, 480, 10, 60));
+        idx.insert(n(2, 0, 10, 64));
+        idx.insert(n(3, 0, 10, 60));
+        let ids: Vec<NoteId> = idx.iter().map(|n| n.id).collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn range_and_overlap_queries() {
+        let idx = NoteIndex::from_vec(vec![
+            n(1, 0, 1000, 60),
+            n(2, 500, 10, 62),
+            n(3, 900, 10, 64),
+            n(4, 2000, 10, 65),
+        ]);
+        let starting: Vec<NoteId> = idx.starting_in(400, 1000).iter().map(|n| n.id).collect();
+        assert_eq!(starting, vec![2, 3]);
+        let overlapping: Vec<NoteId> = idx.overlapping(950, 2001).map(|n| n.id).collect();
+        assert_eq!(overlapping, vec![1, 4]);
+        assert_eq!(idx.sounding_at(505).count(), 2);
+        assert_eq!(idx.position_at(64, 900), Some(2));
+        assert_eq!(idx.position_at(64, 901), None);
+    }
+
+    #[test]
+    fn long_note_does_not_widen_overlap_queries() {
+        let mut notes: Vec<Note> = (1..500).map(|i| n(i, i * 10, 5, 60)).collect();
+        notes.push(n(500, 0, u32::MAX, 48));
+        let mut idx = NoteIndex::from_vec(notes);
+        let sounding: Vec<NoteId> = idx.sounding_at(2502).map(|n| n.id).collect();
+        assert_eq!(sounding, vec![500, 250]);
+        idx.remove_id(500);
+        assert_eq!(idx.sounding_at(2507).count(), 0);
+    }
+
+    #[test]
+    fn queries_at_the_last_tick() {
+        let idx = NoteIndex::from_vec(vec![n(1, u32::MAX, 10, 60)]);
+        assert_eq!(idx.position_at(60, u32::MAX), Some(0));
+        assert_eq!(idx.sounding_at(u32::MAX).count(), 0);
+    }
+
+    #[test]
+    fn edits_keep_ids_and_tree_current() {
+        let mut idx = NoteIndex::from_vec((1..=20).map(|i| n(i, i * 100, 50, 60)).collect());
+        assert_eq!(idx.sounding_at(1010).count(), 1);
+        idx.insert(n(30, 150, 2000, 48));
+        assert!(idx.update(3, |note| note.tick = 1900));
+        assert!(idx.update(15, |note| note.tick = 50));
+        assert!(idx.update(7, |note| note.duration = 1100));
+        idx.remove_id(12);
+        let sounding: Vec<NoteId> = idx.sounding_at(1710).map(|n| n.id).collect();
+        assert_eq!(sounding, vec![30, 7, 17]);
+        assert_eq!(idx.find_id(3).map(|n| n.tick), Some(1900));
+        assert!(idx.find_id(12).is_none());
+        let rebuilt = NoteIndex::from_vec(idx.to_vec());
+        assert!(idx.iter().map(|n| n.id).eq(rebuilt.iter().map(|n| n.id)));
+        let rebuilt_ends = MaxEnds::build(rebuilt.iter().map(|n| n.end_tick()));
+        assert_eq!(format!("{:?}", idx.ends.get()), format!("{:?}", Some(&rebuilt_ends)));
+    }
+
+    #[test]
+    fn update_repositions() {
+        let mut idx = NoteIndex::from_vec(vec![n(1, 0, 10, 60), n(2, 100, 10, 60)]);
+        assert!(idx.update(1, |note| note.tick = 200));
+        assert_eq!(idx[1].id, 1);
+        assert!(!idx.update(9, |_| {}));
+    }
+
+    #[test]
+    fn converts_to_and_from_plain_list() {
+        let idx = NoteIndex::from_vec(vec![n(1, 10, 10, 60)]);
+        let cloned: NoteIndex = Vec::<Note>::from(idx.clone()).into();
+        assert_eq!(cloned.len(), 1);
+        assert_eq!(cloned.overlapping(15, 16).count(), 1);
+    }
+}
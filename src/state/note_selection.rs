@@ -0,0 +1,222 @@
+//! Note selection model keyed by stable note ids.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use super::piano_roll::{Note, NoteId};
+
+/// A set of selected notes. Ids stay valid when notes move or change length.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoteSelection {
+    ids: BTreeSet<NoteId>,
+}
+
+/// A selection change, carried by `PianoRollAction::UpdateSelection`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoteSelectionOp {
+    Clear,
+    All,
+    /// Replace the selection with exactly these notes
+    Set(Vec<NoteId>),
+    Add(Vec<NoteId>),
+    Remove(Vec<NoteId>),
+    Toggle(NoteId),
+    /// Add notes starting in [start_tick, end_tick) with pitch in [low_pitch, high_pitch]
+    Rect { start_tick: u32, end_tick: u32, low_pitch: u8, high_pitch: u8 },
+    Invert,
+    /// Add every note with this pitch
+    ByPitch(u8),
+    /// Add every note with velocity in [min, max]
+    ByVelocity { min: u8, max: u8 },
+}
+
+impl NoteSelection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_ids(ids: impl IntoIterator<Item = NoteId>) -> Self {
+        Self { ids: ids.into_iter().collect() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn contains(&self, id: NoteId) -> bool {
+        self.ids.contains(&id)
+    }
+
+    pub fn contains_any(&self, ids: &[NoteId]) -> bool {
+        ids.iter().any(|id| self.ids.contains(id))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = NoteId> + '_ {
+        self.ids.iter().copied()
+    }
+
+    /// Selected ids in ascending order (for actions)
+    pub fn to_vec(&self) -> Vec<NoteId> {
+        self.ids.iter().copied().collect()
+    }
+
+    pub fn add(&mut self, id: NoteId) {
+        self.ids.insert(id);
+    }
+
+    pub fn remove(&mut self, id: NoteId) {
+        self.ids.remove(&id);
+    }
+
+    pub fn toggle(&mut self, id: NoteId) {
+        if !self.ids.remove(&id) {
+            self.ids.insert(id);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+    }
+
+    pub fn extend(&mut self, ids: impl IntoIterator<Item = NoteId>) {
+        self.ids.extend(ids);
+    }
+
+    pub fn union(&self, other: &NoteSelection) -> NoteSelection {
+        Self { ids: self.ids.union(&other.ids).copied().collect() }
+    }
+
+    pub fn intersection(&self, other: &NoteSelection) -> NoteSelection {
+        Self { ids: self.ids.intersection(&other.ids).copied().collect() }
+    }
+
+    pub fn difference(&self, other: &NoteSelection) -> NoteSelection {
+        Self { ids: self.ids.difference(&other.ids).copied().collect() }
+    }
+
+    pub fn symmetric_difference(&self, other: &NoteSelection) -> NoteSelection {
+        Self { ids: self.ids.symmetric_difference(&other.ids).copied().collect() }
+    }
+
+    pub fn select_all(&mut self, notes: &[Note]) {
+        self.ids.extend(notes.iter().map(|n| n.id));
+    }
+
+    /// Add notes starting in [start_tick, end_tick) with pitch in [low_pitch, high_pitch]
+    pub fn select_in_rect(
+        &mut self,
+        notes: &[Note],
+        start_tick: u32,
+        end_tick: u32,
+        low_pitch: u8,
+        high_pitch: u8,
+    ) {
+        self.ids.extend(
+            notes
+                .iter()
+                .filter(|n| n.tick >= start_tick && n.tick < end_tick)
+                .filter(|n| n.pitch >= low_pitch && n.pitch <= high_pitch)
+                .map(|n| n.id),
+        );
+    }
+
+    /// Select every unselected note and deselect every selected one
+    pub fn invert(&mut self, notes: &[Note]) {
+        self.ids = notes
+            .iter()
+            .map(|n| n.id)
+            .filter(|id| !self.ids.contains(id))
+            .collect();
+    }
+
+    pub fn select_by_pitch(&mut self, notes: &[Note], pitch: u8) {
+        self.ids.extend(notes.iter().filter(|n| n.pitch == pitch).map(|n| n.id));
+    }
+
+    pub fn select_by_velocity(&mut self, notes: &[Note], min: u8, max: u8) {
+        self.ids.extend(
+            notes
+                .iter()
+                .filter(|n| n.velocity >= min && n.velocity <= max)
+                .map(|n| n.id),
+        );
+    }
+
+    /// Drop ids that no longer refer to a note
+    pub fn retain_existing(&mut self, notes: &[Note]) {
+        self.ids.retain(|id| notes.iter().any(|n| n.id == *id));
+    }
+
+    /// The selected notes, in the order they appear in `notes`
+    pub fn selected<'a>(&self, notes: &'a [Note]) -> Vec<&'a Note> {
+        notes.iter().filter(|n| self.ids.contains(&n.id)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes() -> Vec<Note> {
+        let mut notes = vec![
+            Note::new(0, 240, 60, 100),
+            Note::new(240, 240, 64, 60),
+            Note::new(480, 240, 67, 30),
+            Note::new(960, 240, 60, 90),
+        ];
+        for (i, n) in notes.iter_mut().enumerate() {
+            n.id = i as NoteId + 1;
+        }
+        notes
+    }
+
+    #[test]
+    fn toggle_and_set_ops() {
+        let a = NoteSelection::from_ids([1, 2]);
+        let b = NoteSelection::from_ids([2, 3]);
+        assert_eq!(a.union(&b).to_vec(), vec![1, 2, 3]);
+        assert_eq!(a.intersection(&b).to_vec(), vec![2]);
+        assert_eq!(a.difference(&b).to_vec(), vec![1]);
+        assert_eq!(a.symmetric_difference(&b).to_vec(), vec![1, 3]);
+
+        let mut sel = a.clone();
+        sel.toggle(1);
+        sel.toggle(4);
+        assert_eq!(sel.to_vec(), vec![2, 4]);
+    }
+
+    #[test]
+    fn rect_and_invert() {
+        let notes = notes();
+        let mut sel = NoteSelection::new();
+        sel.select_in_rect(&notes, 0, 480, 60, 64);
+        assert_eq!(sel.to_vec(), vec![1, 2]);
+        sel.invert(&notes);
+        assert_eq!(sel.to_vec(), vec![3, 4]);
+    }
+
+    #[test]
+    fn by_pitch_and_velocity() {
+        let notes = notes();
+        let mut sel = NoteSelection::new();
+        sel.select_by_pitch(&notes, 60);
+        assert_eq!(sel.to_vec(), vec![1, 4]);
+        sel.clear();
+        sel.select_by_velocity(&notes, 50, 95);
+        assert_eq!(sel.to_vec(), vec![2, 4]);
+    }
+
+    #[test]
+    fn retain_existing_drops_stale_ids() {
+        let notes = notes();
+        let mut sel = NoteSelection::from_ids([1, 42]);
+        sel.retain_existing(&notes);
+        assert_eq!(sel.to_vec(), vec![1]);
+        assert_eq!(sel.selected(&notes).len(), 1);
+    }
+}
@@ -0,0 +1,338 @@
+//! Batch note transformations for the piano roll.
+//!
+//! Every transform is a pure function over a `Vec<Note>` (usually the selected
+//! notes of one track) and returns the transformed notes sorted by tick.
+//! Note ids are preserved; notes created by a transform (split) get fresh ids.
+
+use super::music::{Key, Scale};
+use super::piano_roll::{Note, NoteIdAllocator};
+use crate::rng::SeededRng;
+
+/// A note transformation, carried by `PianoRollAction::TransformSelection`
+/// and `PianoRollAction::TransformRegion`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoteTransform {
+    /// Shift by semitones
+    Transpose(i32),
+    /// Shift by scale degrees, snapping to the scale first
+    TransposeInScale { steps: i32, key: Key, scale: Scale },
+    /// Extend each note to the start of the next one
+    Legato,
+    /// Set every note to the same length
+    FixedLength(u32),
+    /// Scale positions and lengths around the first note (2.0 = twice as long)
+    TimeStretch(f32),
+    /// Mirror the notes in time within their span
+    Reverse,
+    /// Mirror pitches around an axis pitch
+    InvertPitch(u8),
+    /// Cut notes that span the given tick in two
+    SplitAt(u32),
+    /// Join touching or overlapping notes of the same pitch
+    Glue,
+    /// Linear velocity ramp from the first to the last note
+    VelocityRamp { from: u8, to: u8 },
+    /// Pull velocities toward their average (0.0 = unchanged, 1.0 = flat)
+    VelocityCompress(f32),
+    /// Push velocities away from their average (0.0 = unchanged, 1.0 = double spread)
+    VelocityExpand(f32),
+    /// Random velocity offset of up to +/- amount
+    VelocityRandomize { amount: u8, seed: u64 },
+}
+
+impl NoteTransform {
+    /// Apply the transform. `ids` supplies ids for notes the transform creates.
+    pub fn apply(&self, notes: Vec<Note>, ids: &mut NoteIdAllocator) -> Vec<Note> {
+        match self {
+            NoteTransform::Transpose(semitones) => transpose(notes, *semitones),
+            NoteTransform::TransposeInScale { steps, key, scale } => {
+                transpose_in_scale(notes, *steps, *key, *scale)
+            }
+            NoteTransform::Legato => legato(notes),
+            NoteTransform::FixedLength(duration) => fixed_length(notes, *duration),
+            NoteTransform::TimeStretch(ratio) => time_stretch(notes, *ratio),
+            NoteTransform::Reverse => reverse(notes),
+            NoteTransform::InvertPitch(axis) => invert_pitch(notes, *axis),
+            NoteTransform::SplitAt(tick) => split_at(notes, *tick, ids),
+            NoteTransform::Glue => glue(notes),
+            NoteTransform::VelocityRamp { from, to } => velocity_ramp(notes, *from, *to),
+            NoteTransform::VelocityCompress(amount) => velocity_compress(notes, *amount),
+            NoteTransform::VelocityExpand(amount) => velocity_expand(notes, *amount),
+            NoteTransform::VelocityRandomize { amount, seed } => {
+                velocity_randomize(notes, *amount, *seed)
+            }
+        }
+    }
+}
+
+fn sorted(mut notes: Vec<Note>) -> Vec<Note> {
+    notes.sort_by_key(|n| (n.tick, n.pitch));
+    notes
+}
+
+fn clamp_pitch(pitch: i32) -> u8 {
+    pitch.clamp(0, 127) as u8
+}
+
+fn clamp_velocity(velocity: i32) -> u8 {
+    velocity.clamp(1, 127) as u8
+}
+
+/// Shift every note by `semitones`, clamped to the MIDI range
+pub fn transpose(mut notes: Vec<Note>, semitones: i32) -> Vec<Note> {
+    for note in &mut notes {
+        note.pitch = clamp_pitch((note.pitch as i32).saturating_add(semitones));
+    }
+    sorted(notes)
+}
+
+/// Shift every note by `steps` scale degrees. Notes outside the scale snap
+/// down to the nearest scale tone before moving.
+pub fn transpose_in_scale(mut notes: Vec<Note>, steps: i32, key: Key, scale: Scale) -> Vec<Note> {
+    let intervals = scale.intervals();
+    let len = intervals.len() as i32;
+    let root = key.semitone();
+    // Eleven octaves cross the whole MIDI range; anything more lands at an end
+    let steps = steps.clamp(-11 * len, 11 * len);
+    for note in &mut notes {
+        let rel = note.pitch as i32 - root;
+        let octave = rel.div_euclid(12);
+        let within = rel.rem_euclid(12);
+        let degree = intervals.iter().rposition(|&i| i <= within).unwrap_or(0) as i32;
+        let target = octave * len + degree + steps;
+        let pitch = root + target.div_euclid(len) * 12 + intervals[target.rem_euclid(len) as usize];
+        note.pitch = clamp_pitch(pitch);
+    }
+    sorted(notes)
+}
+
+/// Extend each note up to the start of the next later note. The last note keeps its length.
+pub fn legato(notes: Vec<Note>) -> Vec<Note> {
+    let mut notes = sorted(notes);
+    let starts: Vec<u32> = notes.iter().map(|n| n.tick).collect();
+    for note in &mut notes {
+        let next = starts[starts.partition_point(|&t| t <= note.tick)..].first().copied();
+        if let Some(next) = next {
+            note.duration = next - note.tick;
+        }
+    }
+    notes
+}
+
+/// Give every note the same duration (minimum 1 tick)
+pub fn fixed_length(mut notes: Vec<Note>, duration: u32) -> Vec<Note> {
+    for note in &mut notes {
+        note.duration = duration.max(1);
+    }
+    sorted(notes)
+}
+
+/// Scale note positions (relative to the earliest note) and durations by `ratio`
+pub fn time_stretch(mut notes: Vec<Note>, ratio: f32) -> Vec<Note> {
+    let ratio = ratio.max(0.0);
+    let Some(anchor) = notes.iter().map(|n| n.tick).min() else {
+        return notes;
+    };
+    for note in &mut notes {
+        let offset = (note.tick - anchor) as f32 * ratio;
+        note.tick = anchor.saturating_add(offset.round() as u32);
+        note.duration = ((note.duration as f32 * ratio).round() as u32).max(1);
+    }
+    sorted(notes)
+}
+
+/// Mirror notes in time within the span from the first start to the last end
+pub fn reverse(mut notes: Vec<Note>) -> Vec<Note> {
+    let Some(start) = notes.iter().map(|n| n.tick).min() else {
+        return notes;
+    };
+    let end = notes.iter().map(|n| n.end_tick()).max().unwrap_or(start);
+    for note in &mut notes {
+        note.tick = start + (end - note.end_tick());
+    }
+    sorted(notes)
+}
+
+/// Mirror pitches around `axis` (e.g. 60 turns 64 into 56)
+pub fn invert_pitch(mut notes: Vec<Note>, axis: u8) -> Vec<Note> {
+    for note in &mut notes {
+        note.pitch = clamp_pitch(2 * axis as i32 - note.pitch as i32);
+    }
+    sorted(notes)
+}
+
+/// Split notes that strictly contain `tick`. The right half gets a new id.
+pub fn split_at(notes: Vec<Note>, tick: u32, ids: &mut NoteIdAllocator) -> Vec<Note> {
+    let mut out = Vec::with_capacity(notes.len());
+    for mut note in notes {
+        if note.tick < tick && tick < note.end_tick() {
+            let mut right = note.clone();
+            right.id = ids.alloc();
+            right.tick = tick;
+            right.duration = note.end_tick() - tick;
+            note.duration = tick - note.tick;
+            out.push(note);
+            out.push(right);
+        } else {
+            out.push(note);
+        }
+    }
+    sorted(out)
+}
+
+/// Merge touching or overlapping notes of the same pitch into one.
+/// The merged note keeps the first note's id, velocity and probability.
+pub fn glue(notes: Vec<Note>) -> Vec<Note> {
+    let notes = sorted(notes);
+    let mut out: Vec<Note> = Vec::with_capacity(notes.len());
+    for note in notes {
+        let open = out
+            .iter_mut()
+            .rev()
+            .find(|n| n.pitch == note.pitch && n.end_tick() >= note.tick);
+        match open {
+            Some(prev) => {
+                let end = prev.end_tick().max(note.end_tick());
+                prev.duration = end - prev.tick;
+            }
+            None => out.push(note),
+        }
+    }
+    out
+}
+
+/// Linear velocity ramp across the notes' time span
+pub fn velocity_ramp(notes: Vec<Note>, from: u8, to: u8) -> Vec<Note> {
+    let mut notes = sorted(notes);
+    let (Some(first), Some(last)) = (notes.first().map(|n| n.tick), notes.last().map(|n| n.tick))
+    else {
+        return notes;
+    };
+    let span = (last - first).max(1) as f32;
+    for note in &mut notes {
+        let t = if last == first { 0.0 } else { (note.tick - first) as f32 / span };
+        let v = from as f32 + (to as f32 - from as f32) * t;
+        note.velocity = clamp_velocity(v.round() as i32);
+    }
+    notes
+}
+
+fn scale_velocity_spread(mut notes: Vec<Note>, factor: f32) -> Vec<Note> {
+    if notes.is_empty() {
+        return notes;
+    }
+    let mean = notes.iter().map(|n| n.velocity as f32).sum::<f32>() / notes.len() as f32;
+    for note in &mut notes {
+        let v = mean + (note.velocity as f32 - mean) * factor;
+        note.velocity = clamp_velocity(v.round() as i32);
+    }
+    sorted(notes)
+}
+
+/// Pull velocities toward their average (0.0 = unchanged, 1.0 = all equal)
+pub fn velocity_compress(notes: Vec<Note>, amount: f32) -> Vec<Note> {
+    scale_velocity_spread(notes, 1.0 - amount.clamp(0.0, 1.0))
+}
+
+/// Push velocities away from their average (0.0 = unchanged, 1.0 = double spread)
+pub fn velocity_expand(notes: Vec<Note>, amount: f32) -> Vec<Note> {
+    scale_velocity_spread(notes, 1.0 + amount.max(0.0))
+}
+
+/// Add a seeded random offset of up to +/- `amount` to each velocity
+pub fn velocity_randomize(notes: Vec<Note>, amount: u8, seed: u64) -> Vec<Note> {
+    let mut notes = sorted(notes);
+    let mut rng = SeededRng::new(seed);
+    for note in &mut notes {
+        let offset = rng.range_i32(-(amount as i32), amount as i32);
+        note.velocity = clamp_velocity(note.velocity as i32 + offset);
+    }
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn n(id: u32, tick: u32, duration: u32, pitch: u8, velocity: u8) -> Note {
+        Note { id, ..Note::new(tick, duration, pitch, velocity) }
+    }
+
+    #[test]
+    fn transpose_in_scale_moves_by_degrees() {
+        // C major: E4 (64) up two degrees is G4 (67); B4 (71) up one is C5 (72)
+        let out = transpose_in_scale(vec![n(1, 0, 10, 64, 100), n(2, 10, 10, 71, 100)], 2, Key::C, Scale::Major);
+        assert_eq!(out[0].pitch, 67);
+        let out = transpose_in_scale(vec![n(2, 10, 10, 71, 100)], 1, Key::C, Scale::Major);
+        assert_eq!(out[0].pitch, 72);
+        // Downward across the octave: C4 (60) down one degree is B3 (59)
+        let out = transpose_in_scale(vec![n(3, 0, 10, 60, 100)], -1, Key::C, Scale::Major);
+        assert_eq!(out[0].pitch, 59);
+    }
+
+    #[test]
+    fn huge_transpositions_clamp() {
+        assert_eq!(transpose(vec![n(1, 0, 10, 60, 100)], i32::MAX)[0].pitch, 127);
+        assert_eq!(transpose(vec![n(1, 0, 10, 60, 100)], i32::MIN)[0].pitch, 0);
+        assert_eq!(transpose_in_scale(vec![n(1, 0, 10, 60, 100)], i32::MAX, Key::C, Scale::Major)[0].pitch, 127);
+        assert_eq!(transpose_in_scale(vec![n(1, 0, 10, 60, 100)], i32::MIN, Key::C, Scale::Major)[0].pitch, 0);
+    }
+
+    #[test]
+    fn legato_fills_gaps_and_keeps_chords() {
+        let out = legato(vec![n(1, 0, 10, 60, 100), n(2, 0, 10, 64, 100), n(3, 100, 10, 67, 100)]);
+        assert_eq!(out[0].duration, 100);
+        assert_eq!(out[1].duration, 100);
+        assert_eq!(out[2].duration, 10);
+    }
+
+    #[test]
+    fn stretch_and_reverse() {
+        let out = time_stretch(vec![n(1, 100, 50, 60, 100), n(2, 200, 50, 62, 100)], 2.0);
+        assert_eq!((out[1].tick, out[1].duration), (300, 100));
+        let out = time_stretch(vec![n(1, 100, 50, 60, 100), n(2, 200, 50, 62, 100)], 1.0e9);
+        assert_eq!(out[1].tick, u32::MAX);
+
+        let out = reverse(vec![n(1, 0, 10, 60, 100), n(2, 50, 50, 62, 100)]);
+        assert_eq!(out[0].id, 2);
+        assert_eq!(out[0].tick, 0);
+        assert_eq!(out[1].tick, 90);
+    }
+
+    #[test]
+    fn split_then_glue_round_trips() {
+        let mut ids = NoteIdAllocator::new();
+        ids.reserve_past(&[n(1, 0, 0, 0, 0)]);
+        let split = split_at(vec![n(1, 0, 100, 60, 100)], 40, &mut ids);
+        assert_eq!(split.len(), 2);
+        assert_eq!((split[0].duration, split[1].tick, split[1].duration), (40, 40, 60));
+        assert_ne!(split[0].id, split[1].id);
+
+        let glued = glue(split);
+        assert_eq!(glued.len(), 1);
+        assert_eq!((glued[0].id, glued[0].duration), (1, 100));
+    }
+
+    #[test]
+    fn invert_and_velocity_shapes() {
+        let out = invert_pitch(vec![n(1, 0, 10, 64, 100)], 60);
+        assert_eq!(out[0].pitch, 56);
+
+        let notes = vec![n(1, 0, 10, 60, 40), n(2, 10, 10, 60, 80), n(3, 20, 10, 60, 120)];
+        let ramp = velocity_ramp(notes.clone(), 10, 110);
+        assert_eq!(ramp.iter().map(|n| n.velocity).collect::<Vec<_>>(), vec![10, 60, 110]);
+        let flat = velocity_compress(notes.clone(), 1.0);
+        assert!(flat.iter().all(|n| n.velocity == 80));
+        let wide = velocity_expand(notes.clone(), 0.5);
+        assert_eq!((wide[0].velocity, wide[2].velocity), (20, 127));
+    }
+
+    #[test]
+    fn randomize_is_seeded_and_bounded() {
+        let notes: Vec<Note> = (0..16).map(|i| n(i + 1, i * 10, 10, 60, 64)).collect();
+        let a = velocity_randomize(notes.clone(), 10, 3);
+        let b = velocity_randomize(notes, 10, 3);
+        assert!(a.iter().zip(&b).all(|(x, y)| x.velocity == y.velocity));
+        assert!(a.iter().all(|n| (54..=74).contains(&n.velocity)));
+    }
+}
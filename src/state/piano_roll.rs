@@ -1,16 +1,103 @@
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 
 use serde::{Serialize, Deserialize};
 
+use super::generator::{GeneratorRegion, NoteGenerator};
+use super::loop_range::{LoopRange, NoteRecordMode};
+use super::music::{Key, Scale};
+use super::note_index::NoteIndex;
+use super::note_selection::{NoteSelection, NoteSelectionOp};
+use super::note_transform::{transpose_in_scale, NoteTransform};
+use super::step_input::StepInputState;
+use super::take::{TakeId, TakeLanes};
+use super::tracker::TrackerPattern;
+use super::trig::TrigCondition;
+use super::voice::{
+    overlap_conflicts, resolve_mono_legato, resolve_mono_overlaps, voice_steals, MonoOverlapMode,
+    OverlapConflict, VoiceSettings,
+};
 use crate::InstrumentId;
 
+/// Stable identifier for a note. One allocator hands out ids for every track
+/// of a `PianoRollState` (and another for every clip of an `ArrangementState`),
+/// so ids are unique across all of them; 0 means "not yet assigned" (e.g.
+/// notes loaded from older projects).
+pub type NoteId = u32;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Note {
+    #[serde(default)]
+    pub id: NoteId,
     pub tick: u32,
     pub duration: u32,
     pub pitch: u8,
     pub velocity: u8,
     pub probability: f32, // 0.0-1.0, default 1.0 (always play)
+    /// Pass condition, checked before probability
+    #[serde(default)]
+    pub condition: TrigCondition,
+}
+
+impl Note {
+    /// Create a note with full probability and no assigned id
+    pub fn new(tick: u32, duration: u32, pitch: u8, velocity: u8) -> Self {
+        Self {
+            id: 0,
+            tick,
+            duration,
+            pitch,
+            velocity,
+            probability: 1.0,
+            condition: TrigCondition::Always,
+        }
+    }
+
+    /// Exclusive end tick
+    pub fn end_tick(&self) -> u32 {
+        self.tick.saturating_add(self.duration)
+    }
+}
+
+/// Hands out note ids. Never reuses an id within one owner; once ids run out
+/// at `NoteId::MAX` that last id is handed out again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoteIdAllocator {
+    next: NoteId,
+}
+
+impl Default for NoteIdAllocator {
+    fn default() -> Self {
+        Self { next: 1 }
+    }
+}
+
+impl NoteIdAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocate a fresh id
+    pub fn alloc(&mut self) -> NoteId {
+        let id = self.next;
+        self.next = self.next.saturating_add(1);
+        id
+    }
+
+    /// Make sure future ids are above every id in `notes`
+    pub fn reserve_past(&mut self, notes: &[Note]) {
+        if let Some(max) = notes.iter().map(|n| n.id).max() {
+            self.next = self.next.max(max.saturating_add(1));
+        }
+    }
+
+    /// Give every unassigned note (id 0) a fresh id
+    pub fn assign_missing(&mut self, notes: &mut [Note]) {
+        self.reserve_past(notes);
+        for note in notes.iter_mut().filter(|n| n.id == 0) {
+            note.id = self.alloc();
+        }
+    }
 }
 
 /// A note stored with position relative to the selection anchor.
@@ -22,13 +109,66 @@ pub struct ClipboardNote {
     pub duration: u32,
     pub velocity: u8,
     pub probability: f32,
+    pub condition: TrigCondition,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Track {
     pub module_id: InstrumentId,
-    pub notes: Vec<Note>,
+    /// Notes sorted by tick (indexed for range queries)
+    pub notes: NoteIndex,
     pub polyphonic: bool,
+    /// Voice count, steal mode and legato flag for this instrument
+    #[serde(default)]
+    pub voice: VoiceSettings,
+    /// Recorded takes and the comp built from them
+    #[serde(default)]
+    pub takes: TakeLanes,
+}
+
+impl Track {
+    /// Notes as they will actually sound: mono tracks have overlaps resolved
+    /// (an auditioned take replaces the notes in its region)
+    pub fn playable_notes(&self) -> Vec<Note> {
+        let notes = self.takes.audition_notes(&self.notes).unwrap_or_else(|| self.notes.to_vec());
+        if self.polyphonic {
+            notes
+        } else {
+            resolve_mono_overlaps(notes, self.voice.mono_mode())
+        }
+    }
+
+    /// `playable_notes` with whether each note glides in from the previous
+    /// one instead of retriggering (only on mono legato tracks)
+    pub fn playable_notes_legato(&self) -> Vec<(Note, bool)> {
+        if self.polyphonic || self.voice.mono_mode() != MonoOverlapMode::Legato {
+            return self.playable_notes().into_iter().map(|n| (n, false)).collect();
+        }
+        resolve_mono_legato(self.takes.audition_notes(&self.notes).unwrap_or_else(|| self.notes.to_vec()))
+    }
+
+    /// Overlaps the track can't play as written: any overlap on a mono track,
+    /// voice steals on a polyphonic one
+    pub fn voice_conflicts(&self) -> Vec<OverlapConflict> {
+        if self.polyphonic {
+            voice_steals(&self.notes, &self.voice)
+        } else {
+            overlap_conflicts(&self.notes)
+        }
+    }
+
+    /// Rewrite the notes so a mono track has no overlaps (no-op when polyphonic)
+    pub fn resolve_overlaps(&mut self) {
+        if !self.polyphonic {
+            let notes = resolve_mono_overlaps(self.notes.take(), self.voice.mono_mode());
+            self.notes.replace(notes);
+        }
+    }
+}
+
+/// Four bars at the default 480 ticks per beat
+fn default_punch_out() -> u32 {
+    480 * 16
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,6 +190,35 @@ pub struct PianoRollState {
     pub recording: bool,
     /// Swing amount: 0.0 = no swing, 1.0 = max swing (delays offbeat notes)
     pub swing_amount: f32,
+    /// Note id allocator shared by all tracks (saved with the project, like
+    /// the arrangement's; `recalculate_note_ids` repairs older files)
+    #[serde(default)]
+    pub note_ids: NoteIdAllocator,
+    /// Selected notes on the current track
+    #[serde(skip)]
+    pub selection: NoteSelection,
+    /// Number of times playback has wrapped around the loop (drives probability)
+    #[serde(skip)]
+    pub loop_iteration: u32,
+    /// Fill mode held (enables Fill trig conditions)
+    #[serde(skip)]
+    pub fill: bool,
+    /// Restrict note recording to [punch_in, punch_out)
+    #[serde(default)]
+    pub punch_enabled: bool,
+    #[serde(default)]
+    pub punch_in: u32,
+    #[serde(default = "default_punch_out")]
+    pub punch_out: u32,
+    /// Named loop ranges that can be recalled
+    #[serde(default)]
+    pub saved_loops: Vec<LoopRange>,
+    /// How recorded notes interact with existing ones
+    #[serde(default)]
+    pub note_record_mode: NoteRecordMode,
+    /// Step entry cursor and settings
+    #[serde(skip)]
+    pub step_input: StepInputState,
 }
 
 impl PianoRollState {
@@ -67,19 +236,28 @@ impl PianoRollState {
             ticks_per_beat: 480,
             recording: false,
             swing_amount: 0.0,
+            note_ids: NoteIdAllocator::new(),
+            selection: NoteSelection::new(),
+            loop_iteration: 0,
+            fill: false,
+            punch_enabled: false,
+            punch_in: 0,
+            punch_out: default_punch_out(),
+            saved_loops: Vec::new(),
+            note_record_mode: NoteRecordMode::Overdub,
+            step_input: StepInputState::new(),
         }
     }
 
     pub fn add_track(&mut self, instrument_id: InstrumentId) {
-        if !self.tracks.contains_key(&instrument_id) {
-            self.tracks.insert(
-                instrument_id,
-                Track {
-                    module_id: instrument_id,
-                    notes: Vec::new(),
-                    polyphonic: true,
-                },
-            );
+        if let Entry::Vacant(e) = self.tracks.entry(instrument_id) {
+            e.insert(Track {
+                module_id: instrument_id,
+                notes: NoteIndex::new(),
+                polyphonic: true,
+                voice: VoiceSettings::default(),
+                takes: TakeLanes::new(),
+            });
             self.track_order.push(instrument_id);
         }
     }
@@ -104,19 +282,189 @@ impl PianoRollState {
 
     /// Toggle a note at the given position. If a note exists there, remove it; otherwise add one.
     pub fn toggle_note(&mut self, track_index: usize, pitch: u8, tick: u32, duration: u32, velocity: u8) {
+        let Some(id) = self.track_order.get(track_index).copied() else {
+            return;
+        };
+        let Some(track) = self.tracks.get_mut(&id) else {
+            return;
+        };
+        // Check if a note exists at this pitch/tick
+        if let Some(pos) = track.notes.position_at(pitch, tick) {
+            let removed = track.notes.remove(pos);
+            self.selection.remove(removed.id);
+        } else {
+            let mut note = Note::new(tick, duration, pitch, velocity);
+            note.id = self.note_ids.alloc();
+            track.notes.insert(note);
+        }
+    }
+
+    /// Insert a note in tick order, assigning it a fresh id. Returns the id.
+    pub fn insert_note(&mut self, track_index: usize, mut note: Note) -> Option<NoteId> {
+        let id = self.track_order.get(track_index).copied()?;
+        let track = self.tracks.get_mut(&id)?;
+        note.id = self.note_ids.alloc();
+        let note_id = note.id;
+        track.notes.insert(note);
+        Some(note_id)
+    }
+
+    /// Look up a note by id
+    pub fn note_by_id(&self, track_index: usize, id: NoteId) -> Option<&Note> {
+        self.track_at(track_index)
+            .and_then(|track| track.notes.find_id(id))
+    }
+
+    /// Edit a note by id (keeps the track sorted). Returns false if not found.
+    pub fn update_note(&mut self, track_index: usize, id: NoteId, f: impl FnOnce(&mut Note)) -> bool {
+        self.track_at_mut(track_index)
+            .is_some_and(|track| track.notes.update(id, f))
+    }
+
+    /// Remove the notes with the given ids
+    pub fn remove_notes(&mut self, track_index: usize, ids: &[NoteId]) {
+        if let Some(track) = self.track_at_mut(track_index) {
+            track.notes.retain(|n| !ids.contains(&n.id));
+        }
+        for id in ids {
+            self.selection.remove(*id);
+        }
+    }
+
+    /// Shift notes by a tick and pitch offset (clamped to valid ranges)
+    pub fn move_notes(&mut self, track_index: usize, ids: &[NoteId], tick_delta: i32, pitch_delta: i16) {
         if let Some(track) = self.track_at_mut(track_index) {
-            // Check if a note exists at this pitch/tick
-            if let Some(pos) = track.notes.iter().position(|n| n.pitch == pitch && n.tick == tick) {
-                track.notes.remove(pos);
-            } else {
-                let insert_pos = track.notes.partition_point(|n| n.tick < tick);
-                track.notes.insert(insert_pos, Note {
-                    tick,
-                    duration,
-                    pitch,
-                    velocity,
-                    probability: 1.0,
-                });
+            track.notes.modify(|notes| {
+                for note in notes.iter_mut().filter(|n| ids.contains(&n.id)) {
+                    note.tick = (note.tick as i64 + tick_delta as i64).clamp(0, u32::MAX as i64) as u32;
+                    note.pitch = (note.pitch as i16).saturating_add(pitch_delta).clamp(0, 127) as u8;
+                }
+            });
+        }
+    }
+
+    /// Change note durations by a delta (minimum 1 tick)
+    pub fn resize_notes(&mut self, track_index: usize, ids: &[NoteId], duration_delta: i32) {
+        if let Some(track) = self.track_at_mut(track_index) {
+            track.notes.modify(|notes| {
+                for note in notes.iter_mut().filter(|n| ids.contains(&n.id)) {
+                    note.duration = (note.duration as i64 + duration_delta as i64).max(1) as u32;
+                }
+            });
+        }
+    }
+
+    /// Set velocity on the given notes
+    pub fn set_notes_velocity(&mut self, track_index: usize, ids: &[NoteId], velocity: u8) {
+        if let Some(track) = self.track_at_mut(track_index) {
+            track.notes.modify(|notes| {
+                for note in notes.iter_mut().filter(|n| ids.contains(&n.id)) {
+                    note.velocity = velocity.clamp(1, 127);
+                }
+            });
+        }
+    }
+
+    /// Set the trig condition on the given notes
+    pub fn set_notes_condition(&mut self, track_index: usize, ids: &[NoteId], condition: TrigCondition) {
+        if let Some(track) = self.track_at_mut(track_index) {
+            track.notes.modify(|notes| {
+                for note in notes.iter_mut().filter(|n| ids.contains(&n.id)) {
+                    note.condition = condition;
+                }
+            });
+        }
+    }
+
+    /// Apply a transform to the notes with the given ids, leaving the rest of the track untouched
+    pub fn transform_notes(&mut self, track_index: usize, ids: &[NoteId], transform: &NoteTransform) {
+        let Some(id) = self.track_order.get(track_index).copied() else {
+            return;
+        };
+        let Some(track) = self.tracks.get_mut(&id) else {
+            return;
+        };
+        let (selected, mut rest): (Vec<Note>, Vec<Note>) =
+            track.notes.take().into_iter().partition(|n| ids.contains(&n.id));
+        let before: Vec<Note> = selected.clone();
+        let transformed = transform.apply(selected, &mut self.note_ids);
+        for note in &before {
+            // Notes merged away by the transform (e.g. glue) leave the selection
+            if !transformed.iter().any(|n| n.id == note.id) {
+                self.selection.remove(note.id);
+            }
+        }
+        for note in &transformed {
+            // Notes created by the transform (split halves) follow the note they were cut from
+            if before.iter().any(|n| n.id == note.id) {
+                continue;
+            }
+            let source = before
+                .iter()
+                .find(|n| n.pitch == note.pitch && n.tick < note.tick && note.tick < n.end_tick());
+            if source.is_some_and(|n| self.selection.contains(n.id)) {
+                self.selection.add(note.id);
+            }
+        }
+        rest.extend(transformed);
+        track.notes.replace(rest);
+    }
+
+    /// Ids of notes starting in [start_tick, end_tick) with pitch in [start_pitch, end_pitch]
+    pub fn note_ids_in_region(
+        &self,
+        track_index: usize,
+        start_tick: u32,
+        end_tick: u32,
+        start_pitch: u8,
+        end_pitch: u8,
+    ) -> Vec<NoteId> {
+        self.track_at(track_index)
+            .map(|track| {
+                track
+                    .notes
+                    .iter()
+                    .filter(|n| n.tick >= start_tick && n.tick < end_tick)
+                    .filter(|n| n.pitch >= start_pitch && n.pitch <= end_pitch)
+                    .map(|n| n.id)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Apply a selection operation against the notes of a track
+    pub fn apply_selection(&mut self, track_index: usize, op: &NoteSelectionOp) {
+        let Some(track) = self.track_order.get(track_index).and_then(|id| self.tracks.get(id)) else {
+            return;
+        };
+        let notes = &track.notes;
+        let sel = &mut self.selection;
+        match op {
+            NoteSelectionOp::Clear => sel.clear(),
+            NoteSelectionOp::All => sel.select_all(notes),
+            NoteSelectionOp::Set(ids) => *sel = NoteSelection::from_ids(ids.iter().copied()),
+            NoteSelectionOp::Add(ids) => sel.extend(ids.iter().copied()),
+            NoteSelectionOp::Remove(ids) => ids.iter().for_each(|id| sel.remove(*id)),
+            NoteSelectionOp::Toggle(id) => sel.toggle(*id),
+            NoteSelectionOp::Rect { start_tick, end_tick, low_pitch, high_pitch } => {
+                sel.select_in_rect(notes, *start_tick, *end_tick, *low_pitch, *high_pitch)
+            }
+            NoteSelectionOp::Invert => sel.invert(notes),
+            NoteSelectionOp::ByPitch(pitch) => sel.select_by_pitch(notes, *pitch),
+            NoteSelectionOp::ByVelocity { min, max } => sel.select_by_velocity(notes, *min, *max),
+        }
+    }
+
+    /// Recalculate the note id allocator and assign ids to notes loaded without one
+    pub fn recalculate_note_ids(&mut self) {
+        self.note_ids = NoteIdAllocator::new();
+        for track in self.tracks.values() {
+            self.note_ids.reserve_past(&track.notes);
+        }
+        for id in &self.track_order {
+            if let Some(track) = self.tracks.get_mut(id) {
+                let note_ids = &mut self.note_ids;
+                track.notes.modify(|notes| note_ids.assign_missing(notes));
             }
         }
     }
@@ -125,18 +473,14 @@ impl PianoRollState {
     #[allow(dead_code)]
     pub fn find_note(&self, track_index: usize, pitch: u8, tick: u32) -> Option<&Note> {
         self.track_at(track_index)
-            .and_then(|track| track.notes.iter().find(|n| n.pitch == pitch && n.tick == tick))
+            .and_then(|track| track.notes.position_at(pitch, tick).map(|pos| &track.notes[pos]))
     }
 
     /// Find notes that start within a tick range (for playback)
     #[allow(dead_code)]
     pub fn notes_in_range(&self, track_index: usize, start_tick: u32, end_tick: u32) -> Vec<&Note> {
         if let Some(track) = self.track_at(track_index) {
-            track
-                .notes
-                .iter()
-                .filter(|n| n.tick >= start_tick && n.tick < end_tick)
-                .collect()
+            track.notes.starting_in(start_tick, end_tick).iter().collect()
         } else {
             Vec::new()
         }
@@ -150,9 +494,302 @@ impl PianoRollState {
         self.playhead += ticks;
         if self.looping && self.playhead >= self.loop_end {
             self.playhead = self.loop_start + (self.playhead - self.loop_end);
+            self.loop_iteration = self.loop_iteration.wrapping_add(1);
         }
     }
 
+    /// Set the punch range (endpoints are ordered)
+    pub fn set_punch_range(&mut self, start: u32, end: u32) {
+        self.punch_in = start.min(end);
+        self.punch_out = start.max(end);
+    }
+
+    /// Region recording applies to: the punch range if enabled, else the loop
+    /// range if looping, else everything
+    pub fn record_region(&self) -> (u32, u32) {
+        if self.punch_enabled {
+            (self.punch_in, self.punch_out)
+        } else if self.looping {
+            (self.loop_start, self.loop_end)
+        } else {
+            (0, u32::MAX)
+        }
+    }
+
+    /// Whether a note starting at `tick` would be recorded
+    pub fn in_record_region(&self, tick: u32) -> bool {
+        let (start, end) = self.record_region();
+        tick >= start && tick < end
+    }
+
+    /// Store the current loop range under a name. Returns its index.
+    pub fn save_loop(&mut self, name: impl Into<String>) -> usize {
+        self.saved_loops.push(LoopRange::new(name, self.loop_start, self.loop_end));
+        self.saved_loops.len() - 1
+    }
+
+    /// Make a saved loop range the active loop. Returns false for a bad index.
+    pub fn recall_loop(&mut self, index: usize) -> bool {
+        match self.saved_loops.get(index) {
+            Some(range) if !range.is_empty() => {
+                self.loop_start = range.start;
+                self.loop_end = range.end;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn remove_saved_loop(&mut self, index: usize) -> Option<LoopRange> {
+        (index < self.saved_loops.len()).then(|| self.saved_loops.remove(index))
+    }
+
+    /// Start a recording pass (at record start and on every loop wrap).
+    /// Replace and NewTake remove the notes starting in the record region and
+    /// return them; Overdub leaves the track untouched. In NewTake mode notes
+    /// that aren't already kept as a take are stored as one first.
+    pub fn begin_record_pass(&mut self, track_index: usize) -> Vec<Note> {
+        if self.note_record_mode == NoteRecordMode::Overdub {
+            return Vec::new();
+        }
+        let (start, end) = self.record_region();
+        let ids: Vec<NoteId> = self
+            .track_at(track_index)
+            .map(|t| t.notes.starting_in(start, end).iter().map(|n| n.id).collect())
+            .unwrap_or_default();
+        let removed: Vec<Note> = ids
+            .iter()
+            .filter_map(|id| self.note_by_id(track_index, *id).cloned())
+            .collect();
+        self.remove_notes(track_index, &ids);
+        if self.note_record_mode == NoteRecordMode::NewTake && !removed.is_empty() {
+            if let Some(track) = self.track_at_mut(track_index) {
+                if track.takes.is_empty() {
+                    track.takes.add_take(start, end, removed.clone());
+                }
+            }
+        }
+        removed
+    }
+
+    /// Finish a recording pass. In NewTake mode the notes recorded in the
+    /// region become a new take, comped over the whole region; the notes stay
+    /// on the track so the latest pass is what plays.
+    pub fn end_record_pass(&mut self, track_index: usize) -> Option<TakeId> {
+        if self.note_record_mode != NoteRecordMode::NewTake {
+            return None;
+        }
+        let (start, end) = self.record_region();
+        let track = self.track_at_mut(track_index)?;
+        let notes = track.notes.starting_in(start, end).to_vec();
+        let id = track.takes.add_take(start, end, notes);
+        track.takes.set_comp_region(start, end, id);
+        Some(id)
+    }
+
+    /// Replace the track's notes in the comp range with the comp
+    pub fn flatten_comp(&mut self, track_index: usize) -> bool {
+        let Some(id) = self.track_order.get(track_index).copied() else {
+            return false;
+        };
+        let Some(track) = self.tracks.get_mut(&id) else {
+            return false;
+        };
+        let mut notes = track.notes.take();
+        let flattened = track.takes.flatten_into(&mut notes, &mut self.note_ids);
+        track.notes.replace(notes);
+        flattened
+    }
+
+    /// Record a played note. Notes starting outside the record region are
+    /// dropped; notes running past its end are cut there.
+    pub fn record_note(&mut self, track_index: usize, mut note: Note) -> Option<NoteId> {
+        if !self.in_record_region(note.tick) {
+            return None;
+        }
+        let (_, end) = self.record_region();
+        note.duration = note.duration.min(end - note.tick).max(1);
+        self.insert_note(track_index, note)
+    }
+
+    /// Replace the notes starting in the region with generated ones (the
+    /// replaced notes are the generator's source). Returns the new ids.
+    pub fn generate_notes(
+        &mut self,
+        track_index: usize,
+        region: &GeneratorRegion,
+        generator: &NoteGenerator,
+    ) -> Vec<NoteId> {
+        let Some(track) = self.track_at(track_index) else {
+            return Vec::new();
+        };
+        let source = track.notes.starting_in(region.start_tick, region.end_tick()).to_vec();
+        let generated = generator.generate(region, &source);
+        let old: Vec<NoteId> = source.iter().map(|n| n.id).collect();
+        self.remove_notes(track_index, &old);
+        generated
+            .into_iter()
+            .filter_map(|note| self.insert_note(track_index, note))
+            .collect()
+    }
+
+    /// Replace the notes a tracker pattern covers with the pattern's notes.
+    /// Notes keep their ids; cells typed in the tracker get new ones.
+    pub fn apply_tracker(&mut self, track_index: usize, pattern: &TrackerPattern) -> Vec<NoteId> {
+        let Some(track) = self.track_at(track_index) else {
+            return Vec::new();
+        };
+        let old: Vec<NoteId> = track.notes.starting_in(0, pattern.length_ticks()).iter().map(|n| n.id).collect();
+        self.remove_notes(track_index, &old);
+        let mut ids = Vec::new();
+        for mut note in pattern.to_notes() {
+            if note.id == 0 || !old.contains(&note.id) {
+                note.id = self.note_ids.alloc();
+            }
+            ids.push(note.id);
+            if let Some(track) = self.track_at_mut(track_index) {
+                track.notes.insert(note);
+            }
+        }
+        ids
+    }
+
+    /// Length of one step-input step in ticks
+    pub fn step_ticks(&self) -> u32 {
+        self.step_input.step_ticks(self.ticks_per_beat, self.time_signature)
+    }
+
+    /// Move the step cursor. With `snap` it lands on the step grid.
+    pub fn set_step_cursor(&mut self, tick: u32, snap: bool) {
+        let step = self.step_ticks();
+        self.step_input.cursor = if snap { tick / step * step } else { tick };
+        self.step_input.reset_chord();
+        self.step_input.last_step.clear();
+    }
+
+    /// Enter a note at the step cursor. Keys held together form a chord; the
+    /// cursor advances once they are all released (`step_note_off`). Pass the
+    /// session key and scale to snap the pitch to the scale.
+    pub fn step_note_on(
+        &mut self,
+        track_index: usize,
+        pitch: u8,
+        velocity: u8,
+        scale: Option<(Key, Scale)>,
+    ) -> Option<NoteId> {
+        if self.step_input.held.contains(&pitch) {
+            return None;
+        }
+        let snapped = match scale {
+            Some((key, scale)) => {
+                transpose_in_scale(vec![Note::new(0, 1, pitch, velocity)], 0, key, scale)[0].pitch
+            }
+            None => pitch,
+        };
+        let tick = self.step_input.cursor;
+        let duration = self.step_input.note_ticks(self.ticks_per_beat, self.time_signature);
+        // Re-entering a pitch at the cursor replaces the old note
+        let notes = &self.track_at(track_index)?.notes;
+        let existing = notes.position_at(snapped, tick).map(|pos| notes[pos].id);
+        if let Some(id) = existing {
+            self.remove_notes(track_index, &[id]);
+        }
+        let id = self.insert_note(track_index, Note::new(tick, duration, snapped, velocity))?;
+        // Held keys are tracked as pressed, so the matching `step_note_off` finds them
+        self.step_input.held.push(pitch);
+        self.step_input.chord.push(id);
+        Some(id)
+    }
+
+    /// Release a step-input key; advances the cursor when no keys remain held
+    pub fn step_note_off(&mut self, pitch: u8) {
+        let held = &mut self.step_input.held;
+        let Some(pos) = held.iter().position(|&p| p == pitch) else {
+            return;
+        };
+        held.remove(pos);
+        if held.is_empty() {
+            self.finish_step();
+        }
+    }
+
+    /// Enter a whole chord at the cursor and advance (keyboard entry without key-up)
+    pub fn step_chord(
+        &mut self,
+        track_index: usize,
+        pitches: &[u8],
+        velocity: u8,
+        scale: Option<(Key, Scale)>,
+    ) -> Vec<NoteId> {
+        self.step_input.reset_chord();
+        let ids: Vec<NoteId> = pitches
+            .iter()
+            .filter_map(|&p| self.step_note_on(track_index, p, velocity, scale))
+            .collect();
+        if !ids.is_empty() {
+            self.finish_step();
+        }
+        ids
+    }
+
+    fn finish_step(&mut self) {
+        let step = self.step_ticks();
+        let input = &mut self.step_input;
+        input.held.clear();
+        input.last_step = std::mem::take(&mut input.chord);
+        input.cursor = input.cursor.saturating_add(step);
+    }
+
+    /// Leave the current step empty
+    pub fn step_rest(&mut self) {
+        let step = self.step_ticks();
+        let input = &mut self.step_input;
+        input.reset_chord();
+        input.last_step.clear();
+        input.cursor = input.cursor.saturating_add(step);
+    }
+
+    /// Extend the notes of the last step through the current step
+    pub fn step_tie(&mut self, track_index: usize) {
+        if self.step_input.last_step.is_empty() {
+            self.step_rest();
+            return;
+        }
+        let step = self.step_ticks();
+        let end = self
+            .step_input
+            .cursor
+            .saturating_add(self.step_input.note_ticks(self.ticks_per_beat, self.time_signature));
+        let ids = self.step_input.last_step.clone();
+        for id in ids {
+            self.update_note(track_index, id, |n| n.duration = end.saturating_sub(n.tick).max(1));
+        }
+        self.step_input.reset_chord();
+        self.step_input.cursor = self.step_input.cursor.saturating_add(step);
+    }
+
+    /// Move the cursor back one step, removing notes entered at that step
+    pub fn step_back(&mut self, track_index: usize) {
+        let step = self.step_ticks();
+        let input = &mut self.step_input;
+        input.reset_chord();
+        input.cursor = input.cursor.saturating_sub(step);
+        let ids = std::mem::take(&mut input.last_step);
+        let cursor = input.cursor;
+        let ids: Vec<NoteId> = ids
+            .into_iter()
+            .filter(|id| self.note_by_id(track_index, *id).is_some_and(|n| n.tick == cursor))
+            .collect();
+        self.remove_notes(track_index, &ids);
+    }
+
+    /// Find notes sounding at any point in a tick range (including ones that started earlier)
+    pub fn notes_overlapping(&self, track_index: usize, start_tick: u32, end_tick: u32) -> Vec<&Note> {
+        self.track_at(track_index)
+            .map(|track| track.notes.overlapping(start_tick, end_tick).collect())
+            .unwrap_or_default()
+    }
+
     /// Convert a beat number to ticks
     #[allow(dead_code)]
     pub fn beat_to_tick(&self, beat: u32) -> u32 {
@@ -179,6 +816,7 @@ impl Default for PianoRollState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::state::step_input::StepLength;
 
     #[test]
     fn toggle_note_adds_and_removes() {
@@ -201,6 +839,16 @@ mod tests {
         assert_eq!(notes[0].pitch, 60);
     }
 
+    #[test]
+    fn notes_overlapping_includes_held_notes() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        pr.toggle_note(0, 60, 0, 960, 100);
+        pr.toggle_note(0, 61, 480, 240, 100);
+        assert_eq!(pr.notes_overlapping(0, 700, 800).len(), 2);
+        assert_eq!(pr.notes_overlapping(0, 740, 800).len(), 1);
+    }
+
     #[test]
     fn advance_wraps_when_looping() {
         let mut pr = PianoRollState::new();
@@ -211,6 +859,7 @@ mod tests {
         pr.playhead = 900;
         pr.advance(100);
         assert_eq!(pr.playhead, 520);
+        assert_eq!(pr.loop_iteration, 1);
     }
 
     #[test]
@@ -226,6 +875,133 @@ mod tests {
         assert_eq!(pr.beat_to_tick(2), pr.ticks_per_beat * 2);
     }
 
+    #[test]
+    fn toggle_note_assigns_unique_ids() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        pr.toggle_note(0, 60, 0, 480, 100);
+        pr.toggle_note(0, 62, 0, 480, 100);
+        let ids: Vec<NoteId> = pr.track_at(0).unwrap().notes.iter().map(|n| n.id).collect();
+        assert!(ids.iter().all(|&id| id != 0));
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    #[test]
+    fn note_id_survives_move() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        let id = pr.insert_note(0, Note::new(0, 240, 60, 100)).unwrap();
+        pr.insert_note(0, Note::new(480, 240, 64, 100));
+        pr.move_notes(0, &[id], 960, 2);
+        let note = pr.note_by_id(0, id).unwrap();
+        assert_eq!((note.tick, note.pitch), (960, 62));
+        let ticks: Vec<u32> = pr.track_at(0).unwrap().notes.iter().map(|n| n.tick).collect();
+        assert_eq!(ticks, vec![480, 960]);
+    }
+
+    #[test]
+    fn move_notes_clamps_large_deltas() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        let id = pr.insert_note(0, Note::new(u32::MAX - 100, 10, 120, 100)).unwrap();
+        pr.move_notes(0, &[id], i32::MAX, i16::MAX);
+        let note = pr.note_by_id(0, id).unwrap();
+        assert_eq!((note.tick, note.pitch), (u32::MAX, 127));
+        pr.move_notes(0, &[id], i32::MIN, i16::MIN);
+        let note = pr.note_by_id(0, id).unwrap();
+        assert_eq!((note.tick, note.pitch), (u32::MAX - (1 << 31), 0));
+    }
+
+    #[test]
+    fn recalculate_note_ids_fills_missing() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        let track = pr.track_at_mut(0).unwrap();
+        track.notes.push(Note::new(0, 240, 60, 100));
+        let mut with_id = Note::new(240, 240, 62, 100);
+        with_id.id = 7;
+        track.notes.push(with_id);
+        pr.recalculate_note_ids();
+        let ids: Vec<NoteId> = pr.track_at(0).unwrap().notes.iter().map(|n| n.id).collect();
+        assert_eq!(ids, vec![8, 7]);
+        assert_eq!(pr.note_ids.alloc(), 9);
+    }
+
+    #[test]
+    fn note_ids_and_ends_saturate() {
+        let mut far = Note::new(u32::MAX - 10, 100, 60, 100);
+        assert_eq!(far.end_tick(), u32::MAX);
+        far.id = NoteId::MAX;
+        let mut ids = NoteIdAllocator::new();
+        ids.reserve_past(&[far]);
+        assert_eq!(ids.alloc(), NoteId::MAX);
+        assert_eq!(ids.alloc(), NoteId::MAX);
+    }
+
+    #[test]
+    fn selection_ops_apply_to_track_notes() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        let a = pr.insert_note(0, Note::new(0, 240, 60, 100)).unwrap();
+        let b = pr.insert_note(0, Note::new(480, 240, 72, 50)).unwrap();
+        pr.apply_selection(0, &NoteSelectionOp::ByPitch(60));
+        assert!(pr.selection.contains(a));
+        pr.apply_selection(0, &NoteSelectionOp::Invert);
+        assert!(pr.selection.contains(b) && !pr.selection.contains(a));
+        pr.remove_notes(0, &[b]);
+        assert!(pr.selection.is_empty());
+    }
+
+    #[test]
+    fn transform_only_touches_given_notes() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        let a = pr.insert_note(0, Note::new(0, 480, 60, 100)).unwrap();
+        let b = pr.insert_note(0, Note::new(480, 480, 64, 100)).unwrap();
+        pr.transform_notes(0, &[a], &NoteTransform::Transpose(12));
+        assert_eq!(pr.note_by_id(0, a).unwrap().pitch, 72);
+        assert_eq!(pr.note_by_id(0, b).unwrap().pitch, 64);
+
+        let ids = pr.note_ids_in_region(0, 0, 960, 0, 127);
+        pr.transform_notes(0, &ids, &NoteTransform::SplitAt(720));
+        assert_eq!(pr.track_at(0).unwrap().notes.len(), 3);
+    }
+
+    #[test]
+    fn transform_keeps_selection_to_selected_notes() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        let a = pr.insert_note(0, Note::new(0, 480, 60, 100)).unwrap();
+        let b = pr.insert_note(0, Note::new(0, 480, 64, 100)).unwrap();
+        pr.selection.add(a);
+        pr.transform_notes(0, &[a, b], &NoteTransform::SplitAt(240));
+        let halves = pr.note_ids_in_region(0, 240, 241, 0, 127);
+        assert_eq!(halves.len(), 2);
+        assert_eq!(pr.selection.len(), 2);
+        assert!(pr.selection.contains(a) && !pr.selection.contains(b));
+        let right_a = pr.note_ids_in_region(0, 240, 241, 60, 60)[0];
+        assert!(pr.selection.contains(right_a));
+
+        pr.transform_notes(0, &[a, right_a], &NoteTransform::Glue);
+        assert_eq!(pr.selection.to_vec(), vec![a]);
+        assert!(pr.note_by_id(0, right_a).is_none());
+    }
+
+    #[test]
+    fn mono_track_resolves_overlaps() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        pr.insert_note(0, Note::new(0, 480, 60, 100));
+        pr.insert_note(0, Note::new(240, 480, 64, 100));
+        let track = pr.track_at_mut(0).unwrap();
+        assert!(track.voice_conflicts().is_empty());
+        track.polyphonic = false;
+        assert_eq!(track.voice_conflicts().len(), 1);
+        assert_eq!(track.playable_notes()[0].duration, 240);
+        track.resolve_overlaps();
+        assert!(track.voice_conflicts().is_empty());
+    }
+
     #[test]
     fn notes_stay_sorted_after_toggle() {
         let mut pr = PianoRollState::new();
@@ -237,4 +1013,142 @@ mod tests {
         let ticks: Vec<u32> = track.notes.iter().map(|n| n.tick).collect();
         assert_eq!(ticks, vec![0, 240, 480]);
     }
+
+    #[test]
+    fn punch_region_limits_recording() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        pr.punch_enabled = true;
+        pr.set_punch_range(960, 480);
+        assert_eq!(pr.record_note(0, Note::new(0, 100, 60, 100)), None);
+        let id = pr.record_note(0, Note::new(900, 200, 60, 100)).unwrap();
+        assert_eq!(pr.note_by_id(0, id).unwrap().duration, 60);
+    }
+
+    #[test]
+    fn replace_mode_clears_region_each_pass() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        pr.insert_note(0, Note::new(0, 100, 60, 100));
+        pr.insert_note(0, Note::new(2000, 100, 62, 100));
+        pr.loop_end = 960;
+        assert!(pr.begin_record_pass(0).is_empty());
+        pr.note_record_mode = NoteRecordMode::Replace;
+        let removed = pr.begin_record_pass(0);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(pr.track_at(0).unwrap().notes.len(), 1);
+    }
+
+    #[test]
+    fn saved_loops_recall() {
+        let mut pr = PianoRollState::new();
+        pr.loop_start = 480;
+        pr.loop_end = 960;
+        let idx = pr.save_loop("verse");
+        pr.loop_start = 0;
+        pr.loop_end = 4800;
+        assert!(pr.recall_loop(idx));
+        assert_eq!((pr.loop_start, pr.loop_end), (480, 960));
+        assert!(!pr.recall_loop(5));
+        assert_eq!(pr.remove_saved_loop(idx).unwrap().name, "verse");
+    }
+
+    #[test]
+    fn new_take_passes_become_takes() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        pr.loop_end = 960;
+        pr.note_record_mode = NoteRecordMode::NewTake;
+        pr.insert_note(0, Note::new(0, 100, 60, 100));
+        pr.begin_record_pass(0);
+        pr.record_note(0, Note::new(240, 100, 64, 100));
+        let take = pr.end_record_pass(0).unwrap();
+        let track = pr.track_at(0).unwrap();
+        assert_eq!(track.takes.takes.len(), 2);
+        assert_eq!(track.takes.comp_take_at(0), Some(take));
+        // The take and its comp cover the whole pass, not just the notes played
+        let recorded = track.takes.takes.iter().find(|t| t.id == take).unwrap();
+        assert_eq!((recorded.start, recorded.end), (0, 960));
+        assert_eq!(track.takes.comp_range(), Some((0, 960)));
+
+        pr.track_at_mut(0).unwrap().takes.set_comp_region(0, 960, 0);
+        assert!(pr.flatten_comp(0));
+        let pitches: Vec<u8> = pr.track_at(0).unwrap().notes.iter().map(|n| n.pitch).collect();
+        assert_eq!(pitches, vec![60]);
+    }
+
+    #[test]
+    fn step_input_chords_rests_and_ties() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        pr.step_input.length = StepLength::Quarter;
+        pr.step_note_on(0, 60, 100, None);
+        pr.step_note_on(0, 64, 100, None);
+        pr.step_note_off(60);
+        assert_eq!(pr.step_input.cursor, 0);
+        pr.step_note_off(64);
+        assert_eq!(pr.step_input.cursor, 480);
+        pr.step_tie(0);
+        pr.step_rest();
+        pr.step_chord(0, &[61], 90, Some((Key::C, Scale::Major)));
+        let notes: Vec<(u32, u32, u8)> = pr
+            .track_at(0)
+            .unwrap()
+            .notes
+            .iter()
+            .map(|n| (n.tick, n.duration, n.pitch))
+            .collect();
+        assert_eq!(notes, vec![(0, 960, 60), (0, 960, 64), (1440, 480, 60)]);
+        pr.step_back(0);
+        assert_eq!(pr.step_input.cursor, 1440);
+        assert_eq!(pr.track_at(0).unwrap().notes.len(), 2);
+    }
+
+    #[test]
+    fn step_input_releases_keys_snapped_to_the_scale() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        pr.step_input.length = StepLength::Quarter;
+        let c_major = Some((Key::C, Scale::Major));
+        pr.step_note_on(0, 61, 100, c_major);
+        pr.step_note_on(0, 66, 100, c_major);
+        pr.step_note_off(61);
+        assert_eq!(pr.step_input.cursor, 0);
+        pr.step_note_off(66);
+        assert_eq!(pr.step_input.cursor, 480);
+        assert!(pr.step_input.held.is_empty());
+        let pitches: Vec<u8> = pr.track_at(0).unwrap().notes.iter().map(|n| n.pitch).collect();
+        assert_eq!(pitches, vec![60, 65]);
+
+        pr.step_input.cursor = u32::MAX - 10;
+        pr.step_tie(0);
+        assert_eq!(pr.step_input.cursor, u32::MAX);
+    }
+
+    #[test]
+    fn step_length_follows_time_signature() {
+        let mut pr = PianoRollState::new();
+        pr.time_signature = (6, 8);
+        pr.step_input.length = StepLength::Quarter;
+        assert_eq!(pr.step_ticks(), 960);
+        pr.step_input.length = StepLength::Bar;
+        assert_eq!(pr.step_ticks(), 480 * 6);
+        pr.step_input.length = StepLength::Eighth;
+        pr.set_step_cursor(700, true);
+        assert_eq!(pr.step_input.cursor, 480);
+    }
+
+    #[test]
+    fn generate_replaces_region() {
+        let mut pr = PianoRollState::new();
+        pr.add_track(1);
+        pr.insert_note(0, Note::new(0, 10, 40, 100));
+        pr.insert_note(0, Note::new(5000, 10, 40, 100));
+        let region = GeneratorRegion::new(0, 1920, 120);
+        let generator = NoteGenerator::Euclidean { pulses: 4, steps: 16, rotation: 0, pitches: vec![60] };
+        let ids = pr.generate_notes(0, &region, &generator);
+        assert_eq!(ids.len(), 4);
+        assert_eq!(pr.track_at(0).unwrap().notes.len(), 5);
+    }
+
 }
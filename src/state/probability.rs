@@ -0,0 +1,280 @@
+//! Deterministic resolution of note and step probability.
+//!
+//! Whether a probabilistic hit fires is a pure function of the project seed,
+//! the loop iteration and the hit's position, so two renders of the same
+//! project always agree.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::arrangement::PlacementId;
+use super::drum_sequencer::DrumStep;
+use super::piano_roll::{Note, NoteId};
+use crate::rng::SeededRng;
+use crate::InstrumentId;
+
+/// Identifies a single probabilistic hit within one loop iteration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HitKey {
+    /// Piano roll / clip note, identified by its id so moving it keeps its
+    /// outcome. Notes played from a clip also carry the placement, so each
+    /// copy of the clip rolls separately.
+    Note {
+        instrument_id: InstrumentId,
+        note_id: NoteId,
+        placement: Option<PlacementId>,
+    },
+    /// Drum sequencer step
+    Step {
+        instrument_id: InstrumentId,
+        pad: usize,
+        step: usize,
+    },
+}
+
+impl HitKey {
+    fn parts(&self) -> [u64; 4] {
+        match *self {
+            HitKey::Note { instrument_id, note_id, placement } => {
+                let placement = placement.map_or(0, |p| p as u64 + 1);
+                [1, instrument_id as u64, note_id as u64, placement]
+            }
+            HitKey::Step { instrument_id, pad, step } => {
+                [2, instrument_id as u64, pad as u64, step as u64]
+            }
+        }
+    }
+}
+
+/// Outcome of resolving one hit, kept so the UI can show what played.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitResult {
+    pub key: HitKey,
+    /// Iteration the hit was resolved for (after applying any lock)
+    pub iteration: u32,
+    pub probability: f32,
+    pub fired: bool,
+}
+
+/// Seeded probability resolver (lives in SessionState).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProbabilityResolver {
+    /// Project seed; changing it re-rolls every probabilistic hit
+    pub seed: u64,
+    /// When set, every loop iteration resolves exactly like this one
+    pub locked_iteration: Option<u32>,
+    /// Latest outcome of each hit (for display); a hit's entry is replaced
+    /// each time it is resolved again
+    #[serde(skip)]
+    pub results: HashMap<HitKey, HitResult>,
+}
+
+impl ProbabilityResolver {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            locked_iteration: None,
+            results: HashMap::new(),
+        }
+    }
+
+    /// Iteration actually used for resolution, honoring the lock
+    pub fn effective_iteration(&self, iteration: u32) -> u32 {
+        self.locked_iteration.unwrap_or(iteration)
+    }
+
+    /// Freeze the outcome of the given iteration for all future loops
+    pub fn lock(&mut self, iteration: u32) {
+        self.locked_iteration = Some(iteration);
+    }
+
+    pub fn unlock(&mut self) {
+        self.locked_iteration = None;
+    }
+
+    /// Lock the current iteration, or release an existing lock
+    pub fn toggle_lock(&mut self, current_iteration: u32) {
+        if self.locked_iteration.is_some() {
+            self.unlock();
+        } else {
+            self.lock(current_iteration);
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked_iteration.is_some()
+    }
+
+    /// Decide whether a hit fires. Pure: does not record the result.
+    pub fn fires(&self, key: HitKey, probability: f32, iteration: u32) -> bool {
+        if probability >= 1.0 {
+            return true;
+        }
+        if probability <= 0.0 {
+            return false;
+        }
+        let [kind, a, b, c] = key.parts();
+        let iteration = self.effective_iteration(iteration) as u64;
+        SeededRng::from_parts(&[self.seed, iteration, kind, a, b, c]).next_f32() < probability
+    }
+
+    /// Outcome of a hit, without recording it
+    pub fn result(&self, key: HitKey, probability: f32, iteration: u32) -> HitResult {
+        HitResult {
+            key,
+            iteration: self.effective_iteration(iteration),
+            probability,
+            fired: self.fires(key, probability, iteration),
+        }
+    }
+
+    /// Keep an outcome for display, replacing the hit's previous one
+    pub fn record(&mut self, result: HitResult) {
+        self.results.insert(result.key, result);
+    }
+
+    /// Decide and record a hit
+    pub fn resolve(&mut self, key: HitKey, probability: f32, iteration: u32) -> bool {
+        let result = self.result(key, probability, iteration);
+        self.record(result);
+        result.fired
+    }
+
+    /// Filter notes down to those that fire this iteration, recording results
+    pub fn resolve_notes<'a>(
+        &mut self,
+        instrument_id: InstrumentId,
+        notes: &'a [Note],
+        iteration: u32,
+    ) -> Vec<&'a Note> {
+        notes
+            .iter()
+            .filter(|n| {
+                let key = HitKey::Note { instrument_id, note_id: n.id, placement: None };
+                self.resolve(key, n.probability, iteration)
+            })
+            .collect()
+    }
+
+    /// Indices of active steps on a pad that fire this iteration, recording results
+    pub fn resolve_steps(
+        &mut self,
+        instrument_id: InstrumentId,
+        pad: usize,
+        steps: &[DrumStep],
+        iteration: u32,
+    ) -> Vec<usize> {
+        steps
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.active)
+            .filter(|(step, s)| {
+                let key = HitKey::Step { instrument_id, pad, step: *step };
+                self.resolve(key, s.probability, iteration)
+            })
+            .map(|(step, _)| step)
+            .collect()
+    }
+
+    /// Most recent recorded outcome for a hit
+    pub fn result_for(&self, key: &HitKey) -> Option<&HitResult> {
+        self.results.get(key)
+    }
+
+    pub fn clear_results(&mut self) {
+        self.results.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(tick: u32, pitch: u8, probability: f32) -> Note {
+        Note { id: tick + 1, probability, ..Note::new(tick, 120, pitch, 100) }
+    }
+
+    fn key(note: &Note) -> HitKey {
+        HitKey::Note { instrument_id: 1, note_id: note.id, placement: None }
+    }
+
+    #[test]
+    fn same_seed_same_outcome() {
+        let notes: Vec<Note> = (0..32).map(|i| note(i * 120, 60, 0.5)).collect();
+        let mut a = ProbabilityResolver::new(1234);
+        let mut b = ProbabilityResolver::new(1234);
+        let ticks_a: Vec<u32> = a.resolve_notes(1, &notes, 3).iter().map(|n| n.tick).collect();
+        let ticks_b: Vec<u32> = b.resolve_notes(1, &notes, 3).iter().map(|n| n.tick).collect();
+        assert_eq!(ticks_a, ticks_b);
+        assert!(!ticks_a.is_empty() && ticks_a.len() < notes.len());
+    }
+
+    #[test]
+    fn iterations_differ_unless_locked() {
+        let notes: Vec<Note> = (0..64).map(|i| note(i * 60, 60, 0.5)).collect();
+        let mut r = ProbabilityResolver::new(99);
+        let first = r.resolve_notes(1, &notes, 0).len();
+        let fired_0: Vec<bool> = notes
+            .iter()
+            .map(|n| r.fires(key(n), 0.5, 0))
+            .collect();
+        let fired_1: Vec<bool> = notes
+            .iter()
+            .map(|n| r.fires(key(n), 0.5, 1))
+            .collect();
+        assert_ne!(fired_0, fired_1);
+
+        r.lock(0);
+        assert_eq!(r.resolve_notes(1, &notes, 7).len(), first);
+        r.toggle_lock(7);
+        assert!(!r.is_locked());
+    }
+
+    #[test]
+    fn certain_and_impossible_hits() {
+        let r = ProbabilityResolver::new(5);
+        let key = HitKey::Step { instrument_id: 1, pad: 0, step: 0 };
+        assert!(r.fires(key, 1.0, 0));
+        assert!(!r.fires(key, 0.0, 0));
+    }
+
+    #[test]
+    fn resolve_steps_skips_inactive_and_records() {
+        let mut steps = vec![DrumStep::default(); 4];
+        steps[1].active = true;
+        steps[3].active = true;
+        steps[3].probability = 0.0;
+        let mut r = ProbabilityResolver::new(0);
+        assert_eq!(r.resolve_steps(2, 0, &steps, 0), vec![1]);
+        let key = HitKey::Step { instrument_id: 2, pad: 0, step: 3 };
+        assert!(!r.result_for(&key).unwrap().fired);
+    }
+
+    #[test]
+    fn outcomes_follow_note_ids_and_keep_one_per_hit() {
+        let mut r = ProbabilityResolver::new(42);
+        let notes: Vec<Note> = (0..32).map(|i| note(i * 120, 60, 0.5)).collect();
+        let before: Vec<bool> = notes.iter().map(|n| r.fires(key(n), 0.5, 2)).collect();
+        // Moving notes keeps their outcome
+        let moved: Vec<Note> =
+            notes.iter().map(|n| Note { tick: n.tick + 7, pitch: 61, ..n.clone() }).collect();
+        let after: Vec<bool> = moved.iter().map(|n| r.fires(key(n), 0.5, 2)).collect();
+        assert_eq!(before, after);
+
+        // Each placement of a clip rolls separately
+        let placed = |placement| {
+            notes
+                .iter()
+                .map(|n| r.fires(HitKey::Note { instrument_id: 1, note_id: n.id, placement }, 0.5, 2))
+                .collect::<Vec<bool>>()
+        };
+        assert_ne!(placed(Some(1)), placed(Some(2)));
+
+        for iteration in 0..10 {
+            r.resolve_notes(1, &notes, iteration);
+        }
+        assert_eq!(r.results.len(), notes.len());
+        assert_eq!(r.result_for(&key(&notes[0])).map(|res| res.iteration), Some(9));
+    }
+}
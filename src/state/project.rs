@@ -7,7 +7,7 @@ use serde::{Deserialize, Serialize};
 use super::session::MusicalSettings;
 
 /// Project metadata (path, dirty flag, defaults).
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ProjectMeta {
     /// Current project file path (None = untitled/new project)
     #[serde(skip)]
@@ -19,16 +19,6 @@ pub struct ProjectMeta {
     pub default_settings: MusicalSettings,
 }
 
-impl Default for ProjectMeta {
-    fn default() -> Self {
-        Self {
-            path: None,
-            dirty: false,
-            default_settings: MusicalSettings::default(),
-        }
-    }
-}
-
 impl ProjectMeta {
     pub fn new_with_defaults(defaults: MusicalSettings) -> Self {
         Self {
@@ -11,6 +11,7 @@ use super::midi_recording::MidiRecordingState;
 use super::mixer::{MixerState, DEFAULT_BUS_COUNT};
 use super::music::{Key, Scale};
 use super::piano_roll::PianoRollState;
+use super::probability::ProbabilityResolver;
 use super::vst::VstPluginRegistry;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -75,6 +76,10 @@ pub struct SessionState {
 
     // Humanize settings (extracted)
     pub humanize: HumanizeSettings,
+
+    /// Seeded resolver for note/step probability
+    #[serde(default)]
+    pub probability: ProbabilityResolver,
 }
 
 impl SessionState {
@@ -98,6 +103,7 @@ impl SessionState {
             vst_plugins: VstPluginRegistry::new(),
             mixer: MixerState::new_with_bus_count(bus_count),
             humanize: HumanizeSettings::default(),
+            probability: ProbabilityResolver::default(),
         }
     }
 
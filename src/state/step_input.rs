@@ -0,0 +1,161 @@
+//! Step input: entering notes one step at a time with the transport stopped.
+
+use serde::{Deserialize, Serialize};
+
+use super::piano_roll::NoteId;
+
+/// Note value of one step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StepLength {
+    Whole,
+    Half,
+    Quarter,
+    Eighth,
+    #[default]
+    Sixteenth,
+    ThirtySecond,
+    /// One full bar of the current time signature
+    Bar,
+}
+
+impl StepLength {
+    pub const ALL: [StepLength; 7] = [
+        StepLength::Bar,
+        StepLength::Whole,
+        StepLength::Half,
+        StepLength::Quarter,
+        StepLength::Eighth,
+        StepLength::Sixteenth,
+        StepLength::ThirtySecond,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StepLength::Whole => "1/1",
+            StepLength::Half => "1/2",
+            StepLength::Quarter => "1/4",
+            StepLength::Eighth => "1/8",
+            StepLength::Sixteenth => "1/16",
+            StepLength::ThirtySecond => "1/32",
+            StepLength::Bar => "Bar",
+        }
+    }
+
+    /// Next shorter length, wrapping from 1/32 back to a bar
+    pub fn next(&self) -> StepLength {
+        let i = Self::ALL.iter().position(|l| l == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// Length in ticks. The time signature's denominator sets the beat unit,
+    /// so a quarter is two beats of 6/8.
+    pub fn ticks(&self, ticks_per_beat: u32, time_signature: (u8, u8)) -> u32 {
+        let (num, den) = (time_signature.0.max(1) as u32, time_signature.1.max(1) as u32);
+        let whole = ticks_per_beat * den;
+        match self {
+            StepLength::Whole => whole,
+            StepLength::Half => whole / 2,
+            StepLength::Quarter => whole / 4,
+            StepLength::Eighth => whole / 8,
+            StepLength::Sixteenth => whole / 16,
+            StepLength::ThirtySecond => whole / 32,
+            StepLength::Bar => ticks_per_beat * num,
+        }
+        .max(1)
+    }
+}
+
+/// Dotted or triplet variants of the step length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StepModifier {
+    #[default]
+    Straight,
+    Dotted,
+    Triplet,
+}
+
+impl StepModifier {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StepModifier::Straight => "",
+            StepModifier::Dotted => ".",
+            StepModifier::Triplet => "T",
+        }
+    }
+
+    pub fn next(&self) -> StepModifier {
+        match self {
+            StepModifier::Straight => StepModifier::Dotted,
+            StepModifier::Dotted => StepModifier::Triplet,
+            StepModifier::Triplet => StepModifier::Straight,
+        }
+    }
+
+    pub fn apply(&self, ticks: u32) -> u32 {
+        match self {
+            StepModifier::Straight => ticks,
+            StepModifier::Dotted => ticks + ticks / 2,
+            StepModifier::Triplet => (ticks * 2 / 3).max(1),
+        }
+    }
+}
+
+/// Step input cursor and entry settings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepInputState {
+    pub active: bool,
+    /// Tick where the next step is entered
+    pub cursor: u32,
+    pub length: StepLength,
+    pub modifier: StepModifier,
+    /// Percentage of the step the entered note sounds for (1-100)
+    pub gate: u8,
+    /// Keys currently held down, as pressed before any scale snapping (their
+    /// notes form one chord)
+    #[serde(skip)]
+    pub held: Vec<u8>,
+    /// Notes of the chord currently being entered
+    #[serde(skip)]
+    pub chord: Vec<NoteId>,
+    /// Notes of the last completed step (what Tie extends)
+    #[serde(skip)]
+    pub last_step: Vec<NoteId>,
+}
+
+impl Default for StepInputState {
+    fn default() -> Self {
+        Self {
+            active: false,
+            cursor: 0,
+            length: StepLength::Sixteenth,
+            modifier: StepModifier::Straight,
+            gate: 100,
+            held: Vec::new(),
+            chord: Vec::new(),
+            last_step: Vec::new(),
+        }
+    }
+}
+
+impl StepInputState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Length of one step in ticks
+    pub fn step_ticks(&self, ticks_per_beat: u32, time_signature: (u8, u8)) -> u32 {
+        self.modifier.apply(self.length.ticks(ticks_per_beat, time_signature))
+    }
+
+    /// Sounding length of a note entered for one step
+    pub fn note_ticks(&self, ticks_per_beat: u32, time_signature: (u8, u8)) -> u32 {
+        let step = self.step_ticks(ticks_per_beat, time_signature);
+        (step * self.gate.clamp(1, 100) as u32 / 100).max(1)
+    }
+
+    /// Forget any chord in progress
+    pub fn reset_chord(&mut self) {
+        self.held.clear();
+        self.chord.clear();
+    }
+}
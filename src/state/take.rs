@@ -0,0 +1,244 @@
+//! Recorded takes and comping for tracks and clips.
+
+use serde::{Deserialize, Serialize};
+
+use super::piano_roll::{Note, NoteIdAllocator};
+
+pub type TakeId = u32;
+
+/// One recorded pass over a region.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Take {
+    pub id: TakeId,
+    pub name: String,
+    /// Region the take was recorded over [start, end)
+    pub start: u32,
+    pub end: u32,
+    pub notes: Vec<Note>,
+}
+
+impl Take {
+    /// Notes starting in [start, end), cut at `end`
+    fn notes_in(&self, start: u32, end: u32) -> impl Iterator<Item = Note> + '_ {
+        self.notes
+            .iter()
+            .filter(move |n| n.tick >= start && n.tick < end)
+            .map(move |n| Note { duration: n.duration.min(end - n.tick), ..n.clone() })
+    }
+}
+
+/// A stretch of the comp taken from one take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompRegion {
+    pub start: u32,
+    pub end: u32,
+    pub take: TakeId,
+}
+
+/// Takes recorded on a track or clip, plus the comp built from them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TakeLanes {
+    pub takes: Vec<Take>,
+    /// Non-overlapping comp regions sorted by start
+    pub comp: Vec<CompRegion>,
+    /// Take currently being auditioned in place of the track's notes
+    #[serde(skip)]
+    pub auditioning: Option<TakeId>,
+    pub(crate) next_take_id: TakeId,
+}
+
+impl TakeLanes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.takes.is_empty()
+    }
+
+    pub fn take(&self, id: TakeId) -> Option<&Take> {
+        self.takes.iter().find(|t| t.id == id)
+    }
+
+    /// Store a pass as a new take. Returns its id.
+    pub fn add_take(&mut self, start: u32, end: u32, mut notes: Vec<Note>) -> TakeId {
+        let id = self.next_take_id.max(self.takes.iter().map(|t| t.id + 1).max().unwrap_or(0));
+        self.next_take_id = id + 1;
+        notes.sort_by_key(|n| (n.tick, n.pitch));
+        self.takes.push(Take {
+            id,
+            name: format!("Take {}", id + 1),
+            start: start.min(end),
+            end: start.max(end),
+            notes,
+        });
+        id
+    }
+
+    /// Remove a take along with any comp regions and audition pointing at it
+    pub fn remove_take(&mut self, id: TakeId) -> Option<Take> {
+        let pos = self.takes.iter().position(|t| t.id == id)?;
+        self.comp.retain(|r| r.take != id);
+        if self.auditioning == Some(id) {
+            self.auditioning = None;
+        }
+        Some(self.takes.remove(pos))
+    }
+
+    /// Audition a take (None stops auditioning). Unknown ids are ignored.
+    pub fn audition(&mut self, id: Option<TakeId>) {
+        self.auditioning = id.filter(|id| self.take(*id).is_some());
+    }
+
+    /// Notes to play while auditioning: the auditioned take replaces `base`
+    /// inside its region. None when not auditioning.
+    pub fn audition_notes(&self, base: &[Note]) -> Option<Vec<Note>> {
+        let take = self.take(self.auditioning?)?;
+        let mut notes: Vec<Note> = base
+            .iter()
+            .filter(|n| n.tick < take.start || n.tick >= take.end)
+            .cloned()
+            .collect();
+        notes.extend(take.notes_in(take.start, take.end));
+        notes.sort_by_key(|n| (n.tick, n.pitch));
+        Some(notes)
+    }
+
+    /// Use `take` for [start, end) in the comp, overwriting what was there
+    pub fn set_comp_region(&mut self, start: u32, end: u32, take: TakeId) {
+        let (start, end) = (start.min(end), start.max(end));
+        if start == end || self.take(take).is_none() {
+            return;
+        }
+        let mut comp = Vec::with_capacity(self.comp.len() + 2);
+        for r in self.comp.drain(..) {
+            if r.end <= start || r.start >= end {
+                comp.push(r);
+                continue;
+            }
+            if r.start < start {
+                comp.push(CompRegion { end: start, ..r });
+            }
+            if r.end > end {
+                comp.push(CompRegion { start: end, ..r });
+            }
+        }
+        comp.push(CompRegion { start, end, take });
+        comp.sort_by_key(|r| r.start);
+        // Merge touching regions from the same take
+        comp.dedup_by(|b, a| {
+            if a.take == b.take && a.end == b.start {
+                a.end = b.end;
+                true
+            } else {
+                false
+            }
+        });
+        self.comp = comp;
+    }
+
+    pub fn clear_comp(&mut self) {
+        self.comp.clear();
+    }
+
+    /// Take the comp uses at `tick`
+    pub fn comp_take_at(&self, tick: u32) -> Option<TakeId> {
+        self.comp.iter().find(|r| tick >= r.start && tick < r.end).map(|r| r.take)
+    }
+
+    /// Span covered by the comp
+    pub fn comp_range(&self) -> Option<(u32, u32)> {
+        Some((self.comp.first()?.start, self.comp.last()?.end))
+    }
+
+    /// Notes the comp selects, in tick order (ids are those of the takes)
+    pub fn comp_notes(&self) -> Vec<Note> {
+        let mut notes = Vec::new();
+        for region in &self.comp {
+            if let Some(take) = self.take(region.take) {
+                notes.extend(take.notes_in(region.start, region.end));
+            }
+        }
+        notes
+    }
+
+    /// Replace the notes of `notes` that start inside a comp region with the
+    /// comp, giving the comp notes fresh ids. Notes in gaps between regions
+    /// are kept. Returns false (and changes nothing) when the comp is empty.
+    pub fn flatten_into(&self, notes: &mut Vec<Note>, ids: &mut NoteIdAllocator) -> bool {
+        if self.comp.is_empty() {
+            return false;
+        }
+        notes.retain(|n| !self.comp.iter().any(|r| n.tick >= r.start && n.tick < r.end));
+        for mut note in self.comp_notes() {
+            note.id = ids.alloc();
+            notes.push(note);
+        }
+        notes.sort_by_key(|n| (n.tick, n.pitch));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lanes() -> TakeLanes {
+        let mut lanes = TakeLanes::new();
+        lanes.add_take(0, 960, vec![Note::new(0, 240, 60, 100), Note::new(480, 240, 62, 100)]);
+        lanes.add_take(0, 960, vec![Note::new(0, 240, 72, 100), Note::new(480, 600, 74, 100)]);
+        lanes
+    }
+
+    #[test]
+    fn comp_regions_split_and_merge() {
+        let mut lanes = lanes();
+        lanes.set_comp_region(0, 960, 0);
+        lanes.set_comp_region(480, 960, 1);
+        assert_eq!(lanes.comp_take_at(100), Some(0));
+        assert_eq!(lanes.comp_take_at(500), Some(1));
+        lanes.set_comp_region(480, 960, 0);
+        assert_eq!(lanes.comp, vec![CompRegion { start: 0, end: 960, take: 0 }]);
+        lanes.set_comp_region(0, 100, 9);
+        assert_eq!(lanes.comp.len(), 1);
+    }
+
+    #[test]
+    fn flatten_uses_comp_and_fresh_ids() {
+        let mut lanes = lanes();
+        lanes.set_comp_region(0, 480, 0);
+        lanes.set_comp_region(480, 720, 1);
+        let mut notes = vec![Note::new(100, 10, 50, 100), Note::new(2000, 10, 50, 100)];
+        let mut ids = NoteIdAllocator::new();
+        assert!(lanes.flatten_into(&mut notes, &mut ids));
+        let pitches: Vec<u8> = notes.iter().map(|n| n.pitch).collect();
+        assert_eq!(pitches, vec![60, 74, 50]);
+        assert_eq!(notes[1].duration, 240);
+        assert!(notes[..2].iter().all(|n| n.id != 0));
+    }
+
+    #[test]
+    fn flatten_keeps_notes_between_regions() {
+        let mut lanes = lanes();
+        lanes.set_comp_region(0, 240, 0);
+        lanes.set_comp_region(720, 960, 1);
+        let mut notes = vec![Note::new(100, 10, 50, 100), Note::new(480, 10, 51, 100)];
+        let mut ids = NoteIdAllocator::new();
+        assert!(lanes.flatten_into(&mut notes, &mut ids));
+        let pitches: Vec<u8> = notes.iter().map(|n| n.pitch).collect();
+        assert_eq!(pitches, vec![60, 51]);
+    }
+
+    #[test]
+    fn audition_replaces_take_region() {
+        let mut lanes = lanes();
+        let base = vec![Note::new(0, 10, 40, 100), Note::new(1000, 10, 41, 100)];
+        assert!(lanes.audition_notes(&base).is_none());
+        lanes.audition(Some(1));
+        let notes = lanes.audition_notes(&base).unwrap();
+        let pitches: Vec<u8> = notes.iter().map(|n| n.pitch).collect();
+        assert_eq!(pitches, vec![72, 74, 41]);
+        lanes.remove_take(1);
+        assert_eq!(lanes.auditioning, None);
+    }
+}
@@ -0,0 +1,401 @@
+//! Tracker view model: notes laid out as rows and columns with hex effects.
+//!
+//! A pattern is a grid of rows (`lines_per_beat` per beat) and note columns.
+//! A note lasts until the next event in its column (note or note-off) or the
+//! end of the pattern. Anything that doesn't sit on the row grid is carried by
+//! effect commands, so converting notes to a pattern and back keeps their
+//! timing, pitch, velocity and id exactly. Probability is rounded to a whole
+//! percent, and zero-length notes come back one tick long:
+//!
+//! | Effect | Meaning |
+//! |--------|---------|
+//! | `0Axx` | probability in percent (maps to `Note::probability`) |
+//! | `0Bxx` | retrigger: play the note `xx` times across its length |
+//! | `0Cxx` | cut the note `xx` ticks after it starts |
+//! | `0Dxx` | delay the event `xx` ticks into the row |
+//! | `1Cxx` / `1Dxx` | add `xx * 256` ticks to a cut / delay |
+
+use std::fmt;
+
+use super::arrangement::Clip;
+use super::piano_roll::{Note, NoteId, Track};
+use crate::InstrumentId;
+
+/// One effect command, shown as four hex digits (`CCPP`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackerEffect {
+    pub command: u8,
+    pub param: u8,
+}
+
+impl TrackerEffect {
+    pub const PROBABILITY: u8 = 0x0A;
+    pub const RETRIGGER: u8 = 0x0B;
+    pub const CUT: u8 = 0x0C;
+    pub const DELAY: u8 = 0x0D;
+    pub const CUT_HIGH: u8 = 0x1C;
+    pub const DELAY_HIGH: u8 = 0x1D;
+
+    pub fn new(command: u8, param: u8) -> Self {
+        Self { command, param }
+    }
+
+    /// Parse `CCPP` hex (e.g. "0D40")
+    pub fn parse(text: &str) -> Option<Self> {
+        if text.len() != 4 {
+            return None;
+        }
+        let command = u8::from_str_radix(text.get(..2)?, 16).ok()?;
+        let param = u8::from_str_radix(text.get(2..)?, 16).ok()?;
+        Some(Self { command, param })
+    }
+}
+
+impl fmt::Display for TrackerEffect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02X}{:02X}", self.command, self.param)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackerNote {
+    On(u8),
+    Off,
+}
+
+/// One cell of a note column.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackerCell {
+    pub note: Option<TrackerNote>,
+    pub instrument: Option<InstrumentId>,
+    /// 0x00-0x7F
+    pub velocity: Option<u8>,
+    pub effects: Vec<TrackerEffect>,
+    /// Id of the note this cell came from (0 for cells typed in the tracker)
+    pub note_id: NoteId,
+}
+
+impl TrackerCell {
+    pub fn is_empty(&self) -> bool {
+        self.note.is_none() && self.effects.is_empty()
+    }
+
+    fn ticks_value(&self, low: u8, high: u8) -> Option<u32> {
+        let mut found = false;
+        let mut value = 0u32;
+        for e in &self.effects {
+            if e.command == low {
+                found = true;
+                value += e.param as u32;
+            } else if e.command == high {
+                found = true;
+                value += e.param as u32 * 256;
+            }
+        }
+        found.then_some(value)
+    }
+
+    fn param(&self, command: u8) -> Option<u8> {
+        self.effects.iter().find(|e| e.command == command).map(|e| e.param)
+    }
+
+    /// Delay in ticks into the row
+    pub fn delay(&self) -> u32 {
+        self.ticks_value(TrackerEffect::DELAY, TrackerEffect::DELAY_HIGH).unwrap_or(0)
+    }
+
+    fn push_ticks(&mut self, low: u8, high: u8, ticks: u32) {
+        if ticks >= 256 {
+            self.effects.push(TrackerEffect::new(high, (ticks / 256).min(255) as u8));
+        }
+        if ticks % 256 != 0 || ticks < 256 {
+            self.effects.push(TrackerEffect::new(low, (ticks % 256) as u8));
+        }
+    }
+}
+
+/// A tracker pattern over a track or clip region starting at tick 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackerPattern {
+    pub ticks_per_beat: u32,
+    pub lines_per_beat: u32,
+    /// rows[row][column]
+    pub rows: Vec<Vec<TrackerCell>>,
+}
+
+/// Column bookkeeping while laying out notes
+struct ColumnState {
+    /// Last row holding a cell
+    busy_row: Option<u32>,
+    /// Open note: (row, end tick)
+    open: Option<(u32, u32)>,
+}
+
+impl TrackerPattern {
+    pub fn new(length_ticks: u32, ticks_per_beat: u32, lines_per_beat: u32) -> Self {
+        let mut pattern = Self { ticks_per_beat, lines_per_beat: lines_per_beat.max(1), rows: Vec::new() };
+        let rows = length_ticks.div_ceil(pattern.row_ticks()) as usize;
+        pattern.rows = vec![Vec::new(); rows];
+        pattern
+    }
+
+    /// Ticks per row
+    pub fn row_ticks(&self) -> u32 {
+        (self.ticks_per_beat / self.lines_per_beat.max(1)).max(1)
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.rows.iter().map(Vec::len).max().unwrap_or(0)
+    }
+
+    pub fn length_ticks(&self) -> u32 {
+        self.rows.len() as u32 * self.row_ticks()
+    }
+
+    pub fn cell(&self, row: usize, column: usize) -> Option<&TrackerCell> {
+        self.rows.get(row)?.get(column)
+    }
+
+    /// Cell for editing, growing the row's columns as needed
+    pub fn cell_mut(&mut self, row: usize, column: usize) -> Option<&mut TrackerCell> {
+        let cells = self.rows.get_mut(row)?;
+        if cells.len() <= column {
+            cells.resize(column + 1, TrackerCell::default());
+        }
+        cells.get_mut(column)
+    }
+
+    /// Lay notes out as a pattern at least `length_ticks` long
+    pub fn from_notes(
+        notes: &[Note],
+        length_ticks: u32,
+        ticks_per_beat: u32,
+        lines_per_beat: u32,
+        instrument: Option<InstrumentId>,
+    ) -> Self {
+        // Zero-length notes still need a row (and a cut) of their own
+        let note_end = |n: &Note| n.end_tick().max(n.tick.saturating_add(1));
+        let max_end = notes.iter().map(note_end).max().unwrap_or(0);
+        let mut pattern = Self::new(length_ticks.max(max_end), ticks_per_beat, lines_per_beat);
+        let rt = pattern.row_ticks();
+        let end_of_pattern = pattern.length_ticks();
+        let mut sorted: Vec<&Note> = notes.iter().collect();
+        sorted.sort_by_key(|n| (n.tick, n.pitch));
+
+        let mut columns: Vec<ColumnState> = Vec::new();
+        for note in sorted {
+            let row = note.tick / rt;
+            // First column where this note fits without colliding cells
+            let fits = |col: &ColumnState| match col.open {
+                Some((_, end)) if end > note.tick => false,
+                Some((_, end)) if end == note.tick => col.busy_row.map_or(true, |b| row > b),
+                // The earlier note needs an off (or a cut in its own row)
+                Some((_, end)) => row > end / rt,
+                None => col.busy_row.map_or(true, |b| row > b),
+            };
+            let column = match columns.iter().position(fits) {
+                Some(c) => c,
+                None => {
+                    columns.push(ColumnState { busy_row: None, open: None });
+                    columns.len() - 1
+                }
+            };
+            if let Some((start_row, end)) = columns[column].open {
+                if end < note.tick {
+                    pattern.close_note(column, start_row, end);
+                }
+            }
+
+            // Every note starts before the pattern end, so its row exists
+            let Some(cell) = pattern.cell_mut(row as usize, column) else {
+                continue;
+            };
+            cell.note = Some(TrackerNote::On(note.pitch));
+            cell.instrument = instrument;
+            cell.velocity = Some(note.velocity.min(0x7F));
+            cell.note_id = note.id;
+            if note.tick % rt != 0 {
+                cell.push_ticks(TrackerEffect::DELAY, TrackerEffect::DELAY_HIGH, note.tick % rt);
+            }
+            if note.probability < 1.0 {
+                let percent = (note.probability.max(0.0) * 100.0).round() as u8;
+                cell.effects.push(TrackerEffect::new(TrackerEffect::PROBABILITY, percent));
+            }
+            columns[column] = ColumnState { busy_row: Some(row), open: Some((row, note_end(note))) };
+        }
+        for (column, col) in columns.iter().enumerate() {
+            if let Some((start_row, end)) = col.open {
+                if end < end_of_pattern {
+                    pattern.close_note(column, start_row, end);
+                }
+            }
+        }
+        pattern
+    }
+
+    /// End the note that started on `start_row` at `end`: a note-off cell, or
+    /// a cut effect when the end falls in the note's own row
+    fn close_note(&mut self, column: usize, start_row: u32, end: u32) {
+        let rt = self.row_ticks();
+        let off_row = end / rt;
+        if off_row == start_row {
+            if let Some(cell) = self.cell_mut(start_row as usize, column) {
+                let duration = end.saturating_sub(start_row * rt + cell.delay());
+                cell.push_ticks(TrackerEffect::CUT, TrackerEffect::CUT_HIGH, duration);
+            }
+        } else if let Some(cell) = self.cell_mut(off_row as usize, column) {
+            cell.note = Some(TrackerNote::Off);
+            if end % rt != 0 {
+                cell.push_ticks(TrackerEffect::DELAY, TrackerEffect::DELAY_HIGH, end % rt);
+            }
+        }
+    }
+
+    /// Convert back to notes. Retriggered notes expand into several notes.
+    pub fn to_notes(&self) -> Vec<Note> {
+        let rt = self.row_ticks();
+        let end_of_pattern = self.length_ticks();
+        let mut notes = Vec::new();
+        for column in 0..self.column_count() {
+            let mut open: Option<(Note, u8)> = None;
+            for (row, cells) in self.rows.iter().enumerate() {
+                let Some(cell) = cells.get(column) else {
+                    continue;
+                };
+                let Some(event) = cell.note else {
+                    continue;
+                };
+                let tick = row as u32 * rt + cell.delay();
+                if let Some((note, retrigger)) = open.take() {
+                    push_note(&mut notes, note, tick, retrigger);
+                }
+                if let TrackerNote::On(pitch) = event {
+                    let mut note = Note::new(tick, 0, pitch, cell.velocity.unwrap_or(100).clamp(1, 127));
+                    note.id = cell.note_id;
+                    if let Some(percent) = cell.param(TrackerEffect::PROBABILITY) {
+                        note.probability = (percent.min(100) as f32) / 100.0;
+                    }
+                    let retrigger = cell.param(TrackerEffect::RETRIGGER).unwrap_or(1);
+                    match cell.ticks_value(TrackerEffect::CUT, TrackerEffect::CUT_HIGH) {
+                        Some(cut) => push_note(&mut notes, note, tick + cut.max(1), retrigger),
+                        None => open = Some((note, retrigger)),
+                    }
+                }
+            }
+            if let Some((note, retrigger)) = open {
+                let end = end_of_pattern.max(note.tick + 1);
+                push_note(&mut notes, note, end, retrigger);
+            }
+        }
+        notes.sort_by_key(|n| (n.tick, n.pitch));
+        notes
+    }
+
+    /// Pattern for a track's notes over [0, length_ticks)
+    pub fn from_track(track: &Track, length_ticks: u32, ticks_per_beat: u32, lines_per_beat: u32) -> Self {
+        Self::from_notes(&track.notes, length_ticks, ticks_per_beat, lines_per_beat, Some(track.module_id))
+    }
+
+    pub fn from_clip(clip: &Clip, ticks_per_beat: u32, lines_per_beat: u32) -> Self {
+        let length = clip.length_ticks();
+        Self::from_notes(&clip.notes, length, ticks_per_beat, lines_per_beat, Some(clip.instrument_id))
+    }
+}
+
+/// Finish a note at `end`, splitting it when retriggered
+fn push_note(notes: &mut Vec<Note>, mut note: Note, end: u32, retrigger: u8) {
+    let length = end.saturating_sub(note.tick).max(1);
+    let count = (retrigger.max(1) as u32).min(length);
+    if count <= 1 {
+        note.duration = length;
+        notes.push(note);
+        return;
+    }
+    let part = length / count;
+    for i in 0..count {
+        let mut hit = note.clone();
+        hit.tick = note.tick + i * part;
+        hit.duration = if i + 1 == count { length - i * part } else { part };
+        if i > 0 {
+            hit.id = 0;
+        }
+        notes.push(hit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(notes: &[Note]) -> Vec<(NoteId, u32, u32, u8, u8, u32)> {
+        let mut k: Vec<_> = notes
+            .iter()
+            .map(|n| (n.id, n.tick, n.duration, n.pitch, n.velocity, (n.probability * 100.0).round() as u32))
+            .collect();
+        k.sort();
+        k
+    }
+
+    fn note(id: NoteId, tick: u32, duration: u32, pitch: u8) -> Note {
+        Note { id, ..Note::new(tick, duration, pitch, 90) }
+    }
+
+    #[test]
+    fn round_trip_keeps_notes() {
+        let mut notes = vec![
+            note(1, 0, 480, 60),
+            note(2, 0, 240, 64),
+            note(3, 130, 10, 67),
+            note(4, 480, 1440, 72),
+            note(5, 700, 300, 60),
+            note(6, 1000, 5, 61),
+        ];
+        notes[2].probability = 0.25;
+        for lpb in [1, 4, 8] {
+            let pattern = TrackerPattern::from_notes(&notes, 1920, 480, lpb, Some(1));
+            assert_eq!(key(&pattern.to_notes()), key(&notes), "lines per beat {lpb}");
+        }
+    }
+
+    #[test]
+    fn layout_uses_offs_delays_and_effects() {
+        let mut n = note(1, 130, 200, 60);
+        n.probability = 0.5;
+        let pattern = TrackerPattern::from_notes(&[n], 960, 480, 4, None);
+        assert_eq!(pattern.row_count(), 8);
+        let cell = pattern.cell(1, 0).unwrap();
+        assert_eq!(cell.note, Some(TrackerNote::On(60)));
+        let effects: Vec<String> = cell.effects.iter().map(|e| e.to_string()).collect();
+        assert_eq!(effects, vec!["0D0A", "0A32"]);
+        let off = pattern.cell(2, 0).unwrap();
+        assert_eq!(off.note, Some(TrackerNote::Off));
+        assert_eq!(off.delay(), 90);
+    }
+
+    #[test]
+    fn zero_length_notes_get_a_row() {
+        let notes = [Note::new(0, 480, 60, 100), Note::new(480, 0, 62, 100)];
+        let pattern = TrackerPattern::from_notes(&notes, 0, 480, 4, None);
+        assert_eq!(pattern.row_count(), 5);
+        assert_eq!(pattern.cell(4, 0).unwrap().note, Some(TrackerNote::On(62)));
+        let back: Vec<(u32, u32, u8)> =
+            pattern.to_notes().iter().map(|n| (n.tick, n.duration, n.pitch)).collect();
+        assert_eq!(back, vec![(0, 480, 60), (480, 1, 62)]);
+    }
+
+    #[test]
+    fn typed_retrigger_expands() {
+        let mut pattern = TrackerPattern::new(480, 480, 4);
+        let cell = pattern.cell_mut(0, 0).unwrap();
+        cell.note = Some(TrackerNote::On(36));
+        cell.effects.push(TrackerEffect::parse("0B04").unwrap());
+        pattern.cell_mut(2, 0).unwrap().note = Some(TrackerNote::Off);
+        let notes = pattern.to_notes();
+        let ticks: Vec<(u32, u32)> = notes.iter().map(|n| (n.tick, n.duration)).collect();
+        assert_eq!(ticks, vec![(0, 60), (60, 60), (120, 60), (180, 60)]);
+    }
+}
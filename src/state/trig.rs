@@ -0,0 +1,141 @@
+//! Conditional trigs for drum steps and notes.
+//!
+//! A condition decides whether a hit plays on a given pass of its pattern,
+//! before probability is rolled. Conditions look at the loop counter (0 on
+//! the first pass), whether fill mode is held, and whether the previous hit
+//! on the same pad or track played.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// When a step or note is allowed to play.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TrigCondition {
+    #[default]
+    Always,
+    /// Play on pass `a` of every `b` (1-based: 1:2 plays on passes 1, 3, 5...)
+    Ratio { a: u8, b: u8 },
+    /// Play on every pass except pass `a` of every `b`
+    NotRatio { a: u8, b: u8 },
+    /// Only while fill mode is held
+    Fill,
+    /// Only while fill mode is not held
+    NotFill,
+    /// Only if the previous hit on the same pad or track played
+    Previous,
+    /// Only if the previous hit on the same pad or track did not play
+    NotPrevious,
+    /// Only on the first pass
+    First,
+    /// On every pass but the first
+    NotFirst,
+}
+
+/// What a condition is evaluated against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrigContext {
+    /// Pattern loop counter, 0 on the first pass
+    pub iteration: u32,
+    pub fill: bool,
+    /// Whether the previous hit on the same pad or track played this pass
+    pub previous: bool,
+}
+
+impl TrigCondition {
+    /// Conditions stepped through by [`TrigCondition::next`]
+    pub const CYCLE: [TrigCondition; 13] = [
+        TrigCondition::Always,
+        TrigCondition::Ratio { a: 1, b: 2 },
+        TrigCondition::Ratio { a: 2, b: 2 },
+        TrigCondition::Ratio { a: 1, b: 4 },
+        TrigCondition::Ratio { a: 2, b: 4 },
+        TrigCondition::Ratio { a: 3, b: 4 },
+        TrigCondition::Ratio { a: 4, b: 4 },
+        TrigCondition::Fill,
+        TrigCondition::NotFill,
+        TrigCondition::Previous,
+        TrigCondition::NotPrevious,
+        TrigCondition::First,
+        TrigCondition::NotFirst,
+    ];
+
+    pub fn evaluate(&self, ctx: &TrigContext) -> bool {
+        let on_ratio = |a: u8, b: u8| {
+            let b = b.max(1) as u32;
+            ctx.iteration % b == (a.clamp(1, b as u8) as u32 - 1)
+        };
+        match *self {
+            TrigCondition::Always => true,
+            TrigCondition::Ratio { a, b } => on_ratio(a, b),
+            TrigCondition::NotRatio { a, b } => !on_ratio(a, b),
+            TrigCondition::Fill => ctx.fill,
+            TrigCondition::NotFill => !ctx.fill,
+            TrigCondition::Previous => ctx.previous,
+            TrigCondition::NotPrevious => !ctx.previous,
+            TrigCondition::First => ctx.iteration == 0,
+            TrigCondition::NotFirst => ctx.iteration != 0,
+        }
+    }
+
+    /// Whether evaluating needs the previous hit's outcome
+    pub fn uses_previous(&self) -> bool {
+        matches!(self, TrigCondition::Previous | TrigCondition::NotPrevious)
+    }
+
+    /// Next entry of [`TrigCondition::CYCLE`] (other conditions go back to Always)
+    pub fn next(&self) -> TrigCondition {
+        let i = Self::CYCLE.iter().position(|c| c == self).map_or(0, |i| (i + 1) % Self::CYCLE.len());
+        Self::CYCLE[i]
+    }
+}
+
+impl fmt::Display for TrigCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrigCondition::Always => write!(f, "ALL"),
+            TrigCondition::Ratio { a, b } => write!(f, "{}:{}", a, b),
+            TrigCondition::NotRatio { a, b } => write!(f, "!{}:{}", a, b),
+            TrigCondition::Fill => write!(f, "FILL"),
+            TrigCondition::NotFill => write!(f, "!FILL"),
+            TrigCondition::Previous => write!(f, "PRE"),
+            TrigCondition::NotPrevious => write!(f, "!PRE"),
+            TrigCondition::First => write!(f, "1ST"),
+            TrigCondition::NotFirst => write!(f, "!1ST"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn passes(condition: TrigCondition, fill: bool, previous: bool) -> Vec<bool> {
+        (0..8).map(|iteration| condition.evaluate(&TrigContext { iteration, fill, previous })).collect()
+    }
+
+    #[test]
+    fn ratios_and_first_pass() {
+        let t = true;
+        let f = false;
+        assert_eq!(passes(TrigCondition::Ratio { a: 1, b: 2 }, f, f), vec![t, f, t, f, t, f, t, f]);
+        assert_eq!(passes(TrigCondition::Ratio { a: 3, b: 4 }, f, f), vec![f, f, t, f, f, f, t, f]);
+        assert_eq!(passes(TrigCondition::NotRatio { a: 3, b: 4 }, f, f), vec![t, t, f, t, t, t, f, t]);
+        assert_eq!(passes(TrigCondition::First, f, f), vec![t, f, f, f, f, f, f, f]);
+        assert_eq!(passes(TrigCondition::NotFirst, f, f)[..2], [f, t]);
+    }
+
+    #[test]
+    fn fill_previous_and_cycling() {
+        assert!(passes(TrigCondition::Fill, true, false).iter().all(|&p| p));
+        assert!(!passes(TrigCondition::Fill, false, false).iter().any(|&p| p));
+        assert!(passes(TrigCondition::NotPrevious, false, false).iter().all(|&p| p));
+        assert!(!passes(TrigCondition::Previous, false, false).iter().any(|&p| p));
+
+        assert_eq!(TrigCondition::Always.next(), TrigCondition::Ratio { a: 1, b: 2 });
+        assert_eq!(TrigCondition::NotFirst.next(), TrigCondition::Always);
+        assert_eq!(TrigCondition::NotRatio { a: 1, b: 3 }.next(), TrigCondition::Always);
+        assert_eq!(TrigCondition::Ratio { a: 3, b: 4 }.to_string(), "3:4");
+        assert_eq!(TrigCondition::NotPrevious.to_string(), "!PRE");
+    }
+}
@@ -0,0 +1,248 @@
+//! Voice allocation settings and monophonic overlap resolution.
+
+use serde::{Deserialize, Serialize};
+
+use super::note_transform::glue;
+use super::piano_roll::{Note, NoteId};
+
+/// Which voice to take when all voices are busy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StealMode {
+    /// Steal the voice that started first
+    #[default]
+    Oldest,
+    /// Steal the voice with the lowest velocity
+    Quietest,
+    /// Steal the voice with the lowest pitch
+    Lowest,
+}
+
+impl StealMode {
+    pub const ALL: [StealMode; 3] = [StealMode::Oldest, StealMode::Quietest, StealMode::Lowest];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StealMode::Oldest => "Oldest",
+            StealMode::Quietest => "Quietest",
+            StealMode::Lowest => "Lowest",
+        }
+    }
+
+    pub fn next(&self) -> StealMode {
+        match self {
+            StealMode::Oldest => StealMode::Quietest,
+            StealMode::Quietest => StealMode::Lowest,
+            StealMode::Lowest => StealMode::Oldest,
+        }
+    }
+}
+
+/// Per-instrument voice settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoiceSettings {
+    /// Maximum simultaneous voices when polyphonic (1-64)
+    pub voices: u8,
+    pub steal_mode: StealMode,
+    /// Mono only: overlapping notes glide without retriggering the envelope
+    pub legato: bool,
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        Self {
+            voices: 16,
+            steal_mode: StealMode::Oldest,
+            legato: false,
+        }
+    }
+}
+
+impl VoiceSettings {
+    pub const MAX_VOICES: u8 = 64;
+
+    pub fn adjust_voices(&mut self, delta: i8) {
+        self.voices = (self.voices as i16 + delta as i16).clamp(1, Self::MAX_VOICES as i16) as u8;
+    }
+
+    /// Overlap handling a mono track should use with these settings
+    pub fn mono_mode(&self) -> MonoOverlapMode {
+        if self.legato {
+            MonoOverlapMode::Legato
+        } else {
+            MonoOverlapMode::Truncate
+        }
+    }
+}
+
+/// How overlapping notes on a mono track are resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonoOverlapMode {
+    /// Cut the earlier note where the next one starts (every note retriggers)
+    Truncate,
+    /// Like Truncate, but overlapping notes of the same pitch merge into one
+    /// and a note entering over another pitch glides instead of retriggering
+    /// (see [`resolve_mono_legato`])
+    Legato,
+}
+
+/// Two notes sounding at once on a track that can't play them both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverlapConflict {
+    /// The earlier note (the one that would be cut or stolen)
+    pub first: NoteId,
+    /// The note that starts while `first` is still sounding
+    pub second: NoteId,
+    /// Number of ticks the two notes overlap
+    pub overlap: u32,
+}
+
+/// Every pair of overlapping notes (what a mono track would have to resolve)
+pub fn overlap_conflicts(notes: &[Note]) -> Vec<OverlapConflict> {
+    let mut sorted: Vec<&Note> = notes.iter().collect();
+    sorted.sort_by_key(|n| (n.tick, n.pitch));
+    let mut conflicts = Vec::new();
+    for (i, a) in sorted.iter().enumerate() {
+        for b in sorted[i + 1..].iter().take_while(|b| b.tick < a.end_tick()) {
+            conflicts.push(OverlapConflict {
+                first: a.id,
+                second: b.id,
+                overlap: a.end_tick().min(b.end_tick()) - b.tick,
+            });
+        }
+    }
+    conflicts
+}
+
+/// Notes that would steal a voice when more than `settings.voices` notes sound at once.
+/// Each conflict names the stolen note (`first`) and the note that stole it (`second`).
+pub fn voice_steals(notes: &[Note], settings: &VoiceSettings) -> Vec<OverlapConflict> {
+    let mut sorted: Vec<&Note> = notes.iter().collect();
+    sorted.sort_by_key(|n| (n.tick, n.pitch));
+    let max = settings.voices.max(1) as usize;
+    let mut active: Vec<&Note> = Vec::new();
+    let mut steals = Vec::new();
+    for note in sorted {
+        active.retain(|a| a.end_tick() > note.tick);
+        if active.len() >= max {
+            let victim = match settings.steal_mode {
+                StealMode::Oldest => active.iter().enumerate().min_by_key(|(_, a)| a.tick),
+                StealMode::Quietest => active.iter().enumerate().min_by_key(|(_, a)| a.velocity),
+                StealMode::Lowest => active.iter().enumerate().min_by_key(|(_, a)| a.pitch),
+            }
+            .map(|(i, _)| i);
+            if let Some(i) = victim {
+                let stolen = active.remove(i);
+                steals.push(OverlapConflict {
+                    first: stolen.id,
+                    second: note.id,
+                    overlap: stolen.end_tick().min(note.end_tick()) - note.tick,
+                });
+            }
+        }
+        active.push(note);
+    }
+    steals
+}
+
+/// Make notes playable on a mono track: at most one note sounds at a time.
+/// Notes starting on the same tick keep only the highest pitch.
+pub fn resolve_mono_overlaps(notes: Vec<Note>, mode: MonoOverlapMode) -> Vec<Note> {
+    truncate_overlaps(mono_order(notes, mode))
+}
+
+/// Cut each note of a sorted, one-per-tick list where the next one starts
+fn truncate_overlaps(mut notes: Vec<Note>) -> Vec<Note> {
+    for i in 0..notes.len().saturating_sub(1) {
+        let next_tick = notes[i + 1].tick;
+        if notes[i].end_tick() > next_tick {
+            notes[i].duration = next_tick - notes[i].tick;
+        }
+    }
+    notes
+}
+
+/// Resolve overlaps as [`MonoOverlapMode::Legato`] does, flagging the notes
+/// played without retriggering: each starts while the previous note (of
+/// another pitch) is still held, so the voice glides from it
+pub fn resolve_mono_legato(notes: Vec<Note>) -> Vec<(Note, bool)> {
+    let notes = mono_order(notes, MonoOverlapMode::Legato);
+    let glides: Vec<bool> = std::iter::once(false)
+        .chain(notes.windows(2).map(|w| w[0].end_tick() > w[1].tick))
+        .collect();
+    truncate_overlaps(notes).into_iter().zip(glides).collect()
+}
+
+/// The notes a mono track keeps, sorted by tick: one per tick (the highest
+/// pitch), with same-pitch overlaps merged in legato mode
+fn mono_order(notes: Vec<Note>, mode: MonoOverlapMode) -> Vec<Note> {
+    let mut notes = match mode {
+        MonoOverlapMode::Truncate => notes,
+        MonoOverlapMode::Legato => glue(notes),
+    };
+    // Highest pitch first within a tick so dedup keeps it
+    notes.sort_by(|a, b| a.tick.cmp(&b.tick).then(b.pitch.cmp(&a.pitch)));
+    notes.dedup_by_key(|n| n.tick);
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn n(id: u32, tick: u32, duration: u32, pitch: u8, velocity: u8) -> Note {
+        Note { id, ..Note::new(tick, duration, pitch, velocity) }
+    }
+
+    #[test]
+    fn reports_overlaps() {
+        let notes = vec![n(1, 0, 100, 60, 100), n(2, 50, 100, 62, 100), n(3, 200, 10, 64, 100)];
+        let conflicts = overlap_conflicts(&notes);
+        assert_eq!(conflicts, vec![OverlapConflict { first: 1, second: 2, overlap: 50 }]);
+    }
+
+    #[test]
+    fn truncate_cuts_and_drops_chords() {
+        let notes = vec![n(1, 0, 100, 60, 100), n(2, 0, 100, 67, 100), n(3, 50, 100, 62, 100)];
+        let out = resolve_mono_overlaps(notes, MonoOverlapMode::Truncate);
+        assert_eq!(out.len(), 2);
+        assert_eq!((out[0].id, out[0].duration), (2, 50));
+        assert!(overlap_conflicts(&out).is_empty());
+    }
+
+    #[test]
+    fn legato_merges_same_pitch() {
+        let notes = vec![n(1, 0, 100, 60, 100), n(2, 80, 100, 60, 100), n(3, 150, 50, 64, 100)];
+        let out = resolve_mono_overlaps(notes, MonoOverlapMode::Legato);
+        assert_eq!(out.len(), 2);
+        assert_eq!((out[0].id, out[0].duration), (1, 150));
+        assert!(overlap_conflicts(&out).is_empty());
+    }
+
+    #[test]
+    fn legato_glides_between_pitches() {
+        let notes = vec![
+            n(1, 0, 100, 60, 100),
+            n(2, 80, 100, 60, 100),
+            n(3, 150, 50, 64, 100),
+            n(4, 200, 50, 67, 100),
+        ];
+        let out = resolve_mono_legato(notes);
+        // 3 enters while the merged 1 is held; 4 only touches 3
+        let glides: Vec<(NoteId, bool)> = out.iter().map(|(n, g)| (n.id, *g)).collect();
+        assert_eq!(glides, vec![(1, false), (3, true), (4, false)]);
+        assert_eq!((out[0].0.end_tick(), out[1].0.tick), (150, 150));
+    }
+
+    #[test]
+    fn voice_steals_follow_mode() {
+        let notes = vec![n(1, 0, 100, 50, 90), n(2, 10, 100, 70, 20), n(3, 20, 100, 60, 100)];
+        let mut settings = VoiceSettings { voices: 2, ..VoiceSettings::default() };
+        assert_eq!(voice_steals(&notes, &settings)[0].first, 1);
+        settings.steal_mode = StealMode::Quietest;
+        assert_eq!(voice_steals(&notes, &settings)[0].first, 2);
+        settings.steal_mode = StealMode::Lowest;
+        assert_eq!(voice_steals(&notes, &settings)[0].first, 1);
+        settings.voices = 3;
+        assert!(voice_steals(&notes, &settings).is_empty());
+    }
+}
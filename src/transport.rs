@@ -0,0 +1,310 @@
+//! Headless transport for driving playback without an audio server.
+//!
+//! `Transport` advances through the project at simulated wall-clock time using
+//! the [`Scheduler`], moves the piano roll playhead, applies automation and
+//! produces the same `AudioFeedback` messages the audio thread would send.
+//! Dispatch logic, pattern chaining and song playback can be exercised in tests
+//! by interleaving actions with calls to [`Transport::advance`].
+
+use std::collections::HashMap;
+
+use crate::scheduler::{ScheduledEvent, ScheduledEventKind, Scheduler, SchedulerCache};
+use crate::state::automation::AutomationTarget;
+use crate::state::drum_sequencer::DrumSequencerState;
+use crate::state::session::SessionState;
+use crate::{AudioFeedback, InstrumentId};
+
+/// A drum sequencer played alongside the session.
+#[derive(Debug, Clone)]
+struct DrumWatch {
+    instrument_id: InstrumentId,
+    sequencer: DrumSequencerState,
+}
+
+/// Simulated playback clock.
+#[derive(Debug, Clone, Default)]
+pub struct Transport {
+    playing: bool,
+    start_tick: u32,
+    start_iteration: u32,
+    /// Whole ticks played since `play`
+    stream_tick: u64,
+    /// Fractional tick carried between advances
+    tick_frac: f64,
+    elapsed_secs: f64,
+    drums: Vec<DrumWatch>,
+    drum_steps: HashMap<InstrumentId, (usize, Vec<usize>)>,
+    values: HashMap<AutomationTarget, f32>,
+    events: Vec<ScheduledEvent>,
+    /// Kept across advances until the session is edited
+    cache: SchedulerCache,
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Simulated seconds since `play`
+    pub fn elapsed_secs(&self) -> f64 {
+        self.elapsed_secs
+    }
+
+    /// Ticks played since `play`
+    pub fn stream_tick(&self) -> u64 {
+        self.stream_tick
+    }
+
+    /// Start playback from the piano roll's current playhead
+    pub fn play(&mut self, session: &mut SessionState) {
+        let pr = &mut session.piano_roll;
+        pr.playing = true;
+        self.playing = true;
+        self.start_tick = pr.playhead;
+        self.start_iteration = pr.loop_iteration;
+        self.stream_tick = 0;
+        self.tick_frac = 0.0;
+        self.elapsed_secs = 0.0;
+        self.drum_steps.clear();
+        self.events.clear();
+        self.cache = SchedulerCache::default();
+    }
+
+    pub fn stop(&mut self, session: &mut SessionState) {
+        session.piano_roll.playing = false;
+        self.playing = false;
+    }
+
+    /// Play a drum machine instrument's sequencer and report its step changes,
+    /// for the pattern as a whole and for each pad's row. Call again after
+    /// editing the sequencer so playback follows the edit.
+    pub fn watch_drum_sequencer(&mut self, instrument_id: InstrumentId, sequencer: &DrumSequencerState) {
+        self.drums.retain(|d| d.instrument_id != instrument_id);
+        self.drum_steps.remove(&instrument_id);
+        self.drums.push(DrumWatch { instrument_id, sequencer: sequencer.clone() });
+    }
+
+    /// Drop what the scheduler derived from the session. Call after editing
+    /// the session so playback follows the edit.
+    pub fn session_changed(&mut self) {
+        self.cache = SchedulerCache::default();
+    }
+
+    /// Last automation value applied for a target
+    pub fn automation_value(&self, target: &AutomationTarget) -> Option<f32> {
+        self.values.get(target).copied()
+    }
+
+    /// Scheduled events played so far (drained)
+    pub fn take_events(&mut self) -> Vec<ScheduledEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Advance the clock by `secs` of wall-clock time at the current tempo.
+    /// Tempo automation takes effect from the next call. Negative or
+    /// non-finite times are ignored.
+    pub fn advance(&mut self, session: &mut SessionState, secs: f64) -> Vec<AudioFeedback> {
+        if !self.playing || !secs.is_finite() || secs <= 0.0 {
+            return Vec::new();
+        }
+        let pr = &session.piano_roll;
+        let ticks = secs * pr.bpm as f64 / 60.0 * pr.ticks_per_beat as f64 + self.tick_frac;
+        self.tick_frac = ticks.fract();
+        self.elapsed_secs += secs;
+        self.advance_ticks(session, ticks as u64)
+    }
+
+    /// Advance the clock by a whole number of ticks. The outcome of each hit
+    /// due is recorded in the session's probability resolver.
+    pub fn advance_ticks(&mut self, session: &mut SessionState, ticks: u64) -> Vec<AudioFeedback> {
+        let mut feedback = Vec::new();
+        if !self.playing || ticks == 0 {
+            return feedback;
+        }
+        let from = self.stream_tick;
+        let to = from.saturating_add(ticks);
+        if to == from {
+            return feedback;
+        }
+        let (events, results, position, drum_steps) = {
+            let mut scheduler = Scheduler::new(session)
+                .starting_at(self.start_tick, self.start_iteration)
+                .with_cache(std::mem::take(&mut self.cache));
+            for drum in &self.drums {
+                scheduler = scheduler.with_drums(drum.instrument_id, &drum.sequencer);
+            }
+            let (events, results) = scheduler.events_with_results(from, to);
+            let position = scheduler.position(to);
+            let drum_steps = scheduler.drum_steps(from, to);
+            self.cache = scheduler.into_cache();
+            (events, results, position, drum_steps)
+        };
+        for step in drum_steps {
+            let position = (step.step, step.pad_steps);
+            if self.drum_steps.get(&step.instrument_id) != Some(&position) {
+                self.drum_steps.insert(step.instrument_id, position.clone());
+                feedback.push(AudioFeedback::DrumSequencerStep {
+                    instrument_id: step.instrument_id,
+                    step: position.0,
+                    pad_steps: position.1,
+                });
+            }
+        }
+        for result in results {
+            session.probability.record(result);
+        }
+
+        for event in &events {
+            if let ScheduledEventKind::Automation { target, value } = &event.kind {
+                self.values.insert(target.clone(), *value);
+                if *target == AutomationTarget::Bpm {
+                    session.piano_roll.bpm = *value;
+                    feedback.push(AudioFeedback::BpmUpdate(*value));
+                }
+            }
+        }
+        self.events.extend(events);
+
+        self.stream_tick = to;
+        session.piano_roll.playhead = position.tick;
+        session.piano_roll.loop_iteration = position.iteration;
+        feedback.push(AudioFeedback::PlayheadPosition(position.tick));
+        feedback
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::drum_sequencer::DrumPattern;
+    use crate::state::piano_roll::Note;
+    use crate::state::probability::HitKey;
+
+    fn session() -> SessionState {
+        let mut session = SessionState::new();
+        session.piano_roll.add_track(1);
+        session.piano_roll.insert_note(0, Note::new(0, 240, 60, 100));
+        session.piano_roll.loop_end = 960;
+        session
+    }
+
+    #[test]
+    fn advances_playhead_at_tempo_and_wraps() {
+        let mut session = session();
+        let mut transport = Transport::new();
+        transport.play(&mut session);
+        // 120 bpm, 480 tpb: 0.25s = 240 ticks
+        let feedback = transport.advance(&mut session, 0.25);
+        assert!(matches!(feedback.last(), Some(AudioFeedback::PlayheadPosition(240))));
+        for _ in 0..4 {
+            transport.advance(&mut session, 0.25);
+        }
+        assert_eq!(session.piano_roll.playhead, 240);
+        assert_eq!(session.piano_roll.loop_iteration, 1);
+        let ons = transport
+            .take_events()
+            .iter()
+            .filter(|e| matches!(e.kind, ScheduledEventKind::NoteOn { .. }))
+            .count();
+        assert_eq!(ons, 2);
+    }
+
+    #[test]
+    fn records_hit_outcomes() {
+        let mut session = session();
+        let mut skipped = Note::new(480, 120, 64, 100);
+        skipped.probability = 0.0;
+        session.piano_roll.insert_note(0, skipped);
+        let ids: Vec<_> = session.piano_roll.tracks[&1].notes.iter().map(|n| n.id).collect();
+        let mut transport = Transport::new();
+        transport.play(&mut session);
+        transport.advance_ticks(&mut session, 960);
+        transport.advance_ticks(&mut session, 960);
+        let outcome = |session: &SessionState, note_id| {
+            let key = HitKey::Note { instrument_id: 1, note_id, placement: None };
+            session.probability.result_for(&key).map(|r| (r.iteration, r.fired))
+        };
+        assert_eq!(outcome(&session, ids[0]), Some((1, true)));
+        assert_eq!(outcome(&session, ids[1]), Some((1, false)));
+        assert_eq!(session.probability.results.len(), 2);
+    }
+
+    #[test]
+    fn reports_drum_steps_and_stops() {
+        let mut session = session();
+        let mut transport = Transport::new();
+        let mut seq = DrumSequencerState::new();
+        let pattern = seq.pattern_mut();
+        *pattern = DrumPattern::new(4);
+        // Pad 1: three-step row of eighth triplets (160 ticks)
+        pattern.adjust_pad_length(1, -1);
+        pattern.cycle_pad_resolution(1);
+        pattern.toggle_step(0, 2);
+        transport.watch_drum_sequencer(7, &seq);
+        transport.play(&mut session);
+        let steps: Vec<(usize, usize, usize)> = transport
+            .advance_ticks(&mut session, 600)
+            .iter()
+            .filter_map(|f| match f {
+                AudioFeedback::DrumSequencerStep { step, pad_steps, .. } => {
+                    Some((*step, pad_steps[0], pad_steps[1]))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            steps,
+            vec![(0, 0, 0), (1, 1, 0), (1, 1, 1), (2, 2, 1), (2, 2, 2), (3, 3, 2), (0, 0, 0)]
+        );
+        // The sequencer plays through the scheduler, outcomes included
+        let hits: Vec<u64> = transport
+            .take_events()
+            .iter()
+            .filter(|e| matches!(e.kind, ScheduledEventKind::DrumHit { instrument_id: 7, pad: 0, .. }))
+            .map(|e| e.stream_tick)
+            .collect();
+        assert_eq!(hits, vec![240]);
+        let key = HitKey::Step { instrument_id: 7, pad: 0, step: 2 };
+        assert!(session.probability.result_for(&key).is_some_and(|r| r.fired));
+        transport.stop(&mut session);
+        assert!(transport.advance(&mut session, 1.0).is_empty());
+        assert!(!session.piano_roll.playing);
+    }
+
+    #[test]
+    fn applies_tempo_automation() {
+        let mut session = session();
+        let id = session.automation.add_lane(AutomationTarget::Bpm);
+        let lane = session.automation.lane_mut(id).unwrap();
+        lane.add_point(0, 0.5);
+        let mut transport = Transport::new();
+        transport.play(&mut session);
+        let feedback = transport.advance_ticks(&mut session, 100);
+        let bpm = transport.automation_value(&AutomationTarget::Bpm).unwrap();
+        assert!(feedback.iter().any(|f| matches!(f, AudioFeedback::BpmUpdate(_))));
+        assert_eq!(session.piano_roll.bpm, bpm);
+    }
+
+    #[test]
+    fn ignores_bad_times_and_follows_edits() {
+        let mut session = session();
+        let mut transport = Transport::new();
+        transport.play(&mut session);
+        assert!(transport.advance(&mut session, f64::NAN).is_empty());
+        assert!(transport.advance(&mut session, f64::INFINITY).is_empty());
+        assert_eq!((transport.stream_tick(), transport.elapsed_secs()), (0, 0.0));
+
+        transport.advance_ticks(&mut session, 100);
+        let id = session.automation.add_lane(AutomationTarget::InstrumentLevel(1));
+        let lane = session.automation.lane_mut(id).unwrap();
+        lane.add_point(0, 0.0);
+        lane.add_point(150, 1.0);
+        transport.session_changed();
+        transport.advance_ticks(&mut session, 100);
+        assert!(transport.automation_value(&AutomationTarget::InstrumentLevel(1)).is_some());
+    }
+}